@@ -52,6 +52,12 @@ impl HTTPError {
             data: None,
         }
     }
+
+    /// with_data attaches machine-readable details to the error.
+    pub fn with_data(mut self, data: serde_json::Value) -> Self {
+        self.data = Some(data);
+        self
+    }
 }
 
 impl fmt::Display for HTTPError {
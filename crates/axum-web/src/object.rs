@@ -530,10 +530,12 @@ where
 
         let enc = Encoding::from_header_value(headers.get(header::CONTENT_ENCODING));
         let mut bytes = Bytes::from_request(req, state).await.map_err(|err| {
-            HTTPError::new(
-                StatusCode::BAD_REQUEST.as_u16(),
-                format!("Invalid body, {}", err),
-            )
+            // 413 if the body exceeds the DefaultBodyLimit of the route.
+            let code = match err.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => StatusCode::PAYLOAD_TOO_LARGE,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            HTTPError::new(code.as_u16(), format!("Invalid body, {}", err))
         })?;
 
         if !enc.identity() {
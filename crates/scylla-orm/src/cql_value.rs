@@ -28,6 +28,18 @@ impl ToCqlVal for String {
     }
 }
 
+impl FromCqlVal for bool {
+    fn from_cql(cql_val: &CqlValue) -> Result<Self, FromCqlValError> {
+        cql_to_rust::FromCqlVal::from_cql(cql_val.to_owned())
+    }
+}
+
+impl ToCqlVal for bool {
+    fn to_cql(&self) -> CqlValue {
+        CqlValue::Boolean(self.to_owned())
+    }
+}
+
 impl FromCqlVal for i8 {
     fn from_cql(cql_val: &CqlValue) -> Result<Self, FromCqlValError> {
         cql_to_rust::FromCqlVal::from_cql(cql_val.to_owned())
@@ -250,5 +262,7 @@ mod tests {
             "hello".to_string().to_cql(),
             CqlValue::Text("hello".to_string())
         );
+        assert_eq!(true.to_cql(), CqlValue::Boolean(true));
+        assert!(!bool::from_cql(&CqlValue::Boolean(false)).unwrap());
     }
 }
@@ -0,0 +1,379 @@
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use futures::stream::{self, StreamExt};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+use validator::Validate;
+
+use axum_web::context::{unix_ms, ReqContext};
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+
+use crate::db::{self, TaskStatus};
+use crate::events;
+
+use crate::api::{check_uid, AppState};
+
+// the page size of the reads behind a feed.
+const CALENDAR_PAGE_SIZE: u16 = 100;
+
+// the octets of a content line before it is folded, RFC 5545 3.1.
+const ICS_LINE_OCTETS: usize = 75;
+
+// the token of the feed of the user, HMAC-SHA256 of the uid with the calendar secret.
+pub fn feed_token(secret: &str, uid: xid::Id) -> String {
+    events::sign(secret, uid.to_string().as_bytes())
+}
+
+// the feed is served without the auth, so the token in its URL is the only check.
+fn check_token(secret: &str, uid: xid::Id, token: &str) -> Result<(), HTTPError> {
+    if secret.is_empty() {
+        return Err(HTTPError::new(404, "calendar feed disabled".to_string()));
+    }
+    let signature = hex::decode(token)
+        .map_err(|_| HTTPError::new(401, "invalid calendar token".to_string()))?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(uid.to_string().as_bytes());
+    mac.verify_slice(&signature)
+        .map_err(|_| HTTPError::new(401, "invalid calendar token".to_string()))
+}
+
+// escapes a TEXT value, RFC 5545 3.3.11.
+fn escape_text(val: &str) -> String {
+    let mut res = String::with_capacity(val.len());
+    for c in val.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            ';' => res.push_str("\\;"),
+            ',' => res.push_str("\\,"),
+            '\n' => res.push_str("\\n"),
+            '\r' => {}
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+// writes the content line with CRLF, folded at 75 octets without splitting a character.
+fn write_line(buf: &mut Vec<u8>, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > ICS_LINE_OCTETS {
+            buf.extend_from_slice(b"\r\n ");
+            width = 1;
+        }
+        let mut tmp = [0u8; 4];
+        buf.extend_from_slice(c.encode_utf8(&mut tmp).as_bytes());
+        width += c.len_utf8();
+    }
+    buf.extend_from_slice(b"\r\n");
+}
+
+// the UTC DATE-TIME of the unix timestamp in milliseconds, e.g. 20231114T221320Z.
+fn format_utc(ms: i64) -> String {
+    let secs = ms.div_euclid(1000);
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // the civil date of the days since the epoch, in the proleptic Gregorian calendar.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+// the VEVENT of an open task at its duedate.
+fn write_event(buf: &mut Vec<u8>, doc: &db::Task, now: i64) {
+    let summary = if doc.message.is_empty() {
+        doc.kind.clone()
+    } else {
+        format!("{}: {}", doc.kind, doc.message)
+    };
+    let stamp = if doc.updated_at > 0 {
+        doc.updated_at
+    } else {
+        now
+    };
+
+    write_line(buf, "BEGIN:VEVENT");
+    write_line(buf, &format!("UID:{}@taskbase", doc.id));
+    write_line(buf, &format!("DTSTAMP:{}", format_utc(stamp)));
+    write_line(buf, &format!("DTSTART:{}", format_utc(doc.duedate)));
+    write_line(buf, &format!("SUMMARY:{}", escape_text(&summary)));
+    write_line(buf, "END:VEVENT");
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct QueryCalendar {
+    pub uid: PackObject<xid::Id>,
+    #[validate(length(min = 1, max = 64))]
+    pub token: String,
+}
+
+// reads the open tasks of a user page by page, the events stop at max_events.
+struct CalendarFeed {
+    app: Arc<AppState>,
+    uid: xid::Id,
+    page: Option<Vec<db::Task>>, // the page read before the response
+    paging_state: Option<Vec<u8>>,
+    now: i64,
+    count: usize,
+    done: bool,
+}
+
+impl CalendarFeed {
+    async fn read_page(&mut self) -> anyhow::Result<Vec<db::Task>> {
+        if let Some(docs) = self.page.take() {
+            return Ok(docs);
+        }
+        let (docs, paging_state) = db::Task::list_paged(
+            &self.app.scylla,
+            self.uid,
+            vec![
+                "kind".to_string(),
+                "message".to_string(),
+                "duedate".to_string(),
+                "updated_at".to_string(),
+            ],
+            CALENDAR_PAGE_SIZE,
+            self.paging_state.take(),
+            Some(TaskStatus::Pending),
+            None,
+            false,
+        )
+        .await?;
+        self.paging_state = paging_state;
+        Ok(docs)
+    }
+
+    // the events of the next page, followed by the end of the calendar after the last page.
+    // A failed read aborts the response without the end, so that the calendar apps keep
+    // their copy rather than taking a truncated calendar for the whole one.
+    async fn next_chunk(&mut self) -> Option<anyhow::Result<Bytes>> {
+        if self.done {
+            return None;
+        }
+
+        let max_events = self.app.calendar.max_events;
+        let mut buf: Vec<u8> = Vec::new();
+        match self.read_page().await {
+            Ok(docs) => {
+                for doc in docs {
+                    if doc.duedate == 0 || self.count >= max_events {
+                        continue;
+                    }
+                    write_event(&mut buf, &doc, self.now);
+                    self.count += 1;
+                }
+                if self.paging_state.is_some() && self.count < max_events {
+                    return Some(Ok(Bytes::from(buf)));
+                }
+            }
+            Err(err) => {
+                log::error!(target: "api", "calendar of {} failed: {}", self.uid, err);
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+
+        self.done = true;
+        log::info!(target: "api", "{} events of {} in the calendar", self.count, self.uid);
+        write_line(&mut buf, "END:VCALENDAR");
+        Some(Ok(Bytes::from(buf)))
+    }
+}
+
+// the ICS feed of the duedates of the open tasks of a user, it is served without the auth
+// so that calendar apps can subscribe to it, the token in the query authorizes the read.
+pub async fn feed(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Query(input): Query<QueryCalendar>,
+) -> Result<Response, HTTPError> {
+    input.validate()?;
+    check_token(&app.calendar.secret, *input.uid, &input.token)?;
+
+    ctx.set_kvs(vec![
+        ("action", "task_calendar".into()),
+        ("uid", input.uid.to_string().into()),
+    ])
+    .await;
+
+    let mut feed = CalendarFeed {
+        app: app.clone(),
+        uid: input.uid.unwrap(),
+        page: None,
+        paging_state: None,
+        now: unix_ms() as i64,
+        count: 0,
+        done: false,
+    };
+    // the first page is read before the response, so that database errors
+    // are returned with the status code.
+    let docs = feed.read_page().await?;
+    feed.page = Some(docs);
+
+    let mut head: Vec<u8> = Vec::new();
+    write_line(&mut head, "BEGIN:VCALENDAR");
+    write_line(&mut head, "VERSION:2.0");
+    write_line(&mut head, "PRODID:-//yiwen-ai//taskbase//EN");
+    write_line(&mut head, "CALSCALE:GREGORIAN");
+    write_line(&mut head, "X-WR-CALNAME:Tasks");
+
+    let body = stream::once(async move { Ok::<Bytes, anyhow::Error>(Bytes::from(head)) }).chain(
+        stream::unfold(feed, |mut feed| async move {
+            feed.next_chunk().await.map(|chunk| (chunk, feed))
+        }),
+    );
+    Ok((
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        StreamBody::new(body),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct QueryCalendarToken {
+    pub uid: PackObject<xid::Id>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CalendarTokenOutput {
+    pub token: String,
+}
+
+// the token of the feed URL of the user, it is requested with the auth.
+pub async fn token(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryCalendarToken>,
+) -> Result<PackObject<SuccessResponse<CalendarTokenOutput>>, HTTPError> {
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+    if app.calendar.secret.is_empty() {
+        return Err(HTTPError::new(404, "calendar feed disabled".to_string()));
+    }
+
+    ctx.set_kvs(vec![
+        ("action", "task_calendar_token".into()),
+        ("uid", input.uid.to_string().into()),
+    ])
+    .await;
+
+    Ok(to.with(SuccessResponse::new(CalendarTokenOutput {
+        token: feed_token(&app.calendar.secret, *input.uid),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_token_works() {
+        let uid = xid::new();
+        let token = feed_token("secret", uid);
+        assert!(check_token("secret", uid, &token).is_ok());
+
+        assert_eq!(check_token("", uid, &token).unwrap_err().code, 404);
+        for (secret, uid, token) in [
+            ("other secret", uid, token.as_str()),
+            ("secret", xid::new(), token.as_str()),
+            ("secret", uid, ""),
+            ("secret", uid, "not hex"),
+            ("secret", uid, &token[..token.len() - 2]),
+        ] {
+            let err = check_token(secret, uid, token).unwrap_err();
+            assert_eq!(err.code, 401);
+            assert_eq!(err.message, "invalid calendar token");
+        }
+    }
+
+    #[test]
+    fn escape_text_works() {
+        assert_eq!(escape_text("hello"), "hello");
+        assert_eq!(
+            escape_text("a,b;c\\d\r\ne\nf"),
+            "a\\,b\\;c\\\\d\\ne\\nf".to_string()
+        );
+    }
+
+    #[test]
+    fn write_line_works() {
+        let mut buf = Vec::new();
+        write_line(&mut buf, "VERSION:2.0");
+        assert_eq!(buf, b"VERSION:2.0\r\n");
+
+        let mut buf = Vec::new();
+        let line = format!("SUMMARY:{}", "a".repeat(150));
+        write_line(&mut buf, &line);
+        let text = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = text.trim_end_matches("\r\n").split("\r\n").collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].len(), 75);
+        assert!(lines[1..]
+            .iter()
+            .all(|l| l.starts_with(' ') && l.len() <= 75));
+        assert_eq!(text.replace("\r\n ", ""), format!("{}\r\n", line));
+
+        // multi-byte characters are not split.
+        let mut buf = Vec::new();
+        let line = format!("SUMMARY:{}", "任务".repeat(40));
+        write_line(&mut buf, &line);
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.split("\r\n").all(|l| l.len() <= 75));
+        assert_eq!(text.replace("\r\n ", ""), format!("{}\r\n", line));
+    }
+
+    #[test]
+    fn format_utc_works() {
+        assert_eq!(format_utc(0), "19700101T000000Z");
+        assert_eq!(format_utc(1_700_000_000_123), "20231114T221320Z");
+        assert_eq!(format_utc(951_782_400_000), "20000229T000000Z");
+        assert_eq!(format_utc(-1000), "19691231T235959Z");
+    }
+
+    #[test]
+    fn write_event_works() {
+        let doc = db::Task {
+            id: xid::new(),
+            kind: "review".to_string(),
+            message: "fix a, b; c".to_string(),
+            duedate: 1_700_000_000_000,
+            updated_at: 0,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        write_event(&mut buf, &doc, 0);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            format!(
+                "BEGIN:VEVENT\r\nUID:{}@taskbase\r\nDTSTAMP:19700101T000000Z\r\nDTSTART:20231114T221320Z\r\nSUMMARY:review: fix a\\, b\\; c\r\nEND:VEVENT\r\n",
+                doc.id
+            )
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use axum::{extract::State, Extension};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use validator::Validate;
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+
+use crate::db;
+
+use crate::api::{
+    check_participant, check_reader, token_from_xid, token_to_xid, AppState, PARTICIPANT_FIELDS,
+};
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CommentOutput {
+    pub uid: PackObject<xid::Id>,
+    pub tid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub author: PackObject<xid::Id>,
+    pub created_at: i64,
+    pub message: String,
+}
+
+impl CommentOutput {
+    pub fn from<T>(val: db::TaskComment, to: &PackObject<T>) -> Self {
+        Self {
+            uid: to.with(val.uid),
+            tid: to.with(val.tid),
+            id: to.with(val.id),
+            author: to.with(val.author),
+            created_at: val.created_at,
+            message: val.message,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateCommentInput {
+    pub uid: PackObject<xid::Id>,
+    pub tid: PackObject<xid::Id>,
+    #[validate(length(min = 1, max = 4096))]
+    pub message: String,
+}
+
+pub async fn create(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<CreateCommentInput>,
+) -> Result<PackObject<SuccessResponse<CommentOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    ctx.set_kvs(vec![
+        ("action", "create_comment".into()),
+        ("uid", input.uid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+    ])
+    .await;
+
+    let mut task = db::Task::with_pk(input.uid.unwrap(), input.tid.unwrap());
+    task.get_one(
+        &app.scylla,
+        PARTICIPANT_FIELDS.iter().map(|f| f.to_string()).collect(),
+    )
+    .await?;
+    check_participant(app.pin_uid, &ctx, &task)?;
+
+    let mut doc = db::TaskComment::with_pk(task.uid, task.id, xid::new());
+    doc.author = ctx.user;
+    doc.message = input.message;
+    doc.save(&app.scylla).await?;
+
+    ctx.set("id", doc.id.to_string().into()).await;
+    Ok(to.with(SuccessResponse::new(CommentOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CommentPagination {
+    pub uid: PackObject<xid::Id>,
+    pub tid: PackObject<xid::Id>,
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+}
+
+pub async fn list(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<CommentPagination>,
+) -> Result<PackObject<SuccessResponse<Vec<CommentOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_reader(&app, &ctx, *input.uid, *input.tid).await?;
+
+    let page_size = input.page_size.unwrap_or(10);
+    ctx.set_kvs(vec![
+        ("action", "list_comment".into()),
+        ("uid", input.uid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let res = db::TaskComment::list(
+        &app.scylla,
+        input.uid.unwrap(),
+        input.tid.unwrap(),
+        page_size,
+        token_to_xid(&input.page_token)?,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(res.last().unwrap().id))
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .into_iter()
+            .map(|doc| CommentOutput::from(doc, &to))
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DeleteCommentInput {
+    pub uid: PackObject<xid::Id>,
+    pub tid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+}
+
+pub async fn delete(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<DeleteCommentInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    ctx.set_kvs(vec![
+        ("action", "delete_comment".into()),
+        ("uid", input.uid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+        ("id", input.id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc =
+        db::TaskComment::with_pk(input.uid.unwrap(), input.tid.unwrap(), input.id.unwrap());
+    if !db::scylladb::exists(doc.get_one(&app.scylla).await)? {
+        return Ok(to.with(SuccessResponse::new(false))); // already deleted
+    }
+    if ctx.user != doc.author && ctx.user != doc.uid {
+        return Err(HTTPError::new(
+            403,
+            "only the comment author or the task creator can delete the comment".to_string(),
+        ));
+    }
+
+    doc.delete(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(true)))
+}
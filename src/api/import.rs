@@ -0,0 +1,401 @@
+use axum::{
+    extract::{BodyStream, Query, State},
+    Extension,
+};
+use futures::stream::{self, StreamExt};
+use http_body::LengthLimitError;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Arc};
+use validator::Validate;
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+
+use crate::db::{self, TaskStatus};
+
+use crate::api::{
+    task::{check_content, check_payload_type, discard_blob, fanout, store_payload},
+    AppState,
+};
+
+// the lines imported together, and the tasks written concurrently in them.
+const IMPORT_BATCH_LINES: usize = 100;
+const IMPORT_CONCURRENCY: usize = 16;
+// the errors reported in the summary.
+const IMPORT_MAX_ERRORS: usize = 100;
+// the bytes of a line besides the base64url payload.
+const IMPORT_LINE_OVERHEAD: usize = 64 * 1024;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ImportParams {
+    pub overwrite: Option<bool>, // rewrites existing tasks, default to false
+    pub fanout: Option<bool>,    // notifies the participants of pending tasks, default to true
+}
+
+// a task record of an import, one JSON object per line.
+#[derive(Debug, Deserialize, Validate)]
+pub struct ImportTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub gid: PackObject<xid::Id>,
+    #[serde(default)]
+    pub kind: String,
+    pub status: TaskStatus,
+    pub created_at: i64,
+    #[serde(default)]
+    pub updated_at: i64, // default to created_at
+    #[serde(default)]
+    pub duedate: i64,
+    #[serde(default)]
+    #[validate(range(min = 0, max = 256))]
+    pub threshold: i16,
+    #[serde(default)]
+    #[validate(range(min = 0, max = 4))]
+    pub approver_threshold: i16,
+    #[serde(default)]
+    #[validate(length(min = 0, max = 4))]
+    pub approvers: Vec<PackObject<xid::Id>>,
+    #[serde(default)]
+    #[validate(length(min = 0, max = 256))]
+    pub assignees: Vec<PackObject<xid::Id>>,
+    #[serde(default)]
+    pub resolved: Vec<PackObject<xid::Id>>,
+    #[serde(default)]
+    pub rejected: Vec<PackObject<xid::Id>>,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub payload: PackObject<Vec<u8>>,
+    #[serde(default)]
+    #[validate(length(min = 0, max = 64))]
+    pub payload_type: String,
+    #[serde(default)]
+    #[validate(range(min = 0, max = 2))]
+    pub priority: i8,
+    #[serde(default)]
+    pub deleted_at: i64,
+    #[serde(default)]
+    pub prev_status: TaskStatus, // the status to restore of a trashed task
+    pub notify_creator: Option<bool>, // default to true
+    #[serde(default)]
+    pub allow_late_ack: bool,
+    #[serde(default)]
+    pub veto: bool,
+    pub self_ack_allowed: Option<bool>, // default to true
+}
+
+impl ImportTaskInput {
+    fn into_task(
+        self,
+        max_message_len: usize,
+        max_payload_bytes: usize,
+    ) -> Result<db::Task, HTTPError> {
+        if self.created_at <= 0 {
+            return Err(HTTPError::new(
+                400,
+                format!("invalid created_at, got {}", self.created_at),
+            ));
+        }
+        let updated_at = if self.updated_at == 0 {
+            self.created_at
+        } else {
+            self.updated_at
+        };
+        if updated_at < self.created_at {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "invalid updated_at, expected >= {}, got {}",
+                    self.created_at, updated_at
+                ),
+            ));
+        }
+        check_content(
+            &self.message,
+            &self.payload,
+            max_message_len,
+            max_payload_bytes,
+        )?;
+        check_payload_type(&self.payload_type, &self.payload)?;
+
+        let approvers: HashSet<xid::Id> =
+            self.approvers.into_iter().map(|id| id.unwrap()).collect();
+        let assignees: HashSet<xid::Id> =
+            self.assignees.into_iter().map(|id| id.unwrap()).collect();
+        let resolved: HashSet<xid::Id> = self.resolved.into_iter().map(|id| id.unwrap()).collect();
+        let rejected: HashSet<xid::Id> = self.rejected.into_iter().map(|id| id.unwrap()).collect();
+        let participants: HashSet<xid::Id> = approvers.union(&assignees).cloned().collect();
+        if !resolved.is_subset(&participants) || !rejected.is_subset(&participants) {
+            return Err(HTTPError::new(
+                400,
+                "resolved and rejected should be approvers or assignees".to_string(),
+            ));
+        }
+        if !resolved.is_disjoint(&rejected) {
+            return Err(HTTPError::new(
+                400,
+                "a participant can not both resolve and reject the task".to_string(),
+            ));
+        }
+        db::Task::check_threshold(
+            self.threshold,
+            db::Task::reachable_votes(self.approver_threshold, &approvers, &assignees),
+        )?;
+
+        let mut doc = db::Task::with_pk(self.uid.unwrap(), self.id.unwrap());
+        doc.gid = self.gid.unwrap();
+        doc.kind = self.kind;
+        doc.status = self.status;
+        doc.created_at = self.created_at;
+        doc.updated_at = updated_at;
+        doc.duedate = self.duedate;
+        doc.threshold = self.threshold;
+        doc.approver_threshold = self.approver_threshold;
+        doc.approvers = approvers;
+        doc.assignees = assignees;
+        doc.resolved = resolved;
+        doc.rejected = rejected;
+        doc.message = self.message;
+        doc.payload = self.payload.unwrap();
+        doc.payload_type = self.payload_type;
+        doc.priority = self.priority;
+        doc.deleted_at = self.deleted_at;
+        doc.prev_status = self.prev_status;
+        doc.notify_creator = self.notify_creator.unwrap_or(true);
+        doc.allow_late_ack = self.allow_late_ack;
+        doc.veto = self.veto;
+        doc.self_ack_allowed = self.self_ack_allowed.unwrap_or(true);
+        Ok(doc)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ImportTaskError {
+    pub line: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ImportTaskOutput {
+    pub imported: u64,
+    pub skipped: u64, // existing tasks without overwrite
+    pub failed: u64,
+    pub errors: Vec<ImportTaskError>, // the first 100 errors
+}
+
+impl ImportTaskOutput {
+    fn add(&mut self, line: u64, res: Result<bool, HTTPError>) {
+        match res {
+            Ok(true) => self.imported += 1,
+            Ok(false) => self.skipped += 1,
+            Err(err) => {
+                self.failed += 1;
+                if self.errors.len() < IMPORT_MAX_ERRORS {
+                    self.errors.push(ImportTaskError {
+                        line,
+                        message: err.message,
+                    });
+                }
+            }
+        }
+    }
+}
+
+// imports the task records of a streamed NDJSON body, the lines are validated
+// and written in batches, a failed line does not stop the import.
+pub async fn import(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(params): Query<ImportParams>,
+    mut body: BodyStream,
+) -> Result<PackObject<SuccessResponse<ImportTaskOutput>>, HTTPError> {
+    params.validate()?;
+    let overwrite = params.overwrite.unwrap_or(false);
+    let fanout = params.fanout.unwrap_or(true);
+    ctx.set_kvs(vec![
+        ("action", "import_task".into()),
+        ("overwrite", overwrite.into()),
+        ("fanout", fanout.into()),
+    ])
+    .await;
+
+    // the payload is base64url encoded in JSON.
+    let max_line = app.max_payload_bytes / 3 * 4 + 4 + IMPORT_LINE_OVERHEAD;
+    let mut output = ImportTaskOutput::default();
+    let mut buf: Vec<u8> = Vec::new();
+    let mut lines: Vec<(u64, Vec<u8>)> = Vec::with_capacity(IMPORT_BATCH_LINES);
+    let mut line_no: u64 = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|err| {
+            let err = err.into_inner();
+            // 413 if the body exceeds the RequestBodyLimitLayer of the route.
+            let code = if err.is::<LengthLimitError>() {
+                413
+            } else {
+                400
+            };
+            HTTPError::new(code, format!("Invalid body, {}", err))
+        })?;
+        buf.extend_from_slice(&chunk);
+        while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=pos).collect();
+            line_no += 1;
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                lines.push((line_no, line));
+            }
+            if lines.len() >= IMPORT_BATCH_LINES {
+                import_lines(
+                    &app,
+                    std::mem::take(&mut lines),
+                    overwrite,
+                    fanout,
+                    &mut output,
+                )
+                .await;
+            }
+        }
+        if buf.len() > max_line {
+            return Err(HTTPError::new(
+                400,
+                format!("line {} exceeds {} bytes", line_no + 1, max_line),
+            ));
+        }
+    }
+    if !buf.iter().all(u8::is_ascii_whitespace) {
+        line_no += 1;
+        lines.push((line_no, buf));
+    }
+    import_lines(&app, lines, overwrite, fanout, &mut output).await;
+
+    ctx.set_kvs(vec![
+        ("imported", output.imported.into()),
+        ("skipped", output.skipped.into()),
+        ("failed", output.failed.into()),
+    ])
+    .await;
+    Ok(to.with(SuccessResponse::new(output)))
+}
+
+async fn import_lines(
+    app: &AppState,
+    lines: Vec<(u64, Vec<u8>)>,
+    overwrite: bool,
+    fanout: bool,
+    output: &mut ImportTaskOutput,
+) {
+    let res: Vec<(u64, Result<bool, HTTPError>)> = stream::iter(lines)
+        .map(|(line, data)| async move { (line, import_line(app, &data, overwrite, fanout).await) })
+        .buffered(IMPORT_CONCURRENCY)
+        .collect()
+        .await;
+    for (line, res) in res {
+        output.add(line, res);
+    }
+}
+
+async fn import_line(
+    app: &AppState,
+    data: &[u8],
+    overwrite: bool,
+    should_fanout: bool,
+) -> Result<bool, HTTPError> {
+    let input: ImportTaskInput = serde_json::from_slice(data)
+        .map_err(|err| HTTPError::new(400, format!("Invalid JSON, {}", err)))?;
+    input.validate()?;
+    let mut doc = input.into_task(app.max_message_len, app.max_payload_bytes)?;
+    let blob = store_payload(app, &mut doc).await?;
+    if !doc.import(&app.scylla, overwrite).await? {
+        discard_blob(app, blob).await;
+        return Ok(false);
+    }
+    // only the pending tasks are waiting for the acks of their participants.
+    if should_fanout && doc.status == TaskStatus::Pending {
+        let _ = fanout(app, &doc, app.notification_ttl).await;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<db::Task, HTTPError> {
+        let input: ImportTaskInput = serde_json::from_str(line).unwrap();
+        input.validate()?;
+        input.into_task(4096, 1024)
+    }
+
+    #[test]
+    fn into_task_works() {
+        let (uid, id, gid) = (xid::new(), xid::new(), xid::new());
+        let (a, b) = (xid::new(), xid::new());
+
+        let doc = parse(&format!(
+            r#"{{"uid":"{}","id":"{}","gid":"{}","kind":"approval","status":1,"created_at":1000,"assignees":["{}","{}"],"resolved":["{}"],"threshold":1}}"#,
+            uid, id, gid, a, b, a
+        ))
+        .unwrap();
+        assert_eq!(doc.uid, uid);
+        assert_eq!(doc.id, id);
+        assert_eq!(doc.status, TaskStatus::Resolved);
+        assert_eq!(doc.created_at, 1000);
+        assert_eq!(doc.updated_at, 1000);
+        assert_eq!(doc.resolved, HashSet::from([a]));
+        assert!(doc.notify_creator);
+        assert!(doc.self_ack_allowed);
+
+        let base = format!(
+            r#""uid":"{}","id":"{}","gid":"{}","status":0"#,
+            uid, id, gid
+        );
+        for (line, message) in [
+            (format!("{{{}}}", base), "invalid created_at"),
+            (
+                format!(r#"{{{},"created_at":1000,"updated_at":999}}"#, base),
+                "invalid updated_at",
+            ),
+            (
+                format!(
+                    r#"{{{},"created_at":1000,"assignees":["{}"],"resolved":["{}"]}}"#,
+                    base, a, b
+                ),
+                "resolved and rejected should be",
+            ),
+            (
+                format!(
+                    r#"{{{},"created_at":1000,"assignees":["{}"],"resolved":["{}"],"rejected":["{}"]}}"#,
+                    base, a, a, a
+                ),
+                "can not both resolve and reject",
+            ),
+            (
+                format!(
+                    r#"{{{},"created_at":1000,"assignees":["{}"],"threshold":2}}"#,
+                    base, a
+                ),
+                "Invalid threshold",
+            ),
+        ] {
+            let err = parse(&line).unwrap_err();
+            assert_eq!(err.code, 400);
+            assert!(err.message.contains(message), "{}", err.message);
+        }
+    }
+
+    #[test]
+    fn import_output_works() {
+        let mut output = ImportTaskOutput::default();
+        output.add(1, Ok(true));
+        output.add(2, Ok(false));
+        for line in 3..(IMPORT_MAX_ERRORS as u64 + 10) {
+            output.add(line, Err(HTTPError::new(400, "invalid".to_string())));
+        }
+        assert_eq!(output.imported, 1);
+        assert_eq!(output.skipped, 1);
+        assert_eq!(output.failed, IMPORT_MAX_ERRORS as u64 + 7);
+        assert_eq!(output.errors.len(), IMPORT_MAX_ERRORS);
+        assert_eq!(output.errors[0].line, 3);
+    }
+}
@@ -1,14 +1,38 @@
-use axum::extract::State;
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
 use validator::Validate;
 
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
 use axum_web::object::{cbor_from_slice, cbor_to_vec, PackObject};
 
+use crate::blobs;
+use crate::conf;
 use crate::db::{self};
+use crate::events;
+use crate::metrics;
 
+pub mod calendar;
+pub mod comment;
+pub mod import;
 pub mod notification;
+pub mod recurrence;
 pub mod task;
+pub mod template;
 
 pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
 pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -16,6 +40,78 @@ pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 #[derive(Clone)]
 pub struct AppState {
     pub scylla: Arc<db::scylladb::ScyllaDB>,
+    pub keyspace: String,
+    pub trash_retention_ms: i64,
+    pub notification_ttl: i32, // seconds, 0 means notifications never expire
+    pub webhooks: events::Webhooks,
+    pub max_payload_bytes: usize,
+    pub max_message_len: usize,
+    pub max_page_size: u16,
+    pub kinds: conf::Kinds,
+    pub metrics: Arc<metrics::Metrics>,
+    pub pin_uid: bool, // the uid of the requests should be the user of the token
+    pub shutdown: CancellationToken, // cancelled on SIGTERM or SIGINT
+    pub maintenance: AtomicBool, // the writes are rejected and the worker is paused
+    pub blobs: Option<blobs::Blobs>, // the store of the oversized payloads
+    pub calendar: conf::Calendar, // the ICS feed of the duedates
+}
+
+impl AppState {
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+}
+
+// the versions of the API mounted side by side, they share the handlers and the db layer,
+// only the outputs of tasks and notifications differ.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    #[default]
+    V1,
+    V2, // renames duedate to due_at, and always includes the counts of the sets
+}
+
+impl ApiVersion {
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V2 => "v2",
+        }
+    }
+
+    // the task fields to select, v2 adds the counts unless a set is selected,
+    // the counts of the selected sets are output with them. Empty fields select the sets.
+    pub fn select_fields(&self, fields: Vec<String>) -> Vec<String> {
+        if *self == ApiVersion::V1
+            || fields.is_empty()
+            || fields.iter().any(|f| {
+                f == db::Task::COUNTS_FIELD
+                    || ["approvers", "assignees", "resolved", "rejected"].contains(&f.as_str())
+            })
+        {
+            return fields;
+        }
+
+        let mut fields = fields;
+        fields.push(db::Task::COUNTS_FIELD.to_string());
+        fields
+    }
+}
+
+// provides the version of the router to the handlers, and records it in the context.
+// It should be layered on the versioned routers.
+pub async fn version_middleware<B>(
+    State(ver): State<ApiVersion>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(ctx) = req.extensions().get::<Arc<ReqContext>>() {
+        ctx.set("version", ver.as_str().into()).await;
+    }
+    req.extensions_mut().insert(ver);
+    next.run(req).await
 }
 
 #[derive(Serialize, Deserialize)]
@@ -58,6 +154,105 @@ pub async fn healthz(to: PackObject<()>, State(app): State<Arc<AppState>>) -> Pa
     })
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct AppReadiness {
+    pub keyspace: String,
+    pub schema_version: i32, // 0 if the keyspace was not bootstrapped by the service
+    pub maintenance: bool,
+}
+
+// the readiness check should answer before the probe gives up.
+const READYZ_TIMEOUT: Duration = Duration::from_secs(2);
+
+// healthz is the liveness probe, readyz checks ScyllaDB is reachable and fails with 503 if not.
+pub async fn readyz(
+    to: PackObject<()>,
+    State(app): State<Arc<AppState>>,
+) -> Result<PackObject<AppReadiness>, HTTPError> {
+    // fails as soon as the shutdown starts so that the load balancer stops sending traffic.
+    if app.shutdown.is_cancelled() {
+        return Err(HTTPError::new(503, "shutting down".to_string()));
+    }
+
+    let schema_version = tokio::time::timeout(READYZ_TIMEOUT, async {
+        app.scylla.ping().await?;
+        // the meta table only exists in keyspaces bootstrapped by the service.
+        Ok::<i32, anyhow::Error>(app.scylla.schema_version().await.unwrap_or(0))
+    })
+    .await
+    .map_err(|_| HTTPError::new(503, "ScyllaDB ping timed out".to_string()))?
+    .map_err(|err| HTTPError::new(503, format!("ScyllaDB ping failed: {}", err)))?;
+
+    Ok(to.with(AppReadiness {
+        keyspace: app.keyspace.clone(),
+        schema_version,
+        maintenance: app.in_maintenance(),
+    }))
+}
+
+// the seconds the clients should wait before retrying the writes rejected in maintenance.
+const MAINTENANCE_RETRY_AFTER: u64 = 60;
+
+// rejects the requests with 503 in the maintenance mode, it should be layered on the
+// write routes only so that the reads keep being served.
+pub async fn maintenance_middleware<B>(
+    State(app): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !app.in_maintenance() {
+        return next.run(req).await;
+    }
+
+    let mut res = HTTPError::new(503, "service in maintenance, writes are paused".to_string())
+        .into_response();
+    res.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(MAINTENANCE_RETRY_AFTER),
+    );
+    res
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct MaintenanceInput {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MaintenanceOutput {
+    pub maintenance: bool,
+}
+
+// switches the maintenance mode, the admin scope is required when the auth is enabled.
+pub async fn set_maintenance(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<MaintenanceInput>,
+) -> Result<PackObject<SuccessResponse<MaintenanceOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let prev = app.maintenance.swap(input.enabled, Ordering::Relaxed);
+    ctx.set_kvs(vec![
+        ("action", "set_maintenance".into()),
+        ("enabled", input.enabled.into()),
+        ("prev", prev.into()),
+    ])
+    .await;
+    if prev != input.enabled {
+        log::warn!(target: "maintenance", "maintenance mode {}", if input.enabled { "enabled" } else { "disabled" });
+    }
+
+    Ok(to.with(SuccessResponse::new(MaintenanceOutput {
+        maintenance: input.enabled,
+    })))
+}
+
+// the service metrics in the Prometheus text format.
+pub async fn metrics(State(app): State<Arc<AppState>>) -> Response {
+    metrics::response(&app.metrics)
+}
+
 pub fn get_fields(fields: Option<String>) -> Vec<String> {
     if fields.is_none() {
         return vec![];
@@ -74,22 +269,338 @@ pub fn get_fields(fields: Option<String>) -> Vec<String> {
 pub struct Pagination {
     pub uid: PackObject<xid::Id>,
     pub page_token: Option<PackObject<Vec<u8>>>,
-    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>, // checked against the configured max_page_size
+    pub status: Option<i8>,     // the task status, or the notification status of notification lists
+    pub fields: Option<Vec<String>>,
+    pub with_total: Option<bool>,
+    pub kind: Option<String>,
+    pub order: Option<String>,     // "asc" or "desc", default to "desc"
+    pub unread_only: Option<bool>, // notification list only
+    pub gid: Option<PackObject<xid::Id>>, // notification list only
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct GroupPagination {
+    pub gid: PackObject<xid::Id>,
+    pub page_token: Option<PackObject<Vec<u8>>>,
     pub page_size: Option<u16>,
-    #[validate(range(min = -1, max = 2))]
     pub status: Option<i8>,
     pub fields: Option<Vec<String>>,
 }
 
-pub fn token_to_xid(page_token: &Option<PackObject<Vec<u8>>>) -> Option<xid::Id> {
+impl Pagination {
+    // out-of-range statuses are rejected rather than matching nothing.
+    pub fn task_status(&self) -> Result<Option<db::TaskStatus>, HTTPError> {
+        self.status.map(db::TaskStatus::try_from).transpose()
+    }
+
+    pub fn notification_status(&self) -> Result<Option<db::NotificationStatus>, HTTPError> {
+        self.status
+            .map(db::NotificationStatus::try_from)
+            .transpose()
+    }
+}
+
+impl GroupPagination {
+    pub fn task_status(&self) -> Result<Option<db::TaskStatus>, HTTPError> {
+        self.status.map(db::TaskStatus::try_from).transpose()
+    }
+}
+
+const DEFAULT_PAGE_SIZE: u16 = 10;
+
+// rejects the requests acting on the data of another user when the token pins the uid.
+pub fn check_uid(pin_uid: bool, ctx: &ReqContext, uid: xid::Id) -> Result<(), HTTPError> {
+    if pin_uid && ctx.user != uid {
+        return Err(HTTPError::new(
+            403,
+            format!("uid {} does not match the authenticated user", uid),
+        ));
+    }
+    Ok(())
+}
+
+// the fields `check_participant` needs loaded.
+pub const PARTICIPANT_FIELDS: [&str; 3] = ["approvers", "assignees", "watchers"];
+
+// rejects the reads of a task by users other than its creator and participants when
+// the token pins the uid.
+pub fn check_participant(pin_uid: bool, ctx: &ReqContext, doc: &db::Task) -> Result<(), HTTPError> {
+    if !pin_uid
+        || ctx.user == doc.uid
+        || doc.assignees.contains(&ctx.user)
+        || doc.approvers.contains(&ctx.user)
+        || doc.watchers.contains(&ctx.user)
+    {
+        return Ok(());
+    }
+    Err(HTTPError::new(
+        403,
+        format!("user {} is not a participant of task {}", ctx.user, doc.id),
+    ))
+}
+
+// loads the participants of the task for `check_participant`, nothing is read without
+// pin_uid or for the creator. Archived tasks are checked too.
+pub async fn check_reader(
+    app: &AppState,
+    ctx: &ReqContext,
+    uid: xid::Id,
+    id: xid::Id,
+) -> Result<(), HTTPError> {
+    if !app.pin_uid || ctx.user == uid {
+        return Ok(());
+    }
+
+    let fields: Vec<String> = PARTICIPANT_FIELDS.iter().map(|f| f.to_string()).collect();
+    let mut doc = db::Task::with_pk(uid, id);
+    if let Err(err) = doc.get_one(&app.scylla, fields.clone()).await {
+        let err: HTTPError = err.into();
+        if err.code != 404 {
+            return Err(err);
+        }
+        doc.get_archived(&app.scylla, fields).await?;
+    }
+    check_participant(app.pin_uid, ctx, &doc)
+}
+
+// the page_size of a list request, 10 by default and at most max_page_size.
+pub fn check_page_size(page_size: Option<u16>, max_page_size: u16) -> Result<u16, HTTPError> {
+    match page_size {
+        None => Ok(DEFAULT_PAGE_SIZE.min(max_page_size)),
+        Some(size) if (1..=max_page_size).contains(&size) => Ok(size),
+        Some(size) => Err(HTTPError::new(
+            400,
+            format!(
+                "invalid page_size, expected 1 to {}, got {}",
+                max_page_size, size
+            ),
+        )),
+    }
+}
+
+// a token that can not be decoded is rejected rather than restarting from the first page.
+fn decode_token<T: serde::de::DeserializeOwned>(
+    page_token: &Option<PackObject<Vec<u8>>>,
+) -> Result<Option<T>, HTTPError> {
     match page_token.as_ref().map(|v| v.unwrap_ref()) {
-        Some(v) => cbor_from_slice::<PackObject<xid::Id>>(v)
-            .ok()
-            .map(|v| v.unwrap()),
-        _ => None,
+        Some(v) if !v.is_empty() => cbor_from_slice::<T>(v)
+            .map(Some)
+            .map_err(|_| HTTPError::new(400, "invalid page_token".to_string())),
+        _ => Ok(None),
     }
 }
 
+pub fn token_to_xid(
+    page_token: &Option<PackObject<Vec<u8>>>,
+) -> Result<Option<xid::Id>, HTTPError> {
+    Ok(decode_token::<PackObject<xid::Id>>(page_token)?.map(|v| v.unwrap()))
+}
+
 pub fn token_from_xid(id: xid::Id) -> Option<Vec<u8>> {
     cbor_to_vec(&PackObject::Cbor(id)).ok()
 }
+
+// marks the page tokens that carry the driver's paging state, see `token_from_state`.
+const PAGING_STATE_TAG: &str = "ps";
+
+// a page token is either the xid of the last row (legacy) or the opaque paging state of the driver.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PageToken {
+    Xid(xid::Id),
+    State(Vec<u8>),
+}
+
+impl PageToken {
+    pub fn into_xid(self) -> Option<xid::Id> {
+        match self {
+            PageToken::Xid(id) => Some(id),
+            PageToken::State(_) => None,
+        }
+    }
+
+    pub fn into_state(self) -> Option<Vec<u8>> {
+        match self {
+            PageToken::Xid(_) => None,
+            PageToken::State(state) => Some(state),
+        }
+    }
+}
+
+pub fn token_to_page(
+    page_token: &Option<PackObject<Vec<u8>>>,
+) -> Result<Option<PageToken>, HTTPError> {
+    match token_to_state(page_token) {
+        Some(state) => Ok(Some(PageToken::State(state))),
+        None => Ok(token_to_xid(page_token)?.map(PageToken::Xid)),
+    }
+}
+
+pub fn token_to_state(page_token: &Option<PackObject<Vec<u8>>>) -> Option<Vec<u8>> {
+    match page_token.as_ref().map(|v| v.unwrap_ref()) {
+        Some(v) => cbor_from_slice::<(String, PackObject<Vec<u8>>)>(v)
+            .ok()
+            .filter(|(tag, _)| tag == PAGING_STATE_TAG)
+            .map(|(_, state)| state.unwrap()),
+        _ => None,
+    }
+}
+
+pub fn token_from_state(state: Vec<u8>) -> Option<Vec<u8>> {
+    cbor_to_vec(&(PAGING_STATE_TAG, PackObject::Cbor(state))).ok()
+}
+
+pub fn token_to_duedate(
+    page_token: &Option<PackObject<Vec<u8>>>,
+) -> Result<Option<(i64, xid::Id)>, HTTPError> {
+    Ok(decode_token::<(i64, PackObject<xid::Id>)>(page_token)?
+        .map(|(duedate, id)| (duedate, id.unwrap())))
+}
+
+pub fn token_from_duedate(duedate: i64, id: xid::Id) -> Option<Vec<u8>> {
+    cbor_to_vec(&(duedate, PackObject::Cbor(id))).ok()
+}
+
+pub fn token_to_priority(
+    page_token: &Option<PackObject<Vec<u8>>>,
+) -> Result<Option<(i8, xid::Id)>, HTTPError> {
+    Ok(decode_token::<(i8, PackObject<xid::Id>)>(page_token)?
+        .map(|(priority, id)| (priority, id.unwrap())))
+}
+
+pub fn token_from_priority(priority: i8, id: xid::Id) -> Option<Vec<u8>> {
+    cbor_to_vec(&(priority, PackObject::Cbor(id))).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_version_select_fields_works() {
+        let fields = vec!["duedate".to_string()];
+        assert_eq!(ApiVersion::V1.select_fields(fields.clone()), fields);
+        assert_eq!(
+            ApiVersion::V2.select_fields(fields),
+            vec!["duedate".to_string(), "counts".to_string()]
+        );
+
+        for fields in [
+            vec![],
+            vec!["counts".to_string()],
+            vec!["duedate".to_string(), "assignees".to_string()],
+        ] {
+            assert_eq!(ApiVersion::V2.select_fields(fields.clone()), fields);
+        }
+    }
+
+    #[test]
+    fn page_token_works() {
+        assert_eq!(token_to_page(&None).unwrap(), None);
+
+        let id = xid::new();
+        let token = token_from_xid(id).map(PackObject::Cbor);
+        assert_eq!(token_to_page(&token).unwrap(), Some(PageToken::Xid(id)));
+        assert_eq!(token_to_state(&token), None);
+
+        let token = token_from_state(vec![1, 2, 3]).map(PackObject::Cbor);
+        assert_eq!(
+            token_to_page(&token).unwrap(),
+            Some(PageToken::State(vec![1, 2, 3]))
+        );
+        assert!(token_to_xid(&token).is_err());
+
+        // JSON clients round-trip the token as a base64url string.
+        let token = PackObject::Json(token_from_state(vec![1, 2, 3]).unwrap());
+        let token: PackObject<Vec<u8>> =
+            serde_json::from_str(&serde_json::to_string(&token).unwrap()).unwrap();
+        assert_eq!(
+            token_to_page(&Some(token)).unwrap(),
+            Some(PageToken::State(vec![1, 2, 3]))
+        );
+
+        let token = token_from_duedate(42, id).map(PackObject::Cbor);
+        assert_eq!(token_to_duedate(&token).unwrap(), Some((42, id)));
+        let token = token_from_priority(-1, id).map(PackObject::Cbor);
+        assert_eq!(token_to_priority(&token).unwrap(), Some((-1, id)));
+        assert_eq!(token_to_xid(&Some(PackObject::Cbor(vec![]))).unwrap(), None);
+    }
+
+    #[test]
+    fn corrupted_page_token_works() {
+        let mut token = token_from_xid(xid::new()).unwrap();
+        token.truncate(token.len() - 3);
+        let token = Some(PackObject::Cbor(token));
+        for err in [
+            token_to_page(&token).unwrap_err(),
+            token_to_xid(&token).unwrap_err(),
+            token_to_duedate(&token).unwrap_err(),
+            token_to_priority(&token).unwrap_err(),
+        ] {
+            assert_eq!(err.code, 400);
+            assert_eq!(err.message, "invalid page_token");
+        }
+
+        let token = Some(PackObject::Cbor(vec![0xff, 0x00, 0x13]));
+        assert_eq!(token_to_page(&token).unwrap_err().code, 400);
+    }
+
+    #[test]
+    fn check_page_size_works() {
+        assert_eq!(check_page_size(None, 100).unwrap(), 10);
+        assert_eq!(check_page_size(None, 5).unwrap(), 5);
+        assert_eq!(check_page_size(Some(1), 100).unwrap(), 1);
+        assert_eq!(check_page_size(Some(100), 100).unwrap(), 100);
+
+        let err = check_page_size(Some(0), 100).unwrap_err();
+        assert_eq!(err.code, 400);
+        let err = check_page_size(Some(101), 100).unwrap_err();
+        assert_eq!(err.code, 400);
+        assert_eq!(err.message, "invalid page_size, expected 1 to 100, got 101");
+        assert_eq!(check_page_size(Some(u16::MAX), 100).unwrap_err().code, 400);
+    }
+
+    #[test]
+    fn check_uid_works() {
+        let user = xid::new();
+        let other = xid::new();
+        let ctx = ReqContext::new("rid", user, 0);
+
+        assert!(check_uid(true, &ctx, user).is_ok());
+        assert!(check_uid(false, &ctx, other).is_ok());
+        let err = check_uid(true, &ctx, other).unwrap_err();
+        assert_eq!(err.code, 403);
+
+        // service callers without a user act on behalf of any user unless the uid is pinned.
+        let ctx = ReqContext::new("rid", xid::Id::default(), 0);
+        assert!(check_uid(false, &ctx, user).is_ok());
+        assert_eq!(check_uid(true, &ctx, user).unwrap_err().code, 403);
+    }
+
+    #[test]
+    fn check_participant_works() {
+        let user = xid::new();
+        let ctx = ReqContext::new("rid", user, 0);
+
+        let mut doc = db::Task::with_pk(xid::new(), xid::new());
+        assert!(check_participant(false, &ctx, &doc).is_ok());
+        let err = check_participant(true, &ctx, &doc).unwrap_err();
+        assert_eq!(err.code, 403);
+
+        // a vote alone does not make a participant.
+        doc.resolved.insert(user);
+        assert_eq!(check_participant(true, &ctx, &doc).unwrap_err().code, 403);
+
+        let creator = db::Task::with_pk(user, xid::new());
+        assert!(check_participant(true, &ctx, &creator).is_ok());
+        let mut assigned = db::Task::with_pk(xid::new(), xid::new());
+        assigned.assignees.insert(user);
+        assert!(check_participant(true, &ctx, &assigned).is_ok());
+        let mut approving = db::Task::with_pk(xid::new(), xid::new());
+        approving.approvers.insert(user);
+        assert!(check_participant(true, &ctx, &approving).is_ok());
+        let mut watching = db::Task::with_pk(xid::new(), xid::new());
+        watching.watchers.insert(user);
+        assert!(check_participant(true, &ctx, &watching).is_ok());
+    }
+}
@@ -1,23 +1,31 @@
-use axum::{extract::State, Extension};
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use futures::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use validator::Validate;
 
-use axum_web::context::ReqContext;
+use axum_web::context::{unix_ms, ReqContext};
 use axum_web::erring::{HTTPError, SuccessResponse};
 use axum_web::object::PackObject;
 
-use crate::db;
+use crate::db::{self, scylladb::CqlExecutor};
 
-use crate::api::{token_from_xid, token_to_xid, AppState, Pagination};
+use crate::api::{
+    check_page_size, check_uid, get_fields, token_from_state, token_from_xid, token_to_page,
+    token_to_xid, ApiVersion, AppState, PageToken, Pagination,
+};
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct NotificationOutput {
     pub sender: PackObject<xid::Id>,
     pub tid: PackObject<xid::Id>,
     pub gid: PackObject<xid::Id>,
-    pub status: i8,
-    pub ack_status: i8,
+    pub status: db::TaskStatus,
+    pub ack_status: db::NotificationStatus,
+    pub read_at: i64,
     pub kind: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub created_at: Option<i64>,
@@ -26,6 +34,8 @@ pub struct NotificationOutput {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub duedate: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_at: Option<i64>, // the duedate in v2
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub threshold: Option<i16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub approvers: Option<Vec<PackObject<xid::Id>>>,
@@ -36,28 +46,80 @@ pub struct NotificationOutput {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rejected: Option<Vec<PackObject<xid::Id>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub assignee_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejected_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub payload: Option<PackObject<Vec<u8>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_len: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_external: Option<bool>, // the payload is the reference to the blob
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ack_message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ack_reason: Option<String>, // the reason code of the ack
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub escalated_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_missing: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<i8>,
 }
 
 impl NotificationOutput {
-    pub fn from<T>(val: db::Task, ack_status: i8, to: &PackObject<T>) -> Self {
+    // the output of a notification whose task does not exist anymore.
+    pub fn missing<T>(
+        sender: xid::Id,
+        tid: xid::Id,
+        ack_status: db::NotificationStatus,
+        read_at: i64,
+        to: &PackObject<T>,
+    ) -> Self {
+        Self {
+            sender: to.with(sender),
+            tid: to.with(tid),
+            ack_status,
+            read_at,
+            task_missing: Some(true),
+            ..Default::default()
+        }
+    }
+
+    pub fn from<T>(
+        val: db::Task,
+        ack_status: db::NotificationStatus,
+        read_at: i64,
+        to: &PackObject<T>,
+        ver: ApiVersion,
+    ) -> Self {
         let mut rt = Self {
             sender: to.with(val.uid),
             tid: to.with(val.id),
             gid: to.with(val.gid),
             status: val.status,
             ack_status,
+            read_at,
             kind: val.kind,
             ..Default::default()
         };
 
+        // only the counts of sets are output with the `counts` field.
+        let counts_only = val._fields.iter().any(|f| f == db::Task::COUNTS_FIELD);
         for v in val._fields {
             match v.as_str() {
                 "created_at" => rt.created_at = Some(val.created_at),
                 "updated_at" => rt.updated_at = Some(val.updated_at),
-                "duedate" => rt.duedate = Some(val.duedate),
+                "duedate" if ver == ApiVersion::V1 => rt.duedate = Some(val.duedate),
+                "duedate" => rt.due_at = Some(val.duedate),
                 "threshold" => rt.threshold = Some(val.threshold),
                 "approvers" => {
                     rt.approvers = Some(
@@ -68,31 +130,44 @@ impl NotificationOutput {
                     )
                 }
                 "assignees" => {
-                    rt.assignees = Some(
-                        val.assignees
-                            .iter()
-                            .map(|id| to.with(id.to_owned()))
-                            .collect(),
-                    )
+                    rt.assignee_count = Some(val.assignees.len() as u32);
+                    if !counts_only {
+                        rt.assignees = Some(
+                            val.assignees
+                                .iter()
+                                .map(|id| to.with(id.to_owned()))
+                                .collect(),
+                        )
+                    }
                 }
                 "resolved" => {
-                    rt.resolved = Some(
-                        val.resolved
-                            .iter()
-                            .map(|id| to.with(id.to_owned()))
-                            .collect(),
-                    )
+                    rt.resolved_count = Some(val.resolved.len() as u32);
+                    if !counts_only {
+                        rt.resolved = Some(
+                            val.resolved
+                                .iter()
+                                .map(|id| to.with(id.to_owned()))
+                                .collect(),
+                        )
+                    }
                 }
                 "rejected" => {
-                    rt.rejected = Some(
-                        val.rejected
-                            .iter()
-                            .map(|id| to.with(id.to_owned()))
-                            .collect(),
-                    )
+                    rt.rejected_count = Some(val.rejected.len() as u32);
+                    if !counts_only {
+                        rt.rejected = Some(
+                            val.rejected
+                                .iter()
+                                .map(|id| to.with(id.to_owned()))
+                                .collect(),
+                        )
+                    }
                 }
                 "message" => rt.message = Some(val.message.to_owned()),
                 "payload" => rt.payload = Some(to.with(val.payload.to_owned())),
+                "payload_type" => rt.payload_type = Some(val.payload_type.to_owned()),
+                "payload_len" => rt.payload_len = Some(val.payload_len),
+                "payload_external" if val.payload_external => rt.payload_external = Some(true),
+                "priority" => rt.priority = Some(val.priority),
                 _ => {}
             }
         }
@@ -101,12 +176,124 @@ impl NotificationOutput {
     }
 }
 
+// notification fields that can be selected besides the task fields,
+// `ack_message` is the message of the notification, `message` is the task's.
+const NOTIFICATION_FIELDS: [&str; 2] = ["read_at", "ack_message"];
+
+// splits the selected fields into task fields and notification fields,
+// the task fields are validated before any reads.
+fn split_fields(fields: Vec<String>) -> Result<(Vec<String>, Vec<String>), HTTPError> {
+    let (notification_fields, task_fields): (Vec<String>, Vec<String>) = fields
+        .into_iter()
+        .partition(|f| NOTIFICATION_FIELDS.contains(&f.as_str()));
+    db::Task::select_fields(task_fields.clone(), false)?;
+    Ok((task_fields, notification_fields))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct QueryNotification {
+    pub uid: PackObject<xid::Id>,
+    pub tid: PackObject<xid::Id>,
+    pub sender: PackObject<xid::Id>,
+    pub fields: Option<String>,
+}
+
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<()>,
+    Query(input): Query<QueryNotification>,
+) -> Result<PackObject<SuccessResponse<NotificationOutput>>, HTTPError> {
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "get_notification".into()),
+        ("uid", input.uid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+        ("sender", input.sender.to_string().into()),
+    ])
+    .await;
+
+    let (fields, notification_fields) = split_fields(ver.select_fields(get_fields(input.fields)))?;
+    let mut doc = db::Notification::with_pk(
+        input.uid.unwrap(),
+        input.tid.unwrap(),
+        input.sender.unwrap(),
+    );
+    doc.get_one(&app.scylla).await?;
+
+    let mut output = match load_task(&app, doc.sender, doc.tid, fields).await? {
+        Some(task) => NotificationOutput::from(task, doc.status, doc.read_at, &to, ver),
+        // the task was deleted, but the notification still exists.
+        None => NotificationOutput::missing(doc.sender, doc.tid, doc.status, doc.read_at, &to),
+    };
+    if notification_fields.iter().any(|f| f == "ack_message") {
+        output.ack_message = Some(doc.message);
+    }
+    if !doc.reason.is_empty() {
+        output.ack_reason = Some(doc.reason);
+    }
+    if doc.escalated_at > 0 {
+        output.escalated_at = Some(doc.escalated_at);
+    }
+    Ok(to.with(SuccessResponse::new(output)))
+}
+
+// loads the task of a notification through the task cache if enabled, falling back to
+// the archive table, returns None if the task does not exist anymore.
+async fn load_task(
+    app: &AppState,
+    sender: xid::Id,
+    tid: xid::Id,
+    fields: Vec<String>,
+) -> Result<Option<db::Task>, HTTPError> {
+    let mut task = db::Task::with_pk(sender, tid);
+    let res = match app.scylla.task_cache() {
+        Some(cache) => task
+            .get_cached(&app.scylla, cache, fields.clone())
+            .await
+            .map(|hit| app.metrics.observe_task_cache(hit)),
+        None => task.get_one(&app.scylla, fields.clone()).await,
+    };
+    if db::scylladb::exists(res)? {
+        return Ok(Some(task));
+    }
+    if db::scylladb::exists(task.get_archived(&app.scylla, fields).await)? {
+        return Ok(Some(task));
+    }
+    Ok(None)
+}
+
+// the max number of tasks loaded concurrently when hydrating a page.
+pub(crate) const HYDRATE_CONCURRENCY: usize = 16;
+
+// loads the tasks of a notification page concurrently, the results follow the order of `keys`.
+async fn hydrate_tasks(
+    app: &AppState,
+    keys: Vec<(xid::Id, xid::Id)>,
+    fields: Vec<String>,
+) -> Vec<Result<Option<db::Task>, HTTPError>> {
+    // large fields are hydrated only when requested explicitly.
+    let fields = if fields.is_empty() {
+        db::Task::list_fields()
+    } else {
+        fields
+    };
+    stream::iter(keys)
+        .map(|(sender, tid)| load_task(app, sender, tid, fields.clone()))
+        .buffered(HYDRATE_CONCURRENCY)
+        .collect()
+        .await
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct DeleteNotificationInput {
     pub uid: PackObject<xid::Id>,
     pub tid: Option<PackObject<xid::Id>>,
     pub sender: Option<PackObject<xid::Id>>,
-    pub status: Option<i8>,
+    pub status: Option<db::NotificationStatus>,
 }
 
 pub async fn delete(
@@ -116,6 +303,7 @@ pub async fn delete(
 ) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
     let (to, input) = to.unpack();
     input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
 
     let tid = input
         .tid
@@ -145,6 +333,7 @@ pub async fn batch_delete(
 ) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
     let (to, input) = to.unpack();
     input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
 
     ctx.set_kvs(vec![
         ("action", "batch_delete_notification".into()),
@@ -153,23 +342,246 @@ pub async fn batch_delete(
     .await;
 
     if let Some(status) = input.status {
-        ctx.set("status", status.into()).await
+        ctx.set("status", i8::from(status).into()).await
     }
 
-    db::Notification::batch_delete_by_uid(&app.scylla, input.uid.unwrap(), input.status).await?;
+    let removed =
+        db::Notification::batch_delete_by_uid(&app.scylla, input.uid.unwrap(), input.status)
+            .await?;
+    ctx.set("removed", removed.into()).await;
 
-    Ok(to.with(SuccessResponse::new(true)))
+    // total_size reports the number of notifications removed.
+    Ok(to.with(SuccessResponse {
+        total_size: Some(removed),
+        next_page_token: None,
+        result: true,
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ReadNotificationInput {
+    pub uid: PackObject<xid::Id>,
+    pub tid: PackObject<xid::Id>,
+    pub sender: PackObject<xid::Id>,
+}
+
+pub async fn read(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<ReadNotificationInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "read_notification".into()),
+        ("uid", input.uid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+        ("sender", input.sender.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Notification::with_pk(
+        input.uid.unwrap(),
+        input.tid.unwrap(),
+        input.sender.unwrap(),
+    );
+    let res = doc.read(&app.scylla, unix_ms() as i64).await?;
+    Ok(to.with(SuccessResponse::new(res)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct QueryNotificationCount {
+    pub uid: PackObject<xid::Id>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct NotificationCountOutput {
+    pub pending: u64,
+    pub resolved: u64,
+    pub rejected: u64,
+    pub partial: bool, // some counts timed out and are reported as 0
+}
+
+pub async fn count(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryNotificationCount>,
+) -> Result<PackObject<SuccessResponse<NotificationCountOutput>>, HTTPError> {
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "count_notification".into()),
+        ("uid", input.uid.to_string().into()),
+    ])
+    .await;
+
+    let uid = input.uid.unwrap();
+    let mut output = NotificationCountOutput::default();
+    for status in [
+        db::NotificationStatus::Pending,
+        db::NotificationStatus::Resolved,
+        db::NotificationStatus::Rejected,
+    ] {
+        let total = match db::Notification::count(&app.scylla, uid, Some(status)).await {
+            Ok(total) => total,
+            Err(err) => {
+                let err: HTTPError = err.into();
+                if err.code != 504 {
+                    return Err(err);
+                }
+                output.partial = true;
+                0
+            }
+        };
+        match status {
+            db::NotificationStatus::Pending => output.pending = total,
+            db::NotificationStatus::Resolved => output.resolved = total,
+            db::NotificationStatus::Rejected => output.rejected = total,
+        }
+    }
+
+    ctx.set("partial", output.partial.into()).await;
+    Ok(to.with(SuccessResponse::new(output)))
+}
+
+// the max number of notifications scanned by the digest.
+const DIGEST_SCAN_LIMIT: usize = 5000;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DigestInput {
+    pub uid: PackObject<xid::Id>,
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+    pub status: Option<db::NotificationStatus>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SenderDigestOutput {
+    pub sender: PackObject<xid::Id>,
+    pub count: u64,
+    pub tids: Vec<PackObject<xid::Id>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DigestOutput {
+    pub senders: Vec<SenderDigestOutput>,
+    pub truncated: bool, // only the most recent notifications were scanned
+}
+
+pub async fn digest(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<DigestInput>,
+) -> Result<PackObject<SuccessResponse<DigestOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    let page_size = input.page_size.unwrap_or(10);
+    ctx.set_kvs(vec![
+        ("action", "digest_notification".into()),
+        ("uid", input.uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let (res, truncated) =
+        db::Notification::digest(&app.scylla, *input.uid, input.status, DIGEST_SCAN_LIMIT).await?;
+    // paginated by sender, the page token is the last sender of the previous page.
+    let page_token = token_to_xid(&input.page_token)?;
+    let mut res: Vec<db::NotificationDigest> = res
+        .into_iter()
+        .filter(|d| page_token.map_or(true, |sender| d.sender > sender))
+        .collect();
+    let next_page_token = if res.len() > page_size as usize {
+        res.truncate(page_size as usize);
+        to.with_option(token_from_xid(res.last().unwrap().sender))
+    } else {
+        None
+    };
+
+    ctx.set("truncated", truncated.into()).await;
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: DigestOutput {
+            senders: res
+                .into_iter()
+                .map(|d| SenderDigestOutput {
+                    sender: to.with(d.sender),
+                    count: d.count,
+                    tids: d.tids.into_iter().map(|id| to.with(id)).collect(),
+                })
+                .collect(),
+            truncated,
+        },
+    }))
+}
+
+// the max number of notifications scanned for a page filtered by gid.
+const GID_SCAN_LIMIT: usize = 5000;
+
+// reads notifications of the uid until a full page of the gid or the end of the partition,
+// returns the page and the tid to continue from. It may return a short page with a tid
+// when GID_SCAN_LIMIT is reached.
+async fn list_by_gid(
+    app: &AppState,
+    uid: xid::Id,
+    gid: xid::Id,
+    page_size: u16,
+    page_token: Option<xid::Id>,
+    status: Option<db::NotificationStatus>,
+) -> Result<(Vec<db::Notification>, Option<xid::Id>), HTTPError> {
+    let batch_size = page_size.max(100);
+    let mut res: Vec<db::Notification> = Vec::with_capacity(page_size as usize);
+    let mut token = page_token;
+    let mut scanned: usize = 0;
+    loop {
+        let docs = db::Notification::list(&app.scylla, uid, batch_size, token, status).await?;
+        let partition_end = docs.len() < batch_size as usize;
+        for mut doc in docs {
+            scanned += 1;
+            token = Some(doc.tid);
+            if doc.gid == xid::Id::default() {
+                // notifications created before gid was copied from the task.
+                if let Ok(Some(task)) = load_task(app, doc.sender, doc.tid, vec![]).await {
+                    let _ = doc.update_gid(&app.scylla, task.gid).await;
+                }
+            }
+            if doc.gid == gid {
+                res.push(doc);
+                if res.len() >= page_size as usize {
+                    return Ok((res, token));
+                }
+            }
+        }
+
+        if partition_end {
+            return Ok((res, None));
+        }
+        if scanned >= GID_SCAN_LIMIT {
+            return Ok((res, token));
+        }
+    }
 }
 
 pub async fn list(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
     to: PackObject<Pagination>,
 ) -> Result<PackObject<SuccessResponse<Vec<NotificationOutput>>>, HTTPError> {
     let (to, input) = to.unpack();
     input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+    let status = input.notification_status()?;
 
-    let page_size = input.page_size.unwrap_or(10);
+    let page_size = check_page_size(input.page_size, app.max_page_size)?;
     ctx.set_kvs(vec![
         ("action", "list_notification".into()),
         ("uid", input.uid.to_string().into()),
@@ -177,13 +589,135 @@ pub async fn list(
     ])
     .await;
 
-    let fields = input.fields.unwrap_or_default();
-    let res = db::Notification::list(
+    let (fields, notification_fields) =
+        split_fields(ver.select_fields(input.fields.unwrap_or_default()))?;
+    let with_ack_message = notification_fields.iter().any(|f| f == "ack_message");
+    let uid = input.uid.unwrap();
+    let page_token = token_to_page(&input.page_token)?;
+    let (mut res, next_page_token) = match (input.gid, page_token) {
+        // the gid filter scans by tid, so it pages by xid tokens only.
+        (Some(gid), page_token) => {
+            ctx.set("gid", gid.to_string().into()).await;
+            let page_token = page_token.and_then(PageToken::into_xid);
+            let (res, next_tid) =
+                list_by_gid(&app, uid, *gid, page_size, page_token, status).await?;
+            (
+                res,
+                next_tid.and_then(|tid| to.with_option(token_from_xid(tid))),
+            )
+        }
+        // legacy xid tokens keep paging by tid.
+        (None, Some(PageToken::Xid(tid))) => {
+            let res =
+                db::Notification::list(&app.scylla, uid, page_size, Some(tid), status).await?;
+            let next_page_token = if res.len() >= page_size as usize {
+                to.with_option(token_from_xid(res.last().unwrap().tid))
+            } else {
+                None
+            };
+            (res, next_page_token)
+        }
+        (None, page_token) => {
+            let paging_state = page_token.and_then(PageToken::into_state);
+            let (res, paging_state) =
+                db::Notification::list_paged(&app.scylla, uid, page_size, paging_state, status)
+                    .await?;
+            (
+                res,
+                paging_state.and_then(|state| to.with_option(token_from_state(state))),
+            )
+        }
+    };
+    // read notifications are skipped after paging, so a page may be shorter than page_size.
+    if input.unread_only.unwrap_or(false) {
+        res.retain(|notiy| notiy.read_at == 0);
+    }
+
+    let keys = res.iter().map(|notiy| (notiy.sender, notiy.tid)).collect();
+    let tasks = hydrate_tasks(&app, keys, fields).await;
+    let mut output: Vec<NotificationOutput> = Vec::with_capacity(res.len());
+    for (mut notiy, task) in res.into_iter().zip(tasks) {
+        let mut rt = match task {
+            Ok(Some(task)) => NotificationOutput::from(task, notiy.status, notiy.read_at, &to, ver),
+            Ok(None) => {
+                // the task was deleted out-of-band, the orphaned notification is removed.
+                let _ = notiy.delete(&app.scylla).await;
+                NotificationOutput::missing(
+                    notiy.sender,
+                    notiy.tid,
+                    notiy.status,
+                    notiy.read_at,
+                    &to,
+                )
+            }
+            // a failed lookup should not fail the whole page.
+            Err(_) => NotificationOutput::missing(
+                notiy.sender,
+                notiy.tid,
+                notiy.status,
+                notiy.read_at,
+                &to,
+            ),
+        };
+        if with_ack_message {
+            rt.ack_message = Some(notiy.message);
+        }
+        if !notiy.reason.is_empty() {
+            rt.ack_reason = Some(notiy.reason);
+        }
+        if notiy.escalated_at > 0 {
+            rt.escalated_at = Some(notiy.escalated_at);
+        }
+        output.push(rt);
+    }
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: output,
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct GroupNotificationPagination {
+    pub gid: PackObject<xid::Id>,
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    pub page_size: Option<u16>,
+    #[validate(range(min = -1, max = 2))]
+    pub role: Option<i8>,
+    pub fields: Option<Vec<String>>,
+}
+
+pub async fn group_list(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<GroupNotificationPagination>,
+) -> Result<PackObject<SuccessResponse<Vec<NotificationOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let page_size = check_page_size(input.page_size, app.max_page_size)?;
+    ctx.set_kvs(vec![
+        ("action", "list_group_notification".into()),
+        ("gid", input.gid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let mut fields = ver.select_fields(input.fields.unwrap_or_default());
+    // invalid fields fail up front rather than degrading every item.
+    db::Task::select_fields(fields.clone(), false)?;
+    // the visibility is always hydrated so that private tasks can be filtered out.
+    if !fields.is_empty() && !fields.iter().any(|f| f == "visibility") {
+        fields.push("visibility".to_string());
+    }
+    let res = db::GroupNotification::list(
         &app.scylla,
-        input.uid.unwrap(),
+        input.gid.unwrap(),
         page_size,
-        token_to_xid(&input.page_token),
-        input.status,
+        token_to_xid(&input.page_token)?,
+        input.role,
     )
     .await?;
     let next_page_token = if res.len() >= page_size as usize {
@@ -192,11 +726,26 @@ pub async fn list(
         None
     };
 
+    let keys = res.iter().map(|notiy| (notiy.sender, notiy.tid)).collect();
+    let tasks = hydrate_tasks(&app, keys, fields).await;
     let mut output: Vec<NotificationOutput> = Vec::with_capacity(res.len());
-    for notiy in res {
-        let mut task = db::Task::with_pk(notiy.sender, notiy.tid);
-        task.get_one(&app.scylla, fields.clone()).await?;
-        output.push(NotificationOutput::from(task, notiy.status, &to));
+    for (notiy, task) in res.into_iter().zip(tasks) {
+        let mut rt = match task {
+            // a private task never leaks to the group, even with a stale group notification.
+            Ok(Some(task)) if task.is_private() => continue,
+            Ok(Some(task)) => {
+                NotificationOutput::from(task, db::NotificationStatus::Pending, 0, &to, ver)
+            }
+            _ => NotificationOutput::missing(
+                notiy.sender,
+                notiy.tid,
+                db::NotificationStatus::Pending,
+                0,
+                &to,
+            ),
+        };
+        rt.role = Some(notiy.role);
+        output.push(rt);
     }
 
     Ok(to.with(SuccessResponse {
@@ -205,3 +754,530 @@ pub async fn list(
         result: output,
     }))
 }
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct GroupNotificationInput {
+    pub gid: PackObject<xid::Id>,
+    pub tid: PackObject<xid::Id>,
+    pub sender: PackObject<xid::Id>,
+}
+
+pub async fn group_delete(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<GroupNotificationInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    // the group exposure of a task is managed by its creator.
+    check_uid(app.pin_uid, &ctx, *input.sender)?;
+
+    ctx.set_kvs(vec![
+        ("action", "delete_group_notification".into()),
+        ("gid", input.gid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+        ("sender", input.sender.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::GroupNotification::with_pk(
+        input.gid.unwrap(),
+        input.tid.unwrap(),
+        input.sender.unwrap(),
+    );
+    if !db::scylladb::exists(doc.get_one(&app.scylla).await)? {
+        return Ok(to.with(SuccessResponse::new(false))); // already deleted
+    }
+
+    doc.delete(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(true)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateGroupRoleInput {
+    pub gid: PackObject<xid::Id>,
+    pub tid: PackObject<xid::Id>,
+    pub sender: PackObject<xid::Id>,
+    #[validate(range(min = -1, max = 2))]
+    pub role: i8,
+}
+
+pub async fn update_group_role(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<UpdateGroupRoleInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.sender)?;
+
+    ctx.set_kvs(vec![
+        ("action", "update_group_notification_role".into()),
+        ("gid", input.gid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+        ("sender", input.sender.to_string().into()),
+        ("role", input.role.into()),
+    ])
+    .await;
+
+    let mut doc = db::GroupNotification::with_pk(
+        input.gid.unwrap(),
+        input.tid.unwrap(),
+        input.sender.unwrap(),
+    );
+    let res = doc.update_role(&app.scylla, input.role).await?;
+    Ok(to.with(SuccessResponse::new(res)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct MuteInput {
+    pub uid: PackObject<xid::Id>,
+    pub sender: PackObject<xid::Id>,
+}
+
+pub async fn mute(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<MuteInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "mute_notification".into()),
+        ("uid", input.uid.to_string().into()),
+        ("sender", input.sender.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::NotificationMute::with_pk(input.uid.unwrap(), input.sender.unwrap());
+    doc.save(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(true)))
+}
+
+pub async fn unmute(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<MuteInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "unmute_notification".into()),
+        ("uid", input.uid.to_string().into()),
+        ("sender", input.sender.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::NotificationMute::with_pk(input.uid.unwrap(), input.sender.unwrap());
+    doc.delete(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(true)))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct MuteOutput {
+    pub sender: PackObject<xid::Id>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct QueryMutes {
+    pub uid: PackObject<xid::Id>,
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+}
+
+pub async fn mutes(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryMutes>,
+) -> Result<PackObject<SuccessResponse<Vec<MuteOutput>>>, HTTPError> {
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    let page_size = input.page_size.unwrap_or(10);
+    ctx.set_kvs(vec![
+        ("action", "list_notification_mutes".into()),
+        ("uid", input.uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let res = db::NotificationMute::list(
+        &app.scylla,
+        input.uid.unwrap(),
+        page_size,
+        token_to_xid(&input.page_token)?,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(res.last().unwrap().sender))
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .into_iter()
+            .map(|doc| MuteOutput {
+                sender: to.with(doc.sender),
+                created_at: doc.created_at,
+            })
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::OnceCell;
+
+    use crate::conf;
+
+    use super::*;
+
+    static APP: OnceCell<Arc<AppState>> = OnceCell::const_new();
+
+    async fn get_app() -> Arc<AppState> {
+        APP.get_or_init(|| async {
+            let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+            let scylla = db::schema::test_db("taskbase_test").await;
+            Arc::new(AppState {
+                scylla: Arc::new(scylla),
+                keyspace: "taskbase_test".to_string(),
+                trash_retention_ms: cfg.worker.trash_retention_ms(),
+                notification_ttl: 0,
+                webhooks: Default::default(),
+                max_payload_bytes: cfg.max_payload_bytes,
+                max_message_len: cfg.max_message_len,
+                max_page_size: cfg.max_page_size,
+                kinds: cfg.kinds.clone(),
+                metrics: Default::default(),
+                pin_uid: false,
+                shutdown: Default::default(),
+                maintenance: Default::default(),
+                blobs: None,
+                calendar: Default::default(),
+            })
+        })
+        .await
+        .clone()
+    }
+
+    #[test]
+    fn split_fields_works() {
+        let (task_fields, notification_fields) = split_fields(vec![]).unwrap();
+        assert!(task_fields.is_empty());
+        assert!(notification_fields.is_empty());
+
+        let (task_fields, notification_fields) = split_fields(vec![
+            "message".to_string(),
+            "read_at".to_string(),
+            "ack_message".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(task_fields, vec!["message".to_string()]);
+        assert_eq!(
+            notification_fields,
+            vec!["read_at".to_string(), "ack_message".to_string()]
+        );
+
+        let err = split_fields(vec!["read_at".to_string(), "unknown".to_string()]).unwrap_err();
+        assert_eq!(err.code, 400);
+    }
+
+    #[test]
+    fn notification_output_payload_works() {
+        let to = PackObject::Json(());
+        let mut task = db::Task::with_pk(xid::new(), xid::new());
+        task.payload = vec![1u8; 8];
+        task.payload_type = "application/json".to_string();
+        task.payload_len = 8;
+        task._fields = db::Task::list_fields();
+
+        // inbox UIs decide whether to fetch the payload by its metadata.
+        let rt = NotificationOutput::from(
+            task,
+            db::NotificationStatus::Pending,
+            0,
+            &to,
+            ApiVersion::V1,
+        );
+        assert!(rt.payload.is_none());
+        assert_eq!(rt.payload_type.as_deref(), Some("application/json"));
+        assert_eq!(rt.payload_len, Some(8));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn maintenance_works() {
+        use axum::{
+            body::Body,
+            http::{Request, StatusCode},
+            middleware, routing, Router,
+        };
+        use std::sync::atomic::Ordering;
+        use tower::ServiceExt;
+
+        let app = get_app().await;
+        let router = Router::new()
+            .route("/read", routing::post(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(
+                app.clone(),
+                crate::api::maintenance_middleware,
+            ))
+            .route("/list", routing::post(|| async { "ok" }));
+        let post = |uri: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = router.clone().oneshot(post("/read")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        app.maintenance.store(true, Ordering::Relaxed);
+        let res = router.clone().oneshot(post("/read")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()["retry-after"], "60");
+        let res = router.clone().oneshot(post("/list")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        app.maintenance.store(false, Ordering::Relaxed);
+        let res = router.oneshot(post("/read")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn list_skips_missing_tasks_works() {
+        let app = get_app().await;
+        let uid = xid::new();
+        let sender = xid::new();
+
+        let mut task = db::Task::with_pk(sender, xid::new());
+        task.kind = "test".to_string();
+        task.assignees = [uid].into();
+        task.save(&app.scylla).await.unwrap();
+        let mut notif = db::Notification::with_pk(uid, task.id, sender);
+        notif.save(&app.scylla).await.unwrap();
+
+        // no backing task.
+        let mut orphan = db::Notification::with_pk(uid, xid::new(), sender);
+        orphan.save(&app.scylla).await.unwrap();
+
+        let ctx = Arc::new(ReqContext::new("test", uid, 0));
+        let input = Pagination {
+            uid: PackObject::Json(uid),
+            page_token: None,
+            page_size: Some(10),
+            status: None,
+            fields: None,
+            with_total: None,
+            kind: None,
+            order: None,
+            unread_only: None,
+            gid: None,
+        };
+        let res = list(
+            State(app.clone()),
+            Extension(ctx),
+            Extension(ApiVersion::V1),
+            PackObject::Json(input),
+        )
+        .await
+        .unwrap();
+        let res = res.unwrap().result;
+        assert_eq!(res.len(), 2);
+        let missing: Vec<&NotificationOutput> =
+            res.iter().filter(|o| o.task_missing.is_some()).collect();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(*missing[0].tid, orphan.tid);
+        assert!(res
+            .iter()
+            .any(|o| *o.tid == task.id && o.task_missing.is_none()));
+
+        // the orphaned notification is removed.
+        assert!(orphan.get_one(&app.scylla).await.is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn list_preserves_order_works() {
+        let app = get_app().await;
+        let uid = xid::new();
+        let sender = xid::new();
+
+        let mut tids: Vec<xid::Id> = Vec::new();
+        for i in 0..20 {
+            let mut task = db::Task::with_pk(sender, xid::new());
+            // leaves some holes in the page.
+            if i % 7 != 3 {
+                task.kind = "test".to_string();
+                task.assignees = [uid].into();
+                task.save(&app.scylla).await.unwrap();
+            }
+            let mut notif = db::Notification::with_pk(uid, task.id, sender);
+            notif.save(&app.scylla).await.unwrap();
+            tids.push(task.id);
+        }
+        // notifications are listed in descending order of tid.
+        tids.reverse();
+
+        let ctx = Arc::new(ReqContext::new("test", uid, 0));
+        let input = Pagination {
+            uid: PackObject::Json(uid),
+            page_token: None,
+            page_size: Some(50),
+            status: None,
+            fields: None,
+            with_total: None,
+            kind: None,
+            order: None,
+            unread_only: None,
+            gid: None,
+        };
+        let res = list(
+            State(app.clone()),
+            Extension(ctx),
+            Extension(ApiVersion::V1),
+            PackObject::Json(input),
+        )
+        .await
+        .unwrap();
+        let res = res.unwrap().result;
+        assert_eq!(res.len(), tids.len());
+        for (i, o) in res.iter().enumerate() {
+            assert_eq!(*o.tid, tids[i]);
+            assert_eq!(o.task_missing.is_some(), (19 - i) % 7 == 3);
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn list_versions_works() {
+        let app = get_app().await;
+        let uid = xid::new();
+        let sender = xid::new();
+
+        let mut task = db::Task::with_pk(sender, xid::new());
+        task.kind = "test".to_string();
+        task.duedate = unix_ms() as i64 + 3600 * 1000;
+        task.assignees = [uid].into();
+        task.save(&app.scylla).await.unwrap();
+        let mut notif = db::Notification::with_pk(uid, task.id, sender);
+        notif.save(&app.scylla).await.unwrap();
+
+        // the same notification is listed by both versions side by side.
+        for ver in ApiVersion::ALL {
+            let ctx = Arc::new(ReqContext::new("test", uid, 0));
+            let input = Pagination {
+                uid: PackObject::Json(uid),
+                page_token: None,
+                page_size: Some(10),
+                status: None,
+                fields: Some(vec!["duedate".to_string()]),
+                with_total: None,
+                kind: None,
+                order: None,
+                unread_only: None,
+                gid: None,
+            };
+            let res = list(
+                State(app.clone()),
+                Extension(ctx),
+                Extension(ver),
+                PackObject::Json(input),
+            )
+            .await
+            .unwrap();
+            let res = res.unwrap().result;
+            assert_eq!(res.len(), 1);
+            match ver {
+                ApiVersion::V1 => {
+                    assert_eq!(res[0].duedate, Some(task.duedate));
+                    assert!(res[0].due_at.is_none());
+                    assert!(res[0].assignee_count.is_none());
+                }
+                ApiVersion::V2 => {
+                    assert_eq!(res[0].due_at, Some(task.duedate));
+                    assert!(res[0].duedate.is_none());
+                    assert_eq!(res[0].assignee_count, Some(1));
+                    assert!(res[0].assignees.is_none());
+                }
+            }
+        }
+    }
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn list_by_gid_works() {
+        let app = get_app().await;
+        let uid = xid::new();
+        let sender = xid::new();
+        let gid = xid::new();
+        let other_gid = xid::new();
+
+        let mut tids: Vec<xid::Id> = Vec::new();
+        for i in 0..9 {
+            let mut task = db::Task::with_pk(sender, xid::new());
+            task.gid = if i % 3 == 0 { gid } else { other_gid };
+            task.kind = "test".to_string();
+            task.assignees = [uid].into();
+            task.save(&app.scylla).await.unwrap();
+            let mut notif = db::Notification::with_pk(uid, task.id, sender);
+            // the first one has no gid to exercise the backfill.
+            if i > 0 {
+                notif.gid = task.gid;
+            }
+            notif.save(&app.scylla).await.unwrap();
+            if task.gid == gid {
+                tids.push(task.id);
+            }
+        }
+        tids.reverse();
+
+        let mut page_token: Option<PackObject<Vec<u8>>> = None;
+        let mut got: Vec<xid::Id> = Vec::new();
+        loop {
+            let ctx = Arc::new(ReqContext::new("test", uid, 0));
+            let input = Pagination {
+                uid: PackObject::Json(uid),
+                page_token,
+                page_size: Some(2),
+                status: None,
+                fields: None,
+                with_total: None,
+                kind: None,
+                order: None,
+                unread_only: None,
+                gid: Some(PackObject::Json(gid)),
+            };
+            let res = list(
+                State(app.clone()),
+                Extension(ctx),
+                Extension(ApiVersion::V1),
+                PackObject::Json(input),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+            for o in &res.result {
+                assert_eq!(*o.gid, gid);
+                got.push(*o.tid);
+            }
+            page_token = res.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        assert_eq!(got, tids);
+    }
+}
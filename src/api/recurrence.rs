@@ -0,0 +1,247 @@
+use axum::{extract::State, Extension};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Arc};
+use validator::Validate;
+
+use axum_web::context::{unix_ms, ReqContext};
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+
+use crate::db;
+
+use crate::api::{
+    check_uid,
+    task::{check_content, check_payload_type, CreateTaskInput},
+    token_from_xid, token_to_xid, AppState,
+};
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RecurrenceOutput {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub gid: PackObject<xid::Id>,
+    pub kind: String,
+    pub created_at: i64,
+    pub interval_ms: i64,
+    pub next_run_at: i64,
+    pub threshold: i16,
+    pub approvers: Vec<PackObject<xid::Id>>,
+    pub assignees: Vec<PackObject<xid::Id>>,
+    pub message: String,
+    pub payload: PackObject<Vec<u8>>,
+    pub payload_type: String,
+}
+
+impl RecurrenceOutput {
+    pub fn from<T>(val: db::TaskRecurrence, to: &PackObject<T>) -> Self {
+        Self {
+            uid: to.with(val.uid),
+            id: to.with(val.id),
+            gid: to.with(val.gid),
+            kind: val.kind,
+            created_at: val.created_at,
+            interval_ms: val.interval_ms,
+            next_run_at: val.next_run_at,
+            threshold: val.threshold,
+            approvers: val.approvers.into_iter().map(|id| to.with(id)).collect(),
+            assignees: val.assignees.into_iter().map(|id| to.with(id)).collect(),
+            message: val.message,
+            payload: to.with(val.payload),
+            payload_type: val.payload_type,
+        }
+    }
+}
+
+// the task input of a recurrence run, the message is prefixed with the recurrence id.
+pub fn task_input(val: &db::TaskRecurrence) -> CreateTaskInput {
+    CreateTaskInput {
+        uid: PackObject::Cbor(val.uid),
+        id: None,
+        gid: PackObject::Cbor(val.gid),
+        kind: val.kind.clone(),
+        threshold: Some(val.threshold),
+        approver_threshold: 0,
+        approvers: val
+            .approvers
+            .iter()
+            .map(|id| PackObject::Cbor(*id))
+            .collect(),
+        assignees: val
+            .assignees
+            .iter()
+            .map(|id| PackObject::Cbor(*id))
+            .collect(),
+        message: format!("[recurrence {}] {}", val.id, val.message)
+            .trim_end()
+            .to_string(),
+        payload: PackObject::Cbor(val.payload.clone()),
+        payload_type: val.payload_type.clone(),
+        group_role: None,
+        duedate: None,
+        priority: None,
+        template_id: None,
+        notify_creator: None,
+        notification_ttl: None,
+        escalate_after_ms: None,
+        allow_late_ack: None,
+        veto: None,
+        sequence: Vec::new(),
+        self_ack_allowed: None,
+        visibility: None,
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateRecurrenceInput {
+    pub uid: PackObject<xid::Id>,
+    pub gid: PackObject<xid::Id>,
+    pub kind: String,
+    #[validate(range(min = 60000))] // at least 1 minute
+    pub interval_ms: i64,
+    pub start_at: Option<i64>,
+    #[validate(range(min = 0, max = 256))]
+    pub threshold: i16,
+    #[validate(length(min = 0, max = 4))]
+    pub approvers: Vec<PackObject<xid::Id>>,
+    #[validate(length(min = 0, max = 256))]
+    pub assignees: Vec<PackObject<xid::Id>>,
+    pub message: String,
+    pub payload: PackObject<Vec<u8>>,
+    #[serde(default)]
+    #[validate(length(min = 0, max = 64))]
+    pub payload_type: String,
+}
+
+pub async fn create(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<CreateRecurrenceInput>,
+) -> Result<PackObject<SuccessResponse<RecurrenceOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+    let approvers: HashSet<xid::Id> = input.approvers.iter().map(|id| **id).collect();
+    let assignees: HashSet<xid::Id> = input.assignees.iter().map(|id| **id).collect();
+    db::Task::check_threshold(
+        input.threshold,
+        db::Task::reachable_votes(0, &approvers, &assignees),
+    )?;
+    check_content(
+        &input.message,
+        &input.payload,
+        app.max_message_len,
+        app.max_payload_bytes,
+    )?;
+    check_payload_type(&input.payload_type, &input.payload)?;
+
+    let now = unix_ms() as i64;
+    let next_run_at = input.start_at.unwrap_or(now + input.interval_ms);
+    if next_run_at <= now {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "invalid start_at, expected a future timestamp, got {}",
+                next_run_at
+            ),
+        ));
+    }
+
+    ctx.set_kvs(vec![
+        ("action", "create_recurrence".into()),
+        ("uid", input.uid.to_string().into()),
+        ("gid", input.gid.to_string().into()),
+        ("kind", input.kind.clone().into()),
+    ])
+    .await;
+
+    let mut doc = db::TaskRecurrence::with_pk(input.uid.unwrap(), xid::new());
+    doc.gid = input.gid.unwrap();
+    doc.kind = input.kind;
+    doc.interval_ms = input.interval_ms;
+    doc.next_run_at = next_run_at;
+    doc.threshold = input.threshold;
+    doc.approvers = input.approvers.into_iter().map(|id| id.unwrap()).collect();
+    doc.assignees = input.assignees.into_iter().map(|id| id.unwrap()).collect();
+    doc.message = input.message;
+    doc.payload = input.payload.unwrap();
+    doc.payload_type = input.payload_type;
+    doc.save(&app.scylla).await?;
+
+    ctx.set("id", doc.id.to_string().into()).await;
+    Ok(to.with(SuccessResponse::new(RecurrenceOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RecurrencePagination {
+    pub uid: PackObject<xid::Id>,
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+}
+
+pub async fn list(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<RecurrencePagination>,
+) -> Result<PackObject<SuccessResponse<Vec<RecurrenceOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    let page_size = input.page_size.unwrap_or(10);
+    ctx.set_kvs(vec![
+        ("action", "list_recurrence".into()),
+        ("uid", input.uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let res = db::TaskRecurrence::list(
+        &app.scylla,
+        input.uid.unwrap(),
+        page_size,
+        token_to_xid(&input.page_token)?,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(res.last().unwrap().id))
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .into_iter()
+            .map(|doc| RecurrenceOutput::from(doc, &to))
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DeleteRecurrenceInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+}
+
+pub async fn delete(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<DeleteRecurrenceInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "delete_recurrence".into()),
+        ("uid", input.uid.to_string().into()),
+        ("id", input.id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::TaskRecurrence::with_pk(input.uid.unwrap(), input.id.unwrap());
+    doc.delete(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(true)))
+}
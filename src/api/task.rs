@@ -1,7 +1,14 @@
 use axum::{
+    body::{Bytes, StreamBody},
     extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
     Extension,
 };
+use futures::{
+    future,
+    stream::{self, StreamExt},
+};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashSet, sync::Arc};
 use validator::Validate;
@@ -9,17 +16,26 @@ use validator::Validate;
 use axum_web::context::{unix_ms, ReqContext};
 use axum_web::erring::{HTTPError, SuccessResponse};
 use axum_web::object::PackObject;
+use scylla_orm::ColumnsMap;
 
-use crate::db;
+use crate::blobs::BlobRef;
+use crate::conf;
+use crate::crypto;
+use crate::db::{self, scylladb::CqlExecutor, NotificationStatus, TaskStatus};
 
-use crate::api::{get_fields, token_from_xid, token_to_xid, AppState, Pagination};
+use crate::api::notification::HYDRATE_CONCURRENCY;
+use crate::api::{
+    check_page_size, check_reader, check_uid, get_fields, token_from_duedate, token_from_priority,
+    token_from_state, token_from_xid, token_to_duedate, token_to_page, token_to_priority,
+    token_to_xid, ApiVersion, AppState, GroupPagination, PageToken, Pagination,
+};
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct TaskOutput {
     pub uid: PackObject<xid::Id>,
     pub id: PackObject<xid::Id>,
     pub gid: PackObject<xid::Id>,
-    pub status: i8,
+    pub status: TaskStatus,
     pub kind: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub created_at: Option<i64>,
@@ -28,8 +44,12 @@ pub struct TaskOutput {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub duedate: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_at: Option<i64>, // the duedate in v2
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub threshold: Option<i16>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub approver_threshold: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub approvers: Option<Vec<PackObject<xid::Id>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub assignees: Option<Vec<PackObject<xid::Id>>>,
@@ -38,13 +58,53 @@ pub struct TaskOutput {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rejected: Option<Vec<PackObject<xid::Id>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub approver_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approver_resolved_count: Option<u32>, // resolved votes of approvers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assignee_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejected_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub payload: Option<PackObject<Vec<u8>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_len: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_external: Option<bool>, // the payload is the reference to the blob
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub escalate_after_ms: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub escalated_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_late_ack: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub veto: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<Vec<PackObject<xid::Id>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_ack_allowed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watchers: Option<Vec<PackObject<xid::Id>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fanout_failed: Option<Vec<PackObject<xid::Id>>>, // receivers failed to be notified on create
 }
 
 impl TaskOutput {
-    pub fn from<T>(val: db::Task, to: &PackObject<T>) -> Self {
+    pub fn from<T>(val: db::Task, to: &PackObject<T>, ver: ApiVersion) -> Self {
         let mut rt = Self {
             uid: to.with(val.uid),
             id: to.with(val.id),
@@ -54,46 +114,97 @@ impl TaskOutput {
             ..Default::default()
         };
 
+        // only the counts of sets are output with the `counts` field.
+        let counts_only = val._fields.iter().any(|f| f == db::Task::COUNTS_FIELD);
+        let has_approvers = val._fields.iter().any(|f| f == "approvers");
         for v in val._fields {
             match v.as_str() {
                 "created_at" => rt.created_at = Some(val.created_at),
                 "updated_at" => rt.updated_at = Some(val.updated_at),
-                "duedate" => rt.duedate = Some(val.duedate),
+                "duedate" if ver == ApiVersion::V1 => rt.duedate = Some(val.duedate),
+                "duedate" => rt.due_at = Some(val.duedate),
                 "threshold" => rt.threshold = Some(val.threshold),
+                "approver_threshold" => rt.approver_threshold = Some(val.approver_threshold),
                 "approvers" => {
-                    rt.approvers = Some(
-                        val.approvers
-                            .iter()
-                            .map(|id| to.with(id.to_owned()))
-                            .collect(),
-                    )
+                    rt.approver_count = Some(val.approvers.len() as u32);
+                    if !counts_only {
+                        rt.approvers = Some(
+                            val.approvers
+                                .iter()
+                                .map(|id| to.with(id.to_owned()))
+                                .collect(),
+                        )
+                    }
                 }
                 "assignees" => {
-                    rt.assignees = Some(
-                        val.assignees
-                            .iter()
-                            .map(|id| to.with(id.to_owned()))
-                            .collect(),
-                    )
+                    rt.assignee_count = Some(val.assignees.len() as u32);
+                    if !counts_only {
+                        rt.assignees = Some(
+                            val.assignees
+                                .iter()
+                                .map(|id| to.with(id.to_owned()))
+                                .collect(),
+                        )
+                    }
                 }
                 "resolved" => {
-                    rt.resolved = Some(
-                        val.resolved
+                    rt.resolved_count = Some(val.resolved.len() as u32);
+                    if has_approvers {
+                        rt.approver_resolved_count =
+                            Some(val.resolved.intersection(&val.approvers).count() as u32);
+                    }
+                    if !counts_only {
+                        rt.resolved = Some(
+                            val.resolved
+                                .iter()
+                                .map(|id| to.with(id.to_owned()))
+                                .collect(),
+                        )
+                    }
+                }
+                "rejected" => {
+                    rt.rejected_count = Some(val.rejected.len() as u32);
+                    if !counts_only {
+                        rt.rejected = Some(
+                            val.rejected
+                                .iter()
+                                .map(|id| to.with(id.to_owned()))
+                                .collect(),
+                        )
+                    }
+                }
+                "message" => rt.message = Some(val.message.to_owned()),
+                "payload" => rt.payload = Some(to.with(val.payload.to_owned())),
+                "payload_type" => rt.payload_type = Some(val.payload_type.to_owned()),
+                "payload_len" => rt.payload_len = Some(val.payload_len),
+                "payload_external" if val.payload_external => rt.payload_external = Some(true),
+                "priority" => rt.priority = Some(val.priority),
+                "deleted_at" if val.status == TaskStatus::Trashed => {
+                    rt.deleted_at = Some(val.deleted_at)
+                }
+                "escalate_after_ms" => rt.escalate_after_ms = Some(val.escalate_after_ms),
+                "escalated_at" => rt.escalated_at = Some(val.escalated_at),
+                "allow_late_ack" => rt.allow_late_ack = Some(val.allow_late_ack),
+                "veto" => rt.veto = Some(val.veto),
+                "self_ack_allowed" => rt.self_ack_allowed = Some(val.self_ack_allowed),
+                "sequence" => {
+                    rt.sequence = Some(
+                        val.sequence
                             .iter()
                             .map(|id| to.with(id.to_owned()))
                             .collect(),
                     )
                 }
-                "rejected" => {
-                    rt.rejected = Some(
-                        val.rejected
+                "watchers" => {
+                    rt.watchers = Some(
+                        val.watchers
                             .iter()
                             .map(|id| to.with(id.to_owned()))
                             .collect(),
                     )
                 }
-                "message" => rt.message = Some(val.message.to_owned()),
-                "payload" => rt.payload = Some(to.with(val.payload.to_owned())),
+                "final_reason" => rt.final_reason = Some(val.final_reason.to_owned()),
+                "visibility" => rt.visibility = Some(val.visibility().to_string()),
                 _ => {}
             }
         }
@@ -107,15 +218,18 @@ pub struct QueryTask {
     pub uid: PackObject<xid::Id>,
     pub id: PackObject<xid::Id>,
     pub fields: Option<String>,
+    pub raw_ref: Option<bool>, // returns the reference of a payload in the blob store as is
 }
 
 pub async fn get(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
     to: PackObject<()>,
     Query(input): Query<QueryTask>,
 ) -> Result<PackObject<SuccessResponse<TaskOutput>>, HTTPError> {
     input.validate()?;
+    check_reader(&app, &ctx, *input.uid, *input.id).await?;
 
     ctx.set_kvs(vec![
         ("action", "get_task".into()),
@@ -125,217 +239,3199 @@ pub async fn get(
     .await;
 
     let mut doc = db::Task::with_pk(input.uid.unwrap(), input.id.unwrap());
-    doc.get_one(&app.scylla, get_fields(input.fields)).await?;
+    let fields = ver.select_fields(get_fields(input.fields));
+    if let Err(err) = doc.get_one(&app.scylla, fields.clone()).await {
+        let err: HTTPError = err.into();
+        if err.code != 404 {
+            return Err(err);
+        }
+        // the task may have been archived.
+        doc.get_archived(&app.scylla, fields).await?;
+    }
+    if !input.raw_ref.unwrap_or(false) {
+        load_payload(&app, &mut doc).await?;
+    }
 
-    Ok(to.with(SuccessResponse::new(TaskOutput::from(doc, &to))))
+    Ok(to.with(SuccessResponse::new(TaskOutput::from(doc, &to, ver))))
 }
 
 #[derive(Debug, Deserialize, Validate)]
-pub struct CreateTaskInput {
+pub struct BatchGetTaskInput {
     pub uid: PackObject<xid::Id>,
-    pub gid: PackObject<xid::Id>,
-    pub kind: String,
-    #[validate(range(min = 0, max = 256))]
-    pub threshold: i16,
-    #[validate(length(min = 0, max = 4))]
-    pub approvers: Vec<PackObject<xid::Id>>,
-    #[validate(length(min = 0, max = 256))]
-    pub assignees: Vec<PackObject<xid::Id>>,
-    pub message: String,
-    pub payload: PackObject<Vec<u8>>,
-    #[validate(range(min = -1, max = 2))]
-    pub group_role: Option<i8>,
+    #[validate(length(min = 1, max = 100))]
+    pub ids: Vec<PackObject<xid::Id>>,
+    pub fields: Option<Vec<String>>,
 }
 
-pub async fn create(
+pub async fn batch_get(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
-    to: PackObject<CreateTaskInput>,
-) -> Result<PackObject<SuccessResponse<TaskOutput>>, HTTPError> {
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<BatchGetTaskInput>,
+) -> Result<PackObject<SuccessResponse<Vec<TaskOutput>>>, HTTPError> {
     let (to, input) = to.unpack();
     input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
 
     ctx.set_kvs(vec![
-        ("action", "create_task".into()),
+        ("action", "batch_get_task".into()),
         ("uid", input.uid.to_string().into()),
-        ("gid", input.gid.to_string().into()),
-        ("kind", input.kind.clone().into()),
+        ("ids", input.ids.len().into()),
     ])
     .await;
 
-    let mut doc = db::Task::with_pk(input.uid.unwrap(), xid::new());
-    doc.gid = input.gid.unwrap();
-    doc.status = 0i8;
-    doc.kind = input.kind;
-    doc.created_at = unix_ms() as i64;
-    doc.updated_at = doc.created_at;
-    doc.threshold = input.threshold;
-    doc.approvers = input.approvers.into_iter().map(|id| id.unwrap()).collect();
-    doc.assignees = input.assignees.into_iter().map(|id| id.unwrap()).collect();
-    doc.resolved = HashSet::new();
-    doc.rejected = HashSet::new();
-    doc.message = input.message;
-    doc.payload = input.payload.unwrap();
-
-    doc.save(&app.scylla).await?;
-
-    if let Some(role) = input.group_role {
-        let mut notif = db::GroupNotification::with_pk(doc.gid, doc.id, doc.uid);
-        notif.role = role;
-        let _ = notif.save(&app.scylla).await;
-    }
-    if !doc.approvers.is_empty() {
-        for id in &doc.approvers {
-            let mut notif = db::Notification::with_pk(*id, doc.id, doc.uid);
-            let _ = notif.save(&app.scylla).await;
-        }
-    }
-    if !doc.assignees.is_empty() {
-        for id in &doc.assignees {
-            let mut notif = db::Notification::with_pk(*id, doc.id, doc.uid);
-            let _ = notif.save(&app.scylla).await;
-        }
-    }
+    let res = db::Task::batch_get(
+        &app.scylla,
+        input.uid.unwrap(),
+        input.ids.into_iter().map(|id| id.unwrap()).collect(),
+        ver.select_fields(input.fields.unwrap_or_default()),
+    )
+    .await?;
 
-    Ok(to.with(SuccessResponse::new(TaskOutput::from(doc, &to))))
+    Ok(to.with(SuccessResponse::new(
+        res.into_iter()
+            .map(|doc| TaskOutput::from(doc, &to, ver))
+            .collect(),
+    )))
 }
 
 #[derive(Debug, Deserialize, Validate)]
-pub struct AckTaskInput {
+pub struct QueryTaskCount {
     pub uid: PackObject<xid::Id>,
-    pub tid: PackObject<xid::Id>,
-    pub sender: PackObject<xid::Id>,
-    #[validate(range(min = -1, max = 1))]
-    pub status: i8,
-    pub message: String,
+    pub status: Option<TaskStatus>,
+    pub exact: Option<bool>, // scans the partition instead of reading the counters
 }
 
-pub async fn ack(
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TaskCountOutput {
+    pub total: u64,
+}
+
+pub async fn count(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
-    to: PackObject<AckTaskInput>,
-) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
-    let (to, input) = to.unpack();
+    to: PackObject<()>,
+    Query(input): Query<QueryTaskCount>,
+) -> Result<PackObject<SuccessResponse<TaskCountOutput>>, HTTPError> {
     input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
 
-    if input.status != -1 && input.status != 1 {
-        return Err(HTTPError::new(
-            400,
-            format!("invalid status, expected -1 or 1, got {}", input.status),
-        ));
-    }
+    let exact = input.exact.unwrap_or(false);
     ctx.set_kvs(vec![
-        ("action", "ack_task".into()),
+        ("action", "count_task".into()),
         ("uid", input.uid.to_string().into()),
-        ("tid", input.tid.to_string().into()),
-        ("sender", input.sender.to_string().into()),
+        ("exact", exact.into()),
     ])
     .await;
 
-    let mut doc = db::Notification::with_pk(
-        input.uid.unwrap(),
-        input.tid.unwrap(),
-        input.sender.unwrap(),
-    );
-    doc.get_one(&app.scylla).await?;
-    if doc.status == input.status {
-        return Ok(to.with(SuccessResponse::new(false)));
-    }
-
-    let mut task = db::Task::with_pk(doc.sender, doc.tid);
-    if input.status == 1 {
-        task.update_resolved(&app.scylla, doc.uid).await?;
+    let total = if exact {
+        db::Task::count(&app.scylla, input.uid.unwrap(), input.status).await?
     } else {
-        task.update_rejected(&app.scylla, doc.uid).await?;
-    }
-    doc.status = input.status;
-    doc.message = input.message;
-    doc.update(&app.scylla).await?;
-
-    Ok(to.with(SuccessResponse::new(true)))
+        db::counters::count(&app.scylla, input.uid.unwrap(), input.status).await?
+    };
+    Ok(to.with(SuccessResponse::new(TaskCountOutput { total })))
 }
 
+// the maximum number of days aggregated in a stats request.
+const MAX_STATS_DAYS: i32 = 366;
+
 #[derive(Debug, Deserialize, Validate)]
-pub struct DeleteTaskInput {
-    pub uid: PackObject<xid::Id>,
-    pub id: Option<PackObject<xid::Id>>,
-    pub status: Option<i8>,
+pub struct QueryTaskStats {
+    pub gid: PackObject<xid::Id>,
+    pub from: i64, // unix timestamp in milliseconds, the day of it is included
+    pub to: i64,   // unix timestamp in milliseconds, the day of it is included
 }
 
-pub async fn delete(
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TaskStatsOutput {
+    pub resolved: i64,
+    pub rejected: i64,
+    pub expired: i64,
+    pub resolved_ratio: f64, // resolved / (resolved + rejected), 0 without any
+    pub rejected_ratio: f64, // rejected / (resolved + rejected), 0 without any
+    pub avg_resolution_ms: i64, // the average time to resolve or reject, approximate
+}
+
+impl TaskStatsOutput {
+    pub fn from_days(days: &[db::stats::DayStats]) -> Self {
+        let mut total = db::stats::DayStats::default();
+        for day in days {
+            total.add(day);
+        }
+
+        let mut rt = Self {
+            resolved: total.resolved,
+            rejected: total.rejected,
+            expired: total.expired,
+            ..Default::default()
+        };
+        let decided = total.resolved + total.rejected;
+        if decided > 0 {
+            rt.resolved_ratio = total.resolved as f64 / decided as f64;
+            rt.rejected_ratio = total.rejected as f64 / decided as f64;
+            rt.avg_resolution_ms = total.resolution_ms / decided;
+        }
+        rt
+    }
+}
+
+// the outcomes of the tasks of the group finalized in the days from `from` to `to`.
+pub async fn stats(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
-    to: PackObject<DeleteTaskInput>,
-) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
-    let (to, input) = to.unpack();
+    to: PackObject<()>,
+    Query(input): Query<QueryTaskStats>,
+) -> Result<PackObject<SuccessResponse<TaskStatsOutput>>, HTTPError> {
     input.validate()?;
 
-    let id = input
-        .id
-        .ok_or_else(|| HTTPError::new(400, "Missing required field `id`".to_string()))?;
-
+    let (from_day, to_day) = (db::stats::day(input.from), db::stats::day(input.to));
+    if from_day > to_day || to_day - from_day >= MAX_STATS_DAYS {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "invalid range, expected from <= to within {} days, got from {} to {}",
+                MAX_STATS_DAYS, input.from, input.to
+            ),
+        ));
+    }
     ctx.set_kvs(vec![
-        ("action", "delete_notification".into()),
-        ("uid", input.uid.to_string().into()),
-        ("id", id.to_string().into()),
+        ("action", "task_stats".into()),
+        ("gid", input.gid.to_string().into()),
+        ("days", (to_day - from_day + 1).into()),
     ])
     .await;
 
-    let mut doc = db::Task::with_pk(input.uid.unwrap(), id.unwrap());
-    if doc
-        .get_one(&app.scylla, vec!["gid".to_string()])
-        .await
-        .is_err()
-    {
-        return Ok(to.with(SuccessResponse::new(false)));
-    }
+    let days = db::stats::list(&app.scylla, input.gid.unwrap(), from_day, to_day).await?;
+    Ok(to.with(SuccessResponse::new(TaskStatsOutput::from_days(&days))))
+}
 
-    doc.delete(&app.scylla).await?;
-    let mut notify = db::GroupNotification::with_pk(doc.gid, doc.id, doc.uid);
-    let _ = notify.delete(&app.scylla).await;
-    db::Notification::batch_delete_by_tid(&app.scylla, doc.id).await?;
+#[derive(Debug, Deserialize, Validate)]
+pub struct RecountTaskInput {
+    pub uid: PackObject<xid::Id>,
+}
 
-    Ok(to.with(SuccessResponse::new(true)))
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TaskStatusCountOutput {
+    pub status: i8,
+    pub count: u64,
 }
 
-pub async fn list(
+// rewrites the task counters of the user from a scan of the partition,
+// an internal endpoint to fix drifted counters.
+pub async fn recount(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
-    to: PackObject<Pagination>,
-) -> Result<PackObject<SuccessResponse<Vec<TaskOutput>>>, HTTPError> {
+    to: PackObject<RecountTaskInput>,
+) -> Result<PackObject<SuccessResponse<Vec<TaskStatusCountOutput>>>, HTTPError> {
     let (to, input) = to.unpack();
     input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
 
-    let page_size = input.page_size.unwrap_or(10);
     ctx.set_kvs(vec![
-        ("action", "list_task".into()),
+        ("action", "recount_task".into()),
         ("uid", input.uid.to_string().into()),
-        ("page_size", page_size.into()),
     ])
     .await;
 
-    let fields = input.fields.unwrap_or_default();
-    let res = db::Task::list(
-        &app.scylla,
-        input.uid.unwrap(),
-        fields,
-        page_size,
-        token_to_xid(&input.page_token),
-        input.status,
-    )
-    .await?;
-    let next_page_token = if res.len() >= page_size as usize {
-        to.with_option(token_from_xid(res.last().unwrap().id))
-    } else {
-        None
-    };
-
-    Ok(to.with(SuccessResponse {
-        total_size: None,
-        next_page_token,
-        result: res
-            .iter()
-            .map(|r| TaskOutput::from(r.to_owned(), &to))
+    let res = db::counters::recount(&app.scylla, input.uid.unwrap()).await?;
+    Ok(to.with(SuccessResponse::new(
+        res.into_iter()
+            .map(|(status, count)| TaskStatusCountOutput {
+                status,
+                count: count as u64,
+            })
             .collect(),
-    }))
+    )))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: Option<PackObject<xid::Id>>,
+    pub gid: PackObject<xid::Id>,
+    #[serde(default)]
+    pub kind: String,
+    #[validate(range(min = 0, max = 256))]
+    pub threshold: Option<i16>, // 0: all assignees should agree, default to the template or kind threshold
+    #[serde(default)]
+    #[validate(range(min = 0, max = 4))]
+    pub approver_threshold: i16, // 0: any approver can finalize the task
+    #[serde(default)]
+    #[validate(length(min = 0, max = 4))]
+    pub approvers: Vec<PackObject<xid::Id>>,
+    #[serde(default)]
+    #[validate(length(min = 0, max = 256))]
+    pub assignees: Vec<PackObject<xid::Id>>,
+    #[serde(default)]
+    pub message: String,
+    pub payload: PackObject<Vec<u8>>,
+    #[serde(default)]
+    #[validate(length(min = 0, max = 64))]
+    pub payload_type: String, // the media type of the payload, required with a payload
+    #[validate(range(min = -1, max = 2))]
+    pub group_role: Option<i8>,
+    pub duedate: Option<i64>,
+    #[validate(range(min = 0, max = 2))]
+    pub priority: Option<i8>,
+    pub template_id: Option<PackObject<xid::Id>>,
+    pub notify_creator: Option<bool>, // default to true
+    #[validate(range(min = 1))]
+    pub notification_ttl: Option<i32>, // seconds, overrides notification_ttl_secs in conf
+    #[validate(range(min = 1))]
+    pub escalate_after_ms: Option<i64>,
+    pub allow_late_ack: Option<bool>, // accept acks after the task is finalized or overdue, default to false
+    pub veto: Option<bool>, // a reject from any approver rejects the task, default to false
+    #[serde(default)]
+    #[validate(length(min = 0, max = 256))]
+    pub sequence: Vec<PackObject<xid::Id>>, // assignees who resolve the task one by one in order
+    pub self_ack_allowed: Option<bool>, // the creator can ack the task as a participant, default to true
+    pub visibility: Option<String>,     // "private", "group" or "public", default to "group"
+}
+
+impl CreateTaskInput {
+    // the sequence members are the assignees and should all resolve the task,
+    // so it can not be combined with other participants or thresholds.
+    pub fn check_sequence(&self) -> Result<(), HTTPError> {
+        if self.sequence.is_empty() {
+            return Ok(());
+        }
+        if !self.assignees.is_empty()
+            || !self.approvers.is_empty()
+            || self.threshold.unwrap_or(0) != 0
+            || self.approver_threshold != 0
+        {
+            return Err(HTTPError::new(
+                400,
+                "sequence can not be combined with assignees, approvers or thresholds".to_string(),
+            ));
+        }
+
+        let members: HashSet<xid::Id> = self.sequence.iter().map(|id| **id).collect();
+        if members.len() != self.sequence.len() {
+            return Err(HTTPError::new(
+                400,
+                "sequence has duplicate members".to_string(),
+            ));
+        }
+        if members.contains(&*self.uid) {
+            return Err(HTTPError::new(
+                400,
+                "sequence can not contain the task creator".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // a private task is not exposed to the group, so it can not take a group role.
+    pub fn check_visibility(&self) -> Result<(), HTTPError> {
+        let visibility = match &self.visibility {
+            Some(visibility) => visibility.as_str(),
+            None => return Ok(()),
+        };
+        if !db::Task::VISIBILITIES.contains(&visibility) {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "invalid visibility {:?}, expected one of {:?}",
+                    visibility,
+                    db::Task::VISIBILITIES
+                ),
+            ));
+        }
+        if visibility == "private" && self.group_role.is_some() {
+            return Err(HTTPError::new(
+                400,
+                "a private task can not have a group_role".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // a creator who can not ack the task should not be one of its participants.
+    pub fn check_self_ack(&self) -> Result<(), HTTPError> {
+        if self.self_ack_allowed.unwrap_or(true) {
+            return Ok(());
+        }
+        if self.assignees.iter().any(|id| **id == *self.uid)
+            || self.approvers.iter().any(|id| **id == *self.uid)
+        {
+            return Err(HTTPError::new(
+                400,
+                "the task creator can not be a participant when self_ack_allowed is false"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // checks the kind against the registry and applies the kind defaults to the fields
+    // not provided, an empty registry accepts any kind.
+    pub fn apply_kind(&mut self, kinds: &conf::Kinds, now: i64) -> Result<(), HTTPError> {
+        if kinds.registry.is_empty() {
+            return Ok(());
+        }
+        let kind = match kinds.registry.get(&self.kind) {
+            Some(kind) if kind.allowed => kind,
+            Some(_) => {
+                return Err(HTTPError::new(
+                    400,
+                    format!("kind {:?} is not allowed", self.kind),
+                ))
+            }
+            None if kinds.strict => {
+                return Err(HTTPError::new(400, format!("unknown kind {:?}", self.kind)))
+            }
+            None => return Ok(()),
+        };
+
+        if kind.max_payload > 0 && self.payload.len() > kind.max_payload {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "payload too large for kind {:?}, expected at most {} bytes, got {}",
+                    self.kind,
+                    kind.max_payload,
+                    self.payload.len()
+                ),
+            ));
+        }
+        // a sequence can not be combined with thresholds.
+        if self.threshold.is_none() && self.sequence.is_empty() {
+            self.threshold = Some(kind.default_threshold);
+        }
+        if self.duedate.is_none() && kind.default_duedate_offset_ms > 0 {
+            self.duedate = Some(now + kind.default_duedate_offset_ms);
+        }
+        Ok(())
+    }
+
+    // pre-fills the fields not provided in the request, empty values are treated as not provided
+    // except for the threshold, where 0 is a value.
+    pub fn merge_template(&mut self, tpl: &db::TaskTemplate) {
+        if self.kind.is_empty() {
+            self.kind = tpl.kind.clone();
+        }
+        if self.threshold.is_none() {
+            self.threshold = Some(tpl.threshold);
+        }
+        if self.approvers.is_empty() {
+            self.approvers = tpl.approvers.iter().map(|id| self.gid.with(*id)).collect();
+        }
+        if self.assignees.is_empty() {
+            self.assignees = tpl.assignees.iter().map(|id| self.gid.with(*id)).collect();
+        }
+        if self.message.is_empty() {
+            self.message = tpl.message.clone();
+        }
+    }
+}
+
+// a create input of the assignees with the other fields empty.
+#[cfg(test)]
+pub fn test_create_input(uid: xid::Id, gid: xid::Id, assignees: &[xid::Id]) -> CreateTaskInput {
+    CreateTaskInput {
+        uid: PackObject::Cbor(uid),
+        id: None,
+        gid: PackObject::Cbor(gid),
+        kind: String::new(),
+        threshold: None,
+        approver_threshold: 0,
+        approvers: Vec::new(),
+        assignees: assignees.iter().map(|id| PackObject::Cbor(*id)).collect(),
+        message: String::new(),
+        payload: PackObject::Cbor(Vec::new()),
+        payload_type: String::new(),
+        group_role: None,
+        duedate: None,
+        priority: None,
+        template_id: None,
+        notify_creator: None,
+        notification_ttl: None,
+        escalate_after_ms: None,
+        allow_late_ack: None,
+        veto: None,
+        sequence: Vec::new(),
+        self_ack_allowed: None,
+        visibility: None,
+    }
+}
+
+// applies the template of the input if any, then validates the merged input.
+async fn prepare_task_input(
+    app: &AppState,
+    mut input: CreateTaskInput,
+) -> Result<CreateTaskInput, HTTPError> {
+    if let Some(template_id) = input.template_id.take() {
+        let mut tpl = db::TaskTemplate::with_pk(*input.gid, template_id.unwrap());
+        tpl.get_one(&app.scylla).await?;
+        input.merge_template(&tpl);
+    }
+    input.apply_kind(&app.kinds, unix_ms() as i64)?;
+    input.validate()?;
+    input.check_sequence()?;
+    input.check_visibility()?;
+    input.check_self_ack()?;
+    check_content(
+        &input.message,
+        &input.payload,
+        app.max_message_len,
+        app.max_payload_bytes,
+    )?;
+    check_payload_type(&input.payload_type, &input.payload)?;
+    let approvers: HashSet<xid::Id> = input.approvers.iter().map(|id| **id).collect();
+    let assignees: HashSet<xid::Id> = input.assignees.iter().map(|id| **id).collect();
+    db::Task::check_threshold(
+        input.threshold.unwrap_or(0),
+        db::Task::reachable_votes(input.approver_threshold, &approvers, &assignees),
+    )?;
+    db::Task::check_approver_threshold(input.approver_threshold, input.approvers.len())?;
+    Ok(input)
+}
+
+// payloads and messages are stored in single cells, oversize ones slow down every read of the task.
+pub fn check_content(
+    message: &str,
+    payload: &[u8],
+    max_message_len: usize,
+    max_payload_bytes: usize,
+) -> Result<(), HTTPError> {
+    if payload.len() > max_payload_bytes {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "payload too large, expected at most {} bytes, got {}",
+                max_payload_bytes,
+                payload.len()
+            ),
+        ));
+    }
+    if message.len() > max_message_len {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "message too long, expected at most {} bytes, got {}",
+                max_message_len,
+                message.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// consumers can not decode an opaque payload without its media type, e.g. "application/cbor".
+pub fn check_payload_type(payload_type: &str, payload: &[u8]) -> Result<(), HTTPError> {
+    if !payload.is_empty() && payload_type.is_empty() {
+        return Err(HTTPError::new(
+            400,
+            "payload_type is required with a payload".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// duedate should be 0 (no duedate) or a future unix timestamp in milliseconds.
+pub fn check_duedate(duedate: i64, now: i64) -> Result<(), HTTPError> {
+    if duedate != 0 && duedate <= now {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "invalid duedate, expected 0 or a timestamp after {}, got {}",
+                now, duedate
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// the reason of an ack should be one of the reason codes of the task kind, empty for none.
+pub fn check_reason(kinds: &conf::Kinds, kind: &str, reason: &str) -> Result<(), HTTPError> {
+    if reason.is_empty() {
+        return Ok(());
+    }
+    let reasons = kinds.reasons(kind);
+    if !reasons.iter().any(|r| r == reason) {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "invalid reason {:?} for kind {:?}, expected one of {:?}",
+                reason, kind, reasons
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub async fn create(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<CreateTaskInput>,
+) -> Result<PackObject<SuccessResponse<TaskOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+    let input = prepare_task_input(&app, input).await?;
+
+    ctx.set_kvs(vec![
+        ("action", "create_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("gid", input.gid.to_string().into()),
+        ("kind", input.kind.clone().into()),
+    ])
+    .await;
+
+    let (doc, fanout_failed) = create_task(&app, input, ctx.user).await?;
+    ctx.set("fanout_failed", fanout_failed.len().into()).await;
+    let mut output = TaskOutput::from(doc, &to, ver);
+    output.fanout_failed = Some(fanout_failed.into_iter().map(|id| to.with(id)).collect());
+    Ok(to.with(SuccessResponse::new(output)))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TaskKindOutput {
+    pub name: String,
+    pub max_payload: usize,
+    pub default_threshold: i16,
+    pub default_duedate_offset_ms: i64,
+    pub allowed: bool,
+    pub reasons: Vec<String>, // the reason codes of the acks
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TaskKindsOutput {
+    pub strict: bool,
+    pub reasons: Vec<String>, // the reason codes of the acks of the kinds not registered
+    pub kinds: Vec<TaskKindOutput>, // sorted by name
+}
+
+// the kind registry, clients populate the kind choices with it.
+pub async fn kinds(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+) -> Result<PackObject<SuccessResponse<TaskKindsOutput>>, HTTPError> {
+    ctx.set("action", "list_task_kinds".into()).await;
+
+    let mut kinds: Vec<TaskKindOutput> = app
+        .kinds
+        .registry
+        .iter()
+        .map(|(name, kind)| TaskKindOutput {
+            name: name.clone(),
+            max_payload: kind.max_payload,
+            default_threshold: kind.default_threshold,
+            default_duedate_offset_ms: kind.default_duedate_offset_ms,
+            allowed: kind.allowed,
+            reasons: app.kinds.reasons(name).to_vec(),
+        })
+        .collect();
+    kinds.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(to.with(SuccessResponse::new(TaskKindsOutput {
+        strict: app.kinds.strict,
+        reasons: app.kinds.reasons.clone(),
+        kinds,
+    })))
+}
+
+// the maximum size of all payloads and messages in a batch_create request.
+pub const MAX_BATCH_CREATE_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BatchCreateTaskInput {
+    #[validate(length(min = 1, max = 100))]
+    pub tasks: Vec<CreateTaskInput>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BatchCreateTaskOutput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task: Option<TaskOutput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<HTTPError>,
+}
+
+pub async fn batch_create(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<BatchCreateTaskInput>,
+) -> Result<PackObject<SuccessResponse<Vec<BatchCreateTaskOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let size: usize = input
+        .tasks
+        .iter()
+        .map(|t| t.payload.len() + t.message.len())
+        .sum();
+    if size > MAX_BATCH_CREATE_SIZE {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "batch too large, expected at most {} bytes, got {}",
+                MAX_BATCH_CREATE_SIZE, size
+            ),
+        ));
+    }
+
+    ctx.set_kvs(vec![
+        ("action", "batch_create_task".into()),
+        ("size", input.tasks.len().into()),
+    ])
+    .await;
+
+    let mut output: Vec<BatchCreateTaskOutput> = Vec::with_capacity(input.tasks.len());
+    let mut failed: usize = 0;
+    for item in input.tasks {
+        let res = match check_uid(app.pin_uid, &ctx, *item.uid) {
+            Ok(_) => match prepare_task_input(&app, item).await {
+                Ok(item) => create_task(&app, item, ctx.user).await,
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok((doc, fanout_failed)) => {
+                let mut task = TaskOutput::from(doc, &to, ver);
+                task.fanout_failed =
+                    Some(fanout_failed.into_iter().map(|id| to.with(id)).collect());
+                output.push(BatchCreateTaskOutput {
+                    task: Some(task),
+                    error: None,
+                })
+            }
+            Err(err) => {
+                failed += 1;
+                output.push(BatchCreateTaskOutput {
+                    task: None,
+                    error: Some(err),
+                })
+            }
+        }
+    }
+
+    ctx.set("failed", failed.into()).await;
+    Ok(to.with(SuccessResponse::new(output)))
+}
+
+// creates a validated task and fans out its notifications,
+// returns the task and the receivers failed to be notified, they are retried by the worker.
+pub(crate) async fn create_task(
+    app: &AppState,
+    input: CreateTaskInput,
+    actor: xid::Id,
+) -> Result<(db::Task, Vec<xid::Id>), HTTPError> {
+    let now = unix_ms() as i64;
+    let duedate = input.duedate.unwrap_or_default();
+    check_duedate(duedate, now)?;
+
+    let id = input.id.map(|id| id.unwrap());
+    let mut doc = db::Task::with_pk(input.uid.unwrap(), id.unwrap_or_else(xid::new));
+    doc.gid = input.gid.unwrap();
+    doc.status = TaskStatus::Pending;
+    doc.kind = input.kind;
+    doc.created_at = now;
+    doc.updated_at = doc.created_at;
+    doc.duedate = duedate;
+    doc.threshold = input.threshold.unwrap_or(0);
+    doc.approver_threshold = input.approver_threshold;
+    doc.approvers = input.approvers.into_iter().map(|id| id.unwrap()).collect();
+    doc.assignees = input.assignees.into_iter().map(|id| id.unwrap()).collect();
+    doc.resolved = HashSet::new();
+    doc.rejected = HashSet::new();
+    doc.message = input.message;
+    doc.payload = input.payload.unwrap();
+    doc.payload_type = input.payload_type;
+    doc.priority = input.priority.unwrap_or_default();
+    doc.notify_creator = input.notify_creator.unwrap_or(true);
+    doc.escalate_after_ms = input.escalate_after_ms.unwrap_or_default();
+    doc.allow_late_ack = input.allow_late_ack.unwrap_or(false);
+    doc.veto = input.veto.unwrap_or(false);
+    doc.self_ack_allowed = input.self_ack_allowed.unwrap_or(true);
+    doc.visibility = input.visibility.unwrap_or_else(|| "group".to_string());
+    doc.sequence = input.sequence.into_iter().map(|id| id.unwrap()).collect();
+    if !doc.sequence.is_empty() {
+        doc.assignees = doc.sequence.iter().cloned().collect();
+    }
+
+    let blob = store_payload(app, &mut doc).await?;
+    let saved = if id.is_none() {
+        doc.save(&app.scylla).await
+    } else {
+        doc.save_idempotent(&app.scylla).await
+    };
+    match saved {
+        Ok(true) => {}
+        Ok(false) => {
+            // replayed request, notifications were fanned out by the first one.
+            discard_blob(app, blob).await;
+            return Ok((doc, Vec::new()));
+        }
+        Err(err) => {
+            // the row is not written on conflicts, other errors may follow the insert.
+            let err: HTTPError = err.into();
+            if err.code == 409 {
+                discard_blob(app, blob).await;
+            }
+            return Err(err);
+        }
+    }
+
+    // private tasks never reach the group.
+    if let Some(role) = input.group_role.filter(|_| !doc.is_private()) {
+        let mut notif = db::GroupNotification::with_pk(doc.gid, doc.id, doc.uid);
+        notif.role = role;
+        let _ = notif.save(&app.scylla).await;
+    }
+    let notification_ttl = input.notification_ttl.unwrap_or(app.notification_ttl);
+    let fanout_failed = fanout(app, &doc, notification_ttl).await;
+
+    record_log(app, &doc, actor, db::TaskAction::Created, doc.kind.clone()).await;
+    Ok((doc, fanout_failed))
+}
+
+// moves an oversized payload to the blob store, the row keeps the reference to it.
+pub(crate) async fn store_payload(
+    app: &AppState,
+    doc: &mut db::Task,
+) -> Result<Option<BlobRef>, HTTPError> {
+    let blobs = match &app.blobs {
+        Some(blobs) if !doc.payload_external && blobs.is_oversized(&doc.payload) => blobs,
+        _ => return Ok(None),
+    };
+    let prefix = format!("tasks/{}/{}", doc.uid, doc.id);
+    // the blob is sealed like the rows, see `db::Task::sealed_cols`.
+    let blob = match app.scylla.keyring() {
+        Some(keyring) => blobs.put(&prefix, &keyring.seal(&doc.payload)?).await?,
+        None => blobs.put(&prefix, &doc.payload).await?,
+    };
+    doc.payload_len = doc.payload.len() as i32;
+    doc.payload = blob.to_string().into_bytes();
+    doc.payload_external = true;
+    Ok(Some(blob))
+}
+
+// replaces the reference of an external payload with the bytes of the blob.
+pub(crate) async fn load_payload(app: &AppState, doc: &mut db::Task) -> Result<(), HTTPError> {
+    if !doc.payload_external {
+        return Ok(());
+    }
+    let blobs = app
+        .blobs
+        .as_ref()
+        .ok_or_else(|| HTTPError::new(500, "blob store is not configured".to_string()))?;
+    let blob = BlobRef::from_payload(&doc.payload)?;
+    let data = blobs.get(&blob).await?;
+    doc.payload = match app.scylla.keyring() {
+        Some(keyring) => keyring.open(&data)?,
+        None if crypto::is_sealed(&data) => {
+            return Err(HTTPError::new(
+                500,
+                "blob is encrypted, but the encryption is not configured".to_string(),
+            ))
+        }
+        None => data,
+    };
+    doc.payload_external = false;
+    Ok(())
+}
+
+// deletes a blob no row refers to, failures are logged and leave an orphan blob.
+pub(crate) async fn discard_blob(app: &AppState, blob: Option<BlobRef>) {
+    if let (Some(blobs), Some(blob)) = (&app.blobs, blob) {
+        if let Err(err) = blobs.delete(&blob).await {
+            log::warn!(target: "blobs", "{}: delete blob failed: {}", blob, err);
+        }
+    }
+}
+
+// the maximum number of notifications inserted in one batch by the fanout.
+const FANOUT_BATCH_SIZE: usize = 50;
+
+// the receivers of the task notifications. An id can be both an approver and an assignee,
+// it gets one notification. The creator is skipped, its own notification row is kept
+// for the task resolved or rejected notification. Only the first member of a sequence
+// is notified, the next ones are notified as the sequence resolves.
+fn receivers(task: &db::Task) -> Vec<xid::Id> {
+    if let Some(first) = task.sequence.first() {
+        return vec![*first];
+    }
+    let mut receivers: Vec<xid::Id> = task
+        .approvers
+        .union(&task.assignees)
+        .filter(|id| **id != task.uid)
+        .cloned()
+        .collect();
+    receivers.sort();
+    receivers
+}
+
+// fans out the notifications of a new task to its approvers and assignees in batches,
+// muted receivers are skipped, a failed batch is retried once, then its notifications
+// are recorded for the worker to retry. Returns the receivers that failed.
+pub(crate) async fn fanout(app: &AppState, task: &db::Task, ttl: i32) -> Vec<xid::Id> {
+    let receivers = receivers(task);
+    let mut docs: Vec<db::Notification> = Vec::with_capacity(receivers.len());
+    for uid in receivers {
+        if let Ok(true) = db::NotificationMute::is_muted(&app.scylla, uid, task.uid).await {
+            continue;
+        }
+        let mut notif = db::Notification::with_pk(uid, task.id, task.uid);
+        notif.gid = task.gid;
+        notif._ttl = ttl;
+        docs.push(notif);
+    }
+
+    let mut failed: Vec<&[db::Notification]> = Vec::new();
+    for chunk in docs.chunks(FANOUT_BATCH_SIZE) {
+        if db::Notification::batch_save(&app.scylla, chunk)
+            .await
+            .is_err()
+        {
+            failed.push(chunk);
+        }
+    }
+
+    let mut res: Vec<xid::Id> = Vec::new();
+    for chunk in failed {
+        if let Err(err) = db::Notification::batch_save(&app.scylla, chunk).await {
+            for doc in chunk {
+                log::warn!(target: "fanout", "{}: notify {} failed: {}", task.id, doc.uid, err);
+                let mut retry = db::NotificationRetry::from_notification(doc);
+                if let Err(err) = retry.save(&app.scylla).await {
+                    log::error!(target: "fanout", "{}: record retry of {} failed: {}", task.id, doc.uid, err);
+                }
+                res.push(doc.uid);
+            }
+        }
+    }
+    res
+}
+
+// fans out the task notification to the receiver unless the receiver is the task creator
+// or muted the task creator, failures are ignored.
+async fn notify(app: &AppState, uid: xid::Id, task: &db::Task, ttl: i32) {
+    if uid == task.uid {
+        return;
+    }
+    if let Ok(true) = db::NotificationMute::is_muted(&app.scylla, uid, task.uid).await {
+        return;
+    }
+    let mut notif = db::Notification::with_pk(uid, task.id, task.uid);
+    notif.gid = task.gid;
+    notif._ttl = ttl;
+    let _ = notif.save(&app.scylla).await;
+}
+
+// notifies the watchers of the status change of the task, failures are logged and ignored.
+pub(crate) async fn notify_watchers(db: &db::scylladb::ScyllaDB, task: &db::Task, ttl: i32) {
+    let docs = task.watcher_notifications(ttl);
+    for chunk in docs.chunks(FANOUT_BATCH_SIZE) {
+        if let Err(err) = db::Notification::batch_save(db, chunk).await {
+            log::warn!(target: "task", "{}: notify watchers failed: {}", task.id, err);
+        }
+    }
+}
+
+// records the task activity log, failures are logged and ignored.
+async fn record_log(
+    app: &AppState,
+    task: &db::Task,
+    actor: xid::Id,
+    action: db::TaskAction,
+    detail: String,
+) {
+    let mut doc = db::TaskLog::new(task.uid, task.id, actor, action);
+    doc.detail = detail;
+    if let Err(err) = doc.save(&app.scylla).await {
+        let msg = format!("record {} log failed: {}", action.as_str(), err);
+        log::warn!(target: "task_log", "{}: {}", task.id, msg);
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub updated_at: i64,
+    pub duedate: Option<i64>,
+    pub message: Option<String>,
+    #[validate(range(min = 1, max = 256))]
+    pub threshold: Option<i16>,
+    #[validate(range(min = 0, max = 2))]
+    pub priority: Option<i8>,
+}
+
+impl UpdateTaskInput {
+    fn into(self) -> anyhow::Result<ColumnsMap> {
+        let mut cols = ColumnsMap::new();
+        if let Some(duedate) = self.duedate {
+            check_duedate(duedate, unix_ms() as i64)?;
+            cols.set_as("duedate", &duedate);
+        }
+        if let Some(message) = self.message {
+            cols.set_as("message", &message);
+        }
+        if let Some(threshold) = self.threshold {
+            cols.set_as("threshold", &threshold);
+        }
+        if let Some(priority) = self.priority {
+            cols.set_as("priority", &priority);
+        }
+
+        if cols.is_empty() {
+            return Err(HTTPError::new(400, "No fields to update".to_string()).into());
+        }
+
+        Ok(cols)
+    }
+}
+
+pub async fn update(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<UpdateTaskInput>,
+) -> Result<PackObject<SuccessResponse<TaskOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    if let Some(message) = &input.message {
+        check_content(message, &[], app.max_message_len, app.max_payload_bytes)?;
+    }
+
+    let uid = *input.uid;
+    let id = *input.id;
+    let updated_at = input.updated_at;
+    let cols = input.into()?;
+    let detail = cols.keys().join(",");
+    ctx.set_kvs(vec![
+        ("action", "update_task".into()),
+        ("uid", uid.to_string().into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Task::with_pk(uid, id);
+    doc.update(&app.scylla, cols, updated_at).await?;
+    record_log(&app, &doc, ctx.user, db::TaskAction::Updated, detail).await;
+    doc._fields = vec!["updated_at".to_string()];
+    Ok(to.with(SuccessResponse::new(TaskOutput::from(doc, &to, ver))))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateAssigneesInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub updated_at: i64,
+    #[validate(length(min = 0, max = 256))]
+    pub add: Vec<PackObject<xid::Id>>,
+    #[validate(length(min = 0, max = 256))]
+    pub remove: Vec<PackObject<xid::Id>>,
+}
+
+// returns the task with the new updated_at, it is unchanged if there is nothing to update.
+pub async fn update_assignees(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<UpdateAssigneesInput>,
+) -> Result<PackObject<SuccessResponse<TaskOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    let add: HashSet<xid::Id> = input.add.into_iter().map(|id| id.unwrap()).collect();
+    let remove: HashSet<xid::Id> = input.remove.into_iter().map(|id| id.unwrap()).collect();
+    if add.is_empty() && remove.is_empty() {
+        return Err(HTTPError::new(400, "No assignees to update".to_string()));
+    }
+    if let Some(id) = add.intersection(&remove).next() {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "assignee {} can not be added and removed at the same time",
+                id
+            ),
+        ));
+    }
+
+    ctx.set_kvs(vec![
+        ("action", "update_task_assignees".into()),
+        ("uid", input.uid.to_string().into()),
+        ("id", input.id.to_string().into()),
+        ("add", add.len().into()),
+        ("remove", remove.len().into()),
+    ])
+    .await;
+
+    let mut doc = db::Task::with_pk(input.uid.unwrap(), input.id.unwrap());
+    doc.get_one(
+        &app.scylla,
+        vec!["assignees".to_string(), "updated_at".to_string()],
+    )
+    .await?;
+
+    let remove: Vec<xid::Id> = remove
+        .into_iter()
+        .filter(|id| doc.assignees.contains(id))
+        .collect();
+    let add: Vec<xid::Id> = add
+        .into_iter()
+        .filter(|id| !doc.assignees.contains(id))
+        .collect();
+    let total = doc.assignees.len() + add.len() - remove.len();
+    if total > 256 {
+        return Err(HTTPError::new(
+            400,
+            format!("too many assignees, expected at most 256, got {}", total),
+        ));
+    }
+    if add.is_empty() && remove.is_empty() {
+        doc._fields = vec!["updated_at".to_string()];
+        return Ok(to.with(SuccessResponse::new(TaskOutput::from(doc, &to, ver))));
+    }
+
+    doc.update_assignees(&app.scylla, remove.clone(), add.clone(), input.updated_at)
+        .await?;
+
+    for id in remove {
+        // a removed assignee who is an approver keeps the notification.
+        if !doc.approvers.contains(&id) {
+            let mut notif = db::Notification::with_pk(id, doc.id, doc.uid);
+            let _ = notif.delete(&app.scylla).await;
+        }
+        record_log(
+            &app,
+            &doc,
+            ctx.user,
+            db::TaskAction::AssigneeRemoved,
+            id.to_string(),
+        )
+        .await;
+    }
+    for id in add {
+        notify(&app, id, &doc, app.notification_ttl).await;
+        record_log(
+            &app,
+            &doc,
+            ctx.user,
+            db::TaskAction::AssigneeAdded,
+            id.to_string(),
+        )
+        .await;
+    }
+
+    doc._fields = vec!["updated_at".to_string()];
+    Ok(to.with(SuccessResponse::new(TaskOutput::from(doc, &to, ver))))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateApproversInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub updated_at: i64,
+    #[validate(length(min = 0, max = 4))]
+    pub add: Vec<PackObject<xid::Id>>,
+    #[validate(length(min = 0, max = 4))]
+    pub remove: Vec<PackObject<xid::Id>>,
+}
+
+pub async fn update_approvers(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<UpdateApproversInput>,
+) -> Result<PackObject<SuccessResponse<TaskOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    let add: HashSet<xid::Id> = input.add.into_iter().map(|id| id.unwrap()).collect();
+    let remove: HashSet<xid::Id> = input.remove.into_iter().map(|id| id.unwrap()).collect();
+    if add.is_empty() && remove.is_empty() {
+        return Err(HTTPError::new(400, "No approvers to update".to_string()));
+    }
+    if let Some(id) = add.intersection(&remove).next() {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "approver {} can not be added and removed at the same time",
+                id
+            ),
+        ));
+    }
+
+    ctx.set_kvs(vec![
+        ("action", "update_task_approvers".into()),
+        ("uid", input.uid.to_string().into()),
+        ("id", input.id.to_string().into()),
+        ("add", add.len().into()),
+        ("remove", remove.len().into()),
+    ])
+    .await;
+
+    let mut doc = db::Task::with_pk(input.uid.unwrap(), input.id.unwrap());
+    doc.get_one(
+        &app.scylla,
+        vec!["approvers".to_string(), "updated_at".to_string()],
+    )
+    .await?;
+
+    let remove: Vec<xid::Id> = remove
+        .into_iter()
+        .filter(|id| doc.approvers.contains(id))
+        .collect();
+    let add: Vec<xid::Id> = add
+        .into_iter()
+        .filter(|id| !doc.approvers.contains(id))
+        .collect();
+    let total = doc.approvers.len() + add.len() - remove.len();
+    if total > 4 {
+        return Err(HTTPError::new(
+            400,
+            format!("too many approvers, expected at most 4, got {}", total),
+        ));
+    }
+    if add.is_empty() && remove.is_empty() {
+        doc._fields = vec!["updated_at".to_string()];
+        return Ok(to.with(SuccessResponse::new(TaskOutput::from(doc, &to, ver))));
+    }
+
+    doc.update_approvers(&app.scylla, remove.clone(), add.clone(), input.updated_at)
+        .await?;
+
+    for id in remove {
+        // a removed approver who is an assignee keeps the notification.
+        if !doc.assignees.contains(&id) {
+            let mut notif = db::Notification::with_pk(id, doc.id, doc.uid);
+            let _ = notif.delete(&app.scylla).await;
+        }
+    }
+    for id in add {
+        notify(&app, id, &doc, app.notification_ttl).await;
+    }
+
+    doc._fields = vec!["updated_at".to_string()];
+    Ok(to.with(SuccessResponse::new(TaskOutput::from(doc, &to, ver))))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DelegateTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub from: PackObject<xid::Id>, // the assignee handing over the assignment
+    pub to: PackObject<xid::Id>,
+}
+
+// hands the assignment of the caller over to another user, the vote of the caller is not
+// transferred. Returns the task with the new updated_at.
+pub async fn delegate(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<DelegateTaskInput>,
+) -> Result<PackObject<SuccessResponse<TaskOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.from)?;
+    if *input.from == *input.to {
+        return Err(HTTPError::new(
+            400,
+            "can not delegate the task to the assignee itself".to_string(),
+        ));
+    }
+
+    ctx.set_kvs(vec![
+        ("action", "delegate_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("id", input.id.to_string().into()),
+        ("from", input.from.to_string().into()),
+        ("to", input.to.to_string().into()),
+    ])
+    .await;
+
+    let (from, assignee) = (input.from.unwrap(), input.to.unwrap());
+    let mut doc = db::Task::with_pk(input.uid.unwrap(), input.id.unwrap());
+    doc.delegate(&app.scylla, from, assignee).await?;
+
+    // an approver keeps the notification to ack as an approver.
+    if !doc.approvers.contains(&from) {
+        let mut notif = db::Notification::with_pk(from, doc.id, doc.uid);
+        let _ = notif.delete(&app.scylla).await;
+    }
+    let mut notif = db::Notification::with_pk(assignee, doc.id, doc.uid);
+    notif.gid = doc.gid;
+    notif.message = format!("delegated by {}", from);
+    notif._ttl = app.notification_ttl;
+    let _ = notif.save(&app.scylla).await;
+    record_log(
+        &app,
+        &doc,
+        ctx.user,
+        db::TaskAction::Delegated,
+        format!("{} to {}", from, assignee),
+    )
+    .await;
+
+    doc._fields = vec!["updated_at".to_string()];
+    Ok(to.with(SuccessResponse::new(TaskOutput::from(doc, &to, ver))))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AckTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub tid: PackObject<xid::Id>,
+    pub sender: PackObject<xid::Id>,
+    pub status: NotificationStatus,
+    pub message: String,
+    pub reason: Option<String>, // one of the reason codes of the task kind
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AckTaskOutput {
+    pub result: bool, // false if the notification has been acked with the status already
+    pub status: TaskStatus, // the task status after the ack
+    pub vetoed: bool, // the task was rejected by a single reject of a veto approver or a sequence member
+}
+
+pub async fn ack(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<AckTaskInput>,
+) -> Result<PackObject<SuccessResponse<AckTaskOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    if input.status == NotificationStatus::Pending {
+        return Err(HTTPError::new(
+            400,
+            format!("invalid status, expected -1 or 1, got {}", input.status),
+        ));
+    }
+    check_content(
+        &input.message,
+        &[],
+        app.max_message_len,
+        app.max_payload_bytes,
+    )?;
+    ctx.set_kvs(vec![
+        ("action", "ack_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+        ("sender", input.sender.to_string().into()),
+    ])
+    .await;
+
+    let res = ack_notification(
+        &app,
+        input.uid.unwrap(),
+        input.tid.unwrap(),
+        input.sender.unwrap(),
+        input.status,
+        input.message,
+        input.reason.unwrap_or_default(),
+    )
+    .await?;
+    Ok(to.with(SuccessResponse::new(res)))
+}
+
+// acks the notification and votes on the task, the result is false
+// if the notification has been acked with the status already.
+async fn ack_notification(
+    app: &AppState,
+    uid: xid::Id,
+    tid: xid::Id,
+    sender: xid::Id,
+    status: NotificationStatus,
+    message: String,
+    reason: String,
+) -> Result<AckTaskOutput, HTTPError> {
+    let mut doc = db::Notification::with_pk(uid, tid, sender);
+    doc.get_one(&app.scylla).await?;
+    let mut task = db::Task::with_pk(doc.sender, doc.tid);
+    if doc.status == status {
+        task.get_one(&app.scylla, vec!["status".to_string()])
+            .await?;
+        check_reason(&app.kinds, &task.kind, &reason)?;
+        return Ok(AckTaskOutput {
+            result: false,
+            status: task.status,
+            vetoed: false,
+        });
+    }
+
+    task.get_one(
+        &app.scylla,
+        vec![
+            "status".to_string(),
+            "notify_creator".to_string(),
+            "created_at".to_string(),
+            "updated_at".to_string(),
+            "duedate".to_string(),
+            "allow_late_ack".to_string(),
+            "gid".to_string(),
+            "watchers".to_string(),
+        ],
+    )
+    .await?;
+    check_reason(&app.kinds, &task.kind, &reason)?;
+    if let Err(err) = check_ackable(&task, unix_ms() as i64) {
+        if task.status == TaskStatus::Pending {
+            // past its duedate but not expired by the worker yet.
+            expire_overdue(app, &mut task).await;
+        }
+        return Err(err);
+    }
+    let prev_status = task.status;
+    let notify_creator = task.notify_creator;
+    let action = if status == NotificationStatus::Resolved {
+        task.update_resolved(&app.scylla, doc.uid, &reason).await?;
+        db::TaskAction::Resolved
+    } else {
+        task.update_rejected(&app.scylla, doc.uid, &reason).await?;
+        db::TaskAction::Rejected
+    };
+    doc.status = status;
+    doc.message = message;
+    doc.reason = reason;
+    if doc.read_at == 0 {
+        doc.read_at = unix_ms() as i64;
+    }
+    doc.update(&app.scylla).await?;
+
+    if status == NotificationStatus::Resolved && task.status == TaskStatus::Pending {
+        if let Some(next) = task.next_in_sequence() {
+            notify(app, next, &task, app.notification_ttl).await;
+        }
+    }
+    let vetoed = task.status == TaskStatus::Rejected
+        && prev_status != TaskStatus::Rejected
+        && task.vetoed_by(&doc.uid);
+    record_log(app, &task, doc.uid, action, doc.message.clone()).await;
+    if task.status != prev_status {
+        record_log(
+            app,
+            &task,
+            doc.uid,
+            db::TaskAction::StatusChanged,
+            task.status.to_string(),
+        )
+        .await;
+
+        if notify_creator && task.status.is_final() {
+            // the sender is the creator itself so that the notification can be hydrated
+            // with the task like any other one.
+            let mut notif = db::Notification::with_pk(task.uid, task.id, task.uid);
+            notif.gid = task.gid;
+            notif._ttl = app.notification_ttl;
+            if task.status == TaskStatus::Resolved {
+                notif.status = NotificationStatus::Resolved;
+                notif.message = "task resolved".to_string();
+            } else {
+                notif.status = NotificationStatus::Rejected;
+                notif.message = "task rejected".to_string();
+            }
+            let _ = notif.save(&app.scylla).await;
+        }
+        if task.status.is_final() {
+            notify_watchers(&app.scylla, &task, app.notification_ttl).await;
+            app.webhooks.task_done(&task);
+        }
+    }
+
+    Ok(AckTaskOutput {
+        result: true,
+        status: task.status,
+        vetoed,
+    })
+}
+
+// votes are frozen once the task is finalized or past its duedate, unless the task allows
+// late acks, then a resolved, rejected or expired task still takes votes and its status
+// follows them.
+fn check_ackable(task: &db::Task, now_ms: i64) -> Result<(), HTTPError> {
+    match task.status {
+        TaskStatus::Pending
+            if task.duedate > 0 && now_ms > task.duedate && !task.allow_late_ack =>
+        {
+            Err(HTTPError::new(
+                409,
+                format!("Task expired, duedate {}", task.duedate),
+            ))
+        }
+        TaskStatus::Pending => Ok(()),
+        TaskStatus::Resolved | TaskStatus::Rejected | TaskStatus::Expired
+            if task.allow_late_ack =>
+        {
+            Ok(())
+        }
+        status => Err(HTTPError::new(
+            409,
+            format!(
+                "Task is finalized with status {}, ack is not accepted",
+                status
+            ),
+        )),
+    }
+}
+
+// votes can not be withdrawn once they are frozen, see `check_ackable`. Returns false when
+// the receiver never acked, there is nothing to withdraw then.
+fn check_unackable(
+    task: &db::Task,
+    status: NotificationStatus,
+    now_ms: i64,
+) -> Result<bool, HTTPError> {
+    match check_ackable(task, now_ms) {
+        Ok(()) => Ok(true),
+        Err(_) if status == NotificationStatus::Pending => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// flips the overdue task to expired like the worker does, failures are logged and ignored.
+async fn expire_overdue(app: &AppState, task: &mut db::Task) {
+    match task.expire(&app.scylla).await {
+        Ok(true) => {
+            if task.get_one(&app.scylla, vec![]).await.is_ok() {
+                notify_watchers(&app.scylla, task, app.notification_ttl).await;
+                app.webhooks.task_done(task);
+            }
+        }
+        Ok(false) => {}
+        Err(err) => {
+            log::warn!(target: "task", "{}: expire overdue task failed: {}", task.id, err);
+        }
+    }
+}
+
+// the maximum number of items acked concurrently in a batch_ack request.
+const BATCH_ACK_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BatchAckItem {
+    pub tid: PackObject<xid::Id>,
+    pub sender: PackObject<xid::Id>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BatchAckTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub status: NotificationStatus,
+    pub message: String,
+    pub reason: Option<String>, // one of the reason codes of the task kinds
+    #[validate(length(min = 1, max = 100))]
+    pub items: Vec<BatchAckItem>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BatchAckTaskOutput {
+    pub tid: PackObject<xid::Id>,
+    pub sender: PackObject<xid::Id>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<TaskStatus>, // the task status after the ack
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vetoed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub async fn batch_ack(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<BatchAckTaskInput>,
+) -> Result<PackObject<SuccessResponse<Vec<BatchAckTaskOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    if input.status == NotificationStatus::Pending {
+        return Err(HTTPError::new(
+            400,
+            format!("invalid status, expected -1 or 1, got {}", input.status),
+        ));
+    }
+    check_content(
+        &input.message,
+        &[],
+        app.max_message_len,
+        app.max_payload_bytes,
+    )?;
+    ctx.set_kvs(vec![
+        ("action", "batch_ack_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("size", input.items.len().into()),
+    ])
+    .await;
+
+    let uid = input.uid.unwrap();
+    let status = input.status;
+    let message = input.message;
+    let reason = input.reason.unwrap_or_default();
+    let app = app.as_ref();
+    let output: Vec<BatchAckTaskOutput> = stream::iter(input.items)
+        .map(|item| {
+            let message = message.clone();
+            let reason = reason.clone();
+            async move {
+                let tid = *item.tid;
+                let sender = *item.sender;
+                let mut rt = BatchAckTaskOutput {
+                    tid: item.tid,
+                    sender: item.sender,
+                    ..Default::default()
+                };
+                match ack_notification(app, uid, tid, sender, status, message, reason).await {
+                    Ok(res) => {
+                        rt.result = Some(res.result);
+                        rt.status = Some(res.status);
+                        rt.vetoed = Some(res.vetoed);
+                    }
+                    Err(err) if err.code == 404 => rt.error = Some("not_found".to_string()),
+                    Err(err) => rt.error = Some(err.message),
+                }
+                rt
+            }
+        })
+        .buffered(BATCH_ACK_CONCURRENCY)
+        .collect()
+        .await;
+
+    let failed = output.iter().filter(|rt| rt.error.is_some()).count();
+    ctx.set("failed", failed.into()).await;
+    Ok(to.with(SuccessResponse::new(output)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UnackTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub tid: PackObject<xid::Id>,
+    pub sender: PackObject<xid::Id>,
+}
+
+pub async fn unack(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<UnackTaskInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "unack_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+        ("sender", input.sender.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Notification::with_pk(
+        input.uid.unwrap(),
+        input.tid.unwrap(),
+        input.sender.unwrap(),
+    );
+    doc.get_one(&app.scylla).await?;
+
+    let mut task = db::Task::with_pk(doc.sender, doc.tid);
+    task.get_one(
+        &app.scylla,
+        vec![
+            "status".to_string(),
+            "duedate".to_string(),
+            "allow_late_ack".to_string(),
+        ],
+    )
+    .await?;
+    if !check_unackable(&task, doc.status, unix_ms() as i64)? {
+        return Ok(to.with(SuccessResponse::new(false)));
+    }
+    let removed = task.remove_vote(&app.scylla, doc.uid).await?;
+    if !removed && doc.status == NotificationStatus::Pending {
+        return Ok(to.with(SuccessResponse::new(false)));
+    }
+
+    doc.status = NotificationStatus::Pending;
+    doc.reason.clear();
+    doc.update(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(true)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct WatchTaskInput {
+    pub uid: PackObject<xid::Id>, // the watcher
+    pub tid: PackObject<xid::Id>,
+    pub sender: PackObject<xid::Id>,
+}
+
+// the user is notified of the final status of the task without voting on it,
+// returns false if the user is watching the task already.
+pub async fn watch(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<WatchTaskInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "watch_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+        ("sender", input.sender.to_string().into()),
+    ])
+    .await;
+
+    let mut task = db::Task::with_pk(input.sender.unwrap(), input.tid.unwrap());
+    let res = task.watch(&app.scylla, input.uid.unwrap()).await?;
+    Ok(to.with(SuccessResponse::new(res)))
+}
+
+// returns false if the user is not watching the task.
+pub async fn unwatch(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<WatchTaskInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "unwatch_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("tid", input.tid.to_string().into()),
+        ("sender", input.sender.to_string().into()),
+    ])
+    .await;
+
+    let mut task = db::Task::with_pk(input.sender.unwrap(), input.tid.unwrap());
+    let res = task.unwatch(&app.scylla, input.uid.unwrap()).await?;
+    Ok(to.with(SuccessResponse::new(res)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CancelTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+}
+
+pub async fn cancel(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<CancelTaskInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "cancel_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("id", input.id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Task::with_pk(input.uid.unwrap(), input.id.unwrap());
+    if !doc.cancel(&app.scylla).await? {
+        return Ok(to.with(SuccessResponse::new(false)));
+    }
+
+    let mut notify = db::GroupNotification::with_pk(doc.gid, doc.id, doc.uid);
+    let _ = notify.delete(&app.scylla).await;
+    db::Notification::batch_delete_by_tid(&app.scylla, doc.id).await?;
+    record_log(
+        &app,
+        &doc,
+        ctx.user,
+        db::TaskAction::Cancelled,
+        String::new(),
+    )
+    .await;
+
+    Ok(to.with(SuccessResponse::new(true)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ReopenTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub updated_at: i64,
+}
+
+pub async fn reopen(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<ReopenTaskInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    check_uid(app.pin_uid, &ctx, uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "reopen_task".into()),
+        ("uid", uid.to_string().into()),
+        ("id", input.id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Task::with_pk(uid, input.id.unwrap());
+    if !doc.reopen(&app.scylla, input.updated_at).await? {
+        return Ok(to.with(SuccessResponse::new(false)));
+    }
+
+    db::Notification::batch_reset_by_tid(&app.scylla, doc.id).await?;
+    record_log(
+        &app,
+        &doc,
+        ctx.user,
+        db::TaskAction::Reopened,
+        String::new(),
+    )
+    .await;
+    Ok(to.with(SuccessResponse::new(true)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct QueryTaskAcks {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TaskAckOutput {
+    pub uid: PackObject<xid::Id>,
+    pub status: NotificationStatus,
+    pub message: String,
+    pub reason: String,
+}
+
+pub async fn acks(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryTaskAcks>,
+) -> Result<PackObject<SuccessResponse<Vec<TaskAckOutput>>>, HTTPError> {
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    check_uid(app.pin_uid, &ctx, uid)?;
+
+    let page_size = input.page_size.unwrap_or(10);
+    ctx.set_kvs(vec![
+        ("action", "list_task_acks".into()),
+        ("uid", uid.to_string().into()),
+        ("id", input.id.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let mut doc = db::Task::with_pk(uid, input.id.unwrap());
+    doc.get_one(&app.scylla, vec!["status".to_string()]).await?;
+
+    let res = db::Notification::list_by_tid(
+        &app.scylla,
+        doc.id,
+        page_size,
+        token_to_xid(&input.page_token)?,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(res.last().unwrap().uid))
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .into_iter()
+            .map(|notif| TaskAckOutput {
+                uid: to.with(notif.uid),
+                status: notif.status,
+                message: notif.message,
+                reason: notif.reason,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct QueryTaskLog {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TaskLogOutput {
+    pub id: PackObject<xid::Id>,
+    pub actor: PackObject<xid::Id>,
+    pub action: String,
+    pub detail: String,
+    pub created_at: i64,
+}
+
+pub async fn get_log(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryTaskLog>,
+) -> Result<PackObject<SuccessResponse<Vec<TaskLogOutput>>>, HTTPError> {
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    check_uid(app.pin_uid, &ctx, uid)?;
+
+    let page_size = input.page_size.unwrap_or(10);
+    ctx.set_kvs(vec![
+        ("action", "list_task_log".into()),
+        ("uid", uid.to_string().into()),
+        ("id", input.id.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let res = db::TaskLog::list(
+        &app.scylla,
+        uid,
+        input.id.unwrap(),
+        page_size,
+        token_to_xid(&input.page_token)?,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(res.last().unwrap().id))
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .into_iter()
+            .map(|doc| TaskLogOutput {
+                id: to.with(doc.id),
+                actor: to.with(doc.actor),
+                action: doc.action,
+                detail: doc.detail,
+                created_at: doc.created_at,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct TransferTaskInput {
+    pub from_uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub to_uid: PackObject<xid::Id>,
+}
+
+pub async fn transfer(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<TransferTaskInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.from_uid)?;
+
+    if input.from_uid == input.to_uid {
+        return Err(HTTPError::new(
+            400,
+            "from_uid and to_uid should be different".to_string(),
+        ));
+    }
+
+    ctx.set_kvs(vec![
+        ("action", "transfer_task".into()),
+        ("from_uid", input.from_uid.to_string().into()),
+        ("id", input.id.to_string().into()),
+        ("to_uid", input.to_uid.to_string().into()),
+    ])
+    .await;
+
+    let (from_uid, to_uid) = (*input.from_uid, *input.to_uid);
+    let mut doc = db::Task::with_pk(from_uid, input.id.unwrap());
+    doc.transfer(&app.scylla, to_uid).await?;
+    // the log is moved with the task, the entry goes to the new partition.
+    record_log(
+        &app,
+        &doc,
+        ctx.user,
+        db::TaskAction::Transferred,
+        format!("{} to {}", from_uid, to_uid),
+    )
+    .await;
+
+    Ok(to.with(SuccessResponse::new(true)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DeleteTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: Option<PackObject<xid::Id>>,
+    pub status: Option<TaskStatus>,
+    pub purge: Option<bool>, // removes the tasks permanently instead of moving them to the trash
+    pub updated_at: Option<i64>, // single delete only, deletes the task only if it was not updated since
+}
+
+pub async fn delete(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<DeleteTaskInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    let id = input
+        .id
+        .ok_or_else(|| HTTPError::new(400, "Missing required field `id`".to_string()))?;
+    let purge = input.purge.unwrap_or(false);
+
+    ctx.set_kvs(vec![
+        ("action", "delete_notification".into()),
+        ("uid", input.uid.to_string().into()),
+        ("id", id.to_string().into()),
+        ("purge", purge.into()),
+    ])
+    .await;
+
+    let mut doc = db::Task::with_pk(input.uid.unwrap(), id.unwrap());
+    if !db::scylladb::exists(doc.get_one(&app.scylla, vec!["gid".to_string()]).await)? {
+        return Ok(to.with(SuccessResponse::new(false)));
+    }
+
+    let res = if purge {
+        purge_task(&app, &mut doc, ctx.user, input.updated_at).await?
+    } else {
+        trash_task(&app, &mut doc, ctx.user, input.updated_at).await?
+    };
+    Ok(to.with(SuccessResponse::new(res)))
+}
+
+// moves the task to the trash, returns false if it is already trashed.
+async fn trash_task(
+    app: &AppState,
+    doc: &mut db::Task,
+    actor: xid::Id,
+    updated_at: Option<i64>,
+) -> Result<bool, HTTPError> {
+    if !doc.trash(&app.scylla, updated_at).await? {
+        return Ok(false);
+    }
+    record_log(app, doc, actor, db::TaskAction::Trashed, String::new()).await;
+    Ok(true)
+}
+
+// removes the task and its notifications permanently, returns false if it is already removed.
+pub(crate) async fn purge_task(
+    app: &AppState,
+    doc: &mut db::Task,
+    actor: xid::Id,
+    updated_at: Option<i64>,
+) -> Result<bool, HTTPError> {
+    let removed = doc.delete(&app.scylla, updated_at).await?;
+    let mut notify = db::GroupNotification::with_pk(doc.gid, doc.id, doc.uid);
+    let _ = notify.delete(&app.scylla).await;
+    db::Notification::batch_delete_by_tid(&app.scylla, doc.id).await?;
+    if removed {
+        if doc.payload_external {
+            discard_blob(app, BlobRef::from_payload(&doc.payload).ok()).await;
+        }
+        record_log(app, doc, actor, db::TaskAction::Deleted, String::new()).await;
+    }
+    Ok(removed)
+}
+
+// the tasks deleted per batch_delete request.
+const BATCH_DELETE_LIMIT: usize = 1000;
+const BATCH_DELETE_PAGE_SIZE: u16 = 100;
+
+// lists up to limit tasks of the user to delete, paging until they are found or the
+// partition ends. Trashed tasks are only listed when asked for by status.
+async fn list_deletable(
+    db: &impl CqlExecutor,
+    uid: xid::Id,
+    status: Option<TaskStatus>,
+    limit: usize,
+) -> Result<Vec<db::Task>, HTTPError> {
+    let mut docs: Vec<db::Task> = Vec::new();
+    let mut page_token: Option<xid::Id> = None;
+    while docs.len() < limit {
+        let res = db::Task::list(
+            db,
+            uid,
+            vec!["gid".to_string()],
+            BATCH_DELETE_PAGE_SIZE,
+            page_token,
+            status,
+            None,
+            false,
+        )
+        .await?;
+        let last_page = res.len() < BATCH_DELETE_PAGE_SIZE as usize;
+        page_token = res.last().map(|doc| doc.id);
+        docs.extend(
+            res.into_iter()
+                .filter(|doc| status.is_some() || doc.status != TaskStatus::Trashed),
+        );
+        if last_page {
+            break;
+        }
+    }
+    docs.truncate(limit);
+    Ok(docs)
+}
+
+pub async fn batch_delete(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<DeleteTaskInput>,
+) -> Result<PackObject<SuccessResponse<u32>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+    if input.updated_at.is_some() {
+        return Err(HTTPError::new(
+            400,
+            "updated_at is only supported when deleting a single task".to_string(),
+        ));
+    }
+
+    let purge = input.purge.unwrap_or(false);
+    ctx.set_kvs(vec![
+        ("action", "batch_delete_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("purge", purge.into()),
+    ])
+    .await;
+
+    if let Some(status) = input.status {
+        ctx.set("status", i8::from(status).into()).await
+    }
+
+    // callers should loop until 0 is returned.
+    let docs = list_deletable(
+        &app.scylla,
+        input.uid.unwrap(),
+        input.status,
+        BATCH_DELETE_LIMIT,
+    )
+    .await?;
+    let mut removed: u32 = 0;
+    for mut doc in docs {
+        let ok = if purge {
+            purge_task(&app, &mut doc, ctx.user, None).await?
+        } else {
+            trash_task(&app, &mut doc, ctx.user, None).await?
+        };
+        if ok {
+            removed += 1;
+        }
+    }
+
+    ctx.set("removed", removed.into()).await;
+    Ok(to.with(SuccessResponse::new(removed)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RestoreTaskInput {
+    pub uid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+}
+
+pub async fn restore(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<RestoreTaskInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "restore_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("id", input.id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Task::with_pk(input.uid.unwrap(), input.id.unwrap());
+    let res = doc.restore(&app.scylla, app.trash_retention_ms).await?;
+    if res {
+        record_log(
+            &app,
+            &doc,
+            ctx.user,
+            db::TaskAction::Restored,
+            String::new(),
+        )
+        .await;
+    }
+    Ok(to.with(SuccessResponse::new(res)))
+}
+
+pub async fn list(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<Pagination>,
+) -> Result<PackObject<SuccessResponse<Vec<TaskOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+    let status = input.task_status()?;
+
+    let page_size = check_page_size(input.page_size, app.max_page_size)?;
+    ctx.set_kvs(vec![
+        ("action", "list_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let asc = match input.order.as_deref() {
+        None | Some("desc") => false,
+        Some("asc") => true,
+        Some(order) => {
+            return Err(HTTPError::new(
+                400,
+                format!("Invalid order, expected asc or desc, got {}", order),
+            ))
+        }
+    };
+
+    let uid = input.uid.unwrap();
+    let fields = ver.select_fields(input.fields.unwrap_or_default());
+    let (mut res, next_page_token) = match token_to_page(&input.page_token)? {
+        // legacy xid tokens keep paging by id.
+        Some(PageToken::Xid(id)) => {
+            let res = db::Task::list(
+                &app.scylla,
+                uid,
+                fields,
+                page_size,
+                Some(id),
+                status,
+                input.kind,
+                asc,
+            )
+            .await?;
+            let next_page_token = if res.len() >= page_size as usize {
+                to.with_option(token_from_xid(res.last().unwrap().id))
+            } else {
+                None
+            };
+            (res, next_page_token)
+        }
+        page_token => {
+            let (res, paging_state) = db::Task::list_paged(
+                &app.scylla,
+                uid,
+                fields,
+                page_size,
+                page_token.and_then(PageToken::into_state),
+                status,
+                input.kind,
+                asc,
+            )
+            .await?;
+            (
+                res,
+                paging_state.and_then(|state| to.with_option(token_from_state(state))),
+            )
+        }
+    };
+    // trashed tasks are listed only when requested explicitly, so a page may be shorter
+    // than page_size while next_page_token is still returned.
+    if status.is_none() {
+        res.retain(|doc| doc.status != TaskStatus::Trashed);
+    }
+    let total_size = if input.with_total.unwrap_or(false) {
+        Some(db::Task::count(&app.scylla, uid, status).await?)
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size,
+        next_page_token,
+        result: res
+            .iter()
+            .map(|r| TaskOutput::from(r.to_owned(), &to, ver))
+            .collect(),
+    }))
+}
+
+pub async fn list_archived(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<Pagination>,
+) -> Result<PackObject<SuccessResponse<Vec<TaskOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+    let status = input.task_status()?;
+
+    if input.kind.is_some() || matches!(input.order.as_deref(), Some(o) if o != "desc") {
+        return Err(HTTPError::new(
+            400,
+            "kind and asc order are not supported for archived tasks".to_string(),
+        ));
+    }
+
+    let page_size = check_page_size(input.page_size, app.max_page_size)?;
+    ctx.set_kvs(vec![
+        ("action", "list_archived_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let res = db::Task::list_archived(
+        &app.scylla,
+        input.uid.unwrap(),
+        ver.select_fields(input.fields.unwrap_or_default()),
+        page_size,
+        token_to_xid(&input.page_token)?,
+        status,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(res.last().unwrap().id))
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .into_iter()
+            .map(|doc| TaskOutput::from(doc, &to, ver))
+            .collect(),
+    }))
+}
+
+// loads the listed tasks by their (uid, id) keys concurrently, in the order of `keys`.
+// The index rows may be stale, the tasks failed to load are skipped.
+async fn hydrate_tasks(
+    db: &impl CqlExecutor,
+    keys: Vec<(xid::Id, xid::Id)>,
+    fields: Vec<String>,
+) -> Vec<db::Task> {
+    stream::iter(keys)
+        .map(|(uid, id)| {
+            let fields = fields.clone();
+            async move {
+                let mut doc = db::Task::with_pk(uid, id);
+                doc.get_one(db, fields).await.ok().map(|_| doc)
+            }
+        })
+        .buffered(HYDRATE_CONCURRENCY)
+        .filter_map(future::ready)
+        .collect()
+        .await
+}
+
+pub async fn list_by_group(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<GroupPagination>,
+) -> Result<PackObject<SuccessResponse<Vec<TaskOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    let status = input.task_status()?;
+
+    let page_size = check_page_size(input.page_size, app.max_page_size)?;
+    ctx.set_kvs(vec![
+        ("action", "list_task_by_group".into()),
+        ("gid", input.gid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let mut fields = ver.select_fields(input.fields.unwrap_or_default());
+    // validate fields before any reads.
+    db::Task::select_fields(fields.clone(), false)?;
+    // the visibility is always hydrated so that private tasks can be filtered out.
+    if !fields.is_empty() && !fields.iter().any(|f| f == "visibility") {
+        fields.push("visibility".to_string());
+    }
+    let res = db::Task::list_by_gid(
+        &app.scylla,
+        input.gid.unwrap(),
+        page_size,
+        token_to_xid(&input.page_token)?,
+        status,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(res.last().unwrap().id))
+    } else {
+        None
+    };
+
+    let keys = res.iter().map(|item| (item.uid, item.id)).collect();
+    // private tasks are not listed for the group.
+    let output: Vec<TaskOutput> = hydrate_tasks(&app.scylla, keys, fields)
+        .await
+        .into_iter()
+        .filter(|doc| !doc.is_private())
+        .map(|doc| TaskOutput::from(doc, &to, ver))
+        .collect();
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: output,
+    }))
+}
+
+pub async fn list_assigned(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<Pagination>,
+) -> Result<PackObject<SuccessResponse<Vec<TaskOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+    let status = input.task_status()?;
+
+    let page_size = check_page_size(input.page_size, app.max_page_size)?;
+    ctx.set_kvs(vec![
+        ("action", "list_assigned_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let fields = ver.select_fields(input.fields.unwrap_or_default());
+    db::Task::select_fields(fields.clone(), false)?;
+    let res = db::Task::list_by_participant(
+        &app.scylla,
+        input.uid.unwrap(),
+        page_size,
+        token_to_xid(&input.page_token)?,
+        status,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(res.last().unwrap().id))
+    } else {
+        None
+    };
+
+    let keys = res.iter().map(|item| (item.uid, item.id)).collect();
+    let output: Vec<TaskOutput> = hydrate_tasks(&app.scylla, keys, fields)
+        .await
+        .into_iter()
+        .map(|doc| TaskOutput::from(doc, &to, ver))
+        .collect();
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: output,
+    }))
+}
+
+pub async fn list_overdue(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<Pagination>,
+) -> Result<PackObject<SuccessResponse<Vec<TaskOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    let page_size = check_page_size(input.page_size, app.max_page_size)?;
+    ctx.set_kvs(vec![
+        ("action", "list_overdue_task".into()),
+        ("uid", input.uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let mut fields = ver.select_fields(input.fields.unwrap_or_default());
+    if !fields.is_empty() && !fields.contains(&"duedate".to_string()) {
+        fields.push("duedate".to_string());
+    }
+    db::Task::select_fields(fields.clone(), false)?;
+    let res = db::Task::list_overdue(
+        &app.scylla,
+        input.uid.unwrap(),
+        unix_ms() as i64,
+        page_size,
+        token_to_duedate(&input.page_token)?,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        let last = res.last().unwrap();
+        to.with_option(token_from_duedate(last.duedate, last.id))
+    } else {
+        None
+    };
+
+    let keys = res.iter().map(|item| (item.uid, item.id)).collect();
+    let output: Vec<TaskOutput> = hydrate_tasks(&app.scylla, keys, fields)
+        .await
+        .into_iter()
+        .map(|doc| TaskOutput::from(doc, &to, ver))
+        .collect();
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: output,
+    }))
+}
+
+pub async fn list_by_priority(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    to: PackObject<Pagination>,
+) -> Result<PackObject<SuccessResponse<Vec<TaskOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    let page_size = check_page_size(input.page_size, app.max_page_size)?;
+    ctx.set_kvs(vec![
+        ("action", "list_task_by_priority".into()),
+        ("uid", input.uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let mut fields = ver.select_fields(input.fields.unwrap_or_default());
+    if !fields.is_empty() && !fields.contains(&"priority".to_string()) {
+        fields.push("priority".to_string());
+    }
+    db::Task::select_fields(fields.clone(), false)?;
+    let res = db::Task::list_by_priority(
+        &app.scylla,
+        input.uid.unwrap(),
+        page_size,
+        token_to_priority(&input.page_token)?,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        let last = res.last().unwrap();
+        to.with_option(token_from_priority(last.priority, last.id))
+    } else {
+        None
+    };
+
+    let keys = res.iter().map(|item| (item.uid, item.id)).collect();
+    let output: Vec<TaskOutput> = hydrate_tasks(&app.scylla, keys, fields)
+        .await
+        .into_iter()
+        .map(|doc| TaskOutput::from(doc, &to, ver))
+        .collect();
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: output,
+    }))
+}
+
+// the page size of the reads behind an export.
+const EXPORT_PAGE_SIZE: u16 = 100;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct QueryTaskExport {
+    pub uid: PackObject<xid::Id>,
+    pub status: Option<TaskStatus>,
+    pub fields: Option<String>,
+    pub with_payload: Option<bool>, // the payload is exported only when asked
+}
+
+// the last line of an export, error is set if the export stopped before the end.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TaskExportTrailer {
+    pub count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// the fields of an export, the payload is added only when asked.
+fn export_fields(fields: Vec<String>, with_payload: bool) -> Vec<String> {
+    if !with_payload {
+        return fields;
+    }
+    if fields.is_empty() {
+        return db::Task::fields();
+    }
+    let mut fields = fields;
+    if !fields.iter().any(|f| f == "payload") {
+        fields.push("payload".to_string());
+    }
+    fields
+}
+
+// reads the tasks of a user page by page, so the memory stays flat regardless of the partition size.
+struct TaskExport {
+    app: Arc<AppState>,
+    uid: xid::Id,
+    fields: Vec<String>,
+    status: Option<TaskStatus>,
+    version: ApiVersion,
+    page: Option<Vec<db::Task>>, // the page read before the response
+    paging_state: Option<Vec<u8>>,
+    count: u64,
+    done: bool,
+}
+
+impl TaskExport {
+    async fn read_page(&mut self) -> anyhow::Result<Vec<db::Task>> {
+        if let Some(docs) = self.page.take() {
+            return Ok(docs);
+        }
+        let (docs, paging_state) = db::Task::list_paged(
+            &self.app.scylla,
+            self.uid,
+            self.fields.clone(),
+            EXPORT_PAGE_SIZE,
+            self.paging_state.take(),
+            self.status,
+            None,
+            false,
+        )
+        .await?;
+        self.paging_state = paging_state;
+        Ok(docs)
+    }
+
+    // the NDJSON lines of the next page, followed by the trailer after the last page.
+    async fn next_chunk(&mut self) -> Option<Bytes> {
+        if self.done {
+            return None;
+        }
+
+        let to = PackObject::Json(());
+        let mut buf: Vec<u8> = Vec::new();
+        let mut trailer = match self.read_page().await {
+            Ok(docs) => {
+                for doc in docs {
+                    // trashed tasks are exported only when requested explicitly.
+                    if self.status.is_none() && doc.status == TaskStatus::Trashed {
+                        continue;
+                    }
+                    if serde_json::to_writer(&mut buf, &TaskOutput::from(doc, &to, self.version))
+                        .is_ok()
+                    {
+                        buf.push(b'\n');
+                        self.count += 1;
+                    }
+                }
+                if self.paging_state.is_some() {
+                    return Some(Bytes::from(buf));
+                }
+                TaskExportTrailer::default()
+            }
+            Err(err) => {
+                log::error!(target: "api", "export tasks of {} failed: {}", self.uid, err);
+                TaskExportTrailer {
+                    error: Some(HTTPError::from(err).message),
+                    ..Default::default()
+                }
+            }
+        };
+
+        self.done = true;
+        trailer.count = self.count;
+        log::info!(target: "api", "{} tasks of {} exported", self.count, self.uid);
+        if serde_json::to_writer(&mut buf, &trailer).is_ok() {
+            buf.push(b'\n');
+        }
+        Some(Bytes::from(buf))
+    }
+}
+
+pub async fn export(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    Extension(ver): Extension<ApiVersion>,
+    Query(input): Query<QueryTaskExport>,
+) -> Result<Response, HTTPError> {
+    input.validate()?;
+    check_uid(app.pin_uid, &ctx, *input.uid)?;
+
+    ctx.set_kvs(vec![
+        ("action", "export_task".into()),
+        ("uid", input.uid.to_string().into()),
+    ])
+    .await;
+
+    let mut export = TaskExport {
+        app: app.clone(),
+        uid: input.uid.unwrap(),
+        fields: export_fields(
+            ver.select_fields(get_fields(input.fields)),
+            input.with_payload.unwrap_or(false),
+        ),
+        status: input.status,
+        version: ver,
+        page: None,
+        paging_state: None,
+        count: 0,
+        done: false,
+    };
+    // the first page is read before the response, so that invalid fields
+    // and database errors are returned with the status code.
+    let docs = export.read_page().await?;
+    export.page = Some(docs);
+
+    let body = stream::unfold(export, |mut export| async move {
+        export
+            .next_chunk()
+            .await
+            .map(|chunk| (Ok::<Bytes, std::convert::Infallible>(chunk), export))
+    });
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(body),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::fake::FakeDB;
+
+    use super::*;
+
+    #[test]
+    fn export_fields_works() {
+        assert!(export_fields(vec![], false).is_empty());
+        assert_eq!(export_fields(vec![], true), db::Task::fields());
+        assert_eq!(
+            export_fields(vec!["assignees".to_string()], false),
+            vec!["assignees".to_string()]
+        );
+        assert_eq!(
+            export_fields(vec!["assignees".to_string()], true),
+            vec!["assignees".to_string(), "payload".to_string()]
+        );
+        assert_eq!(
+            export_fields(vec!["payload".to_string()], true),
+            vec!["payload".to_string()]
+        );
+
+        let trailer = TaskExportTrailer {
+            count: 2,
+            error: None,
+        };
+        assert_eq!(serde_json::to_string(&trailer).unwrap(), r#"{"count":2}"#);
+    }
+
+    #[test]
+    fn check_content_works() {
+        assert!(check_content("", &[], 4, 8).is_ok());
+        assert!(check_content("abcd", &[0u8; 8], 4, 8).is_ok());
+
+        let err = check_content("abcd", &[0u8; 9], 4, 8).unwrap_err();
+        assert_eq!(err.code, 400);
+        assert!(err.message.contains("at most 8 bytes"));
+
+        let err = check_content("abcde", &[], 4, 8).unwrap_err();
+        assert_eq!(err.code, 400);
+        assert!(err.message.contains("at most 4 bytes"));
+    }
+
+    #[test]
+    fn check_payload_type_works() {
+        assert!(check_payload_type("", &[]).is_ok());
+        assert!(check_payload_type("application/json", &[]).is_ok());
+        assert!(check_payload_type("application/cbor", &[0u8; 8]).is_ok());
+        assert_eq!(check_payload_type("", &[0u8; 8]).unwrap_err().code, 400);
+    }
+
+    #[test]
+    fn check_duedate_works() {
+        let now = unix_ms() as i64;
+        assert!(check_duedate(0, now).is_ok());
+        assert!(check_duedate(now + 1000, now).is_ok());
+
+        let err = check_duedate(now, now).unwrap_err();
+        assert_eq!(err.code, 400);
+        let err = check_duedate(now - 1000, now).unwrap_err();
+        assert_eq!(err.code, 400);
+        assert!(err.message.contains("invalid duedate"));
+    }
+
+    #[test]
+    fn task_stats_output_works() {
+        assert_eq!(TaskStatsOutput::from_days(&[]), TaskStatsOutput::default());
+
+        let days = vec![
+            db::stats::DayStats {
+                day: 10,
+                resolved: 3,
+                rejected: 1,
+                expired: 2,
+                resolution_ms: 4000,
+            },
+            db::stats::DayStats {
+                day: 11,
+                resolved: 0,
+                rejected: 0,
+                expired: 1,
+                resolution_ms: 0,
+            },
+        ];
+        let rt = TaskStatsOutput::from_days(&days);
+        assert_eq!(rt.resolved, 3);
+        assert_eq!(rt.rejected, 1);
+        assert_eq!(rt.expired, 3);
+        assert_eq!(rt.resolved_ratio, 0.75);
+        assert_eq!(rt.rejected_ratio, 0.25);
+        assert_eq!(rt.avg_resolution_ms, 1000);
+    }
+
+    #[test]
+    fn check_reason_works() {
+        let mut kinds = conf::Kinds::default();
+        assert!(check_reason(&kinds, "approval", "").is_ok());
+        // no reason codes reject any reason.
+        assert_eq!(
+            check_reason(&kinds, "approval", "duplicate")
+                .unwrap_err()
+                .code,
+            400
+        );
+
+        kinds.reasons = vec!["duplicate".to_string(), "out-of-scope".to_string()];
+        kinds.registry.insert(
+            "approval".to_string(),
+            conf::Kind {
+                max_payload: 0,
+                default_threshold: 0,
+                default_duedate_offset_ms: 0,
+                allowed: true,
+                reasons: vec!["needs-changes".to_string()],
+            },
+        );
+        assert!(check_reason(&kinds, "approval", "needs-changes").is_ok());
+        let err = check_reason(&kinds, "approval", "duplicate").unwrap_err();
+        assert_eq!(err.code, 400);
+        assert!(err.message.contains(r#"expected one of ["needs-changes"]"#));
+
+        // the kinds without reason codes use the global ones.
+        assert!(check_reason(&kinds, "review", "duplicate").is_ok());
+        assert!(check_reason(&kinds, "review", "needs-changes").is_err());
+        kinds.registry.get_mut("approval").unwrap().reasons.clear();
+        assert!(check_reason(&kinds, "approval", "out-of-scope").is_ok());
+    }
+
+    #[test]
+    fn check_ackable_works() {
+        let now = unix_ms() as i64;
+        let mut doc = db::Task::with_pk(xid::new(), xid::new());
+        assert!(check_ackable(&doc, now).is_ok());
+
+        for status in [
+            TaskStatus::Resolved,
+            TaskStatus::Rejected,
+            TaskStatus::Cancelled,
+            TaskStatus::Expired,
+            TaskStatus::Trashed,
+        ] {
+            doc.status = status;
+            let err = check_ackable(&doc, now).unwrap_err();
+            assert_eq!(err.code, 409);
+        }
+
+        // past the duedate.
+        doc.status = TaskStatus::Pending;
+        doc.duedate = now - 1000;
+        let err = check_ackable(&doc, now).unwrap_err();
+        assert_eq!(err.code, 409);
+        assert!(err.message.contains("expired"));
+        assert!(check_ackable(&doc, now - 1000).is_ok());
+
+        doc.allow_late_ack = true;
+        for status in [
+            TaskStatus::Pending,
+            TaskStatus::Resolved,
+            TaskStatus::Rejected,
+            TaskStatus::Expired,
+        ] {
+            doc.status = status;
+            assert!(check_ackable(&doc, now).is_ok());
+        }
+        for status in [TaskStatus::Cancelled, TaskStatus::Trashed] {
+            doc.status = status;
+            assert!(check_ackable(&doc, now).is_err());
+        }
+    }
+
+    #[test]
+    fn check_unackable_works() {
+        let now = unix_ms() as i64;
+        let mut doc = db::Task::with_pk(xid::new(), xid::new());
+        assert!(check_unackable(&doc, NotificationStatus::Resolved, now).unwrap());
+        assert!(check_unackable(&doc, NotificationStatus::Pending, now).unwrap());
+
+        // a frozen vote can not be withdrawn, a receiver who never acked has nothing to.
+        doc.status = TaskStatus::Resolved;
+        let err = check_unackable(&doc, NotificationStatus::Resolved, now).unwrap_err();
+        assert_eq!(err.code, 409);
+        let err = check_unackable(&doc, NotificationStatus::Rejected, now).unwrap_err();
+        assert_eq!(err.code, 409);
+        assert!(!check_unackable(&doc, NotificationStatus::Pending, now).unwrap());
+
+        doc.allow_late_ack = true;
+        assert!(check_unackable(&doc, NotificationStatus::Resolved, now).unwrap());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn hydrate_tasks_with_fake_works() {
+        let keys: Vec<(xid::Id, xid::Id)> = (0..3).map(|_| (xid::new(), xid::new())).collect();
+        let db = FakeDB::new();
+        for (i, (uid, id)) in keys.iter().take(2).enumerate() {
+            let mut doc = db::Task::with_pk(*uid, *id);
+            doc.kind = format!("kind-{}", i);
+            db.push_docs("FROM task WHERE uid=? AND id=?", vec![doc.to()]);
+        }
+
+        // the tasks follow the order of the keys, the missing one is skipped.
+        let docs = hydrate_tasks(&db, keys.clone(), vec!["kind".to_string()]).await;
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].id, keys[0].1);
+        assert_eq!(docs[0].kind, "kind-0");
+        assert_eq!(docs[1].id, keys[1].1);
+        assert_eq!(docs[1].kind, "kind-1");
+        assert_eq!(db.queries("FROM task WHERE uid=? AND id=?").len(), 3);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn list_deletable_with_fake_works() {
+        let uid = xid::new();
+        let page = |trashed: usize, size: usize| -> Vec<ColumnsMap> {
+            (0..size)
+                .map(|i| {
+                    let mut doc = db::Task::with_pk(uid, xid::new());
+                    if i < trashed {
+                        doc.status = TaskStatus::Trashed;
+                    }
+                    doc.to()
+                })
+                .collect()
+        };
+
+        // the trashed tasks do not count, it pages on until the limit is reached.
+        let db = FakeDB::new();
+        db.push_docs("FROM task WHERE uid=?", page(10, 100));
+        db.push_docs("FROM task WHERE uid=?", page(0, 100));
+        db.push_docs("FROM task WHERE uid=?", page(0, 100));
+        let docs = list_deletable(&db, uid, None, 150).await.unwrap();
+        assert_eq!(docs.len(), 150);
+        assert!(docs.iter().all(|doc| doc.status != TaskStatus::Trashed));
+        assert_eq!(db.queries("FROM task WHERE uid=?").len(), 2);
+        assert_eq!(db.queries("AND id<?").len(), 1);
+
+        // or until the partition ends.
+        let db = FakeDB::new();
+        db.push_docs("FROM task WHERE uid=?", page(100, 100));
+        db.push_docs("FROM task WHERE uid=?", page(20, 30));
+        let docs = list_deletable(&db, uid, None, 150).await.unwrap();
+        assert_eq!(docs.len(), 10);
+        assert_eq!(db.queries("FROM task WHERE uid=?").len(), 2);
+
+        // the trashed tasks are listed when asked for.
+        let db = FakeDB::new();
+        db.push_docs("FROM task WHERE uid=?", page(30, 30));
+        let docs = list_deletable(&db, uid, Some(TaskStatus::Trashed), 150)
+            .await
+            .unwrap();
+        assert_eq!(docs.len(), 30);
+        assert_eq!(db.queries("AND status=?").len(), 1);
+    }
+
+    #[test]
+    fn receivers_works() {
+        let (a, b, c) = (xid::new(), xid::new(), xid::new());
+        let mut doc = db::Task::with_pk(xid::new(), xid::new());
+        doc.approvers = HashSet::from([a, b]);
+        doc.assignees = HashSet::from([b, c, doc.uid]);
+
+        let mut expected = vec![a, b, c];
+        expected.sort();
+        assert_eq!(receivers(&doc), expected);
+
+        doc.sequence = vec![c, b];
+        assert_eq!(receivers(&doc), vec![c]);
+    }
+
+    #[test]
+    fn check_sequence_works() {
+        let (a, b) = (xid::new(), xid::new());
+        let mut input = test_create_input(xid::new(), xid::new(), &[]);
+        assert!(input.check_sequence().is_ok());
+
+        input.sequence = vec![PackObject::Cbor(a), PackObject::Cbor(b)];
+        assert!(input.check_sequence().is_ok());
+
+        input.threshold = Some(2);
+        assert_eq!(input.check_sequence().unwrap_err().code, 400);
+        input.threshold = Some(0);
+        input.assignees = vec![PackObject::Cbor(a)];
+        assert_eq!(input.check_sequence().unwrap_err().code, 400);
+        input.assignees = Vec::new();
+
+        input.sequence.push(PackObject::Cbor(a));
+        assert_eq!(input.check_sequence().unwrap_err().code, 400);
+        input.sequence = vec![PackObject::Cbor(a), PackObject::Cbor(*input.uid)];
+        assert_eq!(input.check_sequence().unwrap_err().code, 400);
+    }
+
+    #[test]
+    fn check_self_ack_works() {
+        let uid = xid::new();
+        let mut input = test_create_input(uid, xid::new(), &[uid, xid::new()]);
+        assert!(input.check_self_ack().is_ok());
+        input.self_ack_allowed = Some(true);
+        assert!(input.check_self_ack().is_ok());
+
+        input.self_ack_allowed = Some(false);
+        assert_eq!(input.check_self_ack().unwrap_err().code, 400);
+        input.approvers = input.assignees.split_off(0);
+        assert_eq!(input.check_self_ack().unwrap_err().code, 400);
+        input.approvers = vec![PackObject::Cbor(xid::new())];
+        assert!(input.check_self_ack().is_ok());
+    }
+
+    #[test]
+    fn check_visibility_works() {
+        let mut input = test_create_input(xid::new(), xid::new(), &[xid::new()]);
+        assert!(input.check_visibility().is_ok());
+        for visibility in db::Task::VISIBILITIES {
+            input.visibility = Some(visibility.to_string());
+            assert!(input.check_visibility().is_ok());
+        }
+        input.visibility = Some("secret".to_string());
+        let err = input.check_visibility().unwrap_err();
+        assert_eq!(err.code, 400);
+        assert!(err.message.contains("invalid visibility"));
+
+        // a private task can not be exposed to the group.
+        input.group_role = Some(1);
+        input.visibility = Some("group".to_string());
+        assert!(input.check_visibility().is_ok());
+        input.visibility = Some("private".to_string());
+        assert_eq!(input.check_visibility().unwrap_err().code, 400);
+    }
+
+    #[test]
+    fn task_output_counts_works() {
+        let to = PackObject::Json(());
+        let approver = xid::new();
+        let mut doc = db::Task::with_pk(xid::new(), xid::new());
+        doc.approver_threshold = 1;
+        doc.approvers = HashSet::from([approver, xid::new()]);
+        doc.assignees = HashSet::from([xid::new(), xid::new(), xid::new()]);
+        doc.resolved = HashSet::from([approver]);
+        doc._fields = db::Task::select_fields(vec!["counts".to_string()], false).unwrap();
+
+        let rt = TaskOutput::from(doc.clone(), &to, ApiVersion::V1);
+        assert_eq!(rt.assignee_count, Some(3));
+        assert_eq!(rt.resolved_count, Some(1));
+        assert_eq!(rt.rejected_count, Some(0));
+        assert_eq!(rt.approver_count, Some(2));
+        assert_eq!(rt.approver_resolved_count, Some(1));
+        assert_eq!(rt.approver_threshold, Some(1));
+        assert!(rt.assignees.is_none());
+        assert!(rt.resolved.is_none());
+        assert!(rt.approvers.is_none());
+
+        doc._fields = vec!["assignees".to_string()];
+        let rt = TaskOutput::from(doc, &to, ApiVersion::V1);
+        assert_eq!(rt.assignee_count, Some(3));
+        assert_eq!(rt.assignees.map(|v| v.len()), Some(3));
+        assert!(rt.resolved_count.is_none());
+    }
+
+    #[test]
+    fn task_output_payload_works() {
+        let to = PackObject::Json(());
+        let mut doc = db::Task::with_pk(xid::new(), xid::new());
+        doc.payload = vec![1u8; 8];
+        doc.payload_type = "application/cbor".to_string();
+        doc.payload_len = 8;
+
+        // the payload metadata is output without the payload.
+        doc._fields = db::Task::select_fields(vec!["message".to_string()], false).unwrap();
+        let rt = TaskOutput::from(doc.clone(), &to, ApiVersion::V1);
+        assert!(rt.payload.is_none());
+        assert_eq!(rt.payload_type.as_deref(), Some("application/cbor"));
+        assert_eq!(rt.payload_len, Some(8));
+
+        doc._fields = db::Task::select_fields(vec!["payload".to_string()], false).unwrap();
+        let rt = TaskOutput::from(doc, &to, ApiVersion::V1);
+        assert_eq!(rt.payload.map(|v| v.len()), Some(8));
+        assert_eq!(rt.payload_len, Some(8));
+    }
+
+    #[test]
+    fn task_output_watchers_works() {
+        let to = PackObject::Json(());
+        let mut doc = db::Task::with_pk(xid::new(), xid::new());
+        doc.watchers = HashSet::from([xid::new(), xid::new()]);
+        doc.final_reason = "duplicate".to_string();
+
+        // the watchers are only output when selected.
+        doc._fields = db::Task::select_fields(vec![], true).unwrap();
+        let rt = TaskOutput::from(doc.clone(), &to, ApiVersion::V1);
+        assert!(rt.watchers.is_none());
+        assert_eq!(rt.final_reason.as_deref(), Some("duplicate"));
+        // rows without the visibility are visible to the group.
+        assert_eq!(rt.visibility.as_deref(), Some("group"));
+        assert!(!doc.is_private());
+        doc.visibility = "private".to_string();
+        assert!(doc.is_private());
+
+        doc._fields = db::Task::select_fields(vec!["watchers".to_string()], true).unwrap();
+        let rt = TaskOutput::from(doc, &to, ApiVersion::V1);
+        assert_eq!(rt.watchers.map(|v| v.len()), Some(2));
+        assert!(rt.assignee_count.is_none());
+    }
+
+    #[test]
+    fn task_output_versions_works() {
+        let to = PackObject::Json(());
+        let mut doc = db::Task::with_pk(xid::new(), xid::new());
+        doc.duedate = 1000;
+        doc.assignees = HashSet::from([xid::new(), xid::new()]);
+
+        // v1 outputs the fields selected as they are.
+        let fields = vec!["duedate".to_string()];
+        doc._fields =
+            db::Task::select_fields(ApiVersion::V1.select_fields(fields.clone()), false).unwrap();
+        let rt = serde_json::to_value(TaskOutput::from(doc.clone(), &to, ApiVersion::V1)).unwrap();
+        assert_eq!(rt["duedate"], 1000);
+        assert!(rt.get("due_at").is_none());
+        assert!(rt.get("assignee_count").is_none());
+
+        // v2 renames duedate to due_at, and always includes the counts.
+        doc._fields = db::Task::select_fields(ApiVersion::V2.select_fields(fields), false).unwrap();
+        let rt = serde_json::to_value(TaskOutput::from(doc, &to, ApiVersion::V2)).unwrap();
+        assert_eq!(rt["due_at"], 1000);
+        assert!(rt.get("duedate").is_none());
+        assert_eq!(rt["assignee_count"], 2);
+        assert_eq!(rt["resolved_count"], 0);
+        assert!(rt.get("assignees").is_none());
+    }
+
+    #[test]
+    fn merge_template_works() {
+        let mut tpl = db::TaskTemplate::with_pk(xid::new(), xid::new());
+        tpl.kind = "approval".to_string();
+        tpl.threshold = 2;
+        tpl.approvers = HashSet::from([xid::new(), xid::new()]);
+        tpl.assignees = HashSet::from([xid::new()]);
+        tpl.message = "default message".to_string();
+
+        let mut input = test_create_input(xid::new(), tpl.gid, &[]);
+        input.message = "explicit message".to_string();
+        input.template_id = Some(PackObject::Cbor(tpl.id));
+        input.merge_template(&tpl);
+        assert_eq!(input.kind, "approval");
+        assert_eq!(input.threshold, Some(2));
+        assert_eq!(input.approvers.len(), 2);
+        assert_eq!(input.assignees.len(), 1);
+        assert_eq!(input.message, "explicit message");
+        assert!(input.validate().is_ok());
+
+        // an explicit threshold 0 is kept.
+        let mut explicit = test_create_input(xid::new(), tpl.gid, &[]);
+        explicit.threshold = Some(0);
+        explicit.merge_template(&tpl);
+        assert_eq!(explicit.threshold, Some(0));
+
+        // the merged result still goes through the create validation.
+        tpl.approvers = (0..5).map(|_| xid::new()).collect();
+        input.approvers = Vec::new();
+        input.merge_template(&tpl);
+        assert!(input.validate().is_err());
+    }
+
+    #[test]
+    fn apply_kind_works() {
+        let mut input = test_create_input(xid::new(), xid::new(), &[xid::new(), xid::new()]);
+        input.kind = "approval".to_string();
+        input.payload = PackObject::Cbor(vec![0u8; 8]);
+
+        // an empty registry accepts any kind.
+        let mut kinds = conf::Kinds {
+            strict: true,
+            registry: Default::default(),
+            reasons: Vec::new(),
+        };
+        assert!(input.apply_kind(&kinds, 1000).is_ok());
+        assert_eq!(input.threshold, None);
+        assert_eq!(input.duedate, None);
+
+        kinds.registry.insert(
+            "approval".to_string(),
+            conf::Kind {
+                max_payload: 8,
+                default_threshold: 1,
+                default_duedate_offset_ms: 3600,
+                allowed: true,
+                reasons: Vec::new(),
+            },
+        );
+        assert!(input.apply_kind(&kinds, 1000).is_ok());
+        assert_eq!(input.threshold, Some(1));
+        assert_eq!(input.duedate, Some(4600));
+
+        // explicit fields are kept, an explicit 0 too.
+        input.threshold = Some(0);
+        input.duedate = Some(9000);
+        assert!(input.apply_kind(&kinds, 1000).is_ok());
+        assert_eq!(input.threshold, Some(0));
+        assert_eq!(input.duedate, Some(9000));
+
+        input.payload = PackObject::Cbor(vec![0u8; 9]);
+        let err = input.apply_kind(&kinds, 1000).unwrap_err();
+        assert_eq!(err.code, 400);
+        assert!(err.message.contains("at most 8 bytes"));
+        input.payload = PackObject::Cbor(Vec::new());
+
+        input.kind = "typo".to_string();
+        let err = input.apply_kind(&kinds, 1000).unwrap_err();
+        assert!(err.message.contains("unknown kind"));
+        kinds.strict = false;
+        assert!(input.apply_kind(&kinds, 1000).is_ok());
+
+        kinds.registry.get_mut("approval").unwrap().allowed = false;
+        input.kind = "approval".to_string();
+        let err = input.apply_kind(&kinds, 1000).unwrap_err();
+        assert!(err.message.contains("not allowed"));
+    }
 }
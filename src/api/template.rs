@@ -0,0 +1,263 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Arc};
+use validator::Validate;
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+use scylla_orm::ColumnsMap;
+
+use crate::db;
+
+use crate::api::{token_from_xid, token_to_xid, AppState};
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TemplateOutput {
+    pub gid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub name: String,
+    pub kind: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub threshold: i16,
+    pub approvers: Vec<PackObject<xid::Id>>,
+    pub assignees: Vec<PackObject<xid::Id>>,
+    pub message: String,
+}
+
+impl TemplateOutput {
+    pub fn from<T>(val: db::TaskTemplate, to: &PackObject<T>) -> Self {
+        Self {
+            gid: to.with(val.gid),
+            id: to.with(val.id),
+            name: val.name,
+            kind: val.kind,
+            created_at: val.created_at,
+            updated_at: val.updated_at,
+            threshold: val.threshold,
+            approvers: val.approvers.into_iter().map(|id| to.with(id)).collect(),
+            assignees: val.assignees.into_iter().map(|id| to.with(id)).collect(),
+            message: val.message,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateTemplateInput {
+    pub gid: PackObject<xid::Id>,
+    #[validate(length(min = 1, max = 256))]
+    pub name: String,
+    pub kind: String,
+    #[validate(range(min = 0, max = 256))]
+    pub threshold: i16,
+    #[validate(length(min = 0, max = 4))]
+    pub approvers: Vec<PackObject<xid::Id>>,
+    #[validate(length(min = 0, max = 256))]
+    pub assignees: Vec<PackObject<xid::Id>>,
+    pub message: String,
+}
+
+pub async fn create(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<CreateTemplateInput>,
+) -> Result<PackObject<SuccessResponse<TemplateOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    ctx.set_kvs(vec![
+        ("action", "create_template".into()),
+        ("gid", input.gid.to_string().into()),
+        ("kind", input.kind.clone().into()),
+    ])
+    .await;
+
+    let mut doc = db::TaskTemplate::with_pk(input.gid.unwrap(), xid::new());
+    doc.name = input.name;
+    doc.kind = input.kind;
+    doc.threshold = input.threshold;
+    doc.approvers = input.approvers.into_iter().map(|id| id.unwrap()).collect();
+    doc.assignees = input.assignees.into_iter().map(|id| id.unwrap()).collect();
+    doc.message = input.message;
+    doc.save(&app.scylla).await?;
+
+    ctx.set("id", doc.id.to_string().into()).await;
+    Ok(to.with(SuccessResponse::new(TemplateOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct QueryTemplate {
+    pub gid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+}
+
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryTemplate>,
+) -> Result<PackObject<SuccessResponse<TemplateOutput>>, HTTPError> {
+    input.validate()?;
+
+    ctx.set_kvs(vec![
+        ("action", "get_template".into()),
+        ("gid", input.gid.to_string().into()),
+        ("id", input.id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::TaskTemplate::with_pk(input.gid.unwrap(), input.id.unwrap());
+    doc.get_one(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(TemplateOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateTemplateInput {
+    pub gid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+    pub updated_at: i64,
+    #[validate(length(min = 1, max = 256))]
+    pub name: Option<String>,
+    pub kind: Option<String>,
+    #[validate(range(min = 0, max = 256))]
+    pub threshold: Option<i16>,
+    #[validate(length(min = 0, max = 4))]
+    pub approvers: Option<Vec<PackObject<xid::Id>>>,
+    #[validate(length(min = 0, max = 256))]
+    pub assignees: Option<Vec<PackObject<xid::Id>>>,
+    pub message: Option<String>,
+}
+
+impl UpdateTemplateInput {
+    fn into(self) -> anyhow::Result<ColumnsMap> {
+        let mut cols = ColumnsMap::new();
+        if let Some(name) = self.name {
+            cols.set_as("name", &name);
+        }
+        if let Some(kind) = self.kind {
+            cols.set_as("kind", &kind);
+        }
+        if let Some(threshold) = self.threshold {
+            cols.set_as("threshold", &threshold);
+        }
+        if let Some(approvers) = self.approvers {
+            let approvers: HashSet<xid::Id> = approvers.into_iter().map(|id| id.unwrap()).collect();
+            cols.set_as("approvers", &approvers);
+        }
+        if let Some(assignees) = self.assignees {
+            let assignees: HashSet<xid::Id> = assignees.into_iter().map(|id| id.unwrap()).collect();
+            cols.set_as("assignees", &assignees);
+        }
+        if let Some(message) = self.message {
+            cols.set_as("message", &message);
+        }
+
+        if cols.is_empty() {
+            return Err(HTTPError::new(400, "No fields to update".to_string()).into());
+        }
+
+        Ok(cols)
+    }
+}
+
+pub async fn update(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<UpdateTemplateInput>,
+) -> Result<PackObject<SuccessResponse<TemplateOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let gid = *input.gid;
+    let id = *input.id;
+    let updated_at = input.updated_at;
+    let cols = input.into()?;
+    ctx.set_kvs(vec![
+        ("action", "update_template".into()),
+        ("gid", gid.to_string().into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::TaskTemplate::with_pk(gid, id);
+    doc.update(&app.scylla, cols, updated_at).await?;
+    doc.get_one(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(TemplateOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct TemplatePagination {
+    pub gid: PackObject<xid::Id>,
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+}
+
+pub async fn list(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<TemplatePagination>,
+) -> Result<PackObject<SuccessResponse<Vec<TemplateOutput>>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let page_size = input.page_size.unwrap_or(10);
+    ctx.set_kvs(vec![
+        ("action", "list_template".into()),
+        ("gid", input.gid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let res = db::TaskTemplate::list(
+        &app.scylla,
+        input.gid.unwrap(),
+        page_size,
+        token_to_xid(&input.page_token)?,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(res.last().unwrap().id))
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .into_iter()
+            .map(|doc| TemplateOutput::from(doc, &to))
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DeleteTemplateInput {
+    pub gid: PackObject<xid::Id>,
+    pub id: PackObject<xid::Id>,
+}
+
+pub async fn delete(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<DeleteTemplateInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    ctx.set_kvs(vec![
+        ("action", "delete_template".into()),
+        ("gid", input.gid.to_string().into()),
+        ("id", input.id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::TaskTemplate::with_pk(input.gid.unwrap(), input.id.unwrap());
+    doc.delete(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(true)))
+}
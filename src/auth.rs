@@ -0,0 +1,392 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+
+use axum_web::{context::unix_ms, erring::HTTPError};
+
+use crate::conf;
+use crate::events;
+
+// the routes behind the auth, the probes and the metrics are always open.
+const AUTH_PATH_PREFIXES: [&str; 3] = ["/v1/", "/v2/", "/internal/"];
+
+// the routes checking their own tokens, e.g. the calendar feeds subscribed by the calendar apps.
+const OPEN_PATHS: [&str; 2] = ["/v1/task/calendar", "/v2/task/calendar"];
+
+// the scope of the tokens for the admin routes.
+pub const ADMIN_SCOPE: &str = "admin";
+const ADMIN_PATHS: [&str; 3] = [
+    "/v1/task/import",
+    "/v2/task/import",
+    "/internal/maintenance",
+];
+
+// the routes over the data of a group under /v1 and /v2. The group members are not known
+// here, so with pin_uid they are served to the admin scope only, i.e. the services that
+// check the membership.
+const GROUP_PATHS: [&str; 6] = [
+    "/task/list_by_group",
+    "/task/stats",
+    "/task/template",
+    "/task/template/list",
+    "/task/template/delete",
+    "/notification/group_list",
+];
+
+fn is_group_path(path: &str) -> bool {
+    path.strip_prefix("/v1")
+        .or_else(|| path.strip_prefix("/v2"))
+        .map_or(false, |p| GROUP_PATHS.contains(&p))
+}
+
+// the claims of a token: `Authorization: Bearer <base64url(claims JSON)>.<hex(HMAC-SHA256)>`,
+// the HMAC is of the base64url claims with one of the shared secrets.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Claims {
+    pub exp: i64, // unix seconds the token expires at
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub uid: String, // the user the token acts as, empty for service callers
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub scope: String, // "admin" for the admin routes
+}
+
+pub struct Auth {
+    secrets: Vec<String>,
+    pin_uid: bool,
+}
+
+impl Auth {
+    pub fn new(cfg: &conf::Auth) -> Self {
+        Self {
+            secrets: cfg.secrets.clone(),
+            pin_uid: cfg.pin_uid,
+        }
+    }
+
+    // the auth is disabled without secrets.
+    pub fn enabled(&self) -> bool {
+        !self.secrets.is_empty()
+    }
+
+    pub fn sign(secret: &str, claims: &Claims) -> String {
+        let payload =
+            general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap_or_default());
+        let signature = events::sign(secret, payload.as_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    // verifies the token with any of the secrets so that they can be rotated.
+    pub fn verify(&self, token: &str, now_secs: i64) -> Result<Claims, HTTPError> {
+        let (payload, signature) = token
+            .split_once('.')
+            .ok_or_else(|| HTTPError::new(401, "invalid token".to_string()))?;
+        let signature = hex::decode(signature)
+            .map_err(|_| HTTPError::new(401, "invalid token signature".to_string()))?;
+        let verified = self.secrets.iter().any(|secret| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                .expect("HMAC can take key of any size");
+            mac.update(payload.as_bytes());
+            mac.verify_slice(&signature).is_ok()
+        });
+        if !verified {
+            return Err(HTTPError::new(401, "invalid token signature".to_string()));
+        }
+
+        let claims: Claims = general_purpose::URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .ok_or_else(|| HTTPError::new(401, "invalid token claims".to_string()))?;
+        if claims.exp <= now_secs {
+            return Err(HTTPError::new(401, "token expired".to_string()));
+        }
+        if self.pin_uid && claims.uid.is_empty() {
+            return Err(HTTPError::new(401, "token without uid".to_string()));
+        }
+        if !claims.uid.is_empty() && claims.uid.parse::<xid::Id>().is_err() {
+            return Err(HTTPError::new(401, "invalid token uid".to_string()));
+        }
+        Ok(claims)
+    }
+
+    fn authorize<B>(&self, req: &mut Request<B>) -> Result<(), HTTPError> {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(|| HTTPError::new(401, "missing authorization token".to_string()))?;
+        let claims = self.verify(token.trim(), (unix_ms() / 1000) as i64)?;
+        if ADMIN_PATHS.contains(&req.uri().path()) && claims.scope != ADMIN_SCOPE {
+            return Err(HTTPError::new(403, "admin scope required".to_string()));
+        }
+        if self.pin_uid && is_group_path(req.uri().path()) && claims.scope != ADMIN_SCOPE {
+            return Err(HTTPError::new(
+                403,
+                "admin scope required for the group routes".to_string(),
+            ));
+        }
+        // the uid of the token takes the place of the x-auth-user header,
+        // so that it is the user of the request context.
+        if !claims.uid.is_empty() {
+            let uid = HeaderValue::from_str(&claims.uid)
+                .map_err(|_| HTTPError::new(401, "invalid token uid".to_string()))?;
+            req.headers_mut().insert("x-auth-user", uid);
+        }
+        Ok(())
+    }
+}
+
+fn requires_auth(path: &str) -> bool {
+    AUTH_PATH_PREFIXES.iter().any(|p| path.starts_with(p)) && !OPEN_PATHS.contains(&path)
+}
+
+// rejects the requests to the API routes without a valid token, it should be layered
+// before context::middleware.
+pub async fn middleware<B>(
+    State(auth): State<Arc<Auth>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = req.uri().path();
+    if !auth.enabled() || !requires_auth(path) {
+        return next.run(req).await;
+    }
+
+    if let Err(err) = auth.authorize(&mut req) {
+        log::warn!(target: "auth", "{} {} rejected: {}", req.method(), req.uri(), err.message);
+        return err.into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_auth(pin_uid: bool) -> Auth {
+        Auth::new(&conf::Auth {
+            secrets: vec!["old secret".to_string(), "secret".to_string()],
+            pin_uid,
+        })
+    }
+
+    #[test]
+    fn verify_works() {
+        let auth = new_auth(false);
+        assert!(auth.enabled());
+        assert!(!Auth::new(&conf::Auth::default()).enabled());
+
+        let claims = Claims {
+            exp: 1000,
+            uid: "".to_string(),
+            ..Default::default()
+        };
+        let token = Auth::sign("secret", &claims);
+        assert_eq!(auth.verify(&token, 999).unwrap(), claims);
+        let token = Auth::sign("old secret", &claims);
+        assert_eq!(auth.verify(&token, 999).unwrap(), claims);
+
+        let claims = Claims {
+            exp: 1000,
+            uid: xid::new().to_string(),
+            ..Default::default()
+        };
+        let token = Auth::sign("secret", &claims);
+        assert_eq!(auth.verify(&token, 999).unwrap(), claims);
+        assert_eq!(new_auth(true).verify(&token, 999).unwrap(), claims);
+    }
+
+    #[test]
+    fn verify_expiry_works() {
+        let auth = new_auth(false);
+        let claims = Claims {
+            exp: 1000,
+            uid: "".to_string(),
+            ..Default::default()
+        };
+        let token = Auth::sign("secret", &claims);
+        for now in [1000, 1001, i64::MAX] {
+            let err = auth.verify(&token, now).unwrap_err();
+            assert_eq!(err.code, 401);
+            assert_eq!(err.message, "token expired");
+        }
+    }
+
+    #[test]
+    fn verify_signature_works() {
+        let auth = new_auth(false);
+        let claims = Claims {
+            exp: 1000,
+            uid: "".to_string(),
+            ..Default::default()
+        };
+
+        let token = Auth::sign("other secret", &claims);
+        let err = auth.verify(&token, 999).unwrap_err();
+        assert_eq!(err.code, 401);
+        assert_eq!(err.message, "invalid token signature");
+
+        // the claims can not be changed without the secret.
+        let token = Auth::sign("secret", &claims);
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = Claims {
+            exp: i64::MAX,
+            uid: "".to_string(),
+            ..Default::default()
+        };
+        let payload = general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        let err = auth
+            .verify(&format!("{}.{}", payload, signature), 999)
+            .unwrap_err();
+        assert_eq!(err.message, "invalid token signature");
+
+        for token in [
+            "",
+            "abc",
+            "abc.",
+            "abc.xyz",
+            format!("{}.", payload).as_str(),
+        ] {
+            assert_eq!(auth.verify(token, 999).unwrap_err().code, 401);
+        }
+    }
+
+    #[test]
+    fn verify_claims_works() {
+        let token = Auth::sign(
+            "secret",
+            &Claims {
+                exp: 1000,
+                uid: "".to_string(),
+                ..Default::default()
+            },
+        );
+        let err = new_auth(true).verify(&token, 999).unwrap_err();
+        assert_eq!(err.message, "token without uid");
+
+        let token = Auth::sign(
+            "secret",
+            &Claims {
+                exp: 1000,
+                uid: "not a uid".to_string(),
+                ..Default::default()
+            },
+        );
+        let err = new_auth(false).verify(&token, 999).unwrap_err();
+        assert_eq!(err.message, "invalid token uid");
+
+        let token = format!(
+            "{}.{}",
+            general_purpose::URL_SAFE_NO_PAD.encode(b"{}"),
+            events::sign(
+                "secret",
+                general_purpose::URL_SAFE_NO_PAD.encode(b"{}").as_bytes()
+            )
+        );
+        let err = new_auth(false).verify(&token, 999).unwrap_err();
+        assert_eq!(err.message, "invalid token claims");
+    }
+
+    #[test]
+    fn authorize_group_works() {
+        let request = |auth: &Auth, path: &str, scope: &str| {
+            let token = Auth::sign(
+                "secret",
+                &Claims {
+                    exp: i64::MAX,
+                    uid: xid::new().to_string(),
+                    scope: scope.to_string(),
+                },
+            );
+            let mut req = Request::builder()
+                .uri(path)
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(())
+                .unwrap();
+            auth.authorize(&mut req)
+        };
+
+        let (open, pinned) = (new_auth(false), new_auth(true));
+        for path in [
+            "/v1/task/list_by_group",
+            "/v2/task/stats",
+            "/v1/task/template",
+            "/v2/task/template/list",
+            "/v1/task/template/delete",
+            "/v2/notification/group_list",
+        ] {
+            assert!(request(&open, path, "").is_ok(), "{}", path);
+            assert!(request(&pinned, path, ADMIN_SCOPE).is_ok(), "{}", path);
+            let err = request(&pinned, path, "").unwrap_err();
+            assert_eq!(err.code, 403, "{}", path);
+            assert_eq!(err.message, "admin scope required for the group routes");
+        }
+        for path in ["/v1/task", "/v2/task/list", "/v1/notification/group_delete"] {
+            assert!(request(&pinned, path, "").is_ok(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn requires_auth_works() {
+        for path in [
+            "/v1/task/list",
+            "/v2/task/calendar_token",
+            "/internal/maintenance",
+        ] {
+            assert!(requires_auth(path), "{}", path);
+        }
+        for path in [
+            "/healthz",
+            "/metrics",
+            "/v1/task/calendar",
+            "/v2/task/calendar",
+        ] {
+            assert!(!requires_auth(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn authorize_scope_works() {
+        let auth = new_auth(false);
+        let request = |path: &str, scope: &str| {
+            let token = Auth::sign(
+                "secret",
+                &Claims {
+                    exp: i64::MAX,
+                    scope: scope.to_string(),
+                    ..Default::default()
+                },
+            );
+            let mut req = Request::builder()
+                .uri(path)
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(())
+                .unwrap();
+            auth.authorize(&mut req)
+        };
+
+        assert!(request("/v1/task/list", "").is_ok());
+        assert!(request("/v1/task/import", ADMIN_SCOPE).is_ok());
+        assert!(request("/v2/task/import", ADMIN_SCOPE).is_ok());
+        assert!(request("/internal/maintenance", ADMIN_SCOPE).is_ok());
+        for (path, scope) in [
+            ("/v1/task/import", ""),
+            ("/v1/task/import", "user"),
+            ("/v2/task/import", ""),
+            ("/internal/maintenance", "user"),
+        ] {
+            let err = request(path, scope).unwrap_err();
+            assert_eq!(err.code, 403);
+            assert_eq!(err.message, "admin scope required");
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use async_trait::async_trait;
+use std::{io::ErrorKind, path::PathBuf};
+use tokio::fs;
+
+use crate::blobs::BlobStore;
+
+// stores the blobs as files under "<dir>/<bucket>/<key>".
+pub struct FsStore {
+    root: PathBuf,
+    bucket: String,
+}
+
+impl FsStore {
+    pub fn new(dir: &str, bucket: &str) -> anyhow::Result<Self> {
+        if dir.is_empty() || bucket.is_empty() || bucket.contains(['/', '\\']) || bucket == ".." {
+            anyhow::bail!("invalid fs blob store, dir {:?}, bucket {:?}", dir, bucket);
+        }
+        let root = PathBuf::from(dir).join(bucket);
+        std::fs::create_dir_all(&root)?;
+        Ok(Self {
+            root,
+            bucket: bucket.to_string(),
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsStore {
+    fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    // writes a temporary file and renames it, readers never see a partial blob.
+    async fn put(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let tmp = path.with_extension(format!("tmp{}", xid::new()));
+        fs::write(&tmp, data).await?;
+        if let Err(err) = fs::rename(&tmp, &path).await {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+        Ok(fs::read(self.path(key)).await?)
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match fs::remove_file(self.path(key)).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr, sync::Arc};
+
+use axum_web::erring::HTTPError;
+
+use crate::conf;
+
+mod fs;
+#[cfg(feature = "s3")]
+mod s3;
+
+pub use self::fs::FsStore;
+#[cfg(feature = "s3")]
+pub use self::s3::S3Store;
+
+const SCHEME: &str = "blob://";
+
+// an object store of the payloads too large for the task rows.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    fn bucket(&self) -> &str;
+
+    async fn put(&self, key: &str, data: &[u8]) -> anyhow::Result<()>;
+
+    async fn get(&self, key: &str) -> anyhow::Result<Vec<u8>>;
+
+    // deleting a missing blob is not an error.
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+// the reference stored in the payload column of a task, "blob://bucket/key?sha256=<hex>".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobRef {
+    pub bucket: String,
+    pub key: String,
+    pub sha256: String,
+}
+
+impl fmt::Display for BlobRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}/{}?sha256={}",
+            SCHEME, self.bucket, self.key, self.sha256
+        )
+    }
+}
+
+impl FromStr for BlobRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow::anyhow!("invalid blob reference {:?}", s);
+        let (path, sha256) = s
+            .strip_prefix(SCHEME)
+            .and_then(|s| s.split_once("?sha256="))
+            .ok_or_else(invalid)?;
+        let (bucket, key) = path.split_once('/').ok_or_else(invalid)?;
+        // the keys are read back from the rows, they should not escape the bucket.
+        if bucket.is_empty()
+            || key.is_empty()
+            || key
+                .split('/')
+                .any(|p| p.is_empty() || p == "." || p == "..")
+            || sha256.len() != 64
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            sha256: sha256.to_string(),
+        })
+    }
+}
+
+impl BlobRef {
+    pub fn from_payload(payload: &[u8]) -> anyhow::Result<Self> {
+        std::str::from_utf8(payload)?.parse()
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// the blob store with the threshold of the payloads to store in it.
+#[derive(Clone)]
+pub struct Blobs {
+    store: Arc<dyn BlobStore>,
+    threshold: usize,
+}
+
+impl Blobs {
+    pub fn new(cfg: &conf::Blobs) -> anyhow::Result<Self> {
+        let store: Arc<dyn BlobStore> = match cfg.backend.as_str() {
+            "fs" => Arc::new(FsStore::new(&cfg.dir, &cfg.bucket)?),
+            #[cfg(feature = "s3")]
+            "s3" => Arc::new(S3Store::new(cfg)?),
+            #[cfg(not(feature = "s3"))]
+            "s3" => anyhow::bail!("blob backend \"s3\" requires the s3 feature"),
+            backend => anyhow::bail!("unknown blob backend {:?}", backend),
+        };
+        Ok(Self::with_store(store, cfg.threshold_bytes))
+    }
+
+    pub fn with_store(store: Arc<dyn BlobStore>, threshold: usize) -> Self {
+        Self { store, threshold }
+    }
+
+    // whether the payload should be stored out of the row.
+    pub fn is_oversized(&self, payload: &[u8]) -> bool {
+        payload.len() > self.threshold
+    }
+
+    // stores the data under the prefix, every put gets a new key so that a failed write
+    // never overwrites the blob of an existing row.
+    pub async fn put(&self, prefix: &str, data: &[u8]) -> anyhow::Result<BlobRef> {
+        let blob = BlobRef {
+            bucket: self.store.bucket().to_string(),
+            key: format!("{}/{}", prefix, xid::new()),
+            sha256: sha256_hex(data),
+        };
+        self.store.put(&blob.key, data).await?;
+        Ok(blob)
+    }
+
+    // reads the blob and verifies its checksum.
+    pub async fn get(&self, blob: &BlobRef) -> anyhow::Result<Vec<u8>> {
+        self.check_bucket(blob)?;
+        let data = self.store.get(&blob.key).await?;
+        if sha256_hex(&data) != blob.sha256 {
+            return Err(HTTPError::new(500, format!("blob checksum mismatch, {}", blob)).into());
+        }
+        Ok(data)
+    }
+
+    pub async fn delete(&self, blob: &BlobRef) -> anyhow::Result<()> {
+        self.check_bucket(blob)?;
+        self.store.delete(&blob.key).await
+    }
+
+    fn check_bucket(&self, blob: &BlobRef) -> anyhow::Result<()> {
+        if blob.bucket != self.store.bucket() {
+            return Err(HTTPError::new(
+                500,
+                format!("blob bucket {:?} is not configured", blob.bucket),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blob_ref_works() {
+        let blob = BlobRef {
+            bucket: "taskbase".to_string(),
+            key: "tasks/abc/def".to_string(),
+            sha256: sha256_hex(b"hello"),
+        };
+        let s = blob.to_string();
+        assert_eq!(
+            s,
+            "blob://taskbase/tasks/abc/def?sha256=2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(BlobRef::from_payload(s.as_bytes()).unwrap(), blob);
+
+        for s in [
+            "",
+            "s3://taskbase/key?sha256=00",
+            "blob://taskbase/key",
+            "blob://taskbase?sha256=2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            "blob://taskbase/../key?sha256=2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            "blob://taskbase/key?sha256=00",
+        ] {
+            assert!(s.parse::<BlobRef>().is_err(), "{}", s);
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn blobs_works() {
+        let dir = std::env::temp_dir().join(format!("taskbase_blobs_{}", xid::new()));
+        let store = Arc::new(FsStore::new(dir.to_str().unwrap(), "taskbase").unwrap());
+        let blobs = Blobs::with_store(store.clone(), 4);
+        assert!(!blobs.is_oversized(b"1234"));
+        assert!(blobs.is_oversized(b"12345"));
+
+        let blob = blobs.put("tasks/abc", b"hello").await.unwrap();
+        assert_eq!(blob.bucket, "taskbase");
+        assert!(blob.key.starts_with("tasks/abc/"));
+        assert_eq!(blobs.get(&blob).await.unwrap(), b"hello".to_vec());
+        // every put gets a new key.
+        assert_ne!(
+            blobs.put("tasks/abc", b"hello").await.unwrap().key,
+            blob.key
+        );
+
+        store.put(&blob.key, b"world").await.unwrap();
+        let err: HTTPError = blobs.get(&blob).await.unwrap_err().into();
+        assert_eq!(err.code, 500);
+
+        let mut other = blob.clone();
+        other.bucket = "other".to_string();
+        assert!(blobs.get(&other).await.is_err());
+
+        blobs.delete(&blob).await.unwrap();
+        assert!(blobs.get(&blob).await.is_err());
+        // deleting again is a no-op.
+        blobs.delete(&blob).await.unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+use s3::{bucket::Bucket, creds::Credentials, region::Region};
+
+use crate::blobs::BlobStore;
+use crate::conf;
+
+// stores the blobs in a bucket of an S3-compatible service, with path-style requests.
+pub struct S3Store {
+    bucket: Bucket,
+}
+
+impl S3Store {
+    pub fn new(cfg: &conf::Blobs) -> anyhow::Result<Self> {
+        let credentials = Credentials::new(
+            Some(&cfg.access_key),
+            Some(&cfg.secret_key),
+            None,
+            None,
+            None,
+        )?;
+        let region = Region::Custom {
+            region: cfg.region.clone(),
+            endpoint: cfg.endpoint.clone(),
+        };
+        let bucket = Bucket::new(&cfg.bucket, region, credentials)?.with_path_style();
+        Ok(Self { bucket })
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3Store {
+    fn bucket(&self) -> &str {
+        &self.bucket.name
+    }
+
+    async fn put(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        self.bucket.put_object(key, data).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+        let res = self.bucket.get_object(key).await?;
+        Ok(res.bytes().to_vec())
+    }
+
+    // S3 deletes are idempotent.
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.bucket.delete_object(key).await?;
+        Ok(())
+    }
+}
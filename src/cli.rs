@@ -0,0 +1,401 @@
+use axum_web::context::unix_ms;
+use clap::{Parser, Subcommand};
+use std::collections::HashMap;
+
+use crate::conf;
+use crate::db::{
+    self,
+    scylladb::{exists, CqlExecutor},
+};
+use crate::router;
+
+// the rows read per page of the maintenance jobs.
+const PAGE_SIZE: u16 = 500;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "taskbase",
+    version,
+    about = "Taskbase server and maintenance jobs"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve the API, the default command
+    Serve,
+    /// Delete the tasks of the user with their indexes and notifications,
+    /// the notifications received by the user are deleted too without --status
+    PurgeUid {
+        uid: xid::Id,
+        /// Only delete the tasks with the status, e.g. -4 for trashed
+        #[arg(long, allow_hyphen_values = true)]
+        status: Option<i8>,
+    },
+    /// Rewrite the task counters of the user from a scan of the tasks
+    Recount { uid: xid::Id },
+    /// Rewrite the task_by_participant index rows of all tasks
+    BackfillParticipants,
+    /// Find the notifications whose task no longer exists
+    OrphanScan {
+        /// Delete the orphaned notifications
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Re-encrypt the task payloads of the user with the primary encryption key,
+    /// run it for every user after rotating the key before dropping the old one
+    ReencryptUid { uid: xid::Id },
+    /// Count the finalized tasks, the archived ones included, in the group stats.
+    /// The stats are added to, so it should run once after the upgrade
+    BackfillStats {
+        /// Only count the tasks finalized before it, in unix milliseconds, the time
+        /// the stats started being recorded, default to now
+        #[arg(long)]
+        before: Option<i64>,
+    },
+}
+
+// runs a maintenance job, the progress is printed to stdout. It returns an error
+// if any row failed, so that the process exits non-zero.
+pub async fn run(cmd: Command, cfg: conf::Conf) -> anyhow::Result<()> {
+    let keyspace = router::keyspace(&cfg);
+    let keyring = router::new_keyring(&cfg)?;
+    let mut db = router::new_scylla(cfg.scylla, &keyspace).await?;
+    if let Some(keyring) = keyring {
+        db = db.with_keyring(keyring);
+    }
+
+    let failed = match cmd {
+        Command::Serve => 0,
+        Command::PurgeUid { uid, status } => {
+            let status = status.map(db::TaskStatus::try_from).transpose()?;
+            purge_uid(&db, uid, status).await?
+        }
+        Command::Recount { uid } => {
+            for (status, count) in db::counters::recount(&db, uid).await? {
+                println!("status {}: {}", status, count);
+            }
+            0
+        }
+        Command::BackfillParticipants => backfill_participants(&db).await?,
+        Command::OrphanScan { fix } => orphan_scan(&db, fix).await?,
+        Command::ReencryptUid { uid } => reencrypt_uid(&db, uid).await?,
+        Command::BackfillStats { before } => {
+            backfill_stats(&db, before.unwrap_or(unix_ms() as i64)).await?
+        }
+    };
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} rows failed", failed));
+    }
+    Ok(())
+}
+
+async fn purge_uid(
+    db: &db::scylladb::ScyllaDB,
+    uid: xid::Id,
+    status: Option<db::TaskStatus>,
+) -> anyhow::Result<u64> {
+    let (mut removed, mut failed) = (0u64, 0u64);
+    let mut page_token: Option<xid::Id> = None;
+    loop {
+        let docs = db::Task::list(
+            db,
+            uid,
+            vec!["gid".to_string()],
+            PAGE_SIZE,
+            page_token,
+            status,
+            None,
+            false,
+        )
+        .await?;
+        if docs.is_empty() {
+            break;
+        }
+
+        page_token = docs.last().map(|doc| doc.id);
+        for mut doc in docs {
+            match purge_task(db, &mut doc).await {
+                Ok(true) => removed += 1,
+                Ok(false) => {}
+                Err(err) => {
+                    failed += 1;
+                    println!("task {} failed: {}", doc.id, err);
+                }
+            }
+        }
+        println!("{} tasks of {} removed, {} failed", removed, uid, failed);
+    }
+
+    if status.is_none() {
+        let res = db::Notification::batch_delete_by_uid(db, uid, None).await?;
+        println!("{} notifications of {} removed", res, uid);
+    }
+    Ok(failed)
+}
+
+// rewrites the payloads of the user sealed with an older key or not sealed, the trashed
+// tasks included. The archived tasks and the blobs of the external payloads are kept.
+async fn reencrypt_uid(db: &db::scylladb::ScyllaDB, uid: xid::Id) -> anyhow::Result<u64> {
+    if db.keyring().is_none() {
+        anyhow::bail!("the encryption is not configured");
+    }
+
+    let (mut rewritten, mut failed) = (0u64, 0u64);
+    let mut page_token: Option<xid::Id> = None;
+    loop {
+        let docs = db::Task::list(
+            db,
+            uid,
+            vec!["gid".to_string()],
+            PAGE_SIZE,
+            page_token,
+            None,
+            None,
+            false,
+        )
+        .await?;
+        if docs.is_empty() {
+            break;
+        }
+
+        page_token = docs.last().map(|doc| doc.id);
+        for doc in docs {
+            match doc.reencrypt(db).await {
+                Ok(true) => rewritten += 1,
+                Ok(false) => {}
+                Err(err) => {
+                    failed += 1;
+                    println!("task {} failed: {}", doc.id, err);
+                }
+            }
+        }
+        println!(
+            "{} tasks of {} re-encrypted, {} failed",
+            rewritten, uid, failed
+        );
+    }
+    Ok(failed)
+}
+
+// deletes the task with its indexes, comments and notifications.
+async fn purge_task(db: &db::scylladb::ScyllaDB, doc: &mut db::Task) -> anyhow::Result<bool> {
+    let removed = doc.delete(db, None).await?;
+    let mut notify = db::GroupNotification::with_pk(doc.gid, doc.id, doc.uid);
+    let _ = notify.delete(db).await;
+    db::Notification::batch_delete_by_tid(db, doc.id).await?;
+    Ok(removed)
+}
+
+async fn backfill_participants(db: &db::scylladb::ScyllaDB) -> anyhow::Result<u64> {
+    let fields = vec![
+        "status".to_string(),
+        "duedate".to_string(),
+        "approvers".to_string(),
+        "assignees".to_string(),
+    ];
+    let (mut scanned, mut failed) = (0u64, 0u64);
+    let mut paging_state: Option<Vec<u8>> = None;
+    loop {
+        let (docs, next) = db::Task::scan(db, fields.clone(), PAGE_SIZE, paging_state).await?;
+        for doc in docs {
+            scanned += 1;
+            if let Err(err) = doc.save_participant_index(db, &doc.participants()).await {
+                failed += 1;
+                println!("task {} of {} failed: {}", doc.id, doc.uid, err);
+            }
+        }
+        println!("{} tasks scanned, {} failed", scanned, failed);
+
+        paging_state = next;
+        if paging_state.is_none() {
+            break;
+        }
+    }
+    Ok(failed)
+}
+
+async fn backfill_stats(db: &db::scylladb::ScyllaDB, before: i64) -> anyhow::Result<u64> {
+    let fields = vec![
+        "status".to_string(),
+        "created_at".to_string(),
+        "updated_at".to_string(),
+        "duedate".to_string(),
+    ];
+    let (mut counted, mut failed) = (0u64, 0u64);
+    for archived in [false, true] {
+        let mut paging_state: Option<Vec<u8>> = None;
+        loop {
+            let (docs, next) = if archived {
+                db::Task::scan_archived(db, fields.clone(), PAGE_SIZE, paging_state).await?
+            } else {
+                db::Task::scan(db, fields.clone(), PAGE_SIZE, paging_state).await?
+            };
+            for doc in docs {
+                if !db::stats::is_outcome(doc.status) {
+                    continue;
+                }
+                match backfill_task_stats(db, &doc, before).await {
+                    Ok(true) => counted += 1,
+                    Ok(false) => {}
+                    Err(err) => {
+                        failed += 1;
+                        println!("task {} of {} failed: {}", doc.id, doc.uid, err);
+                    }
+                }
+            }
+            println!("{} tasks counted, {} failed", counted, failed);
+
+            paging_state = next;
+            if paging_state.is_none() {
+                break;
+            }
+        }
+    }
+    Ok(failed)
+}
+
+// the task is dated by its last status change in the task log, an expired task without
+// one by its duedate, and others by updated_at.
+async fn backfill_task_stats(
+    db: &db::scylladb::ScyllaDB,
+    doc: &db::Task,
+    before: i64,
+) -> anyhow::Result<bool> {
+    let logs = db::TaskLog::list(db, doc.uid, doc.id, 100, None).await?;
+    let changed = db::TaskAction::StatusChanged.as_str();
+    let finalized_at = match logs.iter().find(|log| log.action == changed) {
+        Some(log) => log.created_at,
+        None if doc.status == db::TaskStatus::Expired && doc.duedate > 0 => doc.duedate,
+        None => doc.updated_at,
+    };
+    if finalized_at >= before {
+        return Ok(false);
+    }
+
+    db::stats::record(db, doc.gid, doc.status, doc.created_at, finalized_at).await?;
+    Ok(true)
+}
+
+async fn orphan_scan(db: &db::scylladb::ScyllaDB, fix: bool) -> anyhow::Result<u64> {
+    let (mut scanned, mut orphans, mut failed) = (0u64, 0u64, 0u64);
+    let mut paging_state: Option<Vec<u8>> = None;
+    loop {
+        let (docs, next) = db::Notification::scan(db, PAGE_SIZE, paging_state).await?;
+        // the receivers of a task are likely in the same page.
+        let mut tasks: HashMap<(xid::Id, xid::Id), bool> = HashMap::new();
+        for mut doc in docs {
+            scanned += 1;
+            let key = (doc.sender, doc.tid);
+            let found = match tasks.get(&key) {
+                Some(found) => *found,
+                None => match task_exists(db, doc.sender, doc.tid).await {
+                    Ok(found) => {
+                        tasks.insert(key, found);
+                        found
+                    }
+                    Err(err) => {
+                        failed += 1;
+                        println!("notification {} of {} failed: {}", doc.tid, doc.uid, err);
+                        continue;
+                    }
+                },
+            };
+            if found {
+                continue;
+            }
+
+            orphans += 1;
+            println!(
+                "orphaned notification: uid {}, tid {}, sender {}",
+                doc.uid, doc.tid, doc.sender
+            );
+            if fix {
+                if let Err(err) = doc.delete(db).await {
+                    failed += 1;
+                    println!("notification {} of {} failed: {}", doc.tid, doc.uid, err);
+                }
+            }
+        }
+        println!(
+            "{} notifications scanned, {} orphaned, {} failed",
+            scanned, orphans, failed
+        );
+
+        paging_state = next;
+        if paging_state.is_none() {
+            break;
+        }
+    }
+    Ok(failed)
+}
+
+// the task of a notification may have been archived.
+async fn task_exists(
+    db: &db::scylladb::ScyllaDB,
+    uid: xid::Id,
+    id: xid::Id,
+) -> anyhow::Result<bool> {
+    let mut doc = db::Task::with_pk(uid, id);
+    if exists(doc.get_one(db, vec!["status".to_string()]).await)? {
+        return Ok(true);
+    }
+    exists(doc.get_archived(db, vec!["status".to_string()]).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_works() {
+        let cli = Cli::try_parse_from(["taskbase"]).unwrap();
+        assert!(cli.command.is_none());
+
+        let uid = xid::new();
+        let cli = Cli::try_parse_from([
+            "taskbase",
+            "purge-uid",
+            uid.to_string().as_str(),
+            "--status",
+            "-4",
+        ])
+        .unwrap();
+        match cli.command {
+            Some(Command::PurgeUid { uid: id, status }) => {
+                assert_eq!(id, uid);
+                assert_eq!(status, Some(-4));
+            }
+            _ => panic!("expected purge-uid"),
+        }
+
+        let cli = Cli::try_parse_from(["taskbase", "orphan-scan", "--fix"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::OrphanScan { fix: true })
+        ));
+
+        let cli =
+            Cli::try_parse_from(["taskbase", "reencrypt-uid", uid.to_string().as_str()]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::ReencryptUid { uid: id }) if id == uid
+        ));
+
+        let cli = Cli::try_parse_from(["taskbase", "backfill-stats", "--before", "1700000000000"])
+            .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::BackfillStats {
+                before: Some(1700000000000)
+            })
+        ));
+
+        assert!(Cli::try_parse_from(["taskbase", "recount", "not a uid"]).is_err());
+        assert!(Cli::try_parse_from(["taskbase", "unknown"]).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use config::{Config, ConfigError, File, FileFormat};
 use serde::Deserialize;
+use std::{collections::HashMap, fmt};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Log {
@@ -19,14 +20,432 @@ pub struct ScyllaDB {
     pub nodes: Vec<String>,
     pub username: String,
     pub password: String,
+    #[serde(default = "default_consistency")]
+    pub consistency: String,
+    #[serde(default = "default_serial_consistency")]
+    pub serial_consistency: String,
+    #[serde(default = "default_lwt_retries")]
+    pub lwt_retries: u32,
+    #[serde(default = "default_lwt_backoff_ms")]
+    pub lwt_backoff_ms: u64,
+    #[serde(default)]
+    pub auto_migrate: bool,
+    #[serde(default = "default_replication")]
+    pub replication: String,
+    #[serde(default)]
+    pub keyspace: String, // empty for "taskbase", or "taskbase_test" in the test env
+}
+
+fn default_consistency() -> String {
+    "QUORUM".to_string()
+}
+
+fn default_serial_consistency() -> String {
+    "SERIAL".to_string()
+}
+
+fn default_lwt_retries() -> u32 {
+    3
+}
+
+fn default_lwt_backoff_ms() -> u64 {
+    50
+}
+
+fn default_replication() -> String {
+    "{ 'class': 'NetworkTopologyStrategy', 'replication_factor': '3' }".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Worker {
+    pub scan_interval: u64,
+    pub batch_size: u16,
+    pub reminder_lead: u64,
+    #[serde(default)]
+    pub reminder_kind_leads: HashMap<String, u64>,
+    #[serde(default = "default_trash_retention")]
+    pub trash_retention: u64,
+    #[serde(default)]
+    pub archive_after_ms: u64,
+}
+
+// 30 days
+fn default_trash_retention() -> u64 {
+    30 * 24 * 3600
+}
+
+impl Worker {
+    // reminder lead time of the task kind in milliseconds.
+    pub fn reminder_lead_ms(&self, kind: &str) -> i64 {
+        let secs = self
+            .reminder_kind_leads
+            .get(kind)
+            .copied()
+            .unwrap_or(self.reminder_lead);
+        secs as i64 * 1000
+    }
+
+    // how long a trashed task can be restored before it is purged, in milliseconds.
+    pub fn trash_retention_ms(&self) -> i64 {
+        self.trash_retention as i64 * 1000
+    }
+
+    pub fn max_reminder_lead_ms(&self) -> i64 {
+        let secs = self
+            .reminder_kind_leads
+            .values()
+            .copied()
+            .fold(self.reminder_lead, u64::max);
+        secs as i64 * 1000
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Webhook {
+    pub url: String,
+    pub secret: String,
+    #[serde(default)]
+    pub kinds: Vec<String>, // task kinds to deliver, empty for all kinds
+    #[serde(default = "default_webhook_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_webhook_max_retries() -> u32 {
+    3
+}
+
+impl Webhook {
+    pub fn matches(&self, kind: &str) -> bool {
+        self.kinds.is_empty() || self.kinds.iter().any(|k| k == kind)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Kind {
+    #[serde(default)]
+    pub max_payload: usize, // bytes, 0 for max_payload_bytes only
+    #[serde(default)]
+    pub default_threshold: i16,
+    #[serde(default)]
+    pub default_duedate_offset_ms: i64, // 0 for no default duedate
+    #[serde(default = "default_kind_allowed")]
+    pub allowed: bool,
+    #[serde(default)]
+    pub reasons: Vec<String>, // reason codes of the acks, empty for the global reasons
+}
+
+fn default_kind_allowed() -> bool {
+    true
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct Kinds {
+    #[serde(default)]
+    pub strict: bool, // rejects the kinds not registered, an empty registry accepts any kind
+    #[serde(default)]
+    pub registry: HashMap<String, Kind>,
+    #[serde(default)]
+    pub reasons: Vec<String>, // reason codes of the acks of any kind, empty to reject any reason
+}
+
+impl Kinds {
+    // the reason codes allowed on the acks of the task kind.
+    pub fn reasons(&self, kind: &str) -> &[String] {
+        match self.registry.get(kind) {
+            Some(k) if !k.reasons.is_empty() => &k.reasons,
+            _ => &self.reasons,
+        }
+    }
+}
+
+// 256 KB
+fn default_max_payload_bytes() -> usize {
+    256 * 1024
+}
+
+// 4 KB
+fn default_max_message_len() -> usize {
+    4 * 1024
+}
+
+fn default_max_page_size() -> u16 {
+    100
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Metrics {
+    #[serde(default = "default_metrics_namespace")]
+    pub namespace: String, // the prefix of the metric names
+    #[serde(default)]
+    pub port: u16, // 0 to serve /metrics on the server port
+}
+
+fn default_metrics_namespace() -> String {
+    "taskbase".to_string()
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            namespace: default_metrics_namespace(),
+            port: 0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct Auth {
+    #[serde(default)]
+    pub secrets: Vec<String>, // the shared secrets of the tokens, empty to disable the auth
+    #[serde(default)]
+    pub pin_uid: bool, // tokens should carry a uid, and the uid of the request should match it
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Observability {
+    #[serde(default)]
+    pub endpoint: String, // the OTLP gRPC endpoint, empty to disable the exporter
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64, // the ratio of the root spans sampled, in [0, 1]
+}
+
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+impl Default for Observability {
+    fn default() -> Self {
+        Self {
+            endpoint: "".to_string(),
+            sample_ratio: default_sample_ratio(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Limits {
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize, // the cap of the request bodies besides the import
+    #[serde(default = "default_max_import_bytes")]
+    pub max_import_bytes: usize, // the cap of the streamed import body
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout: u64, // seconds
+    #[serde(default = "default_write_timeout")]
+    pub write_timeout: u64, // seconds
+    #[serde(default = "default_stream_timeout")]
+    pub stream_timeout: u64, // seconds, of the export and the import
+}
+
+// 1 MB
+fn default_max_body_bytes() -> usize {
+    1024 * 1024
+}
+
+// 256 MB
+fn default_max_import_bytes() -> usize {
+    256 * 1024 * 1024
+}
+
+fn default_read_timeout() -> u64 {
+    5
+}
+
+fn default_write_timeout() -> u64 {
+    10
+}
+
+fn default_stream_timeout() -> u64 {
+    300
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TaskCache {
+    #[serde(default)]
+    pub ttl_ms: u64, // 0 disables the cache
+    #[serde(default = "default_task_cache_capacity")]
+    pub capacity: u64, // the max number of cached tasks
+}
+
+fn default_task_cache_capacity() -> u64 {
+    1000
+}
+
+impl Default for TaskCache {
+    fn default() -> Self {
+        Self {
+            ttl_ms: 0,
+            capacity: default_task_cache_capacity(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Calendar {
+    #[serde(default)]
+    pub secret: String, // the key of the feed tokens, empty disables the feed
+    #[serde(default = "default_calendar_max_events")]
+    pub max_events: usize,
+}
+
+fn default_calendar_max_events() -> usize {
+    2000
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Self {
+            secret: String::new(),
+            max_events: default_calendar_max_events(),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: default_max_body_bytes(),
+            max_import_bytes: default_max_import_bytes(),
+            read_timeout: default_read_timeout(),
+            write_timeout: default_write_timeout(),
+            stream_timeout: default_stream_timeout(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Encryption {
+    pub primary_key_id: String,        // the key of the writes
+    pub keys: HashMap<String, String>, // key id -> base64 encoded 32 bytes AES-256 key
+}
+
+// the keys are not printed.
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption")
+            .field("primary_key_id", &self.primary_key_id)
+            .field("keys", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Blobs {
+    pub threshold_bytes: usize, // payloads larger than it are stored in the blob store
+    #[serde(default = "default_blobs_backend")]
+    pub backend: String, // "fs" or "s3", "s3" requires the s3 feature
+    pub bucket: String,
+    #[serde(default)]
+    pub dir: String, // the root directory of the fs backend, the buckets are its subdirectories
+    #[serde(default)]
+    pub endpoint: String, // the endpoint of the s3 backend, e.g. "http://127.0.0.1:9000"
+    #[serde(default)]
+    pub region: String,
+    #[serde(default)]
+    pub access_key: String,
+    #[serde(default)]
+    pub secret_key: String,
+}
+
+fn default_blobs_backend() -> String {
+    "fs".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Cors {
+    pub allowed_origins: Vec<String>, // "*" for any origin
+    #[serde(default = "default_cors_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    #[serde(default = "default_cors_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+    #[serde(default)]
+    pub expose_headers: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    #[serde(default = "default_cors_max_age")]
+    pub max_age: u64, // seconds the preflight responses are cached
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    vec!["GET".to_string(), "POST".to_string(), "PATCH".to_string()]
+}
+
+// content-type is not safelisted for application/cbor, content-encoding is for
+// the compressed request bodies.
+fn default_cors_allowed_headers() -> Vec<String> {
+    vec![
+        "accept".to_string(),
+        "authorization".to_string(),
+        "content-encoding".to_string(),
+        "content-type".to_string(),
+        "x-request-id".to_string(),
+    ]
+}
+
+fn default_cors_max_age() -> u64 {
+    600
+}
+
+impl Cors {
+    // browsers reject credentials with the "*" wildcard.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allowed_origins.is_empty() {
+            return Err("cors.allowed_origins should not be empty".to_string());
+        }
+        if self.allow_credentials {
+            for (name, vals) in [
+                ("allowed_origins", &self.allowed_origins),
+                ("allowed_methods", &self.allowed_methods),
+                ("allowed_headers", &self.allowed_headers),
+                ("expose_headers", &self.expose_headers),
+            ] {
+                if vals.iter().any(|v| v == "*") {
+                    return Err(format!(
+                        "cors.{} can not be \"*\" with allow_credentials",
+                        name
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Conf {
     pub env: String,
+    #[serde(default)]
+    pub notification_ttl_secs: u32,
+    #[serde(default = "default_max_payload_bytes")]
+    pub max_payload_bytes: usize,
+    #[serde(default = "default_max_message_len")]
+    pub max_message_len: usize,
+    #[serde(default = "default_max_page_size")]
+    pub max_page_size: u16,
+    #[serde(default)]
+    pub maintenance: bool, // start in the maintenance mode
     pub log: Log,
     pub server: Server,
     pub scylla: ScyllaDB,
+    pub worker: Worker,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    #[serde(default)]
+    pub kinds: Kinds,
+    #[serde(default)]
+    pub metrics: Metrics,
+    #[serde(default)]
+    pub observability: Observability,
+    #[serde(default)]
+    pub auth: Auth,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub task_cache: TaskCache,
+    #[serde(default)]
+    pub calendar: Calendar,
+    pub cors: Option<Cors>,             // no CORS layer without the section
+    pub blobs: Option<Blobs>, // payloads are always stored in the task rows without the section
+    pub encryption: Option<Encryption>, // payloads are stored in plaintext without the section
 }
 
 impl Conf {
@@ -38,6 +457,38 @@ impl Conf {
 
     pub fn from(file_name: &str) -> Result<Self, ConfigError> {
         let builder = Config::builder().add_source(File::new(file_name, FileFormat::Toml));
-        builder.build()?.try_deserialize::<Conf>()
+        let conf = builder.build()?.try_deserialize::<Conf>()?;
+        if let Some(cors) = &conf.cors {
+            cors.validate().map_err(ConfigError::Message)?;
+        }
+        Ok(conf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cors_validate_works() {
+        let mut cors = Cors {
+            allowed_origins: vec!["https://www.yiwen.ai".to_string()],
+            allowed_methods: default_cors_allowed_methods(),
+            allowed_headers: default_cors_allowed_headers(),
+            expose_headers: vec![],
+            allow_credentials: true,
+            max_age: default_cors_max_age(),
+        };
+        assert!(cors.validate().is_ok());
+
+        cors.allowed_origins = vec!["*".to_string()];
+        let err = cors.validate().unwrap_err();
+        assert!(err.contains("allowed_origins"));
+
+        cors.allow_credentials = false;
+        assert!(cors.validate().is_ok());
+
+        cors.allowed_origins = vec![];
+        assert!(cors.validate().is_err());
     }
 }
@@ -0,0 +1,227 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose, Engine as _};
+use std::collections::HashMap;
+
+use axum_web::erring::HTTPError;
+
+use crate::conf;
+
+// the sealed data is "MAGIC | key id length | key id | nonce | ciphertext", the header
+// before the nonce is authenticated with the ciphertext.
+const MAGIC: &[u8; 4] = b"\x00tbe";
+const NONCE_LEN: usize = 12;
+
+// the AES-256-GCM keys of the payloads at rest. Writes use the primary key,
+// reads accept any key of the ring so that the keys can be rotated.
+pub struct Keyring {
+    primary: String,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl Keyring {
+    pub fn new(cfg: &conf::Encryption) -> anyhow::Result<Self> {
+        let mut keys = HashMap::with_capacity(cfg.keys.len());
+        for (id, key) in &cfg.keys {
+            if id.is_empty() || id.len() > u8::MAX as usize {
+                anyhow::bail!("invalid encryption key id {:?}", id);
+            }
+            let key = general_purpose::STANDARD.decode(key)?;
+            if key.len() != 32 {
+                anyhow::bail!(
+                    "invalid encryption key {:?}, expected 32 bytes, got {}",
+                    id,
+                    key.len()
+                );
+            }
+            let key = Key::<Aes256Gcm>::from_slice(&key);
+            keys.insert(id.clone(), Aes256Gcm::new(key));
+        }
+        if !keys.contains_key(&cfg.primary_key_id) {
+            anyhow::bail!(
+                "primary encryption key {:?} is not in the keyring",
+                cfg.primary_key_id
+            );
+        }
+        Ok(Self {
+            primary: cfg.primary_key_id.clone(),
+            keys,
+        })
+    }
+
+    pub fn primary(&self) -> &str {
+        &self.primary
+    }
+
+    // encrypts the data with the primary key, empty data is kept empty.
+    pub fn seal(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if data.is_empty() {
+            return Ok(Vec::new());
+        }
+        let cipher = &self.keys[&self.primary];
+        let mut sealed =
+            Vec::with_capacity(MAGIC.len() + 1 + self.primary.len() + NONCE_LEN + data.len() + 16);
+        sealed.extend_from_slice(MAGIC);
+        sealed.push(self.primary.len() as u8);
+        sealed.extend_from_slice(self.primary.as_bytes());
+        let header_len = sealed.len();
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: data,
+                    aad: &sealed[..header_len],
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("encrypt failed"))?;
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    // decrypts the sealed data with the key of its key id, other data is returned as is.
+    pub fn open(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let key_id = match key_id(data) {
+            Some(key_id) => key_id,
+            None => return Ok(data.to_vec()),
+        };
+        let cipher = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| HTTPError::new(500, format!("unknown encryption key {:?}", key_id)))?;
+        let header_len = MAGIC.len() + 1 + key_id.len();
+        let nonce = Nonce::from_slice(&data[header_len..header_len + NONCE_LEN]);
+        cipher
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: &data[header_len + NONCE_LEN..],
+                    aad: &data[..header_len],
+                },
+            )
+            .map_err(|_| {
+                HTTPError::new(500, format!("decrypt failed with key {:?}", key_id)).into()
+            })
+    }
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    key_id(data).is_some()
+}
+
+// the key id of the sealed data.
+pub fn key_id(data: &[u8]) -> Option<&str> {
+    let rest = data.strip_prefix(MAGIC.as_slice())?;
+    let (len, rest) = rest.split_first()?;
+    let len = *len as usize;
+    if len == 0 || rest.len() < len + NONCE_LEN + 16 {
+        return None;
+    }
+    std::str::from_utf8(&rest[..len]).ok()
+}
+
+// a keyring of test keys, the key bytes are the last byte of the key id.
+#[cfg(test)]
+pub fn test_keyring(primary: &str, ids: &[&str]) -> Keyring {
+    Keyring::new(&conf::Encryption {
+        primary_key_id: primary.to_string(),
+        keys: ids
+            .iter()
+            .map(|id| {
+                let key = [*id.as_bytes().last().unwrap(); 32];
+                (id.to_string(), general_purpose::STANDARD.encode(key))
+            })
+            .collect(),
+    })
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyring_works() {
+        let ring = test_keyring("k1", &["k1"]);
+        assert_eq!(ring.primary(), "k1");
+        assert!(ring.seal(&[]).unwrap().is_empty());
+
+        let sealed = ring.seal(b"hello").unwrap();
+        assert!(is_sealed(&sealed));
+        assert_eq!(key_id(&sealed), Some("k1"));
+        assert!(!sealed.windows(5).any(|w| w == b"hello"));
+        assert_eq!(ring.open(&sealed).unwrap(), b"hello".to_vec());
+        // the nonces differ.
+        assert_ne!(ring.seal(b"hello").unwrap(), sealed);
+        // plaintext is returned as is.
+        assert!(!is_sealed(b"hello"));
+        assert_eq!(ring.open(b"hello").unwrap(), b"hello".to_vec());
+
+        // tampered data fails to open.
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(ring.open(&tampered).is_err());
+
+        // a rotated ring writes with the new key and reads both.
+        let rotated = test_keyring("k2", &["k1", "k2"]);
+        assert_eq!(rotated.open(&sealed).unwrap(), b"hello".to_vec());
+        let sealed2 = rotated.seal(b"hello").unwrap();
+        assert_eq!(key_id(&sealed2), Some("k2"));
+        let err: HTTPError = ring.open(&sealed2).unwrap_err().into();
+        assert_eq!(err.code, 500);
+        assert!(err.message.contains("unknown encryption key"));
+    }
+
+    #[test]
+    fn keyring_wrong_key_works() {
+        let ring = test_keyring("k1", &["k1"]);
+        let sealed = ring.seal(b"hello").unwrap();
+
+        // the same key id with another key.
+        let other = Keyring::new(&conf::Encryption {
+            primary_key_id: "k1".to_string(),
+            keys: HashMap::from([(
+                "k1".to_string(),
+                general_purpose::STANDARD.encode([9u8; 32]),
+            )]),
+        })
+        .unwrap();
+        let err: HTTPError = other.open(&sealed).unwrap_err().into();
+        assert_eq!(err.code, 500);
+        assert!(err.message.contains("decrypt failed"));
+
+        // the key id of the header is authenticated, even if both ids have the same key.
+        let key = general_purpose::STANDARD.encode([b'1'; 32]);
+        let aliased = Keyring::new(&conf::Encryption {
+            primary_key_id: "k1".to_string(),
+            keys: HashMap::from([("k1".to_string(), key.clone()), ("k2".to_string(), key)]),
+        })
+        .unwrap();
+        assert_eq!(aliased.open(&sealed).unwrap(), b"hello".to_vec());
+        let mut relabeled = sealed.clone();
+        relabeled[6] = b'2';
+        assert_eq!(key_id(&relabeled), Some("k2"));
+        assert!(aliased.open(&relabeled).is_err());
+
+        assert!(Keyring::new(&conf::Encryption {
+            primary_key_id: "k2".to_string(),
+            keys: HashMap::from([(
+                "k1".to_string(),
+                general_purpose::STANDARD.encode([1u8; 32])
+            )]),
+        })
+        .is_err());
+        assert!(Keyring::new(&conf::Encryption {
+            primary_key_id: "k1".to_string(),
+            keys: HashMap::from([(
+                "k1".to_string(),
+                general_purpose::STANDARD.encode([1u8; 16])
+            )]),
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use moka::sync::Cache;
+use std::time::Duration;
+
+use crate::db::Task;
+
+// an in-process cache of the full rows of hot tasks keyed by (uid, id), see `Task::get_cached`.
+// The entries expire after the ttl, the mutations of `Task` invalidate them, see `Invalidation`.
+pub struct TaskCache {
+    tasks: Cache<(xid::Id, xid::Id), Task>,
+}
+
+impl TaskCache {
+    pub fn new(ttl: Duration, capacity: u64) -> Self {
+        Self {
+            tasks: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_live(ttl)
+                .support_invalidation_closures()
+                .build(),
+        }
+    }
+
+    pub fn get(&self, uid: xid::Id, id: xid::Id) -> Option<Task> {
+        self.tasks.get(&(uid, id))
+    }
+
+    pub fn insert(&self, task: Task) {
+        self.tasks.insert((task.uid, task.id), task);
+    }
+
+    pub fn invalidate(&self, uid: xid::Id, id: xid::Id) {
+        self.tasks.invalidate(&(uid, id));
+    }
+
+    // invalidates all the cached tasks of the user, the entries are removed in the background.
+    pub fn invalidate_uid(&self, uid: xid::Id) {
+        if let Err(err) = self.tasks.invalidate_entries_if(move |k, _| k.0 == uid) {
+            log::error!(target: "db", "failed to invalidate the tasks of {}: {}", uid, err);
+        }
+    }
+}
+
+// invalidates the cached task when dropped. A mutation holds one for its duration, so that
+// the task is invalidated however it returns, a failed or conflicting mutation may have
+// applied some of its writes.
+pub struct Invalidation<'a> {
+    cache: Option<&'a TaskCache>,
+    uid: xid::Id,
+    id: Option<xid::Id>, // None for all the tasks of the user
+}
+
+impl<'a> Invalidation<'a> {
+    pub fn new(cache: Option<&'a TaskCache>, uid: xid::Id, id: xid::Id) -> Self {
+        Self {
+            cache,
+            uid,
+            id: Some(id),
+        }
+    }
+
+    pub fn uid(cache: Option<&'a TaskCache>, uid: xid::Id) -> Self {
+        Self {
+            cache,
+            uid,
+            id: None,
+        }
+    }
+}
+
+impl Drop for Invalidation<'_> {
+    fn drop(&mut self) {
+        match (self.cache, self.id) {
+            (Some(cache), Some(id)) => cache.invalidate(self.uid, id),
+            (Some(cache), None) => cache.invalidate_uid(self.uid),
+            (None, _) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalidation_works() {
+        let cache = TaskCache::new(Duration::from_secs(60), 100);
+        let doc = Task::with_pk(xid::new(), xid::new());
+        cache.insert(doc.clone());
+
+        {
+            let _invalidation = Invalidation::new(Some(&cache), doc.uid, doc.id);
+            assert!(cache.get(doc.uid, doc.id).is_some());
+        }
+        assert!(cache.get(doc.uid, doc.id).is_none());
+
+        // without a cache it does nothing.
+        drop(Invalidation::new(None, doc.uid, doc.id));
+
+        let other = Task::with_pk(xid::new(), xid::new());
+        cache.insert(doc.clone());
+        cache.insert(other.clone());
+        drop(Invalidation::uid(Some(&cache), doc.uid));
+        assert!(cache.get(doc.uid, doc.id).is_none());
+        assert!(cache.get(other.uid, other.id).is_some());
+    }
+}
@@ -0,0 +1,170 @@
+use scylla_orm::{ColumnsMap, ToCqlVal};
+use std::collections::BTreeMap;
+
+use crate::db::{scylladb::CqlExecutor, TaskStatus};
+
+// task counters by uid and status, kept in the task_counter table.
+// counter updates are not idempotent, so the counters may drift, `recount` fixes them.
+
+pub async fn incr(
+    db: &impl CqlExecutor,
+    uid: xid::Id,
+    status: TaskStatus,
+    delta: i64,
+) -> anyhow::Result<()> {
+    add(db, uid, status.into(), delta).await
+}
+
+// the counters are keyed by the raw status, so that recount can fix any bucket.
+async fn add(db: &impl CqlExecutor, uid: xid::Id, status: i8, delta: i64) -> anyhow::Result<()> {
+    if delta == 0 {
+        return Ok(());
+    }
+
+    let query = "UPDATE task_counter SET count=count+? WHERE uid=? AND status=?";
+    let params = (delta, uid.to_cql(), status);
+    let _ = db.execute(query, params).await?;
+    Ok(())
+}
+
+pub async fn decr(
+    db: &impl CqlExecutor,
+    uid: xid::Id,
+    status: TaskStatus,
+    delta: i64,
+) -> anyhow::Result<()> {
+    incr(db, uid, status, -delta).await
+}
+
+// moves a task from the `from` status bucket to the `to` one.
+pub async fn transition(
+    db: &impl CqlExecutor,
+    uid: xid::Id,
+    from: TaskStatus,
+    to: TaskStatus,
+) -> anyhow::Result<()> {
+    if from == to {
+        return Ok(());
+    }
+
+    decr(db, uid, from, 1).await?;
+    incr(db, uid, to, 1).await
+}
+
+// the counters of the user by status, statuses never counted are absent.
+pub async fn get(db: &impl CqlExecutor, uid: xid::Id) -> anyhow::Result<BTreeMap<i8, i64>> {
+    let fields = vec!["status".to_string(), "count".to_string()];
+    let query = "SELECT status,count FROM task_counter WHERE uid=? USING TIMEOUT 3s";
+    let params = (uid.to_cql(),);
+    let rows = db.execute_iter(query, params).await?;
+
+    let mut res: BTreeMap<i8, i64> = BTreeMap::new();
+    for row in rows {
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(row, &fields)?;
+        let status: i8 = cols.get_as("status")?;
+        let count = cols.get("count").and_then(|v| v.as_counter()).map(|c| c.0);
+        res.insert(status, count.unwrap_or(0));
+    }
+    Ok(res)
+}
+
+// the number of tasks of the user, a drifted counter below zero is counted as zero.
+pub async fn count(
+    db: &impl CqlExecutor,
+    uid: xid::Id,
+    status: Option<TaskStatus>,
+) -> anyhow::Result<u64> {
+    let counters = get(db, uid).await?;
+    let total: i64 = match status {
+        Some(status) => counters.get(&status.into()).cloned().unwrap_or(0).max(0),
+        None => counters.values().map(|c| (*c).max(0)).sum(),
+    };
+    Ok(total as u64)
+}
+
+// scans the task partition of the user and rewrites the counters, returns the exact counts.
+// counters can not be set, so they are adjusted by the differences.
+pub async fn recount(db: &impl CqlExecutor, uid: xid::Id) -> anyhow::Result<BTreeMap<i8, i64>> {
+    let fields = vec!["status".to_string()];
+    let query = "SELECT status FROM task WHERE uid=? BYPASS CACHE USING TIMEOUT 10s";
+    let params = (uid.to_cql(),);
+    let rows = db.execute_iter(query, params).await?;
+
+    let mut exact: BTreeMap<i8, i64> = BTreeMap::new();
+    for row in rows {
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(row, &fields)?;
+        let status: i8 = cols.get_as("status")?;
+        *exact.entry(status).or_insert(0) += 1;
+    }
+
+    for (status, delta) in diff(&get(db, uid).await?, &exact) {
+        add(db, uid, status, delta).await?;
+    }
+    Ok(exact)
+}
+
+// the deltas to apply to the current counters to reach the exact ones.
+fn diff(current: &BTreeMap<i8, i64>, exact: &BTreeMap<i8, i64>) -> Vec<(i8, i64)> {
+    let mut res: Vec<(i8, i64)> = Vec::new();
+    for (status, count) in exact {
+        let delta = count - current.get(status).cloned().unwrap_or(0);
+        if delta != 0 {
+            res.push((*status, delta));
+        }
+    }
+    for (status, count) in current {
+        if !exact.contains_key(status) && *count != 0 {
+            res.push((*status, -count));
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{schema, Task};
+
+    #[test]
+    fn diff_works() {
+        let current: BTreeMap<i8, i64> = [(0, 3), (1, 2), (-1, 1)].into();
+        let exact: BTreeMap<i8, i64> = [(0, 3), (1, 4), (-2, 1)].into();
+        let mut res = diff(&current, &exact);
+        res.sort();
+        assert_eq!(res, vec![(-2, 1), (-1, -1), (1, 2)]);
+        assert!(diff(&exact, &exact).is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn recount_works() {
+        let db = schema::test_db("taskbase_test").await;
+        let uid = xid::new();
+
+        for i in 0..3 {
+            let mut doc = Task::with_pk(uid, xid::new());
+            doc.kind = "test".to_string();
+            doc.status = if i == 0 {
+                TaskStatus::Resolved
+            } else {
+                TaskStatus::Pending
+            };
+            doc.save(&db).await.unwrap();
+        }
+        assert_eq!(count(&db, uid, None).await.unwrap(), 3);
+        assert_eq!(count(&db, uid, Some(TaskStatus::Pending)).await.unwrap(), 2);
+
+        // drift the counters, then recount.
+        incr(&db, uid, TaskStatus::Pending, 5).await.unwrap();
+        decr(&db, uid, TaskStatus::Cancelled, 1).await.unwrap();
+        let exact = recount(&db, uid).await.unwrap();
+        assert_eq!(exact, [(0, 2), (1, 1)].into());
+        assert_eq!(
+            get(&db, uid).await.unwrap(),
+            [(-2, 0), (0, 2), (1, 1)].into()
+        );
+        assert_eq!(count(&db, uid, None).await.unwrap(), 3);
+    }
+}
@@ -0,0 +1,293 @@
+use async_trait::async_trait;
+use scylla::{frame::value::ValueList, transport::query_result::QueryResult};
+use scylla_orm::{ColumnsMap, CqlValue};
+use std::sync::Mutex;
+
+use crate::crypto::Keyring;
+use crate::db::scylladb::{is_timeout, Consistency, CqlExecutor, Query, Row};
+use crate::db::TaskCache;
+
+// an in-memory CqlExecutor for the unit tests of the models. It records the executed
+// statements and replies with the canned results queued for them, a canned result is
+// used once by the first statement containing its pattern. Statements without one
+// get no rows, or are applied if they are conditional.
+#[derive(Default)]
+pub struct FakeDB {
+    statements: Mutex<Vec<Statement>>,
+    replies: Mutex<Vec<(String, Reply)>>,
+    keyring: Option<Keyring>,
+    task_cache: Option<TaskCache>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub query: String,
+    pub values: usize, // the number of bound values
+}
+
+enum Reply {
+    Docs(Vec<ColumnsMap>),
+    Rows(Vec<Row>),
+    Error(anyhow::Error),
+}
+
+impl FakeDB {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    pub fn with_task_cache(mut self, cache: TaskCache) -> Self {
+        self.task_cache = Some(cache);
+        self
+    }
+
+    // queues the documents to reply, the rows hold the columns selected by the statement,
+    // e.g. `push_docs("FROM task WHERE uid=? AND id=?", vec![doc.to()])`.
+    pub fn push_docs(&self, pattern: &str, docs: Vec<ColumnsMap>) {
+        self.push(pattern, Reply::Docs(docs));
+    }
+
+    pub fn push_rows(&self, pattern: &str, rows: Vec<Row>) {
+        self.push(pattern, Reply::Rows(rows));
+    }
+
+    // queues the [applied] row of a conditional statement.
+    pub fn push_applied(&self, pattern: &str, applied: bool) {
+        self.push(
+            pattern,
+            Reply::Rows(vec![Row {
+                columns: vec![Some(CqlValue::Boolean(applied))],
+            }]),
+        );
+    }
+
+    pub fn push_error(&self, pattern: &str, err: anyhow::Error) {
+        self.push(pattern, Reply::Error(err));
+    }
+
+    pub fn statements(&self) -> Vec<Statement> {
+        self.statements.lock().unwrap().clone()
+    }
+
+    // the executed statements containing the pattern.
+    pub fn queries(&self, pattern: &str) -> Vec<String> {
+        self.statements
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.query.contains(pattern))
+            .map(|s| s.query.clone())
+            .collect()
+    }
+
+    fn push(&self, pattern: &str, reply: Reply) {
+        self.replies
+            .lock()
+            .unwrap()
+            .push((pattern.to_string(), reply));
+    }
+
+    fn reply(&self, query: Query, params: impl ValueList) -> anyhow::Result<Vec<Row>> {
+        let query = query.contents;
+        let values = params.serialized()?.len() as usize;
+        self.statements.lock().unwrap().push(Statement {
+            query: query.clone(),
+            values,
+        });
+
+        let reply = {
+            let mut replies = self.replies.lock().unwrap();
+            replies
+                .iter()
+                .position(|(pattern, _)| query.contains(pattern.as_str()))
+                .map(|i| replies.remove(i).1)
+        };
+        match reply {
+            Some(Reply::Docs(docs)) => {
+                let columns = selected_columns(&query);
+                Ok(docs
+                    .iter()
+                    .map(|doc| Row {
+                        columns: columns.iter().map(|c| doc.get(c).cloned()).collect(),
+                    })
+                    .collect())
+            }
+            Some(Reply::Rows(rows)) => Ok(rows),
+            Some(Reply::Error(err)) => Err(err),
+            None if query.contains(" IF ") => Ok(vec![Row {
+                columns: vec![Some(CqlValue::Boolean(true))],
+            }]),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+// the columns of "SELECT a,b,c FROM ...".
+fn selected_columns(query: &str) -> Vec<String> {
+    query
+        .strip_prefix("SELECT ")
+        .and_then(|q| q.split(" FROM ").next())
+        .map(|cols| cols.split(',').map(|c| c.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+// the retries of the timed out conditional statements.
+const LWT_RETRIES: u32 = 3;
+
+fn query_result(rows: Vec<Row>) -> QueryResult {
+    QueryResult {
+        rows: Some(rows),
+        ..Default::default()
+    }
+}
+
+#[async_trait]
+impl CqlExecutor for FakeDB {
+    async fn execute<Q, V>(&self, query: Q, params: V) -> anyhow::Result<QueryResult>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        self.reply(query.into(), params).map(query_result)
+    }
+
+    async fn execute_lwt_with_retry<Q, V>(&self, query: Q, params: V) -> anyhow::Result<QueryResult>
+    where
+        Q: Into<Query> + Clone + Send + Sync,
+        V: ValueList + Clone + Send + Sync,
+    {
+        let (res, _) = self.execute_lwt_retried(query, params).await?;
+        Ok(res)
+    }
+
+    // the timeouts are retried as by ScyllaDB, without the backoff.
+    async fn execute_lwt_retried<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+    ) -> anyhow::Result<(QueryResult, bool)>
+    where
+        Q: Into<Query> + Clone + Send + Sync,
+        V: ValueList + Clone + Send + Sync,
+    {
+        let mut attempts: u32 = 0;
+        loop {
+            attempts += 1;
+            match self.reply(query.clone().into(), params.clone()) {
+                Err(err) if attempts <= LWT_RETRIES && is_timeout(&err) => continue,
+                res => return Ok((query_result(res?), attempts > 1)),
+            }
+        }
+    }
+
+    async fn execute_iter<Q, V>(&self, query: Q, params: V) -> anyhow::Result<Vec<Row>>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        self.reply(query.into(), params)
+    }
+
+    async fn execute_iter_with<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+        _consistency: Consistency,
+    ) -> anyhow::Result<Vec<Row>>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        self.reply(query.into(), params)
+    }
+
+    // a single page, the paging state is never returned.
+    async fn execute_paged<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+        _page_size: i32,
+        _paging_state: Option<Vec<u8>>,
+    ) -> anyhow::Result<(Vec<Row>, Option<Vec<u8>>)>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        self.reply(query.into(), params).map(|rows| (rows, None))
+    }
+
+    async fn execute_batch(
+        &self,
+        statements: Vec<(String, Vec<CqlValue>)>,
+    ) -> anyhow::Result<QueryResult> {
+        for (query, params) in statements {
+            self.reply(query.into(), params)?;
+        }
+        Ok(query_result(vec![]))
+    }
+
+    fn keyring(&self) -> Option<&Keyring> {
+        self.keyring.as_ref()
+    }
+
+    fn task_cache(&self) -> Option<&TaskCache> {
+        self.task_cache.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scylla_orm::ToCqlVal;
+
+    use super::*;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn fake_db_works() {
+        let db = FakeDB::new();
+        let mut doc = ColumnsMap::new();
+        doc.set_as("id", &1i64);
+        doc.set_as("kind", &"test".to_string());
+        db.push_docs("FROM task", vec![doc]);
+        db.push_applied("UPDATE task", false);
+
+        let rows = db
+            .execute_iter("SELECT kind,id,gid FROM task WHERE uid=?", (1i64.to_cql(),))
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0].columns,
+            vec![
+                Some(CqlValue::Text("test".to_string())),
+                Some(CqlValue::BigInt(1)),
+                None
+            ]
+        );
+        // the canned rows are used once.
+        let rows = db
+            .execute_iter("SELECT kind FROM task WHERE uid=?", (1i64.to_cql(),))
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+
+        let res = db
+            .execute("UPDATE task SET kind=? WHERE uid=? IF EXISTS", (1, 2))
+            .await
+            .unwrap();
+        assert!(!crate::db::scylladb::extract_applied(res));
+        let res = db
+            .execute("UPDATE task SET kind=? WHERE uid=? IF EXISTS", (1, 2))
+            .await
+            .unwrap();
+        assert!(crate::db::scylladb::extract_applied(res));
+
+        assert_eq!(db.statements().len(), 4);
+        assert_eq!(db.statements()[2].values, 2);
+        assert_eq!(db.queries("SELECT").len(), 2);
+    }
+}
@@ -1,7 +1,26 @@
+mod cache;
+mod model_comment;
+mod model_log;
 mod model_notification;
+mod model_recurrence;
 mod model_task;
+mod model_template;
 
+pub mod counters;
+#[cfg(test)]
+pub mod fake;
+pub mod schema;
 pub mod scylladb;
+pub mod stats;
+pub mod status;
 
-pub use model_notification::{GroupNotification, Notification};
+pub use cache::{Invalidation, TaskCache};
+pub use model_comment::TaskComment;
+pub use model_log::{TaskAction, TaskLog};
+pub use model_notification::{
+    GroupNotification, Notification, NotificationDigest, NotificationMute, NotificationRetry,
+};
+pub use model_recurrence::TaskRecurrence;
 pub use model_task::Task;
+pub use model_template::TaskTemplate;
+pub use status::{NotificationStatus, TaskStatus};
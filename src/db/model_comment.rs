@@ -0,0 +1,196 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::scylladb::{extract_applied, CqlExecutor};
+
+// soft cap of comments per task, checked at insert time.
+pub const MAX_COMMENTS_PER_TASK: i64 = 1000;
+
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct TaskComment {
+    pub uid: xid::Id,
+    pub tid: xid::Id,
+    pub id: xid::Id,
+    pub author: xid::Id,
+    pub created_at: i64,
+    pub message: String,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl TaskComment {
+    pub fn with_pk(uid: xid::Id, tid: xid::Id, id: xid::Id) -> Self {
+        Self {
+            uid,
+            tid,
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub async fn get_one(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM task_comment WHERE uid=? AND tid=? AND id=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.uid.to_cql(), self.tid.to_cql(), self.id.to_cql());
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    pub async fn count(db: &impl CqlExecutor, uid: xid::Id, tid: xid::Id) -> anyhow::Result<i64> {
+        let query = "SELECT COUNT(*) FROM task_comment WHERE uid=? AND tid=? USING TIMEOUT 3s";
+        let params = (uid.to_cql(), tid.to_cql());
+        let row = db.execute(query, params).await?.single_row()?;
+        let count = row.columns[0]
+            .as_ref()
+            .and_then(|v| v.as_bigint())
+            .unwrap_or(0);
+        Ok(count)
+    }
+
+    pub async fn save(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        if Self::count(db, self.uid, self.tid).await? >= MAX_COMMENTS_PER_TASK {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "Too many comments, the task can have at most {} comments",
+                    MAX_COMMENTS_PER_TASK
+                ),
+            )
+            .into());
+        }
+
+        self.created_at = unix_ms() as i64;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO task_comment ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                "TaskComment save failed, please try again".to_string(),
+            )
+            .into());
+        }
+
+        Ok(true)
+    }
+
+    pub async fn delete(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let query = "DELETE FROM task_comment WHERE uid=? AND tid=? AND id=?";
+        let params = (self.uid.to_cql(), self.tid.to_cql(), self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    pub async fn batch_delete_by_tid(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        tid: xid::Id,
+    ) -> anyhow::Result<()> {
+        let query = "DELETE FROM task_comment WHERE uid=? AND tid=?";
+        let params = (uid.to_cql(), tid.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // moves the comments of the task to the new uid, see `Task::transfer`. The rows are
+    // rewritten as is, so it is safe to re-run after a partial failure.
+    pub async fn batch_transfer(
+        db: &impl CqlExecutor,
+        tid: xid::Id,
+        from: xid::Id,
+        to: xid::Id,
+    ) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        let query = format!(
+            "SELECT {} FROM task_comment WHERE uid=? AND tid=? USING TIMEOUT 3s",
+            fields.join(",")
+        );
+        let params = (from.to_cql(), tid.to_cql());
+        let rows = db.execute_iter(query, params).await?;
+
+        let query = format!(
+            "INSERT INTO task_comment ({}) VALUES ({})",
+            fields.join(","),
+            vec!["?"; fields.len()].join(",")
+        );
+        for row in rows {
+            let mut doc = TaskComment::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc.uid = to;
+
+            let cols = doc.to();
+            let params: Vec<&CqlValue> = fields.iter().map(|f| cols.get(f).unwrap()).collect();
+            let _ = db.execute(query.clone(), params).await?;
+        }
+
+        Self::batch_delete_by_tid(db, from, tid).await
+    }
+
+    pub async fn list(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        tid: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<TaskComment>> {
+        let fields = Self::fields();
+
+        let rows = if let Some(id) = page_token {
+            let query = format!(
+                "SELECT {} FROM task_comment WHERE uid=? AND tid=? AND id<? LIMIT ? USING TIMEOUT 3s",
+                fields.join(","));
+            let params = (uid.to_cql(), tid.to_cql(), id.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        } else {
+            let query = format!(
+                "SELECT {} FROM task_comment WHERE uid=? AND tid=? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (uid.to_cql(), tid.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        };
+
+        let mut res: Vec<TaskComment> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = TaskComment::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
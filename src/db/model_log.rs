@@ -0,0 +1,173 @@
+use axum_web::context::unix_ms;
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::scylladb::CqlExecutor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskAction {
+    Created,
+    Updated,
+    AssigneeAdded,
+    AssigneeRemoved,
+    Delegated,
+    Resolved,
+    Rejected,
+    StatusChanged,
+    Trashed,
+    Restored,
+    Deleted,
+    Cancelled,
+    Reopened,
+    Transferred,
+}
+
+impl TaskAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskAction::Created => "created",
+            TaskAction::Updated => "updated",
+            TaskAction::AssigneeAdded => "assignee_added",
+            TaskAction::AssigneeRemoved => "assignee_removed",
+            TaskAction::Delegated => "delegated",
+            TaskAction::Resolved => "resolved",
+            TaskAction::Rejected => "rejected",
+            TaskAction::StatusChanged => "status_changed",
+            TaskAction::Trashed => "trashed",
+            TaskAction::Restored => "restored",
+            TaskAction::Deleted => "deleted",
+            TaskAction::Cancelled => "cancelled",
+            TaskAction::Reopened => "reopened",
+            TaskAction::Transferred => "transferred",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct TaskLog {
+    pub uid: xid::Id,
+    pub tid: xid::Id,
+    pub id: xid::Id,
+    pub actor: xid::Id,
+    pub action: String,
+    pub detail: String,
+    pub created_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl TaskLog {
+    pub fn new(uid: xid::Id, tid: xid::Id, actor: xid::Id, action: TaskAction) -> Self {
+        Self {
+            uid,
+            tid,
+            id: xid::new(),
+            actor,
+            action: action.as_str().to_string(),
+            ..Default::default()
+        }
+    }
+
+    // plain INSERT without LWT, log ids are unique.
+    pub async fn save(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        self.created_at = unix_ms() as i64;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO task_log ({}) VALUES ({})",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+        let _ = db.execute(query, params).await?;
+        Ok(true)
+    }
+
+    // moves the log of the task to the new uid, see `Task::transfer`. The rows are
+    // rewritten as is, so it is safe to re-run after a partial failure.
+    pub async fn batch_transfer(
+        db: &impl CqlExecutor,
+        tid: xid::Id,
+        from: xid::Id,
+        to: xid::Id,
+    ) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        let query = format!(
+            "SELECT {} FROM task_log WHERE uid=? AND tid=? USING TIMEOUT 3s",
+            fields.join(",")
+        );
+        let params = (from.to_cql(), tid.to_cql());
+        let rows = db.execute_iter(query, params).await?;
+
+        let query = format!(
+            "INSERT INTO task_log ({}) VALUES ({})",
+            fields.join(","),
+            vec!["?"; fields.len()].join(",")
+        );
+        for row in rows {
+            let mut doc = TaskLog::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc.uid = to;
+
+            let cols = doc.to();
+            let params: Vec<&CqlValue> = fields.iter().map(|f| cols.get(f).unwrap()).collect();
+            let _ = db.execute(query.clone(), params).await?;
+        }
+
+        let query = "DELETE FROM task_log WHERE uid=? AND tid=?";
+        let params = (from.to_cql(), tid.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    pub async fn list(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        tid: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<TaskLog>> {
+        let fields = Self::fields();
+
+        let rows = if let Some(id) = page_token {
+            let query = format!(
+                "SELECT {} FROM task_log WHERE uid=? AND tid=? AND id<? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (uid.to_cql(), tid.to_cql(), id.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        } else {
+            let query = format!(
+                "SELECT {} FROM task_log WHERE uid=? AND tid=? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (uid.to_cql(), tid.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        };
+
+        let mut res: Vec<TaskLog> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = TaskLog::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
@@ -1,9 +1,23 @@
-use axum_web::erring::HTTPError;
+use axum_web::{context::unix_ms, erring::HTTPError};
 
 use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
 use scylla_orm_macros::CqlOrm;
+use std::collections::BTreeMap;
+use tracing::instrument;
 
-use crate::db::scylladb::{self, extract_applied};
+use crate::db::{
+    scylladb::{self, extract_applied, CqlExecutor},
+    NotificationStatus,
+};
+
+// the page size and the hard cap of the status-filtered batch deletes.
+const BATCH_DELETE_PAGE_SIZE: i32 = 100;
+const BATCH_DELETE_LIMIT: u64 = 10000;
+
+// a task has at most 260 receivers, so a single page is expected in batch_delete_by_tid,
+// the iterations are bounded in case the deletes do not take effect.
+const TID_DELETE_PAGE_SIZE: i32 = 1000;
+const TID_DELETE_MAX_ITERATIONS: usize = 10;
 
 #[derive(Debug, Default, Clone, CqlOrm)]
 pub struct GroupNotification {
@@ -20,10 +34,17 @@ pub struct Notification {
     pub uid: xid::Id,
     pub tid: xid::Id,
     pub sender: xid::Id,
-    pub status: i8,
+    pub gid: xid::Id,
+    pub status: NotificationStatus,
     pub message: String,
+    pub reason: String,
+    pub reminded_at: i64,
+    pub deleted_at: i64,
+    pub read_at: i64,
+    pub escalated_at: i64,
 
     pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+    pub _ttl: i32,            // TTL in seconds for save, 0 means never expires
 }
 
 impl GroupNotification {
@@ -36,7 +57,8 @@ impl GroupNotification {
         }
     }
 
-    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+    #[instrument(skip_all, fields(gid = %self.gid, tid = %self.tid))]
+    pub async fn get_one(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
         let fields = Self::fields();
         self._fields = fields.clone();
 
@@ -54,7 +76,8 @@ impl GroupNotification {
         Ok(())
     }
 
-    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+    #[instrument(skip_all, fields(gid = %self.gid, tid = %self.tid))]
+    pub async fn save(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
         let fields = Self::fields();
         self._fields = fields.clone();
 
@@ -87,15 +110,37 @@ impl GroupNotification {
         Ok(true)
     }
 
-    pub async fn delete(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+    // returns false if the notification does not exist.
+    #[instrument(skip_all, fields(gid = %self.gid, tid = %self.tid))]
+    pub async fn update_role(&mut self, db: &impl CqlExecutor, role: i8) -> anyhow::Result<bool> {
+        let query =
+            "UPDATE group_notification SET role=? WHERE gid=? AND tid=? AND sender=? IF EXISTS";
+        let params = (
+            role,
+            self.gid.to_cql(),
+            self.tid.to_cql(),
+            self.sender.to_cql(),
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Ok(false);
+        }
+        self.role = role;
+        Ok(true)
+    }
+
+    #[instrument(skip_all, fields(gid = %self.gid, tid = %self.tid))]
+    pub async fn delete(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
         let query = "DELETE FROM group_notification WHERE gid=? AND tid=? AND sender=?";
         let params = (self.gid.to_cql(), self.tid.to_cql(), self.sender.to_cql());
         let _ = db.execute(query, params).await?;
         Ok(())
     }
 
+    #[instrument(skip_all, fields(%gid))]
     pub async fn list(
-        db: &scylladb::ScyllaDB,
+        db: &impl CqlExecutor,
         gid: xid::Id,
         page_size: u16,
         page_token: Option<xid::Id>,
@@ -115,7 +160,7 @@ impl GroupNotification {
                 let query = format!(
                     "SELECT {} FROM group_notification WHERE gid=? AND role=? AND tid<? LIMIT ? USING TIMEOUT 3s",
                     fields.clone().join(","));
-                let params = (gid.to_cql(), tid.to_cql(), role.unwrap(), page_size as i32);
+                let params = (gid.to_cql(), role.unwrap(), tid.to_cql(), page_size as i32);
                 db.execute_iter(query, params).await?
             }
         } else if role.is_none() {
@@ -130,7 +175,7 @@ impl GroupNotification {
                 "SELECT {} FROM group_notification WHERE gid=? AND role=? LIMIT ? USING TIMEOUT 3s",
                 fields.clone().join(",")
             );
-            let params = (gid.as_bytes(), role.unwrap(), page_size as i32);
+            let params = (gid.to_cql(), role.unwrap(), page_size as i32);
             db.execute_iter(query, params).await?
         };
 
@@ -158,7 +203,8 @@ impl Notification {
         }
     }
 
-    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+    #[instrument(skip_all, fields(uid = %self.uid, tid = %self.tid))]
+    pub async fn get_one(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
         let fields = Self::fields();
         self._fields = fields.clone();
 
@@ -176,7 +222,8 @@ impl Notification {
         Ok(())
     }
 
-    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+    #[instrument(skip_all, fields(uid = %self.uid, tid = %self.tid))]
+    pub async fn save(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
         let fields = Self::fields();
         self._fields = fields.clone();
 
@@ -191,13 +238,19 @@ impl Notification {
             params.push(cols.get(field).unwrap());
         }
 
+        // the index goes first, so a saved notification is always indexed even if a retry
+        // finds it exists, and an index row without the notification is harmless.
+        let (index_query, index_params) = self.tid_index_statement();
+        let _ = db.execute(index_query, index_params).await?;
+
         let query = format!(
-            "INSERT INTO notification ({}) VALUES ({}) IF NOT EXISTS",
+            "INSERT INTO notification ({}) VALUES ({}) IF NOT EXISTS{}",
             cols_name.join(","),
-            vals_name.join(",")
+            vals_name.join(","),
+            using_ttl(self._ttl)
         );
 
-        let res = db.execute(query, params).await?;
+        let res = db.execute_lwt_with_retry(query, params).await?;
         if !extract_applied(res) {
             return Err(HTTPError::new(
                 409,
@@ -209,17 +262,79 @@ impl Notification {
         Ok(true)
     }
 
-    pub async fn update(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
-        let query = "UPDATE notification SET status=?,message=? WHERE uid=? AND tid=? AND sender=? IF EXISTS";
+    // the notification_by_tid row shares the TTL of the notification.
+    fn tid_index_statement(&self) -> (String, Vec<CqlValue>) {
+        (
+            format!(
+                "INSERT INTO notification_by_tid (tid,uid,sender) VALUES (?,?,?){}",
+                using_ttl(self._ttl)
+            ),
+            vec![self.tid.to_cql(), self.uid.to_cql(), self.sender.to_cql()],
+        )
+    }
+
+    // saves the notifications of a new task in one batch, the rows are written with plain
+    // INSERT since a batch across partitions cannot be conditional.
+    #[instrument(skip_all)]
+    pub async fn batch_save(db: &impl CqlExecutor, docs: &[Notification]) -> anyhow::Result<()> {
+        if docs.is_empty() {
+            return Ok(());
+        }
+
+        let fields = Self::fields();
+        let mut statements: Vec<(String, Vec<CqlValue>)> = Vec::with_capacity(docs.len() * 2);
+        for doc in docs {
+            let cols = doc.to();
+            let mut params: Vec<CqlValue> = Vec::with_capacity(fields.len());
+            for field in &fields {
+                params.push(cols.get(field).unwrap().to_owned());
+            }
+            let query = format!(
+                "INSERT INTO notification ({}) VALUES ({}){}",
+                fields.join(","),
+                vec!["?"; fields.len()].join(","),
+                using_ttl(doc._ttl)
+            );
+            statements.push((query, params));
+            statements.push(doc.tid_index_statement());
+        }
+
+        let _ = db.execute_batch(statements).await?;
+        Ok(())
+    }
+
+    // the remaining TTL of the notification in seconds, 0 if it never expires.
+    // updates should write with the remaining TTL, or the updated cells would outlive the row.
+    #[instrument(skip_all, fields(uid = %self.uid, tid = %self.tid))]
+    pub async fn ttl(&self, db: &impl CqlExecutor) -> anyhow::Result<i32> {
+        let query =
+            "SELECT TTL(status) FROM notification WHERE uid=? AND tid=? AND sender=? LIMIT 1";
+        let params = (self.uid.to_cql(), self.tid.to_cql(), self.sender.to_cql());
+        let row = db.execute(query, params).await?.single_row()?;
+        let ttl = row.columns[0]
+            .as_ref()
+            .and_then(|v| v.as_int())
+            .unwrap_or(0);
+        Ok(ttl)
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, tid = %self.tid))]
+    pub async fn update(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        let query = format!(
+            "UPDATE notification{} SET status=?,message=?,reason=?,read_at=? WHERE uid=? AND tid=? AND sender=? IF EXISTS",
+            using_ttl(self.ttl(db).await?)
+        );
         let params = (
-            self.status,
+            self.status.to_cql(),
             self.message.to_cql(),
+            self.reason.to_cql(),
+            self.read_at.to_cql(),
             self.uid.to_cql(),
             self.tid.to_cql(),
             self.sender.to_cql(),
         );
 
-        let res = db.execute(query, params).await?;
+        let res = db.execute_lwt_with_retry(query, params).await?;
         if !extract_applied(res) {
             return Err(HTTPError::new(
                 409,
@@ -230,63 +345,441 @@ impl Notification {
         Ok(true)
     }
 
-    pub async fn delete(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+    // marks the notification as read, returns false if it has been read already.
+    #[instrument(skip_all, fields(uid = %self.uid, tid = %self.tid))]
+    pub async fn read(&mut self, db: &impl CqlExecutor, now_ms: i64) -> anyhow::Result<bool> {
+        self.get_one(db).await?;
+        if self.read_at > 0 {
+            return Ok(false);
+        }
+
+        let query = format!(
+            "UPDATE notification{} SET read_at=? WHERE uid=? AND tid=? AND sender=? IF EXISTS",
+            using_ttl(self.ttl(db).await?)
+        );
+        let params = (
+            now_ms.to_cql(),
+            self.uid.to_cql(),
+            self.tid.to_cql(),
+            self.sender.to_cql(),
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Ok(false);
+        }
+        self.read_at = now_ms;
+        Ok(true)
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, tid = %self.tid))]
+    pub async fn remind(&mut self, db: &impl CqlExecutor, now_ms: i64) -> anyhow::Result<bool> {
+        let query = format!(
+            "UPDATE notification{} SET reminded_at=? WHERE uid=? AND tid=? AND sender=? IF EXISTS",
+            using_ttl(self.ttl(db).await.unwrap_or_default())
+        );
+        let params = (
+            now_ms.to_cql(),
+            self.uid.to_cql(),
+            self.tid.to_cql(),
+            self.sender.to_cql(),
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Ok(false);
+        }
+        self.reminded_at = now_ms;
+        Ok(true)
+    }
+
+    // backfills the gid of a notification created before gid was copied from the task.
+    #[instrument(skip_all, fields(uid = %self.uid, tid = %self.tid))]
+    pub async fn update_gid(&mut self, db: &impl CqlExecutor, gid: xid::Id) -> anyhow::Result<()> {
+        let query = format!(
+            "UPDATE notification{} SET gid=? WHERE uid=? AND tid=? AND sender=? IF EXISTS",
+            using_ttl(self.ttl(db).await.unwrap_or_default())
+        );
+        let params = (
+            gid.to_cql(),
+            self.uid.to_cql(),
+            self.tid.to_cql(),
+            self.sender.to_cql(),
+        );
+        let _ = db.execute(query, params).await?;
+        self.gid = gid;
+        Ok(())
+    }
+
+    // marks the notification as escalated and unread again, returns false if it does not exist.
+    #[instrument(skip_all, fields(uid = %self.uid, tid = %self.tid))]
+    pub async fn escalate(&mut self, db: &impl CqlExecutor, now_ms: i64) -> anyhow::Result<bool> {
+        let query = format!(
+            "UPDATE notification{} SET escalated_at=?,read_at=0 WHERE uid=? AND tid=? AND sender=? IF EXISTS",
+            using_ttl(self.ttl(db).await.unwrap_or_default())
+        );
+        let params = (
+            now_ms.to_cql(),
+            self.uid.to_cql(),
+            self.tid.to_cql(),
+            self.sender.to_cql(),
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Ok(false);
+        }
+        self.escalated_at = now_ms;
+        self.read_at = 0;
+        Ok(true)
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, tid = %self.tid))]
+    pub async fn delete(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        Self::delete_with_index(db, self.uid, self.tid, self.sender).await
+    }
+
+    async fn delete_with_index(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        tid: xid::Id,
+        sender: xid::Id,
+    ) -> anyhow::Result<()> {
         let query = "DELETE FROM notification WHERE uid=? AND tid=? AND sender=?";
-        let params = (self.uid.to_cql(), self.tid.to_cql(), self.sender.to_cql());
+        let params = (uid.to_cql(), tid.to_cql(), sender.to_cql());
+        let _ = db.execute(query, params).await?;
+
+        let query = "DELETE FROM notification_by_tid WHERE tid=? AND uid=? AND sender=?";
+        let params = (tid.to_cql(), uid.to_cql(), sender.to_cql());
         let _ = db.execute(query, params).await?;
         Ok(())
     }
 
-    pub async fn batch_delete_by_tid(db: &scylladb::ScyllaDB, tid: xid::Id) -> anyhow::Result<()> {
+    // deletes notifications of the task by walking the notification_by_tid table.
+    // notifications written before the table existed are not indexed, they are removed
+    // as orphans when listed.
+    #[instrument(skip_all, fields(%tid))]
+    pub async fn batch_delete_by_tid(db: &impl CqlExecutor, tid: xid::Id) -> anyhow::Result<()> {
+        let fields = vec!["uid".to_string(), "sender".to_string()];
+        for _ in 0..TID_DELETE_MAX_ITERATIONS {
+            let query =
+                "SELECT uid,sender FROM notification_by_tid WHERE tid=? LIMIT ? USING TIMEOUT 3s";
+            let params = (tid.to_cql(), TID_DELETE_PAGE_SIZE);
+            let rows = db.execute_iter(query, params).await?;
+            if rows.is_empty() {
+                return Ok(());
+            }
+
+            for row in rows {
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                let uid: xid::Id = cols.get_as("uid")?;
+                let sender: xid::Id = cols.get_as("sender")?;
+                Self::delete_with_index(db, uid, tid, sender).await?;
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "batch_delete_by_tid: notifications of task {} are not deleted after {} iterations",
+            tid,
+            TID_DELETE_MAX_ITERATIONS
+        ))
+    }
+
+    // lists notifications of the task, ordered by uid. a task has at most 260 receivers,
+    // so rows are fetched from the notification_tid index at once and paged in memory.
+    #[instrument(skip_all, fields(%tid))]
+    pub async fn list_by_tid(
+        db: &impl CqlExecutor,
+        tid: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Notification>> {
+        let fields = Self::fields();
+        let query = format!(
+            "SELECT {} FROM notification WHERE tid=? LIMIT ? BYPASS CACHE USING TIMEOUT 3s",
+            fields.join(",")
+        );
+        let params = (tid.to_cql(), 1000_i32);
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Notification> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Notification::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            if let Some(uid) = page_token {
+                if doc.uid <= uid {
+                    continue;
+                }
+            }
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        res.sort_by(|a, b| a.uid.cmp(&b.uid).then(a.sender.cmp(&b.sender)));
+        res.truncate(page_size as usize);
+        Ok(res)
+    }
+
+    // marks notifications of the task as deleted when the task is trashed, 0 to unmark.
+    #[instrument(skip_all, fields(%tid))]
+    pub async fn batch_mark_deleted_by_tid(
+        db: &impl CqlExecutor,
+        tid: xid::Id,
+        deleted_at: i64,
+    ) -> anyhow::Result<()> {
+        let query = "SELECT uid,tid,sender FROM notification WHERE tid=? LIMIT ? BYPASS CACHE USING TIMEOUT 3s";
+        let params = (tid.to_cql(), 1000_i32);
+        let fields = vec!["uid".to_string(), "tid".to_string(), "sender".to_string()];
+
+        let rows = db.execute_iter(query, params).await?;
+        for row in rows {
+            let mut doc = Notification::default();
+            let mut cols = ColumnsMap::with_capacity(3);
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+
+            let query = format!(
+                "UPDATE notification{} SET deleted_at=? WHERE uid=? AND tid=? AND sender=? IF EXISTS",
+                using_ttl(doc.ttl(db).await.unwrap_or_default())
+            );
+            let params = (
+                deleted_at.to_cql(),
+                doc.uid.to_cql(),
+                doc.tid.to_cql(),
+                doc.sender.to_cql(),
+            );
+            let _ = db.execute(query, params).await;
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(%tid))]
+    pub async fn batch_reset_by_tid(db: &impl CqlExecutor, tid: xid::Id) -> anyhow::Result<()> {
         let query = "SELECT uid,tid,sender FROM notification WHERE tid=? LIMIT ? BYPASS CACHE USING TIMEOUT 3s";
         let params = (tid.to_cql(), 1000_i32);
         let fields = vec!["uid".to_string(), "tid".to_string(), "sender".to_string()];
 
-        loop {
-            let rows = db.execute_iter(query.clone(), params.clone()).await?;
+        let rows = db.execute_iter(query, params).await?;
+        for row in rows {
+            let mut doc = Notification::default();
+            let mut cols = ColumnsMap::with_capacity(3);
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+
+            let query = format!(
+                "UPDATE notification{} SET status=?,reason='' WHERE uid=? AND tid=? AND sender=? IF EXISTS",
+                using_ttl(doc.ttl(db).await.unwrap_or_default())
+            );
+            let params = (
+                NotificationStatus::Pending.to_cql(),
+                doc.uid.to_cql(),
+                doc.tid.to_cql(),
+                doc.sender.to_cql(),
+            );
+            let _ = db.execute(query, params).await;
+        }
+
+        Ok(())
+    }
+
+    // moves notifications of the task from the old sender to the new sender.
+    #[instrument(skip_all, fields(%tid, %from))]
+    pub async fn batch_transfer_sender(
+        db: &impl CqlExecutor,
+        tid: xid::Id,
+        from: xid::Id,
+        to: xid::Id,
+    ) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        let query = format!(
+            "SELECT {} FROM notification WHERE tid=? LIMIT ? BYPASS CACHE USING TIMEOUT 3s",
+            fields.join(",")
+        );
+        let params = (tid.to_cql(), 1000_i32);
+
+        let rows = db.execute_iter(query, params).await?;
+        for row in rows {
+            let mut doc = Notification::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            if doc.sender != from {
+                continue;
+            }
+
+            let mut new_doc = doc.clone();
+            new_doc.sender = to;
+            new_doc._ttl = doc.ttl(db).await?;
+            // the row may exist from a previous run.
+            let _ = new_doc.save(db).await;
+            doc.delete(db).await?;
+        }
+
+        Ok(())
+    }
+
+    // rows are removed page by page when filtered by status, since status is not part of the
+    // primary key; at most `BATCH_DELETE_LIMIT` rows are removed per call.
+    // returns the number of rows removed.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn batch_delete_by_uid(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        status: Option<NotificationStatus>,
+    ) -> anyhow::Result<u64> {
+        let status = match status {
+            Some(status) => status,
+            None => {
+                let query = "SELECT tid FROM notification WHERE uid=? USING TIMEOUT 3s";
+                let params = (uid.to_cql(),);
+                let removed = db.execute_iter(query, params).await?.len() as u64;
+
+                let query = "DELETE FROM notification WHERE uid=?";
+                let params = (uid.to_cql(),);
+                let _ = db.execute(query, params).await?;
+                return Ok(removed);
+            }
+        };
+
+        let mut removed: u64 = 0;
+        let mut page_token: Option<xid::Id> = None;
+        while removed < BATCH_DELETE_LIMIT {
+            let rows = if let Some(tid) = page_token {
+                let query = "SELECT tid,sender FROM notification WHERE uid=? AND status=? AND tid<? LIMIT ? USING TIMEOUT 3s";
+                let params = (
+                    uid.to_cql(),
+                    status.to_cql(),
+                    tid.to_cql(),
+                    BATCH_DELETE_PAGE_SIZE,
+                );
+                db.execute_iter(query, params).await?
+            } else {
+                let query = "SELECT tid,sender FROM notification WHERE uid=? AND status=? LIMIT ? USING TIMEOUT 3s";
+                let params = (uid.to_cql(), status.to_cql(), BATCH_DELETE_PAGE_SIZE);
+                db.execute_iter(query, params).await?
+            };
             if rows.is_empty() {
                 break;
             }
 
+            let fields = vec!["tid".to_string(), "sender".to_string()];
+            let mut statements: Vec<(String, Vec<CqlValue>)> = Vec::with_capacity(rows.len());
             for row in rows {
-                let mut doc = Notification::default();
-                let mut cols = ColumnsMap::with_capacity(3);
+                let mut cols = ColumnsMap::with_capacity(fields.len());
                 cols.fill(row, &fields)?;
-                doc.fill(&cols);
-                let _ = doc.delete(db).await;
+                let tid: xid::Id = cols.get_as("tid")?;
+                let sender: xid::Id = cols.get_as("sender")?;
+                statements.push((
+                    "DELETE FROM notification WHERE uid=? AND tid=? AND sender=?".to_string(),
+                    vec![uid.to_cql(), tid.to_cql(), sender.to_cql()],
+                ));
+                page_token = Some(tid);
             }
+            removed += statements.len() as u64;
+            // the rows are in the same partition, so the unlogged batch is applied at once.
+            let _ = db.execute_batch(statements).await?;
+            log::info!(target: "db", "{} notifications of {} with status {} removed", removed, uid, status);
         }
 
-        Ok(())
+        Ok(removed)
     }
 
-    pub async fn batch_delete_by_uid(
-        db: &scylladb::ScyllaDB,
+    // counting a very large partition may time out, it returns a 504 error in that case.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn count(
+        db: &impl CqlExecutor,
         uid: xid::Id,
-        status: Option<i8>,
-    ) -> anyhow::Result<()> {
-        match status {
+        status: Option<NotificationStatus>,
+    ) -> anyhow::Result<u64> {
+        let res = match status {
             Some(status) => {
-                let query = "DELETE FROM notification WHERE uid=? AND status=?";
-                let params = (uid.to_cql(), status);
-                let _ = db.execute(query, params).await?;
+                let query =
+                    "SELECT COUNT(*) FROM notification WHERE uid=? AND status=? USING TIMEOUT 3s";
+                let params = (uid.to_cql(), status.to_cql());
+                db.execute(query, params).await
             }
             None => {
-                let query = "DELETE FROM notification WHERE uid=?";
+                let query = "SELECT COUNT(*) FROM notification WHERE uid=? USING TIMEOUT 3s";
                 let params = (uid.to_cql(),);
-                let _ = db.execute(query, params).await?;
+                db.execute(query, params).await
+            }
+        };
+
+        let res = res.map_err(|err| {
+            if scylladb::is_timeout(&err) {
+                HTTPError::new(504, "Notification count timed out".to_string()).into()
+            } else {
+                err
+            }
+        })?;
+        let row = res.single_row()?;
+        let count = row.columns[0]
+            .as_ref()
+            .and_then(|v| v.as_bigint())
+            .unwrap_or(0);
+        Ok(count as u64)
+    }
+
+    // groups the first `limit` notifications of the uid by sender,
+    // returns the digests ordered by sender and whether the scan was truncated.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn digest(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        status: Option<NotificationStatus>,
+        limit: usize,
+    ) -> anyhow::Result<(Vec<NotificationDigest>, bool)> {
+        let fields = vec![
+            "uid".to_string(),
+            "tid".to_string(),
+            "sender".to_string(),
+            "status".to_string(),
+        ];
+        // one more row is read to detect truncation.
+        let rows = match status {
+            Some(status) => {
+                let query = format!(
+                    "SELECT {} FROM notification WHERE uid=? AND status=? LIMIT ? USING TIMEOUT 3s",
+                    fields.join(",")
+                );
+                let params = (uid.to_cql(), status.to_cql(), limit as i32 + 1);
+                db.execute_iter(query, params).await?
             }
+            None => {
+                let query = format!(
+                    "SELECT {} FROM notification WHERE uid=? LIMIT ? USING TIMEOUT 3s",
+                    fields.join(",")
+                );
+                let params = (uid.to_cql(), limit as i32 + 1);
+                db.execute_iter(query, params).await?
+            }
+        };
+
+        let truncated = rows.len() > limit;
+        let mut res: Vec<Notification> = Vec::with_capacity(rows.len().min(limit));
+        for row in rows.into_iter().take(limit) {
+            let mut doc = Notification::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
         }
 
-        Ok(())
+        Ok((NotificationDigest::group(res), truncated))
     }
 
+    #[instrument(skip_all, fields(%uid))]
     pub async fn list(
-        db: &scylladb::ScyllaDB,
+        db: &impl CqlExecutor,
         uid: xid::Id,
         page_size: u16,
         page_token: Option<xid::Id>,
-        status: Option<i8>,
+        status: Option<NotificationStatus>,
     ) -> anyhow::Result<Vec<Notification>> {
         let fields = Self::fields();
 
@@ -304,8 +797,8 @@ impl Notification {
                     fields.clone().join(","));
                 let params = (
                     uid.to_cql(),
+                    status.unwrap().to_cql(),
                     tid.to_cql(),
-                    status.unwrap(),
                     page_size as i32,
                 );
                 db.execute_iter(query, params).await?
@@ -322,7 +815,7 @@ impl Notification {
                 "SELECT {} FROM notification WHERE uid=? AND status=? LIMIT ? USING TIMEOUT 3s",
                 fields.clone().join(",")
             );
-            let params = (uid.as_bytes(), status.unwrap(), page_size as i32);
+            let params = (uid.to_cql(), status.unwrap().to_cql(), page_size as i32);
             db.execute_iter(query, params).await?
         };
 
@@ -338,4 +831,723 @@ impl Notification {
 
         Ok(res)
     }
+
+    // lists a single page by the driver's paging state instead of the tid of the last row,
+    // the paging state should be resumed with the same status filter.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn list_paged(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        page_size: u16,
+        paging_state: Option<Vec<u8>>,
+        status: Option<NotificationStatus>,
+    ) -> anyhow::Result<(Vec<Notification>, Option<Vec<u8>>)> {
+        let fields = Self::fields();
+
+        let (rows, paging_state) = if let Some(status) = status {
+            let query = format!(
+                "SELECT {} FROM notification WHERE uid=? AND status=? USING TIMEOUT 3s",
+                fields.clone().join(",")
+            );
+            let params = (uid.to_cql(), status.to_cql());
+            db.execute_paged(query, params, page_size as i32, paging_state)
+                .await?
+        } else {
+            let query = format!(
+                "SELECT {} FROM notification WHERE uid=? USING TIMEOUT 3s",
+                fields.clone().join(",")
+            );
+            let params = (uid.to_cql(),);
+            db.execute_paged(query, params, page_size as i32, paging_state)
+                .await?
+        };
+
+        let mut res: Vec<Notification> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Notification::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok((res, paging_state))
+    }
+
+    // scans a single page of the whole notification table for the maintenance jobs,
+    // only the primary key is filled.
+    #[instrument(skip_all)]
+    pub async fn scan(
+        db: &impl CqlExecutor,
+        page_size: u16,
+        paging_state: Option<Vec<u8>>,
+    ) -> anyhow::Result<(Vec<Notification>, Option<Vec<u8>>)> {
+        let fields = vec!["uid".to_string(), "tid".to_string(), "sender".to_string()];
+        let query = "SELECT uid,tid,sender FROM notification USING TIMEOUT 10s";
+        let (rows, paging_state) = db
+            .execute_paged(query, (), page_size as i32, paging_state)
+            .await?;
+
+        let mut res: Vec<Notification> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Notification::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok((res, paging_state))
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NotificationDigest {
+    pub sender: xid::Id,
+    pub count: u64,
+    pub tids: Vec<xid::Id>, // the most recent tids, at most `RECENT_TIDS`
+}
+
+impl NotificationDigest {
+    pub const RECENT_TIDS: usize = 3;
+
+    // groups notifications by sender, the notifications should be ordered by tid DESC.
+    pub fn group(notifications: Vec<Notification>) -> Vec<NotificationDigest> {
+        let mut res: BTreeMap<xid::Id, NotificationDigest> = BTreeMap::new();
+        for doc in notifications {
+            let digest = res.entry(doc.sender).or_insert_with(|| NotificationDigest {
+                sender: doc.sender,
+                ..Default::default()
+            });
+            digest.count += 1;
+            if digest.tids.len() < Self::RECENT_TIDS {
+                digest.tids.push(doc.tid);
+            }
+        }
+        res.into_values().collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct NotificationMute {
+    pub uid: xid::Id,
+    pub sender: xid::Id,
+    pub created_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl NotificationMute {
+    pub fn with_pk(uid: xid::Id, sender: xid::Id) -> Self {
+        Self {
+            uid,
+            sender,
+            ..Default::default()
+        }
+    }
+
+    #[instrument(skip_all, fields(%uid, %sender))]
+    pub async fn is_muted(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        sender: xid::Id,
+    ) -> anyhow::Result<bool> {
+        let query = "SELECT sender FROM notification_mute WHERE uid=? AND sender=? LIMIT 1";
+        let params = (uid.to_cql(), sender.to_cql());
+        let res = db.execute(query, params).await?;
+        Ok(res.rows.map(|rows| !rows.is_empty()).unwrap_or(false))
+    }
+
+    // plain INSERT, muting a muted sender is a no-op.
+    #[instrument(skip_all, fields(uid = %self.uid, sender = %self.sender))]
+    pub async fn save(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        self.created_at = unix_ms() as i64;
+        let query = "INSERT INTO notification_mute (uid,sender,created_at) VALUES (?,?,?)";
+        let params = (
+            self.uid.to_cql(),
+            self.sender.to_cql(),
+            self.created_at.to_cql(),
+        );
+        let _ = db.execute(query, params).await?;
+        Ok(true)
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, sender = %self.sender))]
+    pub async fn delete(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let query = "DELETE FROM notification_mute WHERE uid=? AND sender=?";
+        let params = (self.uid.to_cql(), self.sender.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // lists muted senders of the user, ordered by sender.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn list(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<NotificationMute>> {
+        let fields = Self::fields();
+
+        let rows = if let Some(sender) = page_token {
+            let query = format!(
+                "SELECT {} FROM notification_mute WHERE uid=? AND sender>? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (uid.to_cql(), sender.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        } else {
+            let query = format!(
+                "SELECT {} FROM notification_mute WHERE uid=? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (uid.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        };
+
+        let mut res: Vec<NotificationMute> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = NotificationMute::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+// a notification failed to fan out, the worker retries it until it is saved.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct NotificationRetry {
+    pub tid: xid::Id,
+    pub uid: xid::Id,
+    pub sender: xid::Id,
+    pub gid: xid::Id,
+    pub ttl: i32,
+    pub attempts: i32,
+    pub created_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl NotificationRetry {
+    pub fn with_pk(tid: xid::Id, uid: xid::Id, sender: xid::Id) -> Self {
+        Self {
+            tid,
+            uid,
+            sender,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_notification(doc: &Notification) -> Self {
+        let mut rt = Self::with_pk(doc.tid, doc.uid, doc.sender);
+        rt.gid = doc.gid;
+        rt.ttl = doc._ttl;
+        rt
+    }
+
+    // the notification to retry with the remaining TTL, None if it would have expired.
+    pub fn notification(&self, now_ms: i64) -> Option<Notification> {
+        let mut doc = Notification::with_pk(self.uid, self.tid, self.sender);
+        doc.gid = self.gid;
+        if self.ttl > 0 {
+            let ttl = self.ttl as i64 - (now_ms - self.created_at) / 1000;
+            if ttl <= 0 {
+                return None;
+            }
+            doc._ttl = ttl as i32;
+        }
+        Some(doc)
+    }
+
+    // plain INSERT, a failed retry of the same notification is recorded again.
+    #[instrument(skip_all, fields(tid = %self.tid, uid = %self.uid))]
+    pub async fn save(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        self.created_at = unix_ms() as i64;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let cols = self.to();
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        for field in &fields {
+            params.push(cols.get(field).unwrap());
+        }
+        let query = format!(
+            "INSERT INTO notification_retry ({}) VALUES ({})",
+            fields.join(","),
+            vec!["?"; fields.len()].join(",")
+        );
+        let _ = db.execute(query, params).await?;
+        Ok(true)
+    }
+
+    #[instrument(skip_all, fields(tid = %self.tid, uid = %self.uid))]
+    pub async fn update_attempts(
+        &mut self,
+        db: &impl CqlExecutor,
+        attempts: i32,
+    ) -> anyhow::Result<()> {
+        let query = "UPDATE notification_retry SET attempts=? WHERE tid=? AND uid=? AND sender=?";
+        let params = (
+            attempts,
+            self.tid.to_cql(),
+            self.uid.to_cql(),
+            self.sender.to_cql(),
+        );
+        let _ = db.execute(query, params).await?;
+        self.attempts = attempts;
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(tid = %self.tid, uid = %self.uid))]
+    pub async fn delete(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let query = "DELETE FROM notification_retry WHERE tid=? AND uid=? AND sender=?";
+        let params = (self.tid.to_cql(), self.uid.to_cql(), self.sender.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // scans the notifications to retry across all tasks.
+    #[instrument(skip_all)]
+    pub async fn list(db: &impl CqlExecutor, limit: u16) -> anyhow::Result<Vec<NotificationRetry>> {
+        let fields = Self::fields();
+        let query = format!(
+            "SELECT {} FROM notification_retry LIMIT ? BYPASS CACHE USING TIMEOUT 10s",
+            fields.join(",")
+        );
+        let params = (limit as i32,);
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<NotificationRetry> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = NotificationRetry::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+fn using_ttl(ttl: i32) -> String {
+    if ttl > 0 {
+        format!(" USING TTL {}", ttl)
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::OnceCell;
+
+    use crate::db::schema;
+
+    use super::*;
+
+    static DB: OnceCell<scylladb::ScyllaDB> = OnceCell::const_new();
+
+    async fn get_db() -> &'static scylladb::ScyllaDB {
+        DB.get_or_init(|| async { schema::test_db("taskbase_test").await })
+            .await
+    }
+
+    #[test]
+    fn using_ttl_works() {
+        assert_eq!(using_ttl(0), "");
+        assert_eq!(using_ttl(-1), "");
+        assert_eq!(using_ttl(3600), " USING TTL 3600");
+    }
+
+    #[test]
+    fn notification_retry_works() {
+        let mut notif = Notification::with_pk(xid::new(), xid::new(), xid::new());
+        notif.gid = xid::new();
+        notif._ttl = 60;
+
+        let mut doc = NotificationRetry::from_notification(&notif);
+        doc.created_at = 1000000;
+        let rt = doc.notification(1000000 + 20 * 1000).unwrap();
+        assert_eq!(
+            (rt.uid, rt.tid, rt.sender, rt.gid),
+            (notif.uid, notif.tid, notif.sender, notif.gid)
+        );
+        assert_eq!(rt._ttl, 40);
+        assert!(doc.notification(1000000 + 60 * 1000).is_none());
+
+        doc.ttl = 0;
+        assert_eq!(doc.notification(i64::MAX).unwrap()._ttl, 0);
+    }
+
+    #[test]
+    fn notification_digest_group_works() {
+        let uid = xid::new();
+        let alice = xid::new();
+        let bot = xid::new();
+        let tids: Vec<xid::Id> = (0..6).map(|_| xid::new()).collect();
+
+        // ordered by tid DESC, as read from the notification table.
+        let mut docs: Vec<Notification> = Vec::new();
+        for (i, tid) in tids.iter().rev().enumerate() {
+            let sender = if i < 5 { alice } else { bot };
+            docs.push(Notification::with_pk(uid, *tid, sender));
+        }
+
+        let res = NotificationDigest::group(docs);
+        let mut expected = vec![
+            NotificationDigest {
+                sender: alice,
+                count: 5,
+                tids: vec![tids[5], tids[4], tids[3]],
+            },
+            NotificationDigest {
+                sender: bot,
+                count: 1,
+                tids: vec![tids[0]],
+            },
+        ];
+        expected.sort_by(|a, b| a.sender.cmp(&b.sender));
+        assert_eq!(res, expected);
+        assert!(NotificationDigest::group(vec![]).is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn notification_batch_save_works() {
+        let db = get_db().await;
+        let tid = xid::new();
+        let sender = xid::new();
+
+        let mut docs: Vec<Notification> = Vec::new();
+        for i in 0..3 {
+            let mut doc = Notification::with_pk(xid::new(), tid, sender);
+            doc._ttl = if i == 0 { 3600 } else { 0 };
+            docs.push(doc);
+        }
+        Notification::batch_save(db, &docs).await.unwrap();
+
+        for (i, doc) in docs.iter_mut().enumerate() {
+            doc.get_one(db).await.unwrap();
+            assert_eq!(doc.status, NotificationStatus::Pending);
+            let ttl = doc.ttl(db).await.unwrap();
+            if i == 0 {
+                assert!(ttl > 0 && ttl <= 3600);
+            } else {
+                assert_eq!(ttl, 0);
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn notification_list_status_paging_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let sender = xid::new();
+
+        let mut expected: Vec<xid::Id> = Vec::new();
+        for i in 0..6 {
+            let mut doc = Notification::with_pk(uid, xid::new(), sender);
+            doc.status = if i % 3 == 1 {
+                NotificationStatus::Resolved
+            } else {
+                NotificationStatus::Pending
+            };
+            doc.save(db).await.unwrap();
+            if doc.status == NotificationStatus::Pending {
+                expected.push(doc.tid);
+            }
+        }
+        expected.sort();
+        expected.reverse();
+
+        let page = Notification::list(db, uid, 2, None, Some(NotificationStatus::Pending))
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 2);
+        let mut tids: Vec<xid::Id> = page.iter().map(|doc| doc.tid).collect();
+
+        let page = Notification::list(
+            db,
+            uid,
+            2,
+            page.last().map(|doc| doc.tid),
+            Some(NotificationStatus::Pending),
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.len(), 2);
+        assert!(page
+            .iter()
+            .all(|doc| doc.status == NotificationStatus::Pending));
+        tids.extend(page.iter().map(|doc| doc.tid));
+        assert_eq!(tids, expected);
+
+        let page = Notification::list(
+            db,
+            uid,
+            2,
+            tids.last().cloned(),
+            Some(NotificationStatus::Pending),
+        )
+        .await
+        .unwrap();
+        assert!(page.is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn group_notification_list_role_paging_works() {
+        let db = get_db().await;
+        let gid = xid::new();
+        let sender = xid::new();
+
+        let mut expected: Vec<xid::Id> = Vec::new();
+        for i in 0..6 {
+            let mut doc = GroupNotification::with_pk(gid, xid::new(), sender);
+            doc.role = if i % 3 == 1 { 1 } else { 0 };
+            doc.save(db).await.unwrap();
+            if doc.role == 0 {
+                expected.push(doc.tid);
+            }
+        }
+        expected.sort();
+        expected.reverse();
+
+        let page = GroupNotification::list(db, gid, 2, None, Some(0))
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 2);
+        let mut tids: Vec<xid::Id> = page.iter().map(|doc| doc.tid).collect();
+
+        let page = GroupNotification::list(db, gid, 2, page.last().map(|doc| doc.tid), Some(0))
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 2);
+        assert!(page.iter().all(|doc| doc.role == 0));
+        tids.extend(page.iter().map(|doc| doc.tid));
+        assert_eq!(tids, expected);
+
+        let page = GroupNotification::list(db, gid, 2, tids.last().cloned(), Some(0))
+            .await
+            .unwrap();
+        assert!(page.is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn notification_list_branches_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let sender = xid::new();
+
+        let mut doc = Notification::with_pk(uid, xid::new(), sender);
+        doc.save(db).await.unwrap();
+        let mut doc2 = Notification::with_pk(uid, xid::new(), sender);
+        doc2.status = NotificationStatus::Resolved;
+        doc2.save(db).await.unwrap();
+
+        // token/no-token × filter/no-filter
+        let cases: [(Option<xid::Id>, Option<NotificationStatus>, Vec<xid::Id>); 4] = [
+            (None, None, vec![doc2.tid, doc.tid]),
+            (None, Some(NotificationStatus::Pending), vec![doc.tid]),
+            (Some(doc2.tid), None, vec![doc.tid]),
+            (Some(doc2.tid), Some(NotificationStatus::Resolved), vec![]),
+        ];
+        for (page_token, status, expected) in cases {
+            let res = Notification::list(db, uid, 10, page_token, status)
+                .await
+                .unwrap();
+            let tids: Vec<xid::Id> = res.iter().map(|doc| doc.tid).collect();
+            assert_eq!(
+                tids, expected,
+                "page_token: {:?}, status: {:?}",
+                page_token, status
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn notification_list_paged_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let sender = xid::new();
+
+        let mut expected: Vec<xid::Id> = Vec::new();
+        for i in 0..5 {
+            let mut doc = Notification::with_pk(uid, xid::new(), sender);
+            doc.status = if i == 2 {
+                NotificationStatus::Resolved
+            } else {
+                NotificationStatus::Pending
+            };
+            doc.save(db).await.unwrap();
+            if doc.status == NotificationStatus::Pending {
+                expected.push(doc.tid);
+            }
+        }
+        expected.sort();
+        expected.reverse();
+
+        let mut tids: Vec<xid::Id> = Vec::new();
+        let mut paging_state: Option<Vec<u8>> = None;
+        loop {
+            let (res, next) = Notification::list_paged(
+                db,
+                uid,
+                2,
+                paging_state,
+                Some(NotificationStatus::Pending),
+            )
+            .await
+            .unwrap();
+            assert!(res.len() <= 2);
+            tids.extend(res.iter().map(|doc| doc.tid));
+            if next.is_none() {
+                break;
+            }
+            paging_state = next;
+        }
+        assert_eq!(tids, expected);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn group_notification_list_branches_works() {
+        let db = get_db().await;
+        let gid = xid::new();
+        let sender = xid::new();
+
+        let mut doc = GroupNotification::with_pk(gid, xid::new(), sender);
+        doc.save(db).await.unwrap();
+        let mut doc2 = GroupNotification::with_pk(gid, xid::new(), sender);
+        doc2.role = 1;
+        doc2.save(db).await.unwrap();
+
+        // token/no-token × filter/no-filter
+        let cases: [(Option<xid::Id>, Option<i8>, Vec<xid::Id>); 4] = [
+            (None, None, vec![doc2.tid, doc.tid]),
+            (None, Some(0), vec![doc.tid]),
+            (Some(doc2.tid), None, vec![doc.tid]),
+            (Some(doc2.tid), Some(1), vec![]),
+        ];
+        for (page_token, role, expected) in cases {
+            let res = GroupNotification::list(db, gid, 10, page_token, role)
+                .await
+                .unwrap();
+            let tids: Vec<xid::Id> = res.iter().map(|doc| doc.tid).collect();
+            assert_eq!(
+                tids, expected,
+                "page_token: {:?}, role: {:?}",
+                page_token, role
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn notification_batch_delete_by_uid_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        for i in 0..5 {
+            let mut doc = Notification::with_pk(uid, xid::new(), xid::new());
+            doc.status = if i % 2 == 0 {
+                NotificationStatus::Resolved
+            } else {
+                NotificationStatus::Pending
+            };
+            doc.save(db).await.unwrap();
+        }
+
+        let removed =
+            Notification::batch_delete_by_uid(db, uid, Some(NotificationStatus::Resolved))
+                .await
+                .unwrap();
+        assert_eq!(removed, 3);
+        let res = Notification::list(db, uid, 10, None, None).await.unwrap();
+        assert_eq!(res.len(), 2);
+        assert!(res
+            .iter()
+            .all(|doc| doc.status == NotificationStatus::Pending));
+
+        let removed = Notification::batch_delete_by_uid(db, uid, None)
+            .await
+            .unwrap();
+        assert_eq!(removed, 2);
+        let res = Notification::list(db, uid, 10, None, None).await.unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn notification_batch_delete_by_tid_works() {
+        let db = get_db().await;
+        let tid = xid::new();
+        let sender = xid::new();
+
+        let mut doc = Notification::with_pk(xid::new(), tid, sender);
+        doc.save(db).await.unwrap();
+        let docs: Vec<Notification> = (0..3)
+            .map(|_| Notification::with_pk(xid::new(), tid, sender))
+            .collect();
+        Notification::batch_save(db, &docs).await.unwrap();
+
+        let query = "SELECT uid FROM notification_by_tid WHERE tid=?";
+        let rows = db.execute_iter(query, (tid.to_cql(),)).await.unwrap();
+        assert_eq!(rows.len(), 4);
+
+        Notification::batch_delete_by_tid(db, tid).await.unwrap();
+        let rows = db.execute_iter(query, (tid.to_cql(),)).await.unwrap();
+        assert!(rows.is_empty());
+        assert!(doc.get_one(db).await.is_err());
+        for mut doc in docs {
+            assert!(doc.get_one(db).await.is_err());
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn notification_ttl_works() {
+        let db = get_db().await;
+
+        let mut doc = Notification::with_pk(xid::new(), xid::new(), xid::new());
+        doc.save(db).await.unwrap();
+        assert_eq!(doc.ttl(db).await.unwrap(), 0);
+
+        let mut doc = Notification::with_pk(xid::new(), xid::new(), xid::new());
+        doc._ttl = 3600;
+        doc.save(db).await.unwrap();
+        let ttl = doc.ttl(db).await.unwrap();
+        assert!(ttl > 3500 && ttl <= 3600);
+
+        // update keeps the TTL instead of dropping it.
+        doc.status = NotificationStatus::Resolved;
+        doc.message = "ok".to_string();
+        doc.update(db).await.unwrap();
+        let ttl = doc.ttl(db).await.unwrap();
+        assert!(ttl > 3500 && ttl <= 3600);
+
+        let query = "SELECT TTL(message) FROM notification WHERE uid=? AND tid=? AND sender=?";
+        let params = (doc.uid.to_cql(), doc.tid.to_cql(), doc.sender.to_cql());
+        let row = db
+            .execute(query, params)
+            .await
+            .unwrap()
+            .single_row()
+            .unwrap();
+        let ttl = row.columns[0].as_ref().and_then(|v| v.as_int()).unwrap();
+        assert!(ttl > 3500 && ttl <= 3600);
+    }
 }
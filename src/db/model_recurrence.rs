@@ -0,0 +1,207 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+use std::collections::HashSet;
+
+use crate::db::scylladb::{extract_applied, Consistency, CqlExecutor};
+
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct TaskRecurrence {
+    pub uid: xid::Id,
+    pub id: xid::Id,
+    pub gid: xid::Id,
+    pub kind: String,
+    pub created_at: i64,
+    pub interval_ms: i64,
+    pub next_run_at: i64,
+    pub threshold: i16,
+    pub approvers: HashSet<xid::Id>,
+    pub assignees: HashSet<xid::Id>,
+    pub message: String,
+    pub payload: Vec<u8>,
+    pub payload_type: String,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl TaskRecurrence {
+    pub fn with_pk(uid: xid::Id, id: xid::Id) -> Self {
+        Self {
+            uid,
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub async fn get_one(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM task_recurrence WHERE uid=? AND id=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.uid.to_cql(), self.id.to_cql());
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    pub async fn save(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        self.created_at = unix_ms() as i64;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO task_recurrence ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                "TaskRecurrence save failed, please try again".to_string(),
+            )
+            .into());
+        }
+
+        Ok(true)
+    }
+
+    pub async fn delete(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let query = "DELETE FROM task_recurrence WHERE uid=? AND id=?";
+        let params = (self.uid.to_cql(), self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // the first run time after now, skipped windows are collapsed into one run.
+    pub fn next_run_after(&self, now_ms: i64) -> i64 {
+        if self.next_run_at > now_ms || self.interval_ms <= 0 {
+            return self.next_run_at;
+        }
+        let skipped = (now_ms - self.next_run_at) / self.interval_ms + 1;
+        self.next_run_at + skipped * self.interval_ms
+    }
+
+    // advances next_run_at with LWT, returns false if another worker has advanced it,
+    // only the worker which advanced it should materialize the task.
+    pub async fn advance(&mut self, db: &impl CqlExecutor, now_ms: i64) -> anyhow::Result<bool> {
+        let next_run_at = self.next_run_after(now_ms);
+        let query =
+            "UPDATE task_recurrence SET next_run_at=? WHERE uid=? AND id=? IF next_run_at=?";
+        let params = (
+            next_run_at.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            self.next_run_at.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Ok(false);
+        }
+        self.next_run_at = next_run_at;
+        Ok(true)
+    }
+
+    pub async fn list(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<TaskRecurrence>> {
+        let fields = Self::fields();
+
+        let rows = if let Some(id) = page_token {
+            let query = format!(
+                "SELECT {} FROM task_recurrence WHERE uid=? AND id<? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (uid.to_cql(), id.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        } else {
+            let query = format!(
+                "SELECT {} FROM task_recurrence WHERE uid=? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (uid.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        };
+
+        let mut res: Vec<TaskRecurrence> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = TaskRecurrence::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // scans recurrences across all users whose next_run_at has passed.
+    pub async fn list_due(
+        db: &impl CqlExecutor,
+        now_ms: i64,
+        limit: u16,
+    ) -> anyhow::Result<Vec<TaskRecurrence>> {
+        let fields = Self::fields();
+        let query = format!(
+            "SELECT {} FROM task_recurrence WHERE next_run_at<=? LIMIT ? ALLOW FILTERING BYPASS CACHE USING TIMEOUT 10s",
+            fields.join(",")
+        );
+        let params = (now_ms.to_cql(), limit as i32);
+        let rows = db
+            .execute_iter_with(query, params, Consistency::One)
+            .await?;
+
+        let mut res: Vec<TaskRecurrence> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = TaskRecurrence::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_run_after_works() {
+        let mut doc = TaskRecurrence::with_pk(xid::new(), xid::new());
+        doc.interval_ms = 1000;
+        doc.next_run_at = 10_000;
+
+        assert_eq!(doc.next_run_after(9_000), 10_000);
+        assert_eq!(doc.next_run_after(10_000), 11_000);
+        assert_eq!(doc.next_run_after(10_500), 11_000);
+        // skipped windows collapse into one catch-up run.
+        assert_eq!(doc.next_run_after(15_200), 16_000);
+    }
+}
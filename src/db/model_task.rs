@@ -2,15 +2,43 @@ use axum_web::{context::unix_ms, erring::HTTPError};
 use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
 use scylla_orm_macros::CqlOrm;
 use std::collections::HashSet;
+use tracing::instrument;
 
-use crate::db::scylladb::{self, extract_applied};
+use crate::crypto;
+use crate::db::{
+    counters,
+    scylladb::{self, exists, extract_applied, Consistency, CqlExecutor},
+    stats, GroupNotification, Invalidation, Notification, NotificationStatus, TaskCache,
+    TaskComment, TaskLog, TaskStatus,
+};
 
-#[derive(Debug, Default, Clone, CqlOrm)]
+// the page size and the hard cap of the status-filtered batch deletes.
+const BATCH_DELETE_PAGE_SIZE: i32 = 100;
+const BATCH_DELETE_LIMIT: u64 = 10000;
+
+// the max attempts to finalize the status while votes keep changing.
+const FINALIZE_RETRIES: usize = 10;
+
+// the 409 error of an updated_at conflict, it carries the current updated_at
+// so that clients can retry without reloading the task.
+pub fn updated_at_conflict(current: i64, updated_at: i64) -> anyhow::Error {
+    HTTPError::new(
+        409,
+        format!(
+            "Task updated_at conflict, expected updated_at {}, got {}",
+            current, updated_at
+        ),
+    )
+    .with_data(serde_json::json!({ "current_updated_at": current }))
+    .into()
+}
+
+#[derive(Debug, Clone, CqlOrm)]
 pub struct Task {
     pub uid: xid::Id,
     pub id: xid::Id,
     pub gid: xid::Id,
-    pub status: i8,
+    pub status: TaskStatus,
     pub kind: String,
     pub created_at: i64,
     pub updated_at: i64,
@@ -22,11 +50,85 @@ pub struct Task {
     pub rejected: HashSet<xid::Id>,
     pub message: String,
     pub payload: Vec<u8>,
+    pub priority: i8,
+    pub deleted_at: i64,
+    pub prev_status: TaskStatus,
+    pub notify_creator: bool,
+    pub escalate_after_ms: i64,
+    pub escalated_at: i64,
+    pub allow_late_ack: bool,
+    pub approver_threshold: i16,
+    pub veto: bool,
+    pub sequence: Vec<xid::Id>,
+    pub self_ack_allowed: bool,
+    pub payload_type: String, // the media type of the payload, required with a payload
+    pub payload_len: i32,     // maintained by the writes of the payload
+    pub payload_external: bool, // the payload is a reference to the blob store, see `blobs::BlobRef`
+    pub watchers: HashSet<xid::Id>, // notified of the status changes, they never vote
+    pub final_reason: String,   // the reason code of the ack that finalized the task
+    pub visibility: String,     // "private", "group" or "public", empty for "group"
+    pub finalized_at: i64,      // when the task was first finalized and counted in the stats
 
     pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
 }
 
+// `fill` skips the null columns, so the defaults are what the rows written before
+// a column was added read as. self_ack_allowed is true for them.
+impl Default for Task {
+    fn default() -> Self {
+        Self {
+            uid: xid::Id::default(),
+            id: xid::Id::default(),
+            gid: xid::Id::default(),
+            status: TaskStatus::default(),
+            kind: String::new(),
+            created_at: 0,
+            updated_at: 0,
+            duedate: 0,
+            threshold: 0,
+            approvers: HashSet::new(),
+            assignees: HashSet::new(),
+            resolved: HashSet::new(),
+            rejected: HashSet::new(),
+            message: String::new(),
+            payload: Vec::new(),
+            priority: 0,
+            deleted_at: 0,
+            prev_status: TaskStatus::default(),
+            notify_creator: false,
+            escalate_after_ms: 0,
+            escalated_at: 0,
+            allow_late_ack: false,
+            approver_threshold: 0,
+            veto: false,
+            sequence: Vec::new(),
+            self_ack_allowed: true,
+            payload_type: String::new(),
+            payload_len: 0,
+            payload_external: false,
+            watchers: HashSet::new(),
+            final_reason: String::new(),
+            visibility: String::new(),
+            finalized_at: 0,
+            _fields: Vec::new(),
+        }
+    }
+}
+
 impl Task {
+    // pseudo field, it selects the sets and thresholds but only the counts of sets are output.
+    pub const COUNTS_FIELD: &str = "counts";
+
+    // fields that can be large, multi-row queries only select them when requested explicitly.
+    pub const EXPLICIT_FIELDS: [&str; 2] = ["payload", "watchers"];
+
+    pub const MAX_WATCHERS: usize = 64;
+
+    // "private" tasks are only visible to their participants, "group" ones are exposed to
+    // the group with the group notification, "public" ones are reserved for the groups the
+    // creator shares and are exposed like "group" ones for now.
+    pub const VISIBILITIES: [&str; 3] = ["private", "group", "public"];
+
     pub fn with_pk(uid: xid::Id, id: xid::Id) -> Self {
         Self {
             uid,
@@ -35,19 +137,257 @@ impl Task {
         }
     }
 
+    // a non-zero threshold can not exceed the votes the task can collect, see `reachable_votes`.
+    pub fn check_threshold(threshold: i16, max_votes: usize) -> anyhow::Result<()> {
+        if threshold > 0 && threshold as usize > max_votes {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "Invalid threshold, expected <= {}, got {}",
+                    max_votes, threshold
+                ),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // a non-zero approver threshold needs as many approvers.
+    pub fn check_approver_threshold(
+        approver_threshold: i16,
+        approvers: usize,
+    ) -> anyhow::Result<()> {
+        if approver_threshold > 0 && approver_threshold as usize > approvers {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "Invalid approver_threshold, expected <= {}, got {}",
+                    approvers, approver_threshold
+                ),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // the most votes counted against the threshold, unlimited when anyone can ack the task.
+    // The participants' votes all count, unless an approver threshold counts the
+    // assignees' votes only.
+    pub fn reachable_votes(
+        approver_threshold: i16,
+        approvers: &HashSet<xid::Id>,
+        assignees: &HashSet<xid::Id>,
+    ) -> usize {
+        if approvers.is_empty() && assignees.is_empty() {
+            return usize::MAX;
+        }
+        if approver_threshold > 0 && !assignees.is_empty() {
+            return assignees.len();
+        }
+        approvers.union(assignees).count()
+    }
+
+    // It needs approver_threshold, approvers and assignees loaded.
+    pub fn max_reachable_votes(&self) -> usize {
+        Self::reachable_votes(self.approver_threshold, &self.approvers, &self.assignees)
+    }
+
+    // the votes needed to finalize the task, threshold 0 requires all assignees to agree,
+    // or a single vote if there are no assignees. It needs threshold and assignees loaded.
+    pub fn required_votes(&self) -> usize {
+        if self.threshold > 0 {
+            self.threshold as usize
+        } else {
+            self.assignees.len().max(1)
+        }
+    }
+
+    // whether the votes meet the quorum. Without an approver threshold all votes count
+    // against the threshold. With it, the assignees' votes count against the threshold
+    // and the approvers' votes against the approver threshold, both should be met.
+    // It needs threshold, approver_threshold, approvers and assignees loaded.
+    pub fn meets_quorum(&self, votes: &HashSet<xid::Id>) -> bool {
+        if self.approver_threshold <= 0 {
+            return votes.len() >= self.required_votes();
+        }
+
+        let assignee_votes = if self.assignees.is_empty() {
+            votes.len()
+        } else {
+            votes.intersection(&self.assignees).count()
+        };
+        let approver_votes = votes.intersection(&self.approvers).count();
+        assignee_votes >= self.required_votes()
+            && approver_votes >= self.approver_threshold as usize
+    }
+
+    // the votes without those of the removed participants, except the ones who are still
+    // in one of the `kept` sets.
+    fn votes_without(
+        &self,
+        removed: &[xid::Id],
+        kept: &[&HashSet<xid::Id>],
+    ) -> (HashSet<xid::Id>, HashSet<xid::Id>) {
+        let keep = |id: &&xid::Id| !removed.contains(id) || kept.iter().any(|set| set.contains(id));
+        (
+            self.resolved.iter().filter(keep).cloned().collect(),
+            self.rejected.iter().filter(keep).cloned().collect(),
+        )
+    }
+
+    // whether a reject from the voter rejects the task at once, that is a veto approver or
+    // a member of the sequence. It needs veto, approvers and sequence loaded.
+    pub fn vetoed_by(&self, voter: &xid::Id) -> bool {
+        (self.veto && self.approvers.contains(voter)) || self.sequence.contains(voter)
+    }
+
+    // the member of the sequence who should resolve next, it needs sequence and resolved loaded.
+    pub fn next_in_sequence(&self) -> Option<xid::Id> {
+        self.sequence
+            .iter()
+            .find(|id| !self.resolved.contains(id))
+            .cloned()
+    }
+
+    // invalidates the cached task when dropped, every mutation of the task holds one.
+    fn invalidation<'a>(&self, db: &'a impl CqlExecutor) -> Invalidation<'a> {
+        Invalidation::new(db.task_cache(), self.uid, self.id)
+    }
+
+    // an insert not applied after a retry may have been applied by the timed out attempt,
+    // the existing row is ours if it has our created_at, gid and kind, see `save_idempotent`.
+    async fn is_own_row(&self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        let mut doc = Task::with_pk(self.uid, self.id);
+        let fields = vec![
+            "created_at".to_string(),
+            "gid".to_string(),
+            "kind".to_string(),
+        ];
+        if !exists(doc.get_one(db, fields).await)? {
+            return Ok(false);
+        }
+        Ok(doc.created_at == self.created_at && doc.gid == self.gid && doc.kind == self.kind)
+    }
+
+    // a vote not applied after a retry may have been applied by the timed out attempt,
+    // it is if the row has the vote of the assignee.
+    async fn has_vote(
+        &self,
+        db: &impl CqlExecutor,
+        assignee: xid::Id,
+        resolved: bool,
+    ) -> anyhow::Result<bool> {
+        let mut doc = Task::with_pk(self.uid, self.id);
+        let fields = vec!["resolved".to_string(), "rejected".to_string()];
+        if !exists(doc.get_one(db, fields).await)? {
+            return Ok(false);
+        }
+        let (voted, other) = if resolved {
+            (&doc.resolved, &doc.rejected)
+        } else {
+            (&doc.rejected, &doc.resolved)
+        };
+        Ok(voted.contains(&assignee) && !other.contains(&assignee))
+    }
+
+    // the updated_at condition failed, reloads the current updated_at for the conflict error.
+    async fn reload_conflict(&mut self, db: &impl CqlExecutor, updated_at: i64) -> anyhow::Error {
+        match self.get_one(db, vec!["updated_at".to_string()]).await {
+            Ok(_) => updated_at_conflict(self.updated_at, updated_at),
+            Err(err) => err,
+        }
+    }
+
+    // the participants of a sequence task are fixed at create.
+    fn check_no_sequence(&self) -> anyhow::Result<()> {
+        if !self.sequence.is_empty() {
+            return Err(HTTPError::new(
+                400,
+                "Task participants follow the sequence, they can not be updated".to_string(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // members of the sequence vote in order, those who resolved can still change their votes.
+    fn check_turn(&self, voter: &xid::Id) -> anyhow::Result<()> {
+        if self.sequence.is_empty() || self.resolved.contains(voter) {
+            return Ok(());
+        }
+        match self.next_in_sequence() {
+            Some(next) if next == *voter => Ok(()),
+            Some(next) => Err(HTTPError::new(
+                409,
+                format!("Task is waiting for {} in the sequence", next),
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    // the participants vote, and anyone but the watchers votes on a task without participants.
+    // It needs approvers, assignees and watchers loaded.
+    fn can_vote(&self, voter: &xid::Id) -> bool {
+        if self.approvers.contains(voter) || self.assignees.contains(voter) {
+            return true;
+        }
+        self.approvers.is_empty() && self.assignees.is_empty() && !self.watchers.contains(voter)
+    }
+
+    // the creator can not vote on their own task unless self acks are allowed.
+    // It needs self_ack_allowed loaded.
+    fn check_self_ack(&self, voter: &xid::Id) -> anyhow::Result<()> {
+        if !self.self_ack_allowed && *voter == self.uid {
+            return Err(HTTPError::new(
+                403,
+                "the task creator can not ack their own task".to_string(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // all fields but the explicit ones, the default fields of multi-row queries.
+    pub fn list_fields() -> Vec<String> {
+        Self::fields()
+            .into_iter()
+            .filter(|f| !Self::EXPLICIT_FIELDS.contains(&f.as_str()))
+            .collect()
+    }
+
+    // multi-row queries select with_pk, they default to the list fields.
     pub fn select_fields(select_fields: Vec<String>, with_pk: bool) -> anyhow::Result<Vec<String>> {
         if select_fields.is_empty() {
+            if with_pk {
+                return Ok(Self::list_fields());
+            }
             return Ok(Self::fields());
         }
 
         let fields = Self::fields();
         for field in &select_fields {
-            if !fields.contains(field) {
+            if field != Self::COUNTS_FIELD && !fields.contains(field) {
                 return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
             }
         }
 
         let mut select_fields = select_fields;
+        if select_fields.iter().any(|f| f == Self::COUNTS_FIELD) {
+            for field in [
+                "threshold",
+                "approver_threshold",
+                "approvers",
+                "assignees",
+                "resolved",
+                "rejected",
+            ] {
+                let field = field.to_string();
+                if !select_fields.contains(&field) {
+                    select_fields.push(field);
+                }
+            }
+        }
         let field = "gid".to_string();
         if !select_fields.contains(&field) {
             select_fields.push(field);
@@ -60,6 +400,21 @@ impl Task {
         if !select_fields.contains(&field) {
             select_fields.push(field);
         }
+        // the payload is a reference without its marker.
+        if select_fields.iter().any(|f| f == "payload") {
+            let field = "payload_external".to_string();
+            if !select_fields.contains(&field) {
+                select_fields.push(field);
+            }
+        }
+        // the payload metadata is selected without the payload, so that clients can decide
+        // whether to read the payload.
+        for field in ["payload_type", "payload_len"] {
+            let field = field.to_string();
+            if !select_fields.contains(&field) {
+                select_fields.push(field);
+            }
+        }
         if with_pk {
             let field = "uid".to_string();
             if !select_fields.contains(&field) {
@@ -74,30 +429,165 @@ impl Task {
         Ok(select_fields)
     }
 
+    // columns to query, without pseudo fields.
+    pub fn columns(fields: &[String]) -> Vec<String> {
+        fields
+            .iter()
+            .filter(|f| f.as_str() != Self::COUNTS_FIELD)
+            .cloned()
+            .collect()
+    }
+
+    // the columns of the row to write, the payload is sealed with the primary key
+    // when the encryption is configured. The message is kept in plaintext.
+    fn sealed_cols(&self, db: &impl CqlExecutor) -> anyhow::Result<ColumnsMap> {
+        let mut cols = self.to();
+        if let Some(keyring) = db.keyring() {
+            cols.set_as("payload", &keyring.seal(&self.payload)?);
+        }
+        Ok(cols)
+    }
+
+    // decrypts the payload read from the row, plaintext payloads written before the
+    // encryption was enabled are kept as they are.
+    fn open_payload(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        if !crypto::is_sealed(&self.payload) {
+            return Ok(());
+        }
+        let keyring = db.keyring().ok_or_else(|| {
+            HTTPError::new(
+                500,
+                "Task payload is encrypted, but the encryption is not configured".to_string(),
+            )
+        })?;
+        self.payload = keyring.open(&self.payload)?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
     pub async fn get_one(
         &mut self,
-        db: &scylladb::ScyllaDB,
+        db: &impl CqlExecutor,
         select_fields: Vec<String>,
     ) -> anyhow::Result<()> {
         let fields = Self::select_fields(select_fields, false)?;
         self._fields = fields.clone();
+        let columns = Self::columns(&fields);
 
         let query = format!(
             "SELECT {} FROM task WHERE uid=? AND id=? LIMIT 1",
-            fields.join(",")
+            columns.join(",")
         );
         let params = (self.uid.to_cql(), self.id.to_cql());
         let res = db.execute(query, params).await?.single_row()?;
 
-        let mut cols = ColumnsMap::with_capacity(fields.len());
-        cols.fill(res, &fields)?;
+        let mut cols = ColumnsMap::with_capacity(columns.len());
+        cols.fill(res, &columns)?;
+        self.fill(&cols);
+        self.open_payload(db)?;
+
+        Ok(())
+    }
+
+    // reads the full row through the cache, and then selects the fields to output,
+    // returns whether the cache was hit. The row may be stale for the ttl of the cache,
+    // only the reads tolerating it should use the cache, e.g. the notification lists.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn get_cached(
+        &mut self,
+        db: &impl CqlExecutor,
+        cache: &TaskCache,
+        select_fields: Vec<String>,
+    ) -> anyhow::Result<bool> {
+        let fields = Self::select_fields(select_fields, false)?;
+        let hit = match cache.get(self.uid, self.id) {
+            Some(doc) => {
+                *self = doc;
+                true
+            }
+            None => {
+                self.get_one(db, vec![]).await?;
+                cache.insert(self.clone());
+                false
+            }
+        };
+        self._fields = fields;
+        Ok(hit)
+    }
+
+    // reads the task from the task_archive table, see `archive`.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn get_archived(
+        &mut self,
+        db: &impl CqlExecutor,
+        select_fields: Vec<String>,
+    ) -> anyhow::Result<()> {
+        let fields = Self::select_fields(select_fields, false)?;
+        self._fields = fields.clone();
+        let columns = Self::columns(&fields);
+
+        let query = format!(
+            "SELECT {} FROM task_archive WHERE uid=? AND id=? LIMIT 1",
+            columns.join(",")
+        );
+        let params = (self.uid.to_cql(), self.id.to_cql());
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(columns.len());
+        cols.fill(res, &columns)?;
         self.fill(&cols);
+        self.open_payload(db)?;
 
         Ok(())
     }
 
-    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+    // missing ids will be absent from the result.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn batch_get(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        ids: Vec<xid::Id>,
+        select_fields: Vec<String>,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = Self::select_fields(select_fields, true)?;
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let columns = Self::columns(&fields);
+
+        let query = format!(
+            "SELECT {} FROM task WHERE uid=? AND id IN ({}) USING TIMEOUT 3s",
+            columns.join(","),
+            ids.iter().map(|_| "?").collect::<Vec<&str>>().join(",")
+        );
+        let mut params: Vec<CqlValue> = Vec::with_capacity(ids.len() + 1);
+        params.push(uid.to_cql());
+        for id in &ids {
+            params.push(id.to_cql());
+        }
+
+        let rows = db.execute_iter(query, params).await?;
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(columns.len());
+            cols.fill(row, &columns)?;
+            doc.fill(&cols);
+            doc.open_payload(db)?;
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn save(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
         self.updated_at = unix_ms() as i64;
+        if !self.payload_external {
+            self.payload_len = self.payload.len() as i32;
+        }
 
         let fields = Self::fields();
         self._fields = fields.clone();
@@ -105,7 +595,7 @@ impl Task {
         let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
         let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
         let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
-        let cols = self.to();
+        let cols = self.sealed_cols(db)?;
 
         for field in &fields {
             cols_name.push(field);
@@ -119,375 +609,4477 @@ impl Task {
             vals_name.join(",")
         );
 
-        let res = db.execute(query, params).await?;
-        if !extract_applied(res) {
+        let (res, retried) = db.execute_lwt_retried(query, params).await?;
+        if !extract_applied(res) && !(retried && self.is_own_row(db).await?) {
             return Err(
                 HTTPError::new(409, "Task save failed, please try again".to_string()).into(),
             );
         }
 
+        self.save_index(db).await?;
+        self.save_participant_index(db, &self.participants())
+            .await?;
+        self.save_duedate_index(db).await?;
+        self.save_priority_index(db).await?;
+        self.save_escalation_index(db).await?;
+        counters::incr(db, self.uid, self.status, 1).await?;
         Ok(true)
     }
 
-    pub async fn update(
-        &mut self,
-        db: &scylladb::ScyllaDB,
-        cols: ColumnsMap,
-        updated_at: i64,
-    ) -> anyhow::Result<bool> {
-        let valid_fields = vec!["duedate", "message"];
-        let update_fields = cols.keys();
-        for field in &update_fields {
-            if !valid_fields.contains(&field.as_str()) {
-                return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
-            }
+    // writes an imported task as is, with its created_at, updated_at, status and votes.
+    // An existing task is skipped unless overwrite, then false is returned. The index rows
+    // are written in one unlogged batch. The counters are only increased for new tasks,
+    // overwritten tasks may change their status, so their users should be recounted.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn import(&mut self, db: &impl CqlExecutor, overwrite: bool) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        if !self.payload_external {
+            self.payload_len = self.payload.len() as i32;
         }
+        let fields = Self::fields();
+        self._fields = fields.clone();
 
-        self.get_one(db, vec!["status".to_string(), "updated_at".to_string()])
-            .await?;
-        if self.updated_at != updated_at {
-            return Err(HTTPError::new(
-                409,
-                format!(
-                    "Task updated_at conflict, expected updated_at {}, got {}",
-                    self.updated_at, updated_at
-                ),
-            )
-            .into());
+        let cols = self.sealed_cols(db)?;
+        let params: Vec<&CqlValue> = fields.iter().map(|f| cols.get(f).unwrap()).collect();
+        let query = format!(
+            "INSERT INTO task ({}) VALUES ({}){}",
+            fields.join(","),
+            vec!["?"; fields.len()].join(","),
+            if overwrite { "" } else { " IF NOT EXISTS" }
+        );
+        if overwrite {
+            let _ = db.execute(query, params).await?;
+        } else {
+            let (res, retried) = db.execute_lwt_retried(query, params).await?;
+            if !extract_applied(res) && !(retried && self.is_own_row(db).await?) {
+                return Ok(false);
+            }
         }
 
-        let mut set_fields: Vec<String> = Vec::with_capacity(update_fields.len() + 1);
-        let mut params: Vec<CqlValue> = Vec::with_capacity(update_fields.len() + 1 + 3);
+        let mut statements: Vec<(String, Vec<CqlValue>)> = vec![(
+            "INSERT INTO task_by_gid (gid,id,uid,status,kind) VALUES (?,?,?,?,?)".to_string(),
+            vec![
+                self.gid.to_cql(),
+                self.id.to_cql(),
+                self.uid.to_cql(),
+                self.status.to_cql(),
+                self.kind.to_cql(),
+            ],
+        )];
+        for id in self.participants() {
+            statements.push((
+                "INSERT INTO task_by_participant (participant,task_id,task_uid,status,duedate) VALUES (?,?,?,?,?)".to_string(),
+                vec![
+                    id.to_cql(),
+                    self.id.to_cql(),
+                    self.uid.to_cql(),
+                    self.status.to_cql(),
+                    self.duedate.to_cql(),
+                ],
+            ));
+        }
+        if self.status == TaskStatus::Pending {
+            statements.push((
+                "INSERT INTO task_by_priority (uid,priority,id) VALUES (?,?,?)".to_string(),
+                vec![self.uid.to_cql(), self.priority.to_cql(), self.id.to_cql()],
+            ));
+            if self.escalate_after_ms > 0 && self.escalated_at <= 0 {
+                statements.push((
+                    "INSERT INTO task_by_escalation (uid,escalate_at,id) VALUES (?,?,?)"
+                        .to_string(),
+                    vec![
+                        self.uid.to_cql(),
+                        (self.created_at + self.escalate_after_ms).to_cql(),
+                        self.id.to_cql(),
+                    ],
+                ));
+            }
+        }
+        let _ = db.execute_batch(statements).await?;
+        // the duedate index is conditional, it can not be batched with the others.
+        self.save_duedate_index(db).await?;
 
-        let new_updated_at = unix_ms() as i64;
-        set_fields.push("updated_at=?".to_string());
-        params.push(new_updated_at.to_cql());
-        for field in &update_fields {
-            set_fields.push(format!("{}=?", field));
-            params.push(cols.get(field).unwrap().to_owned());
+        if !overwrite {
+            counters::incr(db, self.uid, self.status, 1).await?;
         }
+        Ok(true)
+    }
 
-        let query = format!(
-            "UPDATE task SET {} WHERE uid=? AND id=? IF updated_at=?",
-            set_fields.join(",")
+    // task_by_gid index, written with plain INSERT so it is safe to rewrite.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn save_index(&self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let query = "INSERT INTO task_by_gid (gid,id,uid,status,kind) VALUES (?,?,?,?,?)";
+        let params = (
+            self.gid.to_cql(),
+            self.id.to_cql(),
+            self.uid.to_cql(),
+            self.status.to_cql(),
+            self.kind.to_cql(),
         );
-        params.push(self.uid.to_cql());
-        params.push(self.id.to_cql());
-        params.push(updated_at.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
 
-        let res = db.execute(query, params).await?;
-        if !extract_applied(res) {
-            return Err(
-                HTTPError::new(409, "Task update failed, please try again".to_string()).into(),
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn update_index_status(&self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let query = "UPDATE task_by_gid SET status=? WHERE gid=? AND id=?";
+        let params = (self.status.to_cql(), self.gid.to_cql(), self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+
+        let mut doc = Task::with_pk(self.uid, self.id);
+        doc.get_one(
+            db,
+            vec![
+                "approvers".to_string(),
+                "assignees".to_string(),
+                "duedate".to_string(),
+                "priority".to_string(),
+                "kind".to_string(),
+            ],
+        )
+        .await?;
+        doc.status = self.status;
+        if doc.status == TaskStatus::Pending {
+            doc.save_duedate_index(db).await?;
+            doc.save_priority_index(db).await?;
+        } else {
+            doc.delete_duedate_index(db, doc.duedate).await?;
+            doc.delete_priority_index(db, doc.priority).await?;
+        }
+        for id in doc.participants() {
+            let query = "UPDATE task_by_participant SET status=? WHERE participant=? AND task_id=? AND task_uid=?";
+            let params = (
+                self.status.to_cql(),
+                id.to_cql(),
+                self.id.to_cql(),
+                self.uid.to_cql(),
             );
+            let _ = db.execute(query, params).await?;
         }
-
-        self.updated_at = new_updated_at;
-        Ok(true)
+        Ok(())
     }
 
-    pub async fn update_assignees(
-        &mut self,
-        db: &scylladb::ScyllaDB,
-        remove: Vec<xid::Id>,
-        add: Vec<xid::Id>,
-        updated_at: i64,
-    ) -> anyhow::Result<bool> {
-        self.get_one(db, vec!["updated_at".to_string()]).await?;
-        if self.updated_at != updated_at {
-            return Err(HTTPError::new(
-                409,
-                format!(
-                    "Task updated_at conflict, expected updated_at {}, got {}",
-                    self.updated_at, updated_at
-                ),
-            )
-            .into());
+    // rows created before the visibility read it as empty.
+    pub fn visibility(&self) -> &str {
+        if self.visibility.is_empty() {
+            "group"
+        } else {
+            &self.visibility
         }
+    }
 
-        let mut updated_at = updated_at;
-        let new_updated_at = unix_ms() as i64;
-        if !remove.is_empty() {
-            let mut params: Vec<CqlValue> = Vec::with_capacity(remove.len() + 4);
-            let query = format!(
-                "UPDATE task SET assignees=assignees-{{ {} }}, updated_at=? WHERE uid=? AND id=? IF updated_at=?",
-                remove.iter().map(|_| "?").collect::<Vec<&str>>().join(",")
-            );
+    pub fn is_private(&self) -> bool {
+        self.visibility() == "private"
+    }
 
-            for id in &remove {
-                params.push(id.to_cql());
-            }
-            params.push(new_updated_at.to_cql());
-            params.push(self.uid.to_cql());
-            params.push(self.id.to_cql());
-            params.push(updated_at.to_cql());
+    // approvers and assignees.
+    pub fn participants(&self) -> HashSet<xid::Id> {
+        self.approvers.union(&self.assignees).cloned().collect()
+    }
 
-            let res = db.execute(query, params).await?;
-            if !extract_applied(res) {
-                return Err(HTTPError::new(
-                    409,
-                    "Task update failed, please try again".to_string(),
-                )
-                .into());
-            }
-            updated_at = new_updated_at;
+    // task_by_participant index, written with plain INSERT so it is safe to rewrite.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn save_participant_index(
+        &self,
+        db: &impl CqlExecutor,
+        participants: &HashSet<xid::Id>,
+    ) -> anyhow::Result<()> {
+        for id in participants {
+            let query = "INSERT INTO task_by_participant (participant,task_id,task_uid,status,duedate) VALUES (?,?,?,?,?)";
+            let params = (
+                id.to_cql(),
+                self.id.to_cql(),
+                self.uid.to_cql(),
+                self.status.to_cql(),
+                self.duedate.to_cql(),
+            );
+            let _ = db.execute(query, params).await?;
         }
+        Ok(())
+    }
 
-        if !add.is_empty() {
-            let mut params: Vec<CqlValue> = Vec::with_capacity(add.len() + 4);
-            let query = format!(
-                "UPDATE task SET assignees=assignees+{{ {} }}, updated_at=? WHERE uid=? AND id=? IF updated_at=?",
-                add.iter().map(|_| "?").collect::<Vec<&str>>().join(",")
-            );
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn delete_participant_index(
+        &self,
+        db: &impl CqlExecutor,
+        participants: &HashSet<xid::Id>,
+    ) -> anyhow::Result<()> {
+        for id in participants {
+            let query =
+                "DELETE FROM task_by_participant WHERE participant=? AND task_id=? AND task_uid=?";
+            let params = (id.to_cql(), self.id.to_cql(), self.uid.to_cql());
+            let _ = db.execute(query, params).await?;
+        }
+        Ok(())
+    }
 
-            for id in &add {
-                params.push(id.to_cql());
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn delete_index(&self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let query = "DELETE FROM task_by_gid WHERE gid=? AND id=?";
+        let params = (self.gid.to_cql(), self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // task_by_duedate index, only processing tasks with a duedate are kept in it.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn save_duedate_index(&self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        if self.duedate <= 0 || self.status != TaskStatus::Pending {
+            return Ok(());
+        }
+
+        // IF NOT EXISTS keeps the reminder state of an existing row.
+        let query = "INSERT INTO task_by_duedate (uid,duedate,id,kind,reminded_at) VALUES (?,?,?,?,0) IF NOT EXISTS";
+        let params = (
+            self.uid.to_cql(),
+            self.duedate.to_cql(),
+            self.id.to_cql(),
+            self.kind.to_cql(),
+        );
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn delete_duedate_index(
+        &self,
+        db: &impl CqlExecutor,
+        duedate: i64,
+    ) -> anyhow::Result<()> {
+        if duedate <= 0 {
+            return Ok(());
+        }
+
+        let query = "DELETE FROM task_by_duedate WHERE uid=? AND duedate=? AND id=?";
+        let params = (self.uid.to_cql(), duedate.to_cql(), self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // task_by_escalation index, the rows are removed by the worker once they are due.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn save_escalation_index(&self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        if self.escalate_after_ms <= 0
+            || self.escalated_at > 0
+            || self.status != TaskStatus::Pending
+        {
+            return Ok(());
+        }
+
+        let query = "INSERT INTO task_by_escalation (uid,escalate_at,id) VALUES (?,?,?)";
+        let params = (
+            self.uid.to_cql(),
+            (self.created_at + self.escalate_after_ms).to_cql(),
+            self.id.to_cql(),
+        );
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(%uid, %id))]
+    pub async fn delete_escalation_index(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        escalate_at: i64,
+        id: xid::Id,
+    ) -> anyhow::Result<()> {
+        let query = "DELETE FROM task_by_escalation WHERE uid=? AND escalate_at=? AND id=?";
+        let params = (uid.to_cql(), escalate_at.to_cql(), id.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // task_by_priority index, only processing tasks are kept in it.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn save_priority_index(&self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        if self.status != TaskStatus::Pending {
+            return Ok(());
+        }
+
+        let query = "INSERT INTO task_by_priority (uid,priority,id) VALUES (?,?,?)";
+        let params = (self.uid.to_cql(), self.priority, self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn delete_priority_index(
+        &self,
+        db: &impl CqlExecutor,
+        priority: i8,
+    ) -> anyhow::Result<()> {
+        let query = "DELETE FROM task_by_priority WHERE uid=? AND priority=? AND id=?";
+        let params = (self.uid.to_cql(), priority, self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // save for client-supplied ids, a replay with the same gid and kind loads the existing
+    // task and returns false instead of failing with 409.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn save_idempotent(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        match self.save(db).await {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                let mut existing = Task::with_pk(self.uid, self.id);
+                if existing.get_one(db, Vec::new()).await.is_err()
+                    || existing.gid != self.gid
+                    || existing.kind != self.kind
+                {
+                    return Err(err);
+                }
+
+                // the previous request may fail before writing the index.
+                existing.save_index(db).await?;
+                existing
+                    .save_participant_index(db, &existing.participants())
+                    .await?;
+                existing.save_duedate_index(db).await?;
+                existing.save_priority_index(db).await?;
+                existing.save_escalation_index(db).await?;
+                *self = existing;
+                Ok(false)
             }
-            params.push(new_updated_at.to_cql());
-            params.push(self.uid.to_cql());
-            params.push(self.id.to_cql());
-            params.push(updated_at.to_cql());
+        }
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn update(
+        &mut self,
+        db: &impl CqlExecutor,
+        cols: ColumnsMap,
+        updated_at: i64,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        let valid_fields = vec!["duedate", "message", "threshold", "priority"];
+        let update_fields = cols.keys();
+        for field in &update_fields {
+            if !valid_fields.contains(&field.as_str()) {
+                return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
+            }
+        }
+
+        let mut select_fields = vec!["status".to_string(), "updated_at".to_string()];
+        let duedate: Option<i64> = if cols.has("duedate") {
+            select_fields.push("duedate".to_string());
+            Some(cols.get_as("duedate")?)
+        } else {
+            None
+        };
+        let priority: Option<i8> = if cols.has("priority") {
+            select_fields.push("priority".to_string());
+            Some(cols.get_as("priority")?)
+        } else {
+            None
+        };
+        let threshold: Option<i16> = if cols.has("threshold") {
+            select_fields.push("approver_threshold".to_string());
+            select_fields.push("approvers".to_string());
+            select_fields.push("assignees".to_string());
+            Some(cols.get_as("threshold")?)
+        } else {
+            None
+        };
 
-            let res = db.execute(query, params).await?;
-            if !extract_applied(res) {
+        self.get_one(db, select_fields).await?;
+        if let Some(threshold) = threshold {
+            if threshold < 1 {
                 return Err(HTTPError::new(
-                    409,
-                    "Task update failed, please try again".to_string(),
+                    400,
+                    format!("Invalid threshold, expected >= 1, got {}", threshold),
                 )
                 .into());
             }
+            Self::check_threshold(threshold, self.max_reachable_votes())?;
+        }
+
+        if self.updated_at != updated_at {
+            return Err(updated_at_conflict(self.updated_at, updated_at));
+        }
+
+        let mut set_fields: Vec<String> = Vec::with_capacity(update_fields.len() + 1);
+        let mut params: Vec<CqlValue> = Vec::with_capacity(update_fields.len() + 1 + 3);
+
+        let new_updated_at = unix_ms() as i64;
+        set_fields.push("updated_at=?".to_string());
+        params.push(new_updated_at.to_cql());
+        for field in &update_fields {
+            set_fields.push(format!("{}=?", field));
+            params.push(cols.get(field).unwrap().to_owned());
+        }
+
+        let query = format!(
+            "UPDATE task SET {} WHERE uid=? AND id=? IF updated_at=?",
+            set_fields.join(",")
+        );
+        params.push(self.uid.to_cql());
+        params.push(self.id.to_cql());
+        params.push(updated_at.to_cql());
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(self.reload_conflict(db, updated_at).await);
         }
 
+        self.updated_at = new_updated_at;
+        if let Some(duedate) = duedate {
+            if duedate != self.duedate {
+                self.delete_duedate_index(db, self.duedate).await?;
+                self.duedate = duedate;
+                self.save_duedate_index(db).await?;
+            }
+        }
+        if let Some(priority) = priority {
+            if priority != self.priority {
+                // write the new row first so the task is always listed.
+                let prev = self.priority;
+                self.priority = priority;
+                self.save_priority_index(db).await?;
+                self.delete_priority_index(db, prev).await?;
+            }
+        }
+        if threshold.is_some() && self.status == TaskStatus::Pending {
+            self.resolve_by_threshold(db).await?;
+        }
         Ok(true)
     }
 
-    pub async fn update_resolved(
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn update_assignees(
         &mut self,
-        db: &scylladb::ScyllaDB,
-        assignee: xid::Id,
-    ) -> anyhow::Result<bool> {
-        self.get_one(db, vec!["approvers".to_string(), "assignees".to_string()])
-            .await?;
+        db: &impl CqlExecutor,
+        remove: Vec<xid::Id>,
+        add: Vec<xid::Id>,
+        updated_at: i64,
+    ) -> anyhow::Result<i64> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(
+            db,
+            vec![
+                "updated_at".to_string(),
+                "duedate".to_string(),
+                "threshold".to_string(),
+                "approver_threshold".to_string(),
+                "approvers".to_string(),
+                "assignees".to_string(),
+                "resolved".to_string(),
+                "rejected".to_string(),
+                "sequence".to_string(),
+            ],
+        )
+        .await?;
+        self.check_no_sequence()?;
+        if self.updated_at != updated_at {
+            return Err(updated_at_conflict(self.updated_at, updated_at));
+        }
 
-        if (!self.approvers.is_empty() || !self.assignees.is_empty())
-            && !self.approvers.contains(&assignee)
-            && !self.assignees.contains(&assignee)
-        {
-            return Err(HTTPError::new(403, "can not resolve task".to_string()).into());
+        // the final set is written wholesale, so the update is a single conditional statement.
+        let mut assignees = self.assignees.clone();
+        for id in &remove {
+            assignees.remove(id);
         }
+        assignees.extend(add.iter().cloned());
+        Self::check_threshold(
+            self.threshold,
+            Self::reachable_votes(self.approver_threshold, &self.approvers, &assignees),
+        )?;
+        let shrunk = remove.iter().any(|id| self.assignees.contains(id));
+        // the votes of the removed assignees are dropped unless they are approvers, the
+        // statement is conditioned on the votes read so that a concurrent vote is not lost.
+        let (resolved, rejected) = self.votes_without(&remove, &[&assignees, &self.approvers]);
 
-        let query = "UPDATE task SET rejected=rejected-{?}, resolved=resolved+{?} WHERE uid=? AND id=? IF EXISTS";
+        let new_updated_at = unix_ms() as i64;
+        let query = "UPDATE task SET assignees=?, resolved=?, rejected=?, updated_at=? WHERE uid=? AND id=? IF updated_at=? AND resolved=? AND rejected=?";
         let params = (
-            assignee.to_cql(),
-            assignee.to_cql(),
+            assignees.to_cql(),
+            resolved.to_cql(),
+            rejected.to_cql(),
+            new_updated_at.to_cql(),
             self.uid.to_cql(),
             self.id.to_cql(),
+            updated_at.to_cql(),
+            self.resolved.to_cql(),
+            self.rejected.to_cql(),
         );
         let res = db.execute(query, params).await?;
         if !extract_applied(res) {
+            return Err(self.reload_conflict(db, updated_at).await);
+        }
+        self.assignees = assignees;
+        self.resolved = resolved;
+        self.rejected = rejected;
+        self.updated_at = new_updated_at;
+
+        // removed assignees may still be approvers.
+        let removed: HashSet<xid::Id> = remove
+            .into_iter()
+            .filter(|id| !self.approvers.contains(id))
+            .collect();
+        self.delete_participant_index(db, &removed).await?;
+        self.save_participant_index(db, &add.into_iter().collect())
+            .await?;
+
+        // fewer assignees may have agreed unanimously already.
+        if self.threshold == 0 && shrunk {
+            self.resolve_by_threshold(db).await?;
+        }
+        Ok(new_updated_at)
+    }
+
+    // hands the assignment of from over to the new assignee to. The vote of from is dropped,
+    // votes do not transfer, unless from is an approver too. The sets are written wholesale
+    // in one statement conditioned on the sets read, so a concurrent vote or update of the
+    // assignees fails it. Returns the new updated_at.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn delegate(
+        &mut self,
+        db: &impl CqlExecutor,
+        from: xid::Id,
+        to: xid::Id,
+    ) -> anyhow::Result<i64> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(
+            db,
+            vec![
+                "duedate".to_string(),
+                "threshold".to_string(),
+                "approver_threshold".to_string(),
+                "approvers".to_string(),
+                "assignees".to_string(),
+                "resolved".to_string(),
+                "rejected".to_string(),
+                "sequence".to_string(),
+            ],
+        )
+        .await?;
+        self.check_no_sequence()?;
+        if self.status != TaskStatus::Pending {
             return Err(HTTPError::new(
                 409,
-                "Task update_resolved failed, please try again".to_string(),
+                format!(
+                    "Task is finalized with status {}, it can not be delegated",
+                    self.status
+                ),
             )
             .into());
         }
+        if !self.assignees.contains(&from) {
+            return Err(HTTPError::new(400, format!("{} is not an assignee", from)).into());
+        }
+        if self.assignees.contains(&to) {
+            return Err(HTTPError::new(400, format!("{} is an assignee already", to)).into());
+        }
+
+        let mut assignees = self.assignees.clone();
+        assignees.remove(&from);
+        assignees.insert(to);
+        Self::check_threshold(
+            self.threshold,
+            Self::reachable_votes(self.approver_threshold, &self.approvers, &assignees),
+        )?;
+        let (mut resolved, mut rejected) = (self.resolved.clone(), self.rejected.clone());
+        if !self.approvers.contains(&from) {
+            resolved.remove(&from);
+            rejected.remove(&from);
+        }
+
+        let new_updated_at = unix_ms() as i64;
+        let query = "UPDATE task SET assignees=?, resolved=?, rejected=?, updated_at=? WHERE uid=? AND id=? IF status=? AND assignees=? AND resolved=? AND rejected=?";
+        let params = (
+            assignees.to_cql(),
+            resolved.to_cql(),
+            rejected.to_cql(),
+            new_updated_at.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            self.status.to_cql(),
+            self.assignees.to_cql(),
+            self.resolved.to_cql(),
+            self.rejected.to_cql(),
+        );
+        let res = db.execute_lwt_with_retry(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, "Task delegate failed, please try again".to_string()).into(),
+            );
+        }
+        self.assignees = assignees;
+        self.resolved = resolved;
+        self.rejected = rejected;
+        self.updated_at = new_updated_at;
+
+        // from may still be an approver.
+        if !self.approvers.contains(&from) {
+            self.delete_participant_index(db, &HashSet::from([from]))
+                .await?;
+        }
+        self.save_participant_index(db, &HashSet::from([to]))
+            .await?;
+        Ok(new_updated_at)
+    }
 
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn update_approvers(
+        &mut self,
+        db: &impl CqlExecutor,
+        remove: Vec<xid::Id>,
+        add: Vec<xid::Id>,
+        updated_at: i64,
+    ) -> anyhow::Result<i64> {
+        let _invalidation = self.invalidation(db);
         self.get_one(
             db,
             vec![
+                "updated_at".to_string(),
+                "duedate".to_string(),
                 "threshold".to_string(),
-                "status".to_string(),
+                "approver_threshold".to_string(),
+                "approvers".to_string(),
+                "assignees".to_string(),
                 "resolved".to_string(),
                 "rejected".to_string(),
+                "sequence".to_string(),
             ],
         )
         .await?;
-
-        let can_approve = self.approvers.is_empty() || self.approvers.contains(&assignee);
-        if self.status != 1
-            && can_approve
-            && self.resolved.len() >= self.threshold as usize
-            && self.resolved.len() > self.rejected.len()
-        {
-            let query = "UPDATE task SET status=? WHERE uid=? AND id=? IF EXISTS";
-            let params = (1i8, self.uid.to_cql(), self.id.to_cql());
-            let res = db.execute(query, params).await?;
-            if !extract_applied(res) {
-                return Err(HTTPError::new(
-                    409,
-                    "Task update_resolved failed, please try again".to_string(),
-                )
-                .into());
-            }
+        self.check_no_sequence()?;
+        if self.updated_at != updated_at {
+            return Err(updated_at_conflict(self.updated_at, updated_at));
         }
-        Ok(true)
-    }
 
-    pub async fn update_rejected(
-        &mut self,
-        db: &scylladb::ScyllaDB,
-        assignee: xid::Id,
-    ) -> anyhow::Result<bool> {
-        if (!self.approvers.is_empty() || !self.assignees.is_empty())
-            && !self.approvers.contains(&assignee)
-            && !self.assignees.contains(&assignee)
-        {
-            return Err(HTTPError::new(403, "can not reject task".to_string()).into());
+        // the final set is written wholesale, see `update_assignees`.
+        let mut approvers = self.approvers.clone();
+        for id in &remove {
+            approvers.remove(id);
         }
+        approvers.extend(add.iter().cloned());
+        Self::check_approver_threshold(self.approver_threshold, approvers.len())?;
+        Self::check_threshold(
+            self.threshold,
+            Self::reachable_votes(self.approver_threshold, &approvers, &self.assignees),
+        )?;
+        let shrunk = remove.iter().any(|id| self.approvers.contains(id));
+        // the votes of the removed approvers are dropped unless they are assignees.
+        let (resolved, rejected) = self.votes_without(&remove, &[&approvers, &self.assignees]);
 
-        let query = "UPDATE task SET resolved=resolved-{?}, rejected=rejected+{?} WHERE uid=? AND id=? IF EXISTS";
+        let new_updated_at = unix_ms() as i64;
+        let query = "UPDATE task SET approvers=?, resolved=?, rejected=?, updated_at=? WHERE uid=? AND id=? IF updated_at=? AND resolved=? AND rejected=?";
         let params = (
-            assignee.to_cql(),
-            assignee.to_cql(),
+            approvers.to_cql(),
+            resolved.to_cql(),
+            rejected.to_cql(),
+            new_updated_at.to_cql(),
             self.uid.to_cql(),
             self.id.to_cql(),
+            updated_at.to_cql(),
+            self.resolved.to_cql(),
+            self.rejected.to_cql(),
         );
         let res = db.execute(query, params).await?;
         if !extract_applied(res) {
-            return Err(HTTPError::new(
-                409,
-                "Task update_rejected failed, please try again".to_string(),
-            )
-            .into());
+            return Err(self.reload_conflict(db, updated_at).await);
+        }
+        self.approvers = approvers;
+        self.resolved = resolved;
+        self.rejected = rejected;
+        self.updated_at = new_updated_at;
+
+        // removed approvers may still be assignees.
+        let removed: HashSet<xid::Id> = remove
+            .into_iter()
+            .filter(|id| !self.assignees.contains(id))
+            .collect();
+        self.delete_participant_index(db, &removed).await?;
+        self.save_participant_index(db, &add.into_iter().collect())
+            .await?;
+
+        // the remaining approvers may have resolved it already.
+        if shrunk {
+            self.resolve_by_approvers(db).await?;
         }
+        Ok(new_updated_at)
+    }
 
+    // resolve the task when all remaining approvers have already resolved it,
+    // used after approvers were removed.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn resolve_by_approvers(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
         self.get_one(
             db,
             vec![
                 "threshold".to_string(),
+                "approver_threshold".to_string(),
                 "status".to_string(),
+                "approvers".to_string(),
+                "assignees".to_string(),
                 "resolved".to_string(),
                 "rejected".to_string(),
+                "gid".to_string(),
+                "created_at".to_string(),
+                "finalized_at".to_string(),
             ],
         )
         .await?;
 
-        let can_approve = self.approvers.is_empty() || self.approvers.contains(&assignee);
-        if self.status != -1
-            && can_approve
-            && self.rejected.len() >= self.threshold as usize
-            && self.rejected.len() > self.resolved.len()
+        if self.status != TaskStatus::Pending
+            || !self.meets_quorum(&self.resolved)
+            || self.resolved.len() <= self.rejected.len()
+            || !self.approvers.iter().all(|id| self.resolved.contains(id))
         {
-            let query = "UPDATE task SET status=? WHERE uid=? AND id=? IF EXISTS";
-            let params = (-1i8, self.uid.to_cql(), self.id.to_cql());
-            let res = db.execute(query, params).await?;
-            if !extract_applied(res) {
-                return Err(HTTPError::new(
-                    409,
-                    "Task update_rejected failed, please try again".to_string(),
-                )
-                .into());
-            }
+            return Ok(false);
         }
-        Ok(true)
+
+        self.transition_status(db, TaskStatus::Pending, TaskStatus::Resolved)
+            .await
     }
 
-    pub async fn delete(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
-        let res = self.get_one(db, Vec::new()).await;
-        if res.is_err() {
-            return Ok(false); // already deleted
+    // resolve the task when the resolved votes meet the threshold,
+    // used after threshold was changed.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn resolve_by_threshold(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(
+            db,
+            vec![
+                "threshold".to_string(),
+                "approver_threshold".to_string(),
+                "status".to_string(),
+                "approvers".to_string(),
+                "assignees".to_string(),
+                "resolved".to_string(),
+                "rejected".to_string(),
+                "gid".to_string(),
+                "created_at".to_string(),
+                "finalized_at".to_string(),
+            ],
+        )
+        .await?;
+
+        if self.status != TaskStatus::Pending
+            || !self.meets_quorum(&self.resolved)
+            || self.resolved.len() <= self.rejected.len()
+        {
+            return Ok(false);
         }
 
-        let query = "DELETE FROM task WHERE uid=? AND id=?";
-        let params = (self.uid.to_cql(), self.id.to_cql());
-        let _ = db.execute(query, params).await?;
-        Ok(true)
+        self.transition_status(db, TaskStatus::Pending, TaskStatus::Resolved)
+            .await
     }
 
-    pub async fn batch_delete_by_uid(
-        db: &scylladb::ScyllaDB,
-        uid: xid::Id,
-        status: Option<i8>,
-    ) -> anyhow::Result<()> {
-        match status {
-            Some(status) => {
-                let query = "DELETE FROM task WHERE uid=? AND status=?";
-                let params = (uid.to_cql(), status);
-                let _ = db.execute(query, params).await?;
-            }
-            None => {
-                let query = "DELETE FROM task WHERE uid=?";
+    // cancel a processing task, returns false if the task was already cancelled.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn cancel(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(db, vec!["status".to_string()]).await?;
+        if self.status == TaskStatus::Cancelled {
+            return Ok(false);
+        }
+        if self.status != TaskStatus::Pending {
+            return Err(HTTPError::new(
+                409,
+                format!("Task can not be cancelled, status {}", self.status),
+            )
+            .into());
+        }
+
+        if !self
+            .transition_status(db, TaskStatus::Pending, TaskStatus::Cancelled)
+            .await?
+        {
+            return Err(
+                HTTPError::new(409, "Task cancel failed, please try again".to_string()).into(),
+            );
+        }
+        Ok(true)
+    }
+
+    // expire a processing task whose duedate has passed, returns false if the task
+    // is already finalized, the stale task_by_duedate row is removed in that case.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn expire(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        if self
+            .transition_status(db, TaskStatus::Pending, TaskStatus::Expired)
+            .await?
+        {
+            return Ok(true);
+        }
+
+        self.delete_duedate_index(db, self.duedate).await?;
+        Ok(false)
+    }
+
+    // moves the task to the trash (-4), conditioned on updated_at when it is given. Its
+    // notifications are marked but kept so that it can be restored, returns false if the
+    // task is already trashed.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn trash(
+        &mut self,
+        db: &impl CqlExecutor,
+        updated_at: Option<i64>,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(db, vec!["status".to_string(), "updated_at".to_string()])
+            .await?;
+        if self.status == TaskStatus::Trashed {
+            return Ok(false);
+        }
+        if let Some(updated_at) = updated_at {
+            if self.updated_at != updated_at {
+                return Err(updated_at_conflict(self.updated_at, updated_at));
+            }
+        }
+
+        let deleted_at = unix_ms() as i64;
+        let mut query =
+            "UPDATE task SET status=?,prev_status=?,deleted_at=? WHERE uid=? AND id=? IF status=?"
+                .to_string();
+        let mut params: Vec<CqlValue> = vec![
+            TaskStatus::Trashed.to_cql(),
+            self.status.to_cql(),
+            deleted_at.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            self.status.to_cql(),
+        ];
+        if let Some(updated_at) = updated_at {
+            query.push_str(" AND updated_at=?");
+            params.push(updated_at.to_cql());
+        }
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            if let Some(updated_at) = updated_at {
+                return Err(self.reload_conflict(db, updated_at).await);
+            }
+            return Err(
+                HTTPError::new(409, "Task delete failed, please try again".to_string()).into(),
+            );
+        }
+
+        counters::transition(db, self.uid, self.status, TaskStatus::Trashed).await?;
+        self.prev_status = self.status;
+        self.status = TaskStatus::Trashed;
+        self.deleted_at = deleted_at;
+        self.update_index_status(db).await?;
+        Notification::batch_mark_deleted_by_tid(db, self.id, deleted_at).await?;
+        Ok(true)
+    }
+
+    // restores a trashed task to its status before trashing, the task can not be restored
+    // once it has been in the trash longer than retention_ms.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn restore(
+        &mut self,
+        db: &impl CqlExecutor,
+        retention_ms: i64,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(
+            db,
+            vec![
+                "status".to_string(),
+                "prev_status".to_string(),
+                "deleted_at".to_string(),
+            ],
+        )
+        .await?;
+        if self.status != TaskStatus::Trashed {
+            return Ok(false);
+        }
+        if self.deleted_at + retention_ms < unix_ms() as i64 {
+            return Err(HTTPError::new(
+                409,
+                format!(
+                    "Task can not be restored, it was deleted at {}",
+                    self.deleted_at
+                ),
+            )
+            .into());
+        }
+
+        let query =
+            "UPDATE task SET status=?,prev_status=0,deleted_at=0 WHERE uid=? AND id=? IF status=?";
+        let params = (
+            self.prev_status.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            TaskStatus::Trashed.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, "Task restore failed, please try again".to_string()).into(),
+            );
+        }
+
+        counters::transition(db, self.uid, TaskStatus::Trashed, self.prev_status).await?;
+        self.status = self.prev_status;
+        self.prev_status = TaskStatus::Pending;
+        self.deleted_at = 0;
+        self.update_index_status(db).await?;
+        Notification::batch_mark_deleted_by_tid(db, self.id, 0).await?;
+        Ok(true)
+    }
+
+    // moves a resolved or rejected task to the task_archive table, returns false if the task
+    // is not finalized or was changed meanwhile. the index rows are kept as they are.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn archive(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(db, Vec::new()).await?;
+        if !self.status.is_final() {
+            return Ok(false);
+        }
+
+        let fields = Self::fields();
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.sealed_cols(db)?;
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        // plain INSERT, it is safe to rewrite an archived row.
+        let query = format!(
+            "INSERT INTO task_archive ({}) VALUES ({})",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+        let _ = db.execute(query, params).await?;
+
+        let query = "DELETE FROM task WHERE uid=? AND id=? IF status=? AND updated_at=?";
+        let params = (
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            self.status.to_cql(),
+            self.updated_at.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            let query = "DELETE FROM task_archive WHERE uid=? AND id=?";
+            let params = (self.uid.to_cql(), self.id.to_cql());
+            let _ = db.execute(query, params).await?;
+            return Ok(false);
+        }
+
+        counters::decr(db, self.uid, self.status, 1).await?;
+        Ok(true)
+    }
+
+    // reopen a resolved or rejected task and clear its votes, its indexes are restored as
+    // for a processing task. Returns false if the task is still processing.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn reopen(&mut self, db: &impl CqlExecutor, updated_at: i64) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(
+            db,
+            vec![
+                "gid".to_string(),
+                "status".to_string(),
+                "updated_at".to_string(),
+            ],
+        )
+        .await?;
+        if self.status == TaskStatus::Pending {
+            return Ok(false);
+        }
+        if !self.status.is_final() {
+            return Err(HTTPError::new(
+                409,
+                format!("Task can not be reopened, status {}", self.status),
+            )
+            .into());
+        }
+        if self.updated_at != updated_at {
+            return Err(updated_at_conflict(self.updated_at, updated_at));
+        }
+
+        let new_updated_at = unix_ms() as i64;
+        let query = "UPDATE task SET status=?,resolved={},rejected={},final_reason='',updated_at=? WHERE uid=? AND id=? IF updated_at=?";
+        let params = (
+            TaskStatus::Pending.to_cql(),
+            new_updated_at.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            updated_at.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(self.reload_conflict(db, updated_at).await);
+        }
+
+        counters::transition(db, self.uid, self.status, TaskStatus::Pending).await?;
+        self.status = TaskStatus::Pending;
+        self.resolved.clear();
+        self.rejected.clear();
+        self.final_reason.clear();
+        self.updated_at = new_updated_at;
+        self.update_index_status(db).await?;
+        Ok(true)
+    }
+
+    // change status from `from` to `to`, returns false if the task is not in `from` status.
+    // A task reverted to pending has no final reason.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn transition_status(
+        &mut self,
+        db: &impl CqlExecutor,
+        from: TaskStatus,
+        to: TaskStatus,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        let query = if to == TaskStatus::Pending {
+            "UPDATE task SET status=?,final_reason='' WHERE uid=? AND id=? IF status=?"
+        } else {
+            "UPDATE task SET status=? WHERE uid=? AND id=? IF status=?"
+        };
+        let params = (
+            to.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            from.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Ok(false);
+        }
+        counters::transition(db, self.uid, from, to).await?;
+        self.status = to;
+        if to == TaskStatus::Pending {
+            self.final_reason.clear();
+        }
+        self.update_index_status(db).await?;
+        self.record_stats(db, from, to).await?;
+        Ok(true)
+    }
+
+    // counts the task in the stats of its group when it is first finalized from processing.
+    // finalized_at marks it, so a task reverted to processing by a withdrawn vote or reopened
+    // is not counted again when it is finalized once more. The stats keep the first outcome
+    // rather than being decremented on the revert, the day and the resolution time of the
+    // counted outcome are not kept. gid, created_at and finalized_at are read unless they
+    // were selected.
+    async fn record_stats(
+        &mut self,
+        db: &impl CqlExecutor,
+        from: TaskStatus,
+        to: TaskStatus,
+    ) -> anyhow::Result<()> {
+        if from != TaskStatus::Pending || !stats::is_outcome(to) {
+            return Ok(());
+        }
+
+        let selected = ["gid", "created_at", "finalized_at"]
+            .iter()
+            .all(|f| self._fields.iter().any(|s| s == f));
+        let (gid, created_at, finalized_at) = if selected {
+            (self.gid, self.created_at, self.finalized_at)
+        } else {
+            let mut doc = Task::with_pk(self.uid, self.id);
+            doc.get_one(
+                db,
+                vec![
+                    "gid".to_string(),
+                    "created_at".to_string(),
+                    "finalized_at".to_string(),
+                ],
+            )
+            .await?;
+            (doc.gid, doc.created_at, doc.finalized_at)
+        };
+        if finalized_at > 0 {
+            return Ok(());
+        }
+
+        let now = unix_ms() as i64;
+        let query = "UPDATE task SET finalized_at=? WHERE uid=? AND id=?";
+        let params = (now.to_cql(), self.uid.to_cql(), self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        self.finalized_at = now;
+        stats::record(db, gid, to, created_at, now).await
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn update_resolved(
+        &mut self,
+        db: &impl CqlExecutor,
+        assignee: xid::Id,
+        reason: &str,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(
+            db,
+            vec![
+                "approvers".to_string(),
+                "assignees".to_string(),
+                "resolved".to_string(),
+                "sequence".to_string(),
+                "self_ack_allowed".to_string(),
+                "watchers".to_string(),
+            ],
+        )
+        .await?;
+
+        if !self.can_vote(&assignee) {
+            return Err(HTTPError::new(403, "can not resolve task".to_string()).into());
+        }
+        self.check_self_ack(&assignee)?;
+        self.check_turn(&assignee)?;
+
+        let query = "UPDATE task SET rejected=rejected-{?}, resolved=resolved+{?} WHERE uid=? AND id=? IF EXISTS";
+        let params = (
+            assignee.to_cql(),
+            assignee.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+        );
+        let (res, retried) = db.execute_lwt_retried(query, params).await?;
+        if !extract_applied(res) && !(retried && self.has_vote(db, assignee, true).await?) {
+            return Err(HTTPError::new(
+                409,
+                "Task update_resolved failed, please try again".to_string(),
+            )
+            .into());
+        }
+
+        let can_approve = self.approvers.is_empty() || self.approvers.contains(&assignee);
+        self.finalize(db, TaskStatus::Resolved, can_approve, None, reason)
+            .await?;
+        Ok(true)
+    }
+
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn update_rejected(
+        &mut self,
+        db: &impl CqlExecutor,
+        assignee: xid::Id,
+        reason: &str,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(
+            db,
+            vec![
+                "approvers".to_string(),
+                "assignees".to_string(),
+                "resolved".to_string(),
+                "sequence".to_string(),
+                "self_ack_allowed".to_string(),
+                "watchers".to_string(),
+            ],
+        )
+        .await?;
+
+        if !self.can_vote(&assignee) {
+            return Err(HTTPError::new(403, "can not reject task".to_string()).into());
+        }
+        self.check_self_ack(&assignee)?;
+        self.check_turn(&assignee)?;
+
+        let query = "UPDATE task SET resolved=resolved-{?}, rejected=rejected+{?} WHERE uid=? AND id=? IF EXISTS";
+        let params = (
+            assignee.to_cql(),
+            assignee.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+        );
+        let (res, retried) = db.execute_lwt_retried(query, params).await?;
+        if !extract_applied(res) && !(retried && self.has_vote(db, assignee, false).await?) {
+            return Err(HTTPError::new(
+                409,
+                "Task update_rejected failed, please try again".to_string(),
+            )
+            .into());
+        }
+
+        let can_approve = self.approvers.is_empty() || self.approvers.contains(&assignee);
+        self.finalize(
+            db,
+            TaskStatus::Rejected,
+            can_approve,
+            Some(assignee),
+            reason,
+        )
+        .await?;
+        Ok(true)
+    }
+
+    // reloads the votes and flips a processing task to `to` (resolved or rejected) when
+    // they meet the threshold. The flip is conditioned on the votes it was decided on, so
+    // concurrent votes make it re-read and retry, and a task is finalized at most once,
+    // unless it allows late acks, then a finalized or expired task flips over when the votes do.
+    // A reject from a veto approver or a sequence member rejects the task without counting the votes.
+    // The flip records the reason of the tipping ack as the final reason.
+    async fn finalize(
+        &mut self,
+        db: &impl CqlExecutor,
+        to: TaskStatus,
+        can_approve: bool,
+        vetoer: Option<xid::Id>,
+        reason: &str,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        for _ in 0..FINALIZE_RETRIES {
+            self.get_one(
+                db,
+                vec![
+                    "threshold".to_string(),
+                    "approver_threshold".to_string(),
+                    "status".to_string(),
+                    "approvers".to_string(),
+                    "assignees".to_string(),
+                    "resolved".to_string(),
+                    "rejected".to_string(),
+                    "allow_late_ack".to_string(),
+                    "veto".to_string(),
+                    "sequence".to_string(),
+                    "gid".to_string(),
+                    "created_at".to_string(),
+                    "finalized_at".to_string(),
+                ],
+            )
+            .await?;
+
+            let from = self.status;
+            let vetoed = to == TaskStatus::Rejected
+                && vetoer
+                    .map(|id| self.vetoed_by(&id) && self.rejected.contains(&id))
+                    .unwrap_or(false);
+            let late = self.allow_late_ack && (from.is_final() || from == TaskStatus::Expired);
+            let (votes, others) = if to == TaskStatus::Resolved {
+                (&self.resolved, &self.rejected)
+            } else {
+                (&self.rejected, &self.resolved)
+            };
+            if from == to || (from != TaskStatus::Pending && !late) {
+                return Ok(false);
+            }
+            // the approver threshold replaces the approver gate.
+            if !vetoed
+                && ((!can_approve && self.approver_threshold <= 0)
+                    || !self.meets_quorum(votes)
+                    || votes.len() <= others.len())
+            {
+                return Ok(false);
+            }
+
+            let query = "UPDATE task SET status=?,final_reason=? WHERE uid=? AND id=? IF status=? AND resolved=? AND rejected=?";
+            let params = (
+                to.to_cql(),
+                reason.to_cql(),
+                self.uid.to_cql(),
+                self.id.to_cql(),
+                from.to_cql(),
+                self.resolved.to_cql(),
+                self.rejected.to_cql(),
+            );
+            let res = db.execute_lwt_with_retry(query, params).await?;
+            if extract_applied(res) {
+                counters::transition(db, self.uid, from, to).await?;
+                self.status = to;
+                self.final_reason = reason.to_string();
+                self.update_index_status(db).await?;
+                self.record_stats(db, from, to).await?;
+                return Ok(true);
+            }
+        }
+
+        Err(HTTPError::new(
+            409,
+            "Task status update failed, please try again".to_string(),
+        )
+        .into())
+    }
+
+    // withdraw the vote of the assignee, returns false if the assignee never voted.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn remove_vote(
+        &mut self,
+        db: &impl CqlExecutor,
+        assignee: xid::Id,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(db, vec!["resolved".to_string(), "rejected".to_string()])
+            .await?;
+        if !self.resolved.contains(&assignee) && !self.rejected.contains(&assignee) {
+            return Ok(false);
+        }
+
+        let query = "UPDATE task SET resolved=resolved-{?}, rejected=rejected-{?} WHERE uid=? AND id=? IF EXISTS";
+        let params = (
+            assignee.to_cql(),
+            assignee.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                "Task remove_vote failed, please try again".to_string(),
+            )
+            .into());
+        }
+
+        self.get_one(
+            db,
+            vec![
+                "threshold".to_string(),
+                "approver_threshold".to_string(),
+                "status".to_string(),
+                "approvers".to_string(),
+                "assignees".to_string(),
+                "resolved".to_string(),
+                "rejected".to_string(),
+                "veto".to_string(),
+                "sequence".to_string(),
+            ],
+        )
+        .await?;
+
+        let still_met = match self.status {
+            TaskStatus::Resolved => {
+                self.meets_quorum(&self.resolved) && self.resolved.len() > self.rejected.len()
+            }
+            TaskStatus::Rejected => {
+                self.rejected.iter().any(|id| self.vetoed_by(id))
+                    || (self.meets_quorum(&self.rejected)
+                        && self.rejected.len() > self.resolved.len())
+            }
+            _ => true,
+        };
+        if !still_met {
+            self.transition_status(db, self.status, TaskStatus::Pending)
+                .await?;
+        }
+        Ok(true)
+    }
+
+    // move the task to the new_uid partition with its notifications, comments and log, the
+    // index rows of the old partition are removed. It is safe to re-run after a partial failure,
+    // the copy is kept and the rest is moved again.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn transfer(
+        &mut self,
+        db: &impl CqlExecutor,
+        new_uid: xid::Id,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(db, Vec::new()).await?;
+
+        let mut doc = self.clone();
+        doc.uid = new_uid;
+        // returns false when the copy already exists from a previous run.
+        doc.save_idempotent(db).await?;
+
+        let mut notif = GroupNotification::with_pk(self.gid, self.id, self.uid);
+        if notif.get_one(db).await.is_ok() {
+            let mut new_notif = GroupNotification::with_pk(self.gid, self.id, new_uid);
+            new_notif.role = notif.role;
+            let _ = new_notif.save(db).await;
+            notif.delete(db).await?;
+        }
+        Notification::batch_transfer_sender(db, self.id, self.uid, new_uid).await?;
+        TaskComment::batch_transfer(db, self.id, self.uid, new_uid).await?;
+        TaskLog::batch_transfer(db, self.id, self.uid, new_uid).await?;
+        self.delete_participant_index(db, &self.participants())
+            .await?;
+        self.delete_duedate_index(db, self.duedate).await?;
+        self.delete_priority_index(db, self.priority).await?;
+        if self.escalate_after_ms > 0 {
+            Self::delete_escalation_index(
+                db,
+                self.uid,
+                self.created_at + self.escalate_after_ms,
+                self.id,
+            )
+            .await?;
+        }
+
+        let query = "DELETE FROM task WHERE uid=? AND id=?";
+        let params = (self.uid.to_cql(), self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        counters::decr(db, self.uid, self.status, 1).await?;
+
+        *self = doc;
+        Ok(true)
+    }
+
+    // adds the watcher, returns false if it is watching already. The cap is checked on the
+    // watchers read before the write, concurrent watches may exceed it by a few.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn watch(&mut self, db: &impl CqlExecutor, watcher: xid::Id) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(db, vec!["watchers".to_string()]).await?;
+        if self.watchers.contains(&watcher) {
+            return Ok(false);
+        }
+        if self.watchers.len() >= Self::MAX_WATCHERS {
+            return Err(HTTPError::new(
+                400,
+                format!("too many watchers, expected at most {}", Self::MAX_WATCHERS),
+            )
+            .into());
+        }
+
+        let query = "UPDATE task SET watchers=watchers+{?} WHERE uid=? AND id=? IF EXISTS";
+        let params = (watcher.to_cql(), self.uid.to_cql(), self.id.to_cql());
+        let res = db.execute_lwt_with_retry(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, "Task watch failed, please try again".to_string()).into(),
+            );
+        }
+        self.watchers.insert(watcher);
+        Ok(true)
+    }
+
+    // removes the watcher, returns false if it is not watching.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn unwatch(
+        &mut self,
+        db: &impl CqlExecutor,
+        watcher: xid::Id,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        self.get_one(db, vec!["watchers".to_string()]).await?;
+        if !self.watchers.contains(&watcher) {
+            return Ok(false);
+        }
+
+        let query = "UPDATE task SET watchers=watchers-{?} WHERE uid=? AND id=? IF EXISTS";
+        let params = (watcher.to_cql(), self.uid.to_cql(), self.id.to_cql());
+        let res = db.execute_lwt_with_retry(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, "Task unwatch failed, please try again".to_string()).into(),
+            );
+        }
+        self.watchers.remove(&watcher);
+        Ok(true)
+    }
+
+    // the notifications of the status change to the watchers. The creator and the
+    // participants are skipped, they have their own notification of the task.
+    // It needs status, approvers, assignees and watchers loaded.
+    pub fn watcher_notifications(&self, ttl: i32) -> Vec<Notification> {
+        let (status, message) = match self.status {
+            TaskStatus::Resolved => (NotificationStatus::Resolved, "task resolved"),
+            TaskStatus::Rejected => (NotificationStatus::Rejected, "task rejected"),
+            TaskStatus::Expired => (NotificationStatus::Pending, "task expired"),
+            _ => (NotificationStatus::Pending, "task status changed"),
+        };
+        let mut watchers: Vec<xid::Id> = self
+            .watchers
+            .iter()
+            .filter(|id| {
+                **id != self.uid && !self.approvers.contains(id) && !self.assignees.contains(id)
+            })
+            .cloned()
+            .collect();
+        watchers.sort();
+        watchers
+            .into_iter()
+            .map(|uid| {
+                let mut notif = Notification::with_pk(uid, self.id, self.uid);
+                notif.gid = self.gid;
+                notif.status = status;
+                notif.message = message.to_string();
+                notif._ttl = ttl;
+                notif
+            })
+            .collect()
+    }
+
+    // rewrites the payload sealed with the primary key, returns false if it is empty or
+    // already sealed with the primary key. It is conditioned on the read payload, so a
+    // concurrent write is never overwritten. The blob of an external payload is kept,
+    // only its reference is rewritten.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn reencrypt(&self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        let keyring = db
+            .keyring()
+            .ok_or_else(|| anyhow::anyhow!("the encryption is not configured"))?;
+
+        let fields = vec!["payload".to_string()];
+        let query = "SELECT payload FROM task WHERE uid=? AND id=? LIMIT 1";
+        let params = (self.uid.to_cql(), self.id.to_cql());
+        let res = db.execute(query, params).await?.single_row()?;
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        let payload: Vec<u8> = cols.get_as("payload")?;
+        if payload.is_empty() || crypto::key_id(&payload) == Some(keyring.primary()) {
+            return Ok(false);
+        }
+
+        let sealed = keyring.seal(&keyring.open(&payload)?)?;
+        let query = "UPDATE task SET payload=? WHERE uid=? AND id=? IF payload=?";
+        let params = (
+            sealed.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            payload.to_cql(),
+        );
+        let res = db.execute_lwt_with_retry(query, params).await?;
+        Ok(extract_applied(res))
+    }
+
+    // removes the task permanently, conditioned on updated_at when it is given.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn delete(
+        &mut self,
+        db: &impl CqlExecutor,
+        updated_at: Option<i64>,
+    ) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        if !exists(self.get_one(db, Vec::new()).await)? {
+            return Ok(false); // already deleted
+        }
+
+        match updated_at {
+            Some(updated_at) => {
+                if self.updated_at != updated_at {
+                    return Err(updated_at_conflict(self.updated_at, updated_at));
+                }
+                let query = "DELETE FROM task WHERE uid=? AND id=? IF updated_at=?";
+                let params = (self.uid.to_cql(), self.id.to_cql(), updated_at.to_cql());
+                let res = db.execute(query, params).await?;
+                if !extract_applied(res) {
+                    return Err(self.reload_conflict(db, updated_at).await);
+                }
+            }
+            None => {
+                let query = "DELETE FROM task WHERE uid=? AND id=?";
+                let params = (self.uid.to_cql(), self.id.to_cql());
+                let _ = db.execute(query, params).await?;
+            }
+        }
+        counters::decr(db, self.uid, self.status, 1).await?;
+        self.delete_index(db).await?;
+        self.delete_participant_index(db, &self.participants())
+            .await?;
+        self.delete_duedate_index(db, self.duedate).await?;
+        self.delete_priority_index(db, self.priority).await?;
+        TaskComment::batch_delete_by_tid(db, self.uid, self.id).await?;
+        Ok(true)
+    }
+
+    // rows are removed page by page when filtered by status, since status is not part of the
+    // primary key; at most `BATCH_DELETE_LIMIT` rows are removed per call.
+    // returns the number of rows removed.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn batch_delete_by_uid(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        status: Option<TaskStatus>,
+    ) -> anyhow::Result<u64> {
+        let _invalidation = Invalidation::uid(db.task_cache(), uid);
+        let status = match status {
+            Some(status) => status,
+            None => {
+                let query = "SELECT id FROM task WHERE uid=? USING TIMEOUT 3s";
+                let params = (uid.to_cql(),);
+                let removed = db.execute_iter(query, params).await?.len() as u64;
+
+                let query = "DELETE FROM task WHERE uid=?";
+                let params = (uid.to_cql(),);
+                let _ = db.execute(query, params).await?;
+                // the counters of the emptied partition are zeroed by the recount.
+                counters::recount(db, uid).await?;
+                return Ok(removed);
+            }
+        };
+
+        let mut removed: u64 = 0;
+        let mut page_token: Option<xid::Id> = None;
+        while removed < BATCH_DELETE_LIMIT {
+            let rows = if let Some(id) = page_token {
+                let query = "SELECT id FROM task WHERE uid=? AND status=? AND id<? LIMIT ? USING TIMEOUT 3s";
+                let params = (
+                    uid.to_cql(),
+                    status.to_cql(),
+                    id.to_cql(),
+                    BATCH_DELETE_PAGE_SIZE,
+                );
+                db.execute_iter(query, params).await?
+            } else {
+                let query = "SELECT id FROM task WHERE uid=? AND status=? LIMIT ? USING TIMEOUT 3s";
+                let params = (uid.to_cql(), status.to_cql(), BATCH_DELETE_PAGE_SIZE);
+                db.execute_iter(query, params).await?
+            };
+            if rows.is_empty() {
+                break;
+            }
+
+            let fields = vec!["id".to_string()];
+            let mut statements: Vec<(String, Vec<CqlValue>)> = Vec::with_capacity(rows.len());
+            for row in rows {
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                let id: xid::Id = cols.get_as("id")?;
+                statements.push((
+                    "DELETE FROM task WHERE uid=? AND id=?".to_string(),
+                    vec![uid.to_cql(), id.to_cql()],
+                ));
+                page_token = Some(id);
+            }
+            removed += statements.len() as u64;
+            // the rows are in the same partition, so the unlogged batch is applied at once.
+            let _ = db.execute_batch(statements).await?;
+            log::info!(target: "db", "{} tasks of {} with status {} removed", removed, uid, status);
+        }
+
+        counters::decr(db, uid, status, removed as i64).await?;
+        Ok(removed)
+    }
+
+    // lists tasks of the group from the task_by_gid index, only uid, id, gid, status
+    // and kind are filled.
+    #[instrument(skip_all, fields(%gid))]
+    pub async fn list_by_gid(
+        db: &impl CqlExecutor,
+        gid: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+        status: Option<TaskStatus>,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = vec![
+            "gid".to_string(),
+            "id".to_string(),
+            "uid".to_string(),
+            "status".to_string(),
+            "kind".to_string(),
+        ];
+
+        let rows = if let Some(id) = page_token {
+            if status.is_none() {
+                let query = format!(
+                    "SELECT {} FROM task_by_gid WHERE gid=? AND id<? LIMIT ? USING TIMEOUT 3s",
+                    fields.join(",")
+                );
+                let params = (gid.to_cql(), id.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            } else {
+                let query = format!(
+                    "SELECT {} FROM task_by_gid WHERE gid=? AND status=? AND id<? LIMIT ? USING TIMEOUT 3s",
+                    fields.join(","));
+                let params = (
+                    gid.to_cql(),
+                    status.unwrap().to_cql(),
+                    id.to_cql(),
+                    page_size as i32,
+                );
+                db.execute_iter(query, params).await?
+            }
+        } else if status.is_none() {
+            let query = format!(
+                "SELECT {} FROM task_by_gid WHERE gid=? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (gid.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        } else {
+            let query = format!(
+                "SELECT {} FROM task_by_gid WHERE gid=? AND status=? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (gid.to_cql(), status.unwrap().to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        };
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // lists tasks which the participant is an approver or assignee of, only uid, id,
+    // status and duedate are filled.
+    #[instrument(skip_all, fields(%participant))]
+    pub async fn list_by_participant(
+        db: &impl CqlExecutor,
+        participant: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+        status: Option<TaskStatus>,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = vec![
+            "task_uid".to_string(),
+            "task_id".to_string(),
+            "status".to_string(),
+            "duedate".to_string(),
+        ];
+
+        let mut conditions: Vec<&str> = vec!["participant=?"];
+        let mut params: Vec<CqlValue> = vec![participant.to_cql()];
+        if let Some(status) = status {
+            conditions.push("status=?");
+            params.push(status.to_cql());
+        }
+        if let Some(id) = page_token {
+            conditions.push("task_id<?");
+            params.push(id.to_cql());
+        }
+        params.push((page_size as i32).to_cql());
+
+        let query = format!(
+            "SELECT {} FROM task_by_participant WHERE {} LIMIT ? USING TIMEOUT 3s",
+            fields.join(","),
+            conditions.join(" AND ")
+        );
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            let mut doc = Task::with_pk(cols.get_as("task_uid")?, cols.get_as("task_id")?);
+            doc.status = cols.get_as("status").unwrap_or_default();
+            doc.duedate = cols.get_as("duedate").unwrap_or_default();
+            doc._fields = vec!["status".to_string(), "duedate".to_string()];
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // lists processing tasks of the user whose duedate has passed, oldest duedate first.
+    // only uid, id and duedate are filled, page_token is the (duedate, id) of the last row.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn list_overdue(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        now_ms: i64,
+        page_size: u16,
+        page_token: Option<(i64, xid::Id)>,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = vec!["uid".to_string(), "duedate".to_string(), "id".to_string()];
+
+        let rows = if let Some((duedate, id)) = page_token {
+            let query = format!(
+                "SELECT {} FROM task_by_duedate WHERE uid=? AND (duedate,id)>(?,?) AND (duedate)<(?) LIMIT ? USING TIMEOUT 3s",
+                fields.join(","));
+            let params = (
+                uid.to_cql(),
+                duedate.to_cql(),
+                id.to_cql(),
+                now_ms.to_cql(),
+                page_size as i32,
+            );
+            db.execute_iter(query, params).await?
+        } else {
+            let query = format!(
+                "SELECT {} FROM task_by_duedate WHERE uid=? AND duedate>0 AND duedate<? LIMIT ? USING TIMEOUT 3s",
+                fields.join(","));
+            let params = (uid.to_cql(), now_ms.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        };
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // lists processing tasks of the user ordered by priority then recency, only uid, id and
+    // priority are filled, page_token is the (priority, id) of the last row.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn list_by_priority(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        page_size: u16,
+        page_token: Option<(i8, xid::Id)>,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = vec!["uid".to_string(), "priority".to_string(), "id".to_string()];
+
+        let rows = if let Some((priority, id)) = page_token {
+            let query = format!(
+                "SELECT {} FROM task_by_priority WHERE uid=? AND (priority,id)<(?,?) LIMIT ? USING TIMEOUT 3s",
+                fields.join(","));
+            let params = (uid.to_cql(), priority, id.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        } else {
+            let query = format!(
+                "SELECT {} FROM task_by_priority WHERE uid=? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (uid.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        };
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // scans resolved and rejected tasks across all users which were last updated before
+    // before_ms, only uid and id are filled.
+    #[instrument(skip_all)]
+    pub async fn list_archivable(
+        db: &impl CqlExecutor,
+        before_ms: i64,
+        limit: u16,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = vec!["uid".to_string(), "id".to_string()];
+        let mut res: Vec<Task> = Vec::with_capacity(limit as usize);
+        for status in [TaskStatus::Resolved, TaskStatus::Rejected] {
+            let query = format!(
+                "SELECT {} FROM task WHERE status=? AND updated_at<? LIMIT ? ALLOW FILTERING BYPASS CACHE USING TIMEOUT 10s",
+                fields.join(","));
+            let params = (status.to_cql(), before_ms.to_cql(), limit as i32);
+            let rows = db
+                .execute_iter_with(query, params, Consistency::One)
+                .await?;
+
+            for row in rows {
+                let mut doc = Task::default();
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                doc.fill(&cols);
+                doc._fields = fields.clone();
+                res.push(doc);
+            }
+        }
+
+        res.truncate(limit as usize);
+        Ok(res)
+    }
+
+    // lists the archived tasks of the user, status is filtered inside the partition.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn list_archived(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        select_fields: Vec<String>,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+        status: Option<TaskStatus>,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = Self::select_fields(select_fields, true)?;
+        let columns = Self::columns(&fields);
+
+        let mut conditions: Vec<&str> = vec!["uid=?"];
+        let mut params: Vec<CqlValue> = vec![uid.to_cql()];
+        if let Some(status) = status {
+            conditions.push("status=?");
+            params.push(status.to_cql());
+        }
+        if let Some(id) = page_token {
+            conditions.push("id<?");
+            params.push(id.to_cql());
+        }
+        params.push((page_size as i32).to_cql());
+
+        let query = format!(
+            "SELECT {} FROM task_archive WHERE {} LIMIT ?{} USING TIMEOUT 3s",
+            columns.join(","),
+            conditions.join(" AND "),
+            if status.is_some() {
+                " ALLOW FILTERING"
+            } else {
+                ""
+            }
+        );
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(columns.len());
+            cols.fill(row, &columns)?;
+            doc.fill(&cols);
+            doc.open_payload(db)?;
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // scans trashed tasks across all users which were deleted before before_ms,
+    // only uid, id and deleted_at are filled.
+    #[instrument(skip_all)]
+    pub async fn list_trashed(
+        db: &impl CqlExecutor,
+        before_ms: i64,
+        limit: u16,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = vec![
+            "uid".to_string(),
+            "id".to_string(),
+            "deleted_at".to_string(),
+        ];
+        let query = format!(
+            "SELECT {} FROM task WHERE status=? AND deleted_at<? LIMIT ? ALLOW FILTERING BYPASS CACHE USING TIMEOUT 10s",
+            fields.join(","));
+        let params = (
+            TaskStatus::Trashed.to_cql(),
+            before_ms.to_cql(),
+            limit as i32,
+        );
+        let rows = db
+            .execute_iter_with(query, params, Consistency::One)
+            .await?;
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // scans the task_by_duedate index across all users for tasks whose duedate has passed,
+    // only uid, id and duedate are filled.
+    #[instrument(skip_all)]
+    pub async fn list_expired(
+        db: &impl CqlExecutor,
+        now_ms: i64,
+        limit: u16,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = vec!["uid".to_string(), "duedate".to_string(), "id".to_string()];
+        let query = format!(
+            "SELECT {} FROM task_by_duedate WHERE duedate>0 AND duedate<? LIMIT ? ALLOW FILTERING BYPASS CACHE USING TIMEOUT 10s",
+            fields.join(","));
+        let params = (now_ms.to_cql(), limit as i32);
+        let rows = db
+            .execute_iter_with(query, params, Consistency::One)
+            .await?;
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // scans the task_by_duedate index across all users for processing tasks whose duedate is
+    // in (from_ms, to_ms) and have not been reminded, only uid, id, duedate and kind are filled.
+    #[instrument(skip_all)]
+    pub async fn list_reminding(
+        db: &impl CqlExecutor,
+        from_ms: i64,
+        to_ms: i64,
+        limit: u16,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = vec![
+            "uid".to_string(),
+            "duedate".to_string(),
+            "id".to_string(),
+            "kind".to_string(),
+        ];
+        let query = format!(
+            "SELECT {} FROM task_by_duedate WHERE duedate>? AND duedate<? AND reminded_at=0 LIMIT ? ALLOW FILTERING BYPASS CACHE USING TIMEOUT 10s",
+            fields.join(","));
+        let params = (from_ms.to_cql(), to_ms.to_cql(), limit as i32);
+        let rows = db
+            .execute_iter_with(query, params, Consistency::One)
+            .await?;
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // records the reminder on the task_by_duedate row, returns false if the task has been
+    // reminded already, so that a task is reminded at most once for a duedate.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn claim_reminder(&self, db: &impl CqlExecutor, now_ms: i64) -> anyhow::Result<bool> {
+        let query = "UPDATE task_by_duedate SET reminded_at=? WHERE uid=? AND duedate=? AND id=? IF reminded_at=0";
+        let params = (
+            now_ms.to_cql(),
+            self.uid.to_cql(),
+            self.duedate.to_cql(),
+            self.id.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        Ok(extract_applied(res))
+    }
+
+    // lists (uid, escalate_at, id) of the tasks whose escalation is due.
+    #[instrument(skip_all)]
+    pub async fn list_escalating(
+        db: &impl CqlExecutor,
+        now_ms: i64,
+        limit: u16,
+    ) -> anyhow::Result<Vec<(xid::Id, i64, xid::Id)>> {
+        let fields = vec![
+            "uid".to_string(),
+            "escalate_at".to_string(),
+            "id".to_string(),
+        ];
+        let query = format!(
+            "SELECT {} FROM task_by_escalation WHERE escalate_at<? LIMIT ? ALLOW FILTERING BYPASS CACHE USING TIMEOUT 10s",
+            fields.join(","));
+        let params = (now_ms.to_cql(), limit as i32);
+        let rows = db
+            .execute_iter_with(query, params, Consistency::One)
+            .await?;
+
+        let mut res: Vec<(xid::Id, i64, xid::Id)> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            res.push((
+                cols.get_as("uid")?,
+                cols.get_as("escalate_at")?,
+                cols.get_as("id")?,
+            ));
+        }
+
+        Ok(res)
+    }
+
+    // marks the task as escalated, returns false if the task is not processing
+    // or has been escalated already, so that a task is escalated at most once.
+    #[instrument(skip_all, fields(uid = %self.uid, id = %self.id))]
+    pub async fn escalate(&mut self, db: &impl CqlExecutor, now_ms: i64) -> anyhow::Result<bool> {
+        let _invalidation = self.invalidation(db);
+        let query =
+            "UPDATE task SET escalated_at=? WHERE uid=? AND id=? IF status=0 AND escalated_at=0";
+        let params = (now_ms.to_cql(), self.uid.to_cql(), self.id.to_cql());
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Ok(false);
+        }
+        self.escalated_at = now_ms;
+        Ok(true)
+    }
+
+    // assignees who have not resolved or rejected the task.
+    pub fn pending_assignees(&self) -> HashSet<xid::Id> {
+        self.assignees
+            .iter()
+            .filter(|id| !self.resolved.contains(id) && !self.rejected.contains(id))
+            .cloned()
+            .collect()
+    }
+
+    // counting a very large partition may time out, it returns a 504 error in that case.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn count(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        status: Option<TaskStatus>,
+    ) -> anyhow::Result<u64> {
+        let res = match status {
+            Some(status) => {
+                let query = "SELECT COUNT(*) FROM task WHERE uid=? AND status=? USING TIMEOUT 3s";
+                let params = (uid.to_cql(), status.to_cql());
+                db.execute(query, params).await
+            }
+            None => {
+                let query = "SELECT COUNT(*) FROM task WHERE uid=? USING TIMEOUT 3s";
                 let params = (uid.to_cql(),);
-                let _ = db.execute(query, params).await?;
+                db.execute(query, params).await
+            }
+        };
+
+        let res = res.map_err(|err| {
+            if scylladb::is_timeout(&err) {
+                HTTPError::new(504, "Task count timed out".to_string()).into()
+            } else {
+                err
+            }
+        })?;
+        let row = res.single_row()?;
+        let count = row.columns[0]
+            .as_ref()
+            .and_then(|v| v.as_bigint())
+            .unwrap_or(0);
+        Ok(count as u64)
+    }
+
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn list(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        select_fields: Vec<String>,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+        status: Option<TaskStatus>,
+        kind: Option<String>,
+        asc: bool,
+    ) -> anyhow::Result<Vec<Task>> {
+        let fields = Self::select_fields(select_fields, true)?;
+        let columns = Self::columns(&fields);
+
+        let rows = if kind.is_some() || asc {
+            // kind is served by the task_uid_kind index, status is filtered inside the partition.
+            // in asc order, page_token is the largest id of the previous page.
+            let mut conditions: Vec<&str> = vec!["uid=?"];
+            let mut params: Vec<CqlValue> = vec![uid.to_cql()];
+            if let Some(kind) = &kind {
+                conditions.push("kind=?");
+                params.push(kind.to_cql());
+            }
+            if let Some(status) = status {
+                conditions.push("status=?");
+                params.push(status.to_cql());
+            }
+            if let Some(id) = page_token {
+                conditions.push(if asc { "id>?" } else { "id<?" });
+                params.push(id.to_cql());
+            }
+            params.push((page_size as i32).to_cql());
+
+            let query = format!(
+                "SELECT {} FROM task WHERE {}{} LIMIT ?{} USING TIMEOUT 3s",
+                columns.join(","),
+                conditions.join(" AND "),
+                if asc { " ORDER BY id ASC" } else { "" },
+                if status.is_some() && (kind.is_some() || asc) {
+                    " ALLOW FILTERING"
+                } else {
+                    ""
+                }
+            );
+            db.execute_iter(query, params).await?
+        } else if let Some(id) = page_token {
+            if status.is_none() {
+                let query = format!(
+                    "SELECT {} FROM task WHERE uid=? AND id<? LIMIT ? USING TIMEOUT 3s",
+                    columns.join(",")
+                );
+                let params = (uid.to_cql(), id.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            } else {
+                let query = format!(
+                    "SELECT {} FROM task WHERE uid=? AND status=? AND id<? LIMIT ? USING TIMEOUT 3s",
+                    columns.join(","));
+                let params = (
+                    uid.to_cql(),
+                    status.unwrap().to_cql(),
+                    id.to_cql(),
+                    page_size as i32,
+                );
+                db.execute_iter(query, params).await?
+            }
+        } else if status.is_none() {
+            let query = format!(
+                "SELECT {} FROM task WHERE uid=? LIMIT ? USING TIMEOUT 3s",
+                columns.join(",")
+            );
+            let params = (uid.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        } else {
+            let query = format!(
+                "SELECT {} FROM task WHERE uid=? AND status=? LIMIT ? USING TIMEOUT 3s",
+                columns.join(",")
+            );
+            let params = (uid.to_cql(), status.unwrap().to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        };
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(columns.len());
+            cols.fill(row, &columns)?;
+            doc.fill(&cols);
+            doc.open_payload(db)?;
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // lists a single page by the driver's paging state instead of the id of the last row,
+    // the paging state should be resumed with the same filters and order.
+    #[instrument(skip_all, fields(%uid))]
+    pub async fn list_paged(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        select_fields: Vec<String>,
+        page_size: u16,
+        paging_state: Option<Vec<u8>>,
+        status: Option<TaskStatus>,
+        kind: Option<String>,
+        asc: bool,
+    ) -> anyhow::Result<(Vec<Task>, Option<Vec<u8>>)> {
+        let fields = Self::select_fields(select_fields, true)?;
+        let columns = Self::columns(&fields);
+
+        let mut conditions: Vec<&str> = vec!["uid=?"];
+        let mut params: Vec<CqlValue> = vec![uid.to_cql()];
+        if let Some(kind) = &kind {
+            conditions.push("kind=?");
+            params.push(kind.to_cql());
+        }
+        if let Some(status) = status {
+            conditions.push("status=?");
+            params.push(status.to_cql());
+        }
+
+        let query = format!(
+            "SELECT {} FROM task WHERE {}{}{} USING TIMEOUT 3s",
+            columns.join(","),
+            conditions.join(" AND "),
+            if asc { " ORDER BY id ASC" } else { "" },
+            if status.is_some() && (kind.is_some() || asc) {
+                " ALLOW FILTERING"
+            } else {
+                ""
+            }
+        );
+        let (rows, paging_state) = db
+            .execute_paged(query, params, page_size as i32, paging_state)
+            .await?;
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(columns.len());
+            cols.fill(row, &columns)?;
+            doc.fill(&cols);
+            doc.open_payload(db)?;
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok((res, paging_state))
+    }
+
+    // scans a single page of the whole task table for the maintenance jobs,
+    // the paging state should be resumed with the same fields.
+    #[instrument(skip_all)]
+    pub async fn scan(
+        db: &impl CqlExecutor,
+        select_fields: Vec<String>,
+        page_size: u16,
+        paging_state: Option<Vec<u8>>,
+    ) -> anyhow::Result<(Vec<Task>, Option<Vec<u8>>)> {
+        Self::scan_table(db, "task", select_fields, page_size, paging_state).await
+    }
+
+    // scans the task_archive table across all users like `scan`.
+    #[instrument(skip_all)]
+    pub async fn scan_archived(
+        db: &impl CqlExecutor,
+        select_fields: Vec<String>,
+        page_size: u16,
+        paging_state: Option<Vec<u8>>,
+    ) -> anyhow::Result<(Vec<Task>, Option<Vec<u8>>)> {
+        Self::scan_table(db, "task_archive", select_fields, page_size, paging_state).await
+    }
+
+    async fn scan_table(
+        db: &impl CqlExecutor,
+        table: &str,
+        select_fields: Vec<String>,
+        page_size: u16,
+        paging_state: Option<Vec<u8>>,
+    ) -> anyhow::Result<(Vec<Task>, Option<Vec<u8>>)> {
+        let fields = Self::select_fields(select_fields, true)?;
+        let columns = Self::columns(&fields);
+
+        let query = format!(
+            "SELECT {} FROM {} USING TIMEOUT 10s",
+            columns.join(","),
+            table
+        );
+        let (rows, paging_state) = db
+            .execute_paged(query, (), page_size as i32, paging_state)
+            .await?;
+
+        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Task::default();
+            let mut cols = ColumnsMap::with_capacity(columns.len());
+            cols.fill(row, &columns)?;
+            doc.fill(&cols);
+            doc.open_payload(db)?;
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok((res, paging_state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::OnceCell;
+
+    use crate::db::{fake::FakeDB, schema};
+
+    use super::*;
+
+    static DB: OnceCell<scylladb::ScyllaDB> = OnceCell::const_new();
+
+    async fn get_db() -> &'static scylladb::ScyllaDB {
+        DB.get_or_init(|| async { schema::test_db("taskbase_test").await })
+            .await
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_update_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 1;
+        doc.save(db).await.unwrap();
+        let updated_at = doc.updated_at;
+
+        let mut cols = ColumnsMap::new();
+        cols.set_as("message", &"hello".to_string());
+        let mut doc2 = Task::with_pk(uid, doc.id);
+        let res = doc2.update(db, cols, updated_at - 1).await;
+        let err: HTTPError = res.unwrap_err().into();
+        assert_eq!(err.code, 409);
+        assert_eq!(
+            err.data,
+            Some(serde_json::json!({ "current_updated_at": updated_at }))
+        );
+
+        let mut cols = ColumnsMap::new();
+        cols.set_as("message", &"hello".to_string());
+        let mut doc2 = Task::with_pk(uid, doc.id);
+        assert!(doc2.update(db, cols, updated_at).await.unwrap());
+        assert!(doc2.updated_at >= updated_at);
+
+        let mut cols = ColumnsMap::new();
+        cols.set_as("status", &1i8);
+        let mut doc3 = Task::with_pk(uid, doc.id);
+        let res = doc3.update(db, cols, doc2.updated_at).await;
+        let err: HTTPError = res.unwrap_err().into();
+        assert_eq!(err.code, 400);
+
+        let mut doc4 = Task::with_pk(uid, doc.id);
+        doc4.get_one(db, vec!["message".to_string()]).await.unwrap();
+        assert_eq!(doc4.message, "hello");
+
+        doc4.delete(db, None).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_save_idempotent_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let id = xid::new();
+        let gid = xid::new();
+
+        let mut doc = Task::with_pk(uid, id);
+        doc.gid = gid;
+        doc.kind = "test".to_string();
+        doc.message = "first".to_string();
+        assert!(doc.save_idempotent(db).await.unwrap());
+
+        // replay
+        let mut doc2 = Task::with_pk(uid, id);
+        doc2.gid = gid;
+        doc2.kind = "test".to_string();
+        doc2.message = "second".to_string();
+        assert!(!doc2.save_idempotent(db).await.unwrap());
+        assert_eq!(doc2.message, "first");
+        assert_eq!(doc2.updated_at, doc.updated_at);
+
+        // mismatched replay
+        let mut doc3 = Task::with_pk(uid, id);
+        doc3.gid = gid;
+        doc3.kind = "other".to_string();
+        let err: HTTPError = doc3.save_idempotent(db).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+
+        let mut doc4 = Task::with_pk(uid, id);
+        doc4.gid = xid::new();
+        doc4.kind = "test".to_string();
+        let err: HTTPError = doc4.save_idempotent(db).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+
+        doc.delete(db, None).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_participant_index_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let a = xid::new();
+        let b = xid::new();
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 1;
+        doc.assignees = HashSet::from([a]);
+        doc.save(db).await.unwrap();
+
+        let res = Task::list_by_participant(db, a, 10, None, Some(TaskStatus::Pending))
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].id, doc.id);
+        assert_eq!(res[0].uid, uid);
+
+        let mut doc2 = Task::with_pk(uid, doc.id);
+        let updated_at = doc2
+            .update_assignees(db, vec![a], vec![b], doc.updated_at)
+            .await
+            .unwrap();
+        assert!(updated_at > doc.updated_at);
+        assert_eq!(doc2.assignees, HashSet::from([b]));
+        // the stale updated_at is rejected without touching the assignees.
+        let err: HTTPError = doc2
+            .update_assignees(db, vec![b], vec![a], doc.updated_at)
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 409);
+        assert_eq!(
+            err.data,
+            Some(serde_json::json!({ "current_updated_at": updated_at }))
+        );
+        let mut doc3 = Task::with_pk(uid, doc.id);
+        doc3.get_one(db, vec!["assignees".to_string(), "updated_at".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(doc3.assignees, HashSet::from([b]));
+        assert_eq!(doc3.updated_at, updated_at);
+        let res = Task::list_by_participant(db, a, 10, None, None)
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+        let res = Task::list_by_participant(db, b, 10, None, None)
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+
+        let mut doc3 = Task::with_pk(uid, doc.id);
+        assert!(doc3.delete(db, None).await.unwrap());
+        let res = Task::list_by_participant(db, b, 10, None, None)
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+        // a missing task is reported as already deleted.
+        let mut doc3 = Task::with_pk(uid, doc.id);
+        assert!(!doc3.delete(db, None).await.unwrap());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_list_overdue_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let now = unix_ms() as i64;
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.duedate = now + 1000;
+        doc.save(db).await.unwrap();
+
+        let mut doc2 = Task::with_pk(uid, xid::new());
+        doc2.kind = "test".to_string();
+        doc2.save(db).await.unwrap();
+
+        let res = Task::list_overdue(db, uid, now, 10, None).await.unwrap();
+        assert!(res.is_empty());
+
+        let res = Task::list_overdue(db, uid, now + 2000, 10, None)
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].id, doc.id);
+        assert_eq!(res[0].duedate, doc.duedate);
+
+        let res = Task::list_overdue(db, uid, now + 2000, 10, Some((doc.duedate, doc.id)))
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+
+        let mut doc3 = Task::with_pk(uid, doc.id);
+        assert!(doc3.cancel(db).await.unwrap());
+        let res = Task::list_overdue(db, uid, now + 2000, 10, None)
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+    }
+
+    async fn walk_pages(
+        db: &impl CqlExecutor,
+        uid: xid::Id,
+        status: Option<TaskStatus>,
+        asc: bool,
+    ) -> Vec<xid::Id> {
+        let mut ids: Vec<xid::Id> = Vec::new();
+        let mut page_token: Option<xid::Id> = None;
+        for _ in 0..3 {
+            let res = Task::list(db, uid, vec![], 2, page_token, status, None, asc)
+                .await
+                .unwrap();
+            assert_eq!(res.len(), 2);
+            page_token = res.last().map(|doc| doc.id);
+            ids.extend(res.iter().map(|doc| doc.id));
+        }
+        let res = Task::list(db, uid, vec![], 2, page_token, status, None, asc)
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+        ids
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_list_order_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut expected: Vec<xid::Id> = Vec::new();
+        for _ in 0..6 {
+            let mut doc = Task::with_pk(uid, xid::new());
+            doc.kind = "test".to_string();
+            doc.save(db).await.unwrap();
+            expected.push(doc.id);
+        }
+        expected.sort();
+
+        let ids = walk_pages(db, uid, None, true).await;
+        assert_eq!(ids, expected);
+        let ids = walk_pages(db, uid, Some(TaskStatus::Pending), true).await;
+        assert_eq!(ids, expected);
+
+        expected.reverse();
+        let ids = walk_pages(db, uid, None, false).await;
+        assert_eq!(ids, expected);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_list_status_paging_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut expected: Vec<xid::Id> = Vec::new();
+        for i in 0..9 {
+            let mut doc = Task::with_pk(uid, xid::new());
+            doc.kind = "test".to_string();
+            // interleave tasks of other status, they should never show up in the pages.
+            doc.status = if i % 3 == 1 {
+                TaskStatus::Rejected
+            } else {
+                TaskStatus::Pending
+            };
+            doc.save(db).await.unwrap();
+            if doc.status == TaskStatus::Pending {
+                expected.push(doc.id);
+            }
+        }
+        expected.sort();
+        expected.reverse();
+
+        let ids = walk_pages(db, uid, Some(TaskStatus::Pending), false).await;
+        assert_eq!(ids, expected);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_list_branches_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.save(db).await.unwrap();
+        let mut doc2 = Task::with_pk(uid, xid::new());
+        doc2.kind = "test".to_string();
+        doc2.status = TaskStatus::Rejected;
+        doc2.save(db).await.unwrap();
+
+        // token/no-token × filter/no-filter
+        let cases: [(Option<xid::Id>, Option<TaskStatus>, Vec<xid::Id>); 4] = [
+            (None, None, vec![doc2.id, doc.id]),
+            (None, Some(TaskStatus::Pending), vec![doc.id]),
+            (Some(doc2.id), None, vec![doc.id]),
+            (Some(doc2.id), Some(TaskStatus::Rejected), vec![]),
+        ];
+        for (page_token, status, expected) in cases {
+            let res = Task::list(db, uid, vec![], 10, page_token, status, None, false)
+                .await
+                .unwrap();
+            let ids: Vec<xid::Id> = res.iter().map(|doc| doc.id).collect();
+            assert_eq!(
+                ids, expected,
+                "page_token: {:?}, status: {:?}",
+                page_token, status
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_list_paged_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut expected: Vec<xid::Id> = Vec::new();
+        for _ in 0..5 {
+            let mut doc = Task::with_pk(uid, xid::new());
+            doc.kind = "test".to_string();
+            doc.save(db).await.unwrap();
+            expected.push(doc.id);
+        }
+        expected.sort();
+
+        for asc in [true, false] {
+            let mut ids: Vec<xid::Id> = Vec::new();
+            let mut paging_state: Option<Vec<u8>> = None;
+            loop {
+                let (res, next) = Task::list_paged(
+                    db,
+                    uid,
+                    vec![],
+                    2,
+                    paging_state,
+                    Some(TaskStatus::Pending),
+                    None,
+                    asc,
+                )
+                .await
+                .unwrap();
+                assert!(res.len() <= 2);
+                ids.extend(res.iter().map(|doc| doc.id));
+                if next.is_none() {
+                    break;
+                }
+                paging_state = next;
+            }
+            if !asc {
+                ids.reverse();
+            }
+            assert_eq!(ids, expected);
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_batch_delete_by_uid_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        for i in 0..5 {
+            let mut doc = Task::with_pk(uid, xid::new());
+            doc.kind = "test".to_string();
+            doc.status = if i % 2 == 0 {
+                TaskStatus::Rejected
+            } else {
+                TaskStatus::Pending
+            };
+            doc.save(db).await.unwrap();
+        }
+
+        let removed = Task::batch_delete_by_uid(db, uid, Some(TaskStatus::Rejected))
+            .await
+            .unwrap();
+        assert_eq!(removed, 3);
+        let res = Task::list(db, uid, vec![], 10, None, None, None, false)
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 2);
+        assert!(res.iter().all(|doc| doc.status == TaskStatus::Pending));
+
+        let removed = Task::batch_delete_by_uid(db, uid, Some(TaskStatus::Rejected))
+            .await
+            .unwrap();
+        assert_eq!(removed, 0);
+
+        let removed = Task::batch_delete_by_uid(db, uid, None).await.unwrap();
+        assert_eq!(removed, 2);
+        let res = Task::list(db, uid, vec![], 10, None, None, None, false)
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_concurrent_resolve_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let assignees: Vec<xid::Id> = (0..20).map(|_| xid::new()).collect();
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 10;
+        doc.assignees = assignees.iter().cloned().collect();
+        doc.save(db).await.unwrap();
+
+        let id = doc.id;
+        let res = futures::future::join_all(assignees.iter().map(|assignee| async move {
+            let mut doc = Task::with_pk(uid, id);
+            doc.update_resolved(db, *assignee, "").await
+        }))
+        .await;
+        for r in res {
+            assert!(r.unwrap());
+        }
+
+        let mut doc = Task::with_pk(uid, id);
+        doc.get_one(db, vec![]).await.unwrap();
+        assert_eq!(doc.status, TaskStatus::Resolved);
+        assert_eq!(doc.resolved.len(), 20);
+        assert!(doc.rejected.is_empty());
+    }
+
+    #[test]
+    fn select_fields_works() {
+        let fields = Task::select_fields(vec![], false).unwrap();
+        assert!(fields.contains(&"payload".to_string()));
+
+        let fields = Task::select_fields(vec![], true).unwrap();
+        assert!(!fields.contains(&"payload".to_string()));
+        assert!(!fields.contains(&"watchers".to_string()));
+        assert!(fields.contains(&"message".to_string()));
+        assert!(fields.contains(&"uid".to_string()));
+
+        let fields = Task::select_fields(vec!["payload".to_string()], true).unwrap();
+        assert!(fields.contains(&"payload".to_string()));
+        assert!(fields.contains(&"payload_external".to_string()));
+
+        // the payload metadata is selected without the payload.
+        let fields = Task::select_fields(vec!["message".to_string()], false).unwrap();
+        assert!(!fields.contains(&"payload".to_string()));
+        assert!(fields.contains(&"payload_type".to_string()));
+        assert!(fields.contains(&"payload_len".to_string()));
+        let fields = Task::select_fields(vec![], true).unwrap();
+        assert!(fields.contains(&"payload_len".to_string()));
+
+        assert!(Task::select_fields(vec!["unknown".to_string()], true).is_err());
+    }
+
+    #[test]
+    fn updated_at_conflict_works() {
+        let err: HTTPError = updated_at_conflict(2000, 1000).into();
+        assert_eq!(err.code, 409);
+        assert!(err.message.contains("expected updated_at 2000, got 1000"));
+        assert_eq!(
+            err.data,
+            Some(serde_json::json!({ "current_updated_at": 2000 }))
+        );
+    }
+
+    #[test]
+    fn check_threshold_works() {
+        assert!(Task::check_threshold(0, 0).is_ok());
+        assert!(Task::check_threshold(0, 3).is_ok());
+        assert!(Task::check_threshold(3, 3).is_ok());
+        assert!(Task::check_threshold(5, usize::MAX).is_ok());
+        let err: HTTPError = Task::check_threshold(4, 3).unwrap_err().into();
+        assert_eq!(err.code, 400);
+        assert_eq!(err.message, "Invalid threshold, expected <= 3, got 4");
+    }
+
+    #[test]
+    fn max_reachable_votes_works() {
+        let approvers: Vec<xid::Id> = (0..2).map(|_| xid::new()).collect();
+        let assignees: Vec<xid::Id> = (0..3).map(|_| xid::new()).collect();
+        let mut doc = Task::with_pk(xid::new(), xid::new());
+        // anyone can ack
+        assert_eq!(doc.max_reachable_votes(), usize::MAX);
+
+        doc.approvers = approvers.iter().cloned().collect();
+        assert_eq!(doc.max_reachable_votes(), 2);
+        doc.assignees = assignees.iter().cloned().collect();
+        assert_eq!(doc.max_reachable_votes(), 5);
+        // an approver who is also an assignee votes once
+        doc.assignees.insert(approvers[0]);
+        assert_eq!(doc.max_reachable_votes(), 5);
+
+        doc.approver_threshold = 1;
+        assert_eq!(doc.max_reachable_votes(), 4);
+        doc.assignees.clear();
+        assert_eq!(doc.max_reachable_votes(), 2);
+
+        doc.approver_threshold = 0;
+        doc.approvers.clear();
+        doc.assignees = assignees.iter().cloned().collect();
+        assert_eq!(doc.max_reachable_votes(), 3);
+        assert!(Task::check_threshold(3, doc.max_reachable_votes()).is_ok());
+        assert!(Task::check_threshold(10, doc.max_reachable_votes()).is_err());
+    }
+
+    #[test]
+    fn check_approver_threshold_works() {
+        assert!(Task::check_approver_threshold(0, 0).is_ok());
+        assert!(Task::check_approver_threshold(2, 2).is_ok());
+        let err: HTTPError = Task::check_approver_threshold(1, 0).unwrap_err().into();
+        assert_eq!(err.code, 400);
+        assert!(Task::check_approver_threshold(3, 2).is_err());
+    }
+
+    #[test]
+    fn meets_quorum_works() {
+        let approvers: Vec<xid::Id> = (0..2).map(|_| xid::new()).collect();
+        let assignees: Vec<xid::Id> = (0..4).map(|_| xid::new()).collect();
+        let mut doc = Task::with_pk(xid::new(), xid::new());
+        doc.threshold = 3;
+        doc.approvers = approvers.iter().cloned().collect();
+        doc.assignees = assignees.iter().cloned().collect();
+
+        // (approver votes, assignee votes, met without and with the approver threshold 1)
+        let cases = [
+            (0, 3, true, false),
+            (1, 2, true, false),
+            (1, 3, true, true),
+            (2, 1, true, false),
+            (2, 4, true, true),
+            (0, 2, false, false),
+        ];
+        for (approver_votes, assignee_votes, met, met_with_approvers) in cases {
+            let votes: HashSet<xid::Id> = approvers[..approver_votes]
+                .iter()
+                .chain(assignees[..assignee_votes].iter())
+                .cloned()
+                .collect();
+            doc.approver_threshold = 0;
+            assert_eq!(doc.meets_quorum(&votes), met);
+            doc.approver_threshold = 1;
+            assert_eq!(doc.meets_quorum(&votes), met_with_approvers);
+        }
+
+        // an approver who is also an assignee counts for both.
+        doc.assignees.insert(approvers[0]);
+        let votes: HashSet<xid::Id> = [approvers[0], assignees[0], assignees[1]].into();
+        assert!(doc.meets_quorum(&votes));
+
+        // without assignees all votes count against the threshold.
+        doc.assignees.clear();
+        let votes: HashSet<xid::Id> = [approvers[0], xid::new(), xid::new()].into();
+        assert!(doc.meets_quorum(&votes));
+        doc.approver_threshold = 2;
+        assert!(!doc.meets_quorum(&votes));
+    }
+
+    #[test]
+    fn required_votes_works() {
+        let mut doc = Task::with_pk(xid::new(), xid::new());
+        assert_eq!(doc.required_votes(), 1);
+        doc.assignees = HashSet::from([xid::new(), xid::new(), xid::new()]);
+        assert_eq!(doc.required_votes(), 3);
+        doc.threshold = 2;
+        assert_eq!(doc.required_votes(), 2);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_unanimous_threshold_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let (a, b, c) = (xid::new(), xid::new(), xid::new());
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.assignees = HashSet::from([a, b, c]);
+        doc.save(db).await.unwrap();
+
+        // the first ack no longer finalizes a task with threshold 0.
+        let mut task = Task::with_pk(uid, doc.id);
+        task.update_resolved(db, a, "").await.unwrap();
+        assert_eq!(task.status, TaskStatus::Pending);
+        task.update_resolved(db, b, "").await.unwrap();
+        assert_eq!(task.status, TaskStatus::Pending);
+
+        // removing the pending assignee makes the rest unanimous.
+        let mut task = Task::with_pk(uid, doc.id);
+        task.get_one(db, vec!["updated_at".to_string()])
+            .await
+            .unwrap();
+        task.update_assignees(db, vec![c], vec![], task.updated_at)
+            .await
+            .unwrap();
+        task.get_one(db, vec![]).await.unwrap();
+        assert_eq!(task.status, TaskStatus::Resolved);
+
+        // a non-zero threshold can not exceed the remaining assignees.
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 2;
+        doc.assignees = HashSet::from([a, b]);
+        doc.save(db).await.unwrap();
+        let mut task = Task::with_pk(uid, doc.id);
+        task.get_one(db, vec!["updated_at".to_string()])
+            .await
+            .unwrap();
+        let err: HTTPError = task
+            .update_assignees(db, vec![b], vec![], task.updated_at)
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 400);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_approver_threshold_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let approver = xid::new();
+        let assignees: Vec<xid::Id> = (0..3).map(|_| xid::new()).collect();
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 2;
+        doc.approver_threshold = 1;
+        doc.approvers = HashSet::from([approver]);
+        doc.assignees = assignees.iter().cloned().collect();
+        doc.save(db).await.unwrap();
+
+        // the assignees meet the threshold but the approver has not voted.
+        let mut task = Task::with_pk(uid, doc.id);
+        task.update_resolved(db, assignees[0], "").await.unwrap();
+        task.update_resolved(db, assignees[1], "").await.unwrap();
+        assert_eq!(task.status, TaskStatus::Pending);
+
+        // the approver's vote is not enough alone, but completes the quorum.
+        task.update_resolved(db, approver, "").await.unwrap();
+        assert_eq!(task.status, TaskStatus::Resolved);
+
+        // the approver list can not shrink below the approver threshold.
+        task.get_one(db, vec!["updated_at".to_string()])
+            .await
+            .unwrap();
+        let err: HTTPError = task
+            .update_approvers(db, vec![approver], vec![], task.updated_at)
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 400);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_veto_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let approver = xid::new();
+        let assignees: Vec<xid::Id> = (0..4).map(|_| xid::new()).collect();
+
+        for veto in [false, true] {
+            let mut doc = Task::with_pk(uid, xid::new());
+            doc.kind = "test".to_string();
+            doc.threshold = 4;
+            doc.approvers = HashSet::from([approver]);
+            doc.assignees = assignees.iter().cloned().collect();
+            doc.veto = veto;
+            doc.save(db).await.unwrap();
+
+            let mut task = Task::with_pk(uid, doc.id);
+            for assignee in &assignees[..3] {
+                task.update_resolved(db, *assignee, "").await.unwrap();
             }
+            assert_eq!(task.status, TaskStatus::Pending);
+
+            // a reject from an assignee is never a veto.
+            task.update_rejected(db, assignees[3], "").await.unwrap();
+            assert_eq!(task.status, TaskStatus::Pending);
+
+            task.update_rejected(db, approver, "").await.unwrap();
+            assert_eq!(
+                task.status,
+                if veto {
+                    TaskStatus::Rejected
+                } else {
+                    TaskStatus::Pending
+                }
+            );
+            assert_eq!(task.vetoed_by(&approver), veto);
+
+            // withdrawing the veto reopens the task.
+            assert!(task.remove_vote(db, approver).await.unwrap());
+            task.get_one(db, vec!["status".to_string()]).await.unwrap();
+            assert_eq!(task.status, TaskStatus::Pending);
         }
+    }
 
-        Ok(())
+    #[test]
+    fn next_in_sequence_works() {
+        let (a, b, c) = (xid::new(), xid::new(), xid::new());
+        let mut doc = Task::with_pk(xid::new(), xid::new());
+        assert_eq!(doc.next_in_sequence(), None);
+        assert!(doc.check_turn(&a).is_ok());
+
+        doc.sequence = vec![a, b, c];
+        assert_eq!(doc.next_in_sequence(), Some(a));
+        assert!(doc.check_turn(&a).is_ok());
+        let err: HTTPError = doc.check_turn(&b).unwrap_err().into();
+        assert_eq!(err.code, 409);
+
+        doc.resolved = HashSet::from([a]);
+        assert_eq!(doc.next_in_sequence(), Some(b));
+        assert!(doc.check_turn(&a).is_ok());
+        assert!(doc.check_turn(&b).is_ok());
+        assert!(doc.check_turn(&c).is_err());
+
+        doc.resolved = HashSet::from([a, b, c]);
+        assert_eq!(doc.next_in_sequence(), None);
     }
 
-    pub async fn list(
-        db: &scylladb::ScyllaDB,
-        uid: xid::Id,
-        select_fields: Vec<String>,
-        page_size: u16,
-        page_token: Option<xid::Id>,
-        status: Option<i8>,
-    ) -> anyhow::Result<Vec<Task>> {
-        let fields = Self::select_fields(select_fields, true)?;
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_sequence_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let (a, b, c) = (xid::new(), xid::new(), xid::new());
 
-        let rows = if let Some(id) = page_token {
-            if status.is_none() {
-                let query = format!(
-                    "SELECT {} FROM task WHERE uid=? AND id<? LIMIT ? USING TIMEOUT 3s",
-                    fields.clone().join(",")
-                );
-                let params = (uid.to_cql(), id.to_cql(), page_size as i32);
-                db.execute_iter(query, params).await?
+        for reject in [false, true] {
+            let mut doc = Task::with_pk(uid, xid::new());
+            doc.kind = "test".to_string();
+            doc.sequence = vec![a, b, c];
+            doc.assignees = HashSet::from([a, b, c]);
+            doc.save(db).await.unwrap();
+
+            let mut task = Task::with_pk(uid, doc.id);
+            let err: HTTPError = task.update_resolved(db, b, "").await.unwrap_err().into();
+            assert_eq!(err.code, 409);
+
+            task.update_resolved(db, a, "").await.unwrap();
+            assert_eq!(task.status, TaskStatus::Pending);
+            assert_eq!(task.next_in_sequence(), Some(b));
+            task.update_resolved(db, b, "").await.unwrap();
+            assert_eq!(task.status, TaskStatus::Pending);
+            assert_eq!(task.next_in_sequence(), Some(c));
+
+            if reject {
+                task.update_rejected(db, c, "").await.unwrap();
+                assert_eq!(task.status, TaskStatus::Rejected);
             } else {
-                let query = format!(
-                    "SELECT {} FROM task WHERE uid=? AND status=? AND id<? LIMIT ? USING TIMEOUT 3s",
-                    fields.clone().join(","));
-                let params = (uid.to_cql(), id.to_cql(), status.unwrap(), page_size as i32);
-                db.execute_iter(query, params).await?
+                task.update_resolved(db, c, "").await.unwrap();
+                assert_eq!(task.status, TaskStatus::Resolved);
             }
-        } else if status.is_none() {
-            let query = format!(
-                "SELECT {} FROM task WHERE uid=? LIMIT ? USING TIMEOUT 3s",
-                fields.clone().join(",")
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_ack_permission_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let (assignee, approver, other) = (xid::new(), xid::new(), xid::new());
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 2;
+        doc.assignees = HashSet::from([assignee]);
+        doc.approvers = HashSet::from([approver]);
+        doc.save(db).await.unwrap();
+
+        let mut task = Task::with_pk(uid, doc.id);
+        let err: HTTPError = task
+            .update_resolved(db, other, "")
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 403);
+        let mut task = Task::with_pk(uid, doc.id);
+        let err: HTTPError = task
+            .update_rejected(db, other, "")
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 403);
+
+        let mut task = Task::with_pk(uid, doc.id);
+        assert!(task.update_rejected(db, assignee, "").await.unwrap());
+        let mut task = Task::with_pk(uid, doc.id);
+        assert!(task.update_resolved(db, approver, "").await.unwrap());
+
+        let mut task = Task::with_pk(uid, doc.id);
+        task.get_one(db, vec![]).await.unwrap();
+        assert_eq!(task.resolved, HashSet::from([approver]));
+        assert_eq!(task.rejected, HashSet::from([assignee]));
+        assert_eq!(task.status, TaskStatus::Pending);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_self_ack_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let assignee = xid::new();
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 2;
+        doc.assignees = HashSet::from([uid, assignee]);
+        doc.self_ack_allowed = false;
+        doc.save(db).await.unwrap();
+
+        let mut task = Task::with_pk(uid, doc.id);
+        let err: HTTPError = task.update_resolved(db, uid, "").await.unwrap_err().into();
+        assert_eq!(err.code, 403);
+        let mut task = Task::with_pk(uid, doc.id);
+        let err: HTTPError = task.update_rejected(db, uid, "").await.unwrap_err().into();
+        assert_eq!(err.code, 403);
+        let mut task = Task::with_pk(uid, doc.id);
+        assert!(task.update_resolved(db, assignee, "").await.unwrap());
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.assignees = HashSet::from([uid]);
+        doc.self_ack_allowed = true;
+        doc.save(db).await.unwrap();
+
+        let mut task = Task::with_pk(uid, doc.id);
+        assert!(task.update_resolved(db, uid, "").await.unwrap());
+        assert_eq!(task.status, TaskStatus::Resolved);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_expired_late_ack_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let assignee = xid::new();
+
+        for allow_late_ack in [false, true] {
+            let mut doc = Task::with_pk(uid, xid::new());
+            doc.kind = "test".to_string();
+            doc.threshold = 1;
+            doc.assignees = HashSet::from([assignee]);
+            doc.duedate = unix_ms() as i64 + 1000;
+            doc.allow_late_ack = allow_late_ack;
+            doc.save(db).await.unwrap();
+            assert!(doc.expire(db).await.unwrap());
+
+            let mut doc = Task::with_pk(uid, doc.id);
+            doc.update_resolved(db, assignee, "").await.unwrap();
+            assert_eq!(
+                doc.status,
+                if allow_late_ack {
+                    TaskStatus::Resolved
+                } else {
+                    TaskStatus::Expired
+                }
             );
-            let params = (uid.to_cql(), page_size as i32);
-            db.execute_iter(query, params).await?
-        } else {
-            let query = format!(
-                "SELECT {} FROM task WHERE uid=? AND status=? LIMIT ? USING TIMEOUT 3s",
-                fields.clone().join(",")
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_late_ack_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let (a, b, c) = (xid::new(), xid::new(), xid::new());
+
+        // a resolves, then b and c reject after the task is resolved.
+        for allow_late_ack in [false, true] {
+            let mut doc = Task::with_pk(uid, xid::new());
+            doc.kind = "test".to_string();
+            doc.threshold = 1;
+            doc.assignees = HashSet::from([a, b, c]);
+            doc.allow_late_ack = allow_late_ack;
+            doc.save(db).await.unwrap();
+
+            let mut doc = Task::with_pk(uid, doc.id);
+            doc.update_resolved(db, a, "").await.unwrap();
+            assert_eq!(doc.status, TaskStatus::Resolved);
+            doc.update_rejected(db, b, "").await.unwrap();
+            // a tie never flips the task.
+            assert_eq!(doc.status, TaskStatus::Resolved);
+            doc.update_rejected(db, c, "").await.unwrap();
+            assert_eq!(
+                doc.status,
+                if allow_late_ack {
+                    TaskStatus::Rejected
+                } else {
+                    TaskStatus::Resolved
+                }
             );
-            let params = (uid.as_bytes(), status.unwrap(), page_size as i32);
-            db.execute_iter(query, params).await?
+
+            let mut doc = Task::with_pk(uid, doc.id);
+            doc.get_one(db, vec![]).await.unwrap();
+            assert_eq!(
+                doc.status,
+                if allow_late_ack {
+                    TaskStatus::Rejected
+                } else {
+                    TaskStatus::Resolved
+                }
+            );
+            assert_eq!(doc.rejected.len(), 2);
+        }
+
+        // one task is frozen as resolved, the other flipped to rejected.
+        let counters = counters::get(db, uid).await.unwrap();
+        assert_eq!(counters.get(&0).cloned().unwrap_or(0), 0);
+        assert_eq!(counters.get(&1).cloned().unwrap_or(0), 1);
+        assert_eq!(counters.get(&-1).cloned().unwrap_or(0), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_list_by_priority_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut normal = Task::with_pk(uid, xid::new());
+        normal.kind = "test".to_string();
+        normal.save(db).await.unwrap();
+
+        let mut urgent = Task::with_pk(uid, xid::new());
+        urgent.kind = "test".to_string();
+        urgent.priority = 2;
+        urgent.save(db).await.unwrap();
+
+        let res = Task::list_by_priority(db, uid, 10, None).await.unwrap();
+        assert_eq!(
+            res.iter().map(|doc| doc.id).collect::<Vec<_>>(),
+            vec![urgent.id, normal.id]
+        );
+
+        let mut cols = ColumnsMap::new();
+        cols.set_as("priority", &1i8);
+        let mut doc = Task::with_pk(uid, normal.id);
+        doc.update(db, cols, normal.updated_at).await.unwrap();
+        let mut cols = ColumnsMap::new();
+        cols.set_as("priority", &0i8);
+        let mut doc = Task::with_pk(uid, urgent.id);
+        doc.update(db, cols, urgent.updated_at).await.unwrap();
+
+        let res = Task::list_by_priority(db, uid, 10, None).await.unwrap();
+        assert_eq!(
+            res.iter()
+                .map(|doc| (doc.priority, doc.id))
+                .collect::<Vec<_>>(),
+            vec![(1, normal.id), (0, urgent.id)]
+        );
+
+        let res = Task::list_by_priority(db, uid, 10, Some((1, normal.id)))
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+
+        let mut doc = Task::with_pk(uid, urgent.id);
+        assert!(doc.cancel(db).await.unwrap());
+        let res = Task::list_by_priority(db, uid, 10, None).await.unwrap();
+        assert_eq!(res.len(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_delete_updated_at_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 1;
+        doc.save(db).await.unwrap();
+        let stale = doc.updated_at - 1;
+
+        // a stale updated_at is rejected with the current one.
+        let mut task = Task::with_pk(uid, doc.id);
+        let err: HTTPError = task.delete(db, Some(stale)).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+        assert_eq!(
+            err.data,
+            Some(serde_json::json!({ "current_updated_at": doc.updated_at }))
+        );
+        let mut task = Task::with_pk(uid, doc.id);
+        let err: HTTPError = task.trash(db, Some(stale)).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+
+        let mut task = Task::with_pk(uid, doc.id);
+        task.get_one(db, vec![]).await.unwrap();
+        assert_eq!(task.status, TaskStatus::Pending);
+
+        let mut task = Task::with_pk(uid, doc.id);
+        assert!(task.delete(db, Some(doc.updated_at)).await.unwrap());
+        let mut task = Task::with_pk(uid, doc.id);
+        assert!(!task.delete(db, Some(doc.updated_at)).await.unwrap());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_import_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let (a, b) = (xid::new(), xid::new());
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.gid = xid::new();
+        doc.kind = "test".to_string();
+        doc.status = TaskStatus::Resolved;
+        doc.created_at = 1000;
+        doc.updated_at = 2000;
+        doc.threshold = 1;
+        doc.assignees = HashSet::from([a, b]);
+        doc.resolved = HashSet::from([a]);
+        assert!(doc.import(db, false).await.unwrap());
+
+        // the timestamps and the votes are kept as is.
+        let mut task = Task::with_pk(uid, doc.id);
+        task.get_one(db, vec![]).await.unwrap();
+        assert_eq!(task.status, TaskStatus::Resolved);
+        assert_eq!(task.created_at, 1000);
+        assert_eq!(task.updated_at, 2000);
+        assert_eq!(task.resolved, HashSet::from([a]));
+
+        // an existing task is skipped unless overwrite.
+        doc.status = TaskStatus::Rejected;
+        doc.rejected = HashSet::from([b]);
+        assert!(!doc.import(db, false).await.unwrap());
+        let mut task = Task::with_pk(uid, doc.id);
+        task.get_one(db, vec![]).await.unwrap();
+        assert_eq!(task.status, TaskStatus::Resolved);
+
+        assert!(doc.import(db, true).await.unwrap());
+        let mut task = Task::with_pk(uid, doc.id);
+        task.get_one(db, vec![]).await.unwrap();
+        assert_eq!(task.status, TaskStatus::Rejected);
+        assert_eq!(task.rejected, HashSet::from([b]));
+
+        let res = Task::list_by_participant(db, b, 10, None, None)
+            .await
+            .unwrap();
+        assert!(res
+            .iter()
+            .any(|t| t.id == doc.id && t.status == TaskStatus::Rejected));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_trash_restore_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 1;
+        doc.priority = 1;
+        doc.save(db).await.unwrap();
+        assert!(doc.cancel(db).await.unwrap());
+
+        let mut task = Task::with_pk(uid, doc.id);
+        assert!(task.trash(db, None).await.unwrap());
+        assert!(!task.trash(db, None).await.unwrap());
+        task.get_one(db, Vec::new()).await.unwrap();
+        assert_eq!(task.status, TaskStatus::Trashed);
+        assert_eq!(task.prev_status, TaskStatus::Cancelled);
+        assert!(task.deleted_at > 0);
+
+        let res = Task::list_trashed(db, unix_ms() as i64 + 1, 1000)
+            .await
+            .unwrap();
+        assert!(res.iter().any(|t| t.id == doc.id));
+
+        // out of the retention window.
+        let err: HTTPError = task.restore(db, -1000).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+
+        let mut task = Task::with_pk(uid, doc.id);
+        assert!(task.restore(db, 60 * 1000).await.unwrap());
+        assert!(!task.restore(db, 60 * 1000).await.unwrap());
+        task.get_one(db, Vec::new()).await.unwrap();
+        assert_eq!(task.status, TaskStatus::Cancelled);
+        assert_eq!(task.deleted_at, 0);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn task_archive_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut doc = Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 1;
+        doc.save(db).await.unwrap();
+
+        let mut task = Task::with_pk(uid, doc.id);
+        assert!(!task.archive(db).await.unwrap());
+
+        assert!(task
+            .transition_status(db, TaskStatus::Pending, TaskStatus::Resolved)
+            .await
+            .unwrap());
+        let mut task = Task::with_pk(uid, doc.id);
+        assert!(task.archive(db).await.unwrap());
+        assert!(Task::with_pk(uid, doc.id)
+            .get_one(db, Vec::new())
+            .await
+            .is_err());
+
+        let mut task = Task::with_pk(uid, doc.id);
+        task.get_archived(db, Vec::new()).await.unwrap();
+        assert_eq!(task.status, TaskStatus::Resolved);
+        assert_eq!(task.kind, "test");
+
+        let res = Task::list_archived(db, uid, Vec::new(), 10, None, Some(TaskStatus::Resolved))
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        let res = Task::list_archived(db, uid, Vec::new(), 10, None, Some(TaskStatus::Rejected))
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+    }
+
+    // the single row lookups of get_one.
+    const GET_ONE: &str = "FROM task WHERE uid=? AND id=? LIMIT 1";
+
+    fn pending_task() -> Task {
+        let mut doc = Task::with_pk(xid::new(), xid::new());
+        doc.gid = xid::new();
+        doc.kind = "test".to_string();
+        doc.status = TaskStatus::Pending;
+        doc.created_at = 1000;
+        doc.updated_at = 1000;
+        doc.threshold = 3;
+        doc.assignees = (0..3).map(|_| xid::new()).collect();
+        doc
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn get_one_with_fake_works() {
+        let db = FakeDB::new();
+        let doc = pending_task();
+
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err = task.get_one(&db, vec![]).await.unwrap_err();
+        assert!(scylladb::is_not_found(&err));
+
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        task.get_one(&db, vec!["counts".to_string(), "message".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(task.status, TaskStatus::Pending);
+        assert_eq!(task.threshold, 3);
+        assert_eq!(task.assignees, doc.assignees);
+        assert!(task._fields.contains(&Task::COUNTS_FIELD.to_string()));
+
+        // the pseudo field selects the sets and thresholds, it is not queried.
+        let queries = db.queries(GET_ONE);
+        assert_eq!(queries.len(), 2);
+        assert_eq!(
+            queries[1],
+            "SELECT message,threshold,approver_threshold,approvers,assignees,resolved,rejected,gid,status,kind,payload_type,payload_len FROM task WHERE uid=? AND id=? LIMIT 1"
+        );
+        assert!(db.statements().iter().all(|s| s.values == 2));
+
+        db.push_error(GET_ONE, anyhow::anyhow!("unavailable"));
+        let err = task.get_one(&db, vec![]).await.unwrap_err();
+        assert!(!scylladb::is_not_found(&err));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn self_ack_allowed_default_with_fake_works() {
+        let db = FakeDB::new();
+        let mut doc = pending_task();
+        doc.assignees.insert(doc.uid);
+        assert!(doc.self_ack_allowed);
+
+        // the rows written before the column was added have it null.
+        let mut cols = ColumnsMap::new();
+        for (k, v) in doc.to().iter() {
+            if k != "self_ack_allowed" {
+                cols.set_as(k, v);
+            }
+        }
+        db.push_docs(GET_ONE, vec![cols]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        task.get_one(&db, vec!["self_ack_allowed".to_string()])
+            .await
+            .unwrap();
+        assert!(task.self_ack_allowed);
+        assert!(task.check_self_ack(&doc.uid).is_ok());
+
+        doc.self_ack_allowed = false;
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        task.get_one(&db, vec!["self_ack_allowed".to_string()])
+            .await
+            .unwrap();
+        assert!(!task.self_ack_allowed);
+        let err: HTTPError = task.check_self_ack(&doc.uid).unwrap_err().into();
+        assert_eq!(err.code, 403);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn get_cached_with_fake_works() {
+        let db = FakeDB::new();
+        let cache = TaskCache::new(std::time::Duration::from_secs(60), 100);
+        let doc = pending_task();
+
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err = task.get_cached(&db, &cache, vec![]).await.unwrap_err();
+        assert!(scylladb::is_not_found(&err));
+        assert!(cache.get(doc.uid, doc.id).is_none());
+
+        // the miss caches the full row, the hit selects the fields from it.
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        assert!(!task.get_cached(&db, &cache, vec![]).await.unwrap());
+        assert_eq!(task._fields, Task::fields());
+
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task
+            .get_cached(&db, &cache, vec!["threshold".to_string()])
+            .await
+            .unwrap());
+        assert_eq!(task.threshold, 3);
+        assert_eq!(task.assignees, doc.assignees);
+        assert!(task._fields.contains(&"threshold".to_string()));
+        assert!(!task._fields.contains(&"assignees".to_string()));
+        assert_eq!(db.queries(GET_ONE).len(), 2);
+
+        // the invalidated row is read again.
+        cache.invalidate(doc.uid, doc.id);
+        let mut updated = doc.clone();
+        updated.threshold = 2;
+        db.push_docs(GET_ONE, vec![updated.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(!task.get_cached(&db, &cache, vec![]).await.unwrap());
+        assert_eq!(task.threshold, 2);
+        assert_eq!(db.queries(GET_ONE).len(), 3);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn invalidation_with_fake_works() {
+        let db =
+            FakeDB::new().with_task_cache(TaskCache::new(std::time::Duration::from_secs(60), 100));
+        let cache = db.task_cache().unwrap();
+        let doc = pending_task();
+        let message = || {
+            let mut cols = ColumnsMap::new();
+            cols.set_as("message", &"hello".to_string());
+            cols
         };
 
-        let mut res: Vec<Task> = Vec::with_capacity(rows.len());
-        for row in rows {
-            let mut doc = Task::default();
-            let mut cols = ColumnsMap::with_capacity(fields.len());
-            cols.fill(row, &fields)?;
-            doc.fill(&cols);
-            doc._fields = fields.clone();
-            res.push(doc);
+        // the conflicting update invalidates the cached task.
+        cache.insert(doc.clone());
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_applied("IF updated_at=?", false);
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err: HTTPError = task.update(&db, message(), 1000).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+        assert!(cache.get(doc.uid, doc.id).is_none());
+
+        // so does the failed one.
+        cache.insert(doc.clone());
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_error("IF updated_at=?", anyhow::anyhow!("timeout"));
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.update(&db, message(), 1000).await.is_err());
+        assert!(cache.get(doc.uid, doc.id).is_none());
+
+        // and the one of the worker.
+        cache.insert(doc.clone());
+        let mut task = doc.clone();
+        assert!(task.escalate(&db, 2000).await.unwrap());
+        assert!(cache.get(doc.uid, doc.id).is_none());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn save_payload_len_with_fake_works() {
+        let db = FakeDB::new();
+        let mut doc = pending_task();
+        doc.payload = vec![1u8; 8];
+        doc.payload_type = "application/cbor".to_string();
+        doc.payload_len = 1;
+        assert!(doc.save(&db).await.unwrap());
+        assert_eq!(doc.payload_len, 8);
+
+        let mut doc = pending_task();
+        doc.payload_len = 8;
+        assert!(doc.import(&db, true).await.unwrap());
+        assert_eq!(doc.payload_len, 0);
+
+        // the length of an external payload is the size of the blob.
+        let mut doc = pending_task();
+        doc.payload = b"blob://taskbase/key".to_vec();
+        doc.payload_external = true;
+        doc.payload_len = 1 << 20;
+        assert!(doc.save(&db).await.unwrap());
+        assert_eq!(doc.payload_len, 1 << 20);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn count_with_fake_works() {
+        let uid = xid::new();
+        let db = FakeDB::new();
+        db.push_rows(
+            "SELECT COUNT(*)",
+            vec![scylladb::Row {
+                columns: vec![Some(CqlValue::BigInt(5))],
+            }],
+        );
+        assert_eq!(Task::count(&db, uid, None).await.unwrap(), 5);
+
+        // a timed out count of a large partition is a gateway timeout.
+        db.push_error(
+            "SELECT COUNT(*)",
+            scylla::transport::errors::QueryError::TimeoutError.into(),
+        );
+        let err: HTTPError = Task::count(&db, uid, Some(TaskStatus::Pending))
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 504);
+        assert_eq!(db.queries("AND status=?").len(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn save_retried_with_fake_works() {
+        const INSERT: &str = "INSERT INTO task (";
+        const COUNTER: &str = "UPDATE task_counter";
+        let timeout = || anyhow::Error::new(scylla::transport::errors::QueryError::TimeoutError);
+        let doc = pending_task();
+
+        // the timed out attempt was applied, so the retry is not, but the row is ours.
+        let db = FakeDB::new();
+        db.push_error(INSERT, timeout());
+        db.push_applied(INSERT, false);
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = doc.clone();
+        assert!(task.save(&db).await.unwrap());
+        assert_eq!(db.queries(INSERT).len(), 2);
+        assert_eq!(db.queries("INSERT INTO task_by_gid").len(), 1);
+        assert_eq!(db.queries("INSERT INTO task_by_participant").len(), 3);
+        assert_eq!(db.queries(COUNTER).len(), 1);
+
+        // the row of another task is a conflict.
+        let mut other = doc.clone();
+        other.created_at = 999;
+        let db = FakeDB::new();
+        db.push_error(INSERT, timeout());
+        db.push_applied(INSERT, false);
+        db.push_docs(GET_ONE, vec![other.to()]);
+        let mut task = doc.clone();
+        let err: HTTPError = task.save(&db).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+        assert!(db.queries("task_by_gid").is_empty());
+        assert!(db.queries(COUNTER).is_empty());
+
+        // without a retry, the existing row is not read.
+        let db = FakeDB::new();
+        db.push_applied(INSERT, false);
+        let mut task = doc.clone();
+        let err: HTTPError = task.save(&db).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+        assert!(db.queries(GET_ONE).is_empty());
+
+        // so does the import, an existing task is skipped.
+        let db = FakeDB::new();
+        db.push_error(INSERT, timeout());
+        db.push_applied(INSERT, false);
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = doc.clone();
+        assert!(task.import(&db, false).await.unwrap());
+        assert_eq!(db.queries(COUNTER).len(), 1);
+
+        let db = FakeDB::new();
+        db.push_applied(INSERT, false);
+        let mut task = doc.clone();
+        assert!(!task.import(&db, false).await.unwrap());
+        assert!(db.queries(COUNTER).is_empty());
+
+        // the vote of the timed out attempt is kept.
+        let assignee = *doc.assignees.iter().next().unwrap();
+        let mut voted = doc.clone();
+        voted.resolved.insert(assignee);
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_error("resolved=resolved+{?}", timeout());
+        db.push_applied("resolved=resolved+{?}", false);
+        db.push_docs(GET_ONE, vec![voted.to()]); // the vote
+        db.push_docs(GET_ONE, vec![voted.to()]); // the votes to finalize on
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.update_resolved(&db, assignee, "").await.unwrap());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn payload_encryption_with_fake_works() {
+        let db = FakeDB::new().with_keyring(crypto::test_keyring("k1", &["k1"]));
+        let mut doc = pending_task();
+        doc.message = "hello".to_string();
+        doc.payload = b"secret".to_vec();
+        doc.payload_type = "text/plain".to_string();
+        assert!(doc.save(&db).await.unwrap());
+        assert_eq!(doc.payload, b"secret".to_vec());
+        assert_eq!(doc.payload_len, 6);
+
+        // the row holds the sealed payload and the plaintext message.
+        let cols = doc.sealed_cols(&db).unwrap();
+        let sealed: Vec<u8> = cols.get_as("payload").unwrap();
+        assert_eq!(crypto::key_id(&sealed), Some("k1"));
+        assert_eq!(cols.get_as::<String>("message").unwrap(), "hello");
+
+        let mut got = Task::with_pk(doc.uid, doc.id);
+        db.push_docs(GET_ONE, vec![doc.sealed_cols(&db).unwrap()]);
+        got.get_one(&db, vec!["payload".to_string()]).await.unwrap();
+        assert_eq!(got.payload, b"secret".to_vec());
+
+        // the payloads written before the encryption was enabled are read as is.
+        let mut got = Task::with_pk(doc.uid, doc.id);
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        got.get_one(&db, vec!["payload".to_string()]).await.unwrap();
+        assert_eq!(got.payload, b"secret".to_vec());
+
+        // a rotated ring reads the old key and re-encrypts with the new one.
+        let rotated = FakeDB::new().with_keyring(crypto::test_keyring("k2", &["k1", "k2"]));
+        let mut got = Task::with_pk(doc.uid, doc.id);
+        rotated.push_docs(GET_ONE, vec![doc.sealed_cols(&db).unwrap()]);
+        got.get_one(&rotated, vec!["payload".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(got.payload, b"secret".to_vec());
+
+        rotated.push_docs(GET_ONE, vec![cols]);
+        assert!(doc.reencrypt(&rotated).await.unwrap());
+        assert_eq!(
+            rotated.queries("UPDATE task SET payload=?"),
+            vec!["UPDATE task SET payload=? WHERE uid=? AND id=? IF payload=?".to_string()]
+        );
+        rotated.push_docs(GET_ONE, vec![doc.sealed_cols(&rotated).unwrap()]);
+        assert!(!doc.reencrypt(&rotated).await.unwrap());
+        assert_eq!(rotated.queries("UPDATE task SET payload=?").len(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn payload_wrong_key_with_fake_works() {
+        let db = FakeDB::new().with_keyring(crypto::test_keyring("k1", &["k1"]));
+        let mut doc = pending_task();
+        doc.payload = b"secret".to_vec();
+        let cols = doc.sealed_cols(&db).unwrap();
+
+        // the key was dropped from the ring.
+        let other = FakeDB::new().with_keyring(crypto::test_keyring("k2", &["k2"]));
+        let mut got = Task::with_pk(doc.uid, doc.id);
+        other.push_docs(GET_ONE, vec![doc.sealed_cols(&db).unwrap()]);
+        let err: HTTPError = got
+            .get_one(&other, vec!["payload".to_string()])
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 500);
+        assert!(err.message.contains("unknown encryption key"));
+
+        // the encryption is not configured.
+        let plain = FakeDB::new();
+        let mut got = Task::with_pk(doc.uid, doc.id);
+        plain.push_docs(GET_ONE, vec![cols]);
+        let err: HTTPError = got
+            .get_one(&plain, vec!["payload".to_string()])
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 500);
+        assert!(doc.reencrypt(&plain).await.is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn update_conflict_with_fake_works() {
+        let doc = pending_task();
+        let message = || {
+            let mut cols = ColumnsMap::new();
+            cols.set_as("message", &"hello".to_string());
+            cols
+        };
+
+        // a stale updated_at is rejected before the update.
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err: HTTPError = task.update(&db, message(), 999).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+        assert_eq!(
+            err.data,
+            Some(serde_json::json!({ "current_updated_at": 1000 }))
+        );
+        assert!(db.queries("UPDATE task SET").is_empty());
+
+        // the task is updated between the read and the update,
+        // the conflict carries the reloaded updated_at.
+        let mut updated = doc.clone();
+        updated.updated_at = 2000;
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_applied("IF updated_at=?", false);
+        db.push_docs(GET_ONE, vec![updated.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err: HTTPError = task.update(&db, message(), 1000).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+        assert_eq!(
+            err.data,
+            Some(serde_json::json!({ "current_updated_at": 2000 }))
+        );
+        assert_eq!(db.queries(GET_ONE).len(), 2);
+
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.update(&db, message(), 1000).await.unwrap());
+        assert!(task.updated_at > 1000);
+        assert_eq!(
+            db.queries("IF updated_at=?"),
+            vec!["UPDATE task SET updated_at=?,message=? WHERE uid=? AND id=? IF updated_at=?"]
+        );
+
+        let mut cols = ColumnsMap::new();
+        cols.set_as("kind", &"other".to_string());
+        let err: HTTPError = task.update(&db, cols, 1000).await.unwrap_err().into();
+        assert_eq!(err.code, 400);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn finalize_with_fake_works() {
+        let mut doc = pending_task();
+        let voters: Vec<xid::Id> = doc.assignees.iter().cloned().collect();
+        doc.threshold = 2;
+        doc.resolved.insert(voters[0]);
+        let mut voted = doc.clone();
+        voted.resolved.insert(voters[1]);
+
+        // the second vote meets the threshold, the task is resolved once.
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]); // the voters
+        db.push_docs(GET_ONE, vec![voted.to()]); // the votes to finalize on
+        db.push_docs(GET_ONE, vec![voted.to()]); // the indexes to update
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task
+            .update_resolved(&db, voters[1], "duplicate")
+            .await
+            .unwrap());
+        assert_eq!(task.status, TaskStatus::Resolved);
+        assert_eq!(task.final_reason, "duplicate");
+        assert_eq!(
+            db.queries("IF status=? AND resolved=? AND rejected=?"),
+            vec!["UPDATE task SET status=?,final_reason=? WHERE uid=? AND id=? IF status=? AND resolved=? AND rejected=?"]
+        );
+        assert_eq!(db.queries("UPDATE task_stats SET resolved=").len(), 1);
+        assert_eq!(db.queries("UPDATE task SET finalized_at=?").len(), 1);
+        assert!(task.finalized_at > 0);
+        assert_eq!(db.queries("UPDATE task_counter").len(), 2);
+        assert_eq!(db.queries("UPDATE task_by_gid SET status=?").len(), 1);
+
+        // a task reverted or reopened after it was counted is not counted again.
+        let mut counted = voted.clone();
+        counted.finalized_at = 2000;
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_docs(GET_ONE, vec![counted.to()]);
+        db.push_docs(GET_ONE, vec![counted.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task
+            .update_resolved(&db, voters[1], "duplicate")
+            .await
+            .unwrap());
+        assert_eq!(task.status, TaskStatus::Resolved);
+        assert_eq!(db.queries("UPDATE task_counter").len(), 2);
+        assert!(db.queries("UPDATE task_stats").is_empty());
+        assert!(db.queries("finalized_at=?").is_empty());
+
+        // the votes do not meet a higher threshold.
+        let mut strict = voted.clone();
+        strict.threshold = 3;
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_docs(GET_ONE, vec![strict.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task
+            .update_resolved(&db, voters[1], "duplicate")
+            .await
+            .unwrap());
+        assert_eq!(task.status, TaskStatus::Pending);
+        assert_eq!(task.final_reason, "");
+        assert!(db.queries("IF status=?").is_empty());
+        assert!(db.queries("UPDATE task_stats").is_empty());
+        assert!(db.queries("UPDATE task_counter").is_empty());
+
+        // a concurrent vote fails the first flip, the votes are re-read and it is retried.
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_docs(GET_ONE, vec![voted.to()]);
+        db.push_applied("IF status=? AND resolved=?", false);
+        db.push_docs(GET_ONE, vec![voted.to()]);
+        db.push_docs(GET_ONE, vec![voted.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.update_resolved(&db, voters[1], "").await.unwrap());
+        assert_eq!(task.status, TaskStatus::Resolved);
+        assert_eq!(db.queries("IF status=? AND resolved=?").len(), 2);
+        assert_eq!(db.queries("UPDATE task_counter").len(), 2);
+
+        // lowering the threshold resolves the task with the votes it has.
+        let mut lowered = voted.clone();
+        lowered.threshold = 3;
+        let mut resolvable = voted.clone();
+        resolvable.threshold = 2;
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![lowered.to()]);
+        db.push_docs(GET_ONE, vec![resolvable.to()]);
+        db.push_docs(GET_ONE, vec![resolvable.to()]);
+        let mut cols = ColumnsMap::new();
+        cols.set_as("threshold", &2i16);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.update(&db, cols, 1000).await.unwrap());
+        assert_eq!(task.status, TaskStatus::Resolved);
+        assert_eq!(
+            db.queries("UPDATE task SET status=? WHERE uid=? AND id=? IF status=?")
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn reopen_with_fake_works() {
+        let mut doc = pending_task();
+        doc.status = TaskStatus::Resolved;
+        doc.duedate = unix_ms() as i64 + 3600 * 1000;
+        doc.final_reason = "duplicate".to_string();
+        doc.resolved = doc.assignees.clone();
+
+        // the votes are cleared and the indexes of a processing task are restored.
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_docs(GET_ONE, vec![doc.to()]); // the indexes to update
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.reopen(&db, 1000).await.unwrap());
+        assert_eq!(task.status, TaskStatus::Pending);
+        assert!(task.resolved.is_empty());
+        assert_eq!(task.final_reason, "");
+        assert_eq!(db.queries("UPDATE task_counter").len(), 2);
+        assert_eq!(db.queries("UPDATE task_by_gid SET status=?").len(), 1);
+        assert_eq!(
+            db.queries("UPDATE task_by_participant SET status=?").len(),
+            doc.assignees.len()
+        );
+        assert_eq!(db.queries("INSERT INTO task_by_duedate").len(), 1);
+        assert_eq!(db.queries("INSERT INTO task_by_priority").len(), 1);
+
+        // a processing task is left as is.
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![pending_task().to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(!task.reopen(&db, 1000).await.unwrap());
+        assert!(db.queries("UPDATE").is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn remove_vote_with_fake_works() {
+        let mut doc = pending_task();
+        let voters: Vec<xid::Id> = doc.assignees.iter().cloned().collect();
+
+        // a voter who never acked has nothing to withdraw.
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(!task.remove_vote(&db, voters[0]).await.unwrap());
+        assert!(db.queries("UPDATE task").is_empty());
+
+        // withdrawing the vote that resolved the task reverts it to pending.
+        doc.threshold = 1;
+        doc.status = TaskStatus::Resolved;
+        doc.final_reason = "duplicate".to_string();
+        doc.resolved.insert(voters[0]);
+        let mut withdrawn = doc.clone();
+        withdrawn.resolved.clear();
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]); // the votes
+        db.push_docs(GET_ONE, vec![withdrawn.to()]); // the votes left
+        db.push_docs(GET_ONE, vec![withdrawn.to()]); // the indexes to update
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.remove_vote(&db, voters[0]).await.unwrap());
+        assert_eq!(task.status, TaskStatus::Pending);
+        assert_eq!(task.final_reason, "");
+        assert_eq!(
+            db.queries("IF status=?"),
+            vec!["UPDATE task SET status=?,final_reason='' WHERE uid=? AND id=? IF status=?"]
+        );
+        assert_eq!(db.queries("UPDATE task_counter").len(), 2);
+        assert_eq!(db.queries("UPDATE task_by_gid SET status=?").len(), 1);
+
+        // the votes left still meet the threshold.
+        let mut still_met = withdrawn.clone();
+        still_met.resolved.insert(voters[1]);
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_docs(GET_ONE, vec![still_met.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.remove_vote(&db, voters[0]).await.unwrap());
+        assert_eq!(task.status, TaskStatus::Resolved);
+        assert!(db.queries("IF status=?").is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn delegate_with_fake_works() {
+        let mut doc = pending_task();
+        let assignees: Vec<xid::Id> = doc.assignees.iter().cloned().collect();
+        let (from, to) = (assignees[0], xid::new());
+        doc.resolved.insert(from);
+        doc.rejected.insert(assignees[1]);
+
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let updated_at = task.delegate(&db, from, to).await.unwrap();
+        assert_eq!(task.updated_at, updated_at);
+        assert!(!task.assignees.contains(&from));
+        assert!(task.assignees.contains(&to));
+        assert_eq!(task.assignees.len(), 3);
+        // the vote of from is dropped, the others are kept.
+        assert!(task.resolved.is_empty());
+        assert!(task.rejected.contains(&assignees[1]));
+        assert_eq!(
+            db.queries("UPDATE task SET"),
+            vec!["UPDATE task SET assignees=?, resolved=?, rejected=?, updated_at=? WHERE uid=? AND id=? IF status=? AND assignees=? AND resolved=? AND rejected=?".to_string()]
+        );
+        assert_eq!(db.queries("DELETE FROM task_by_participant").len(), 1);
+        assert_eq!(db.queries("INSERT INTO task_by_participant").len(), 1);
+
+        // an approver keeps its vote and its index row.
+        let mut approver = doc.clone();
+        approver.approvers.insert(from);
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![approver.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        task.delegate(&db, from, to).await.unwrap();
+        assert!(task.resolved.contains(&from));
+        assert!(db.queries("DELETE FROM task_by_participant").is_empty());
+
+        for (from, to) in [(to, xid::new()), (from, assignees[1])] {
+            let db = FakeDB::new();
+            db.push_docs(GET_ONE, vec![doc.to()]);
+            let mut task = Task::with_pk(doc.uid, doc.id);
+            let err: HTTPError = task.delegate(&db, from, to).await.unwrap_err().into();
+            assert_eq!(err.code, 400);
         }
 
-        Ok(res)
+        // a concurrent vote fails the delegation.
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_applied("UPDATE task SET assignees=?", false);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err: HTTPError = task.delegate(&db, from, to).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+        assert!(db.queries("task_by_participant").is_empty());
+
+        let mut resolved = doc.clone();
+        resolved.status = TaskStatus::Resolved;
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![resolved.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err: HTTPError = task.delegate(&db, from, to).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use tokio::sync::OnceCell;
+    #[tokio::test(flavor = "current_thread")]
+    async fn update_approvers_with_fake_works() {
+        let mut doc = pending_task();
+        let (a, b, c) = (xid::new(), xid::new(), xid::new());
+        doc.approvers = HashSet::from([a, b]);
+
+        // the final set is written in one conditional statement, and then the remaining
+        // approvers are checked.
+        let mut updated = doc.clone();
+        updated.approvers = HashSet::from([b, c]);
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_docs(GET_ONE, vec![updated.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let updated_at = task
+            .update_approvers(&db, vec![a], vec![c], 1000)
+            .await
+            .unwrap();
+        assert_eq!(task.updated_at, updated_at);
+        assert!(updated_at > 1000);
+        assert_eq!(task.approvers, HashSet::from([b, c]));
+        assert_eq!(
+            db.queries("UPDATE task SET"),
+            vec![
+                "UPDATE task SET approvers=?, resolved=?, rejected=?, updated_at=? WHERE uid=? AND id=? IF updated_at=? AND resolved=? AND rejected=?"
+                    .to_string()
+            ]
+        );
+        assert_eq!(db.queries("DELETE FROM task_by_participant").len(), 1);
+        assert_eq!(db.queries("INSERT INTO task_by_participant").len(), 1);
 
-    use crate::conf;
+        // a concurrent update fails it as a whole.
+        let mut updated = doc.clone();
+        updated.updated_at = 2000;
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_applied("IF updated_at=?", false);
+        db.push_docs(GET_ONE, vec![updated.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err: HTTPError = task
+            .update_approvers(&db, vec![a], vec![c], 1000)
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 409);
+        assert_eq!(
+            err.data,
+            Some(serde_json::json!({ "current_updated_at": 2000 }))
+        );
+        assert_eq!(db.queries("UPDATE task SET").len(), 1);
+        assert!(db.queries("task_by_participant").is_empty());
+    }
 
-    use super::*;
+    #[tokio::test(flavor = "current_thread")]
+    async fn update_participants_drops_votes_with_fake_works() {
+        let mut doc = pending_task();
+        let (a, b) = (xid::new(), xid::new());
+        doc.threshold = 0;
+        doc.assignees = HashSet::from([a, b]);
+        doc.resolved = HashSet::from([a]);
 
-    static DB: OnceCell<scylladb::ScyllaDB> = OnceCell::const_new();
+        // the vote of a removed assignee does not count for the unanimous threshold.
+        let mut updated = doc.clone();
+        updated.assignees = HashSet::from([b]);
+        updated.resolved = HashSet::new();
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_docs(GET_ONE, vec![updated.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        task.update_assignees(&db, vec![a], vec![], 1000)
+            .await
+            .unwrap();
+        assert_eq!(task.assignees, HashSet::from([b]));
+        assert!(task.resolved.is_empty());
+        assert!(db.queries("IF status=? AND resolved=?").is_empty());
+        assert!(db.queries("task_counter").is_empty());
+
+        // a removed assignee who is an approver keeps the vote.
+        doc.threshold = 2;
+        doc.approvers = HashSet::from([a]);
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        task.update_assignees(&db, vec![a], vec![], 1000)
+            .await
+            .unwrap();
+        assert_eq!(task.resolved, HashSet::from([a]));
+
+        // and so does a removed approver who is an assignee, unlike the others.
+        let c = xid::new();
+        doc.approvers = HashSet::from([a, c]);
+        doc.rejected = HashSet::from([c]);
+        let mut updated = doc.clone();
+        updated.approvers = HashSet::new();
+        updated.rejected = HashSet::new();
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_docs(GET_ONE, vec![updated.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        task.update_approvers(&db, vec![a, c], vec![], 1000)
+            .await
+            .unwrap();
+        assert_eq!(task.resolved, HashSet::from([a]));
+        assert!(task.rejected.is_empty());
+    }
 
-    async fn get_db() -> scylladb::ScyllaDB {
-        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
-        let res = scylladb::ScyllaDB::new(cfg.scylla, "logbase_test").await;
-        res.unwrap()
+    #[tokio::test(flavor = "current_thread")]
+    async fn transfer_with_fake_works() {
+        let mut doc = pending_task();
+        doc.escalate_after_ms = 60000;
+        let new_uid = xid::new();
+        let mut copy = doc.clone();
+        copy.uid = new_uid;
+        let mut comment = TaskComment::with_pk(doc.uid, doc.id, xid::new());
+        comment.message = "hi".to_string();
+
+        // the copy is saved, then moving the comments fails.
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_error("FROM task_comment WHERE", anyhow::anyhow!("unavailable"));
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.transfer(&db, new_uid).await.is_err());
+        assert_eq!(db.queries("INSERT INTO task (").len(), 1);
+        assert!(db.queries("DELETE FROM task WHERE").is_empty());
+
+        // the re-run keeps the copy, and moves the rest.
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_applied("INSERT INTO task (", false);
+        db.push_docs(GET_ONE, vec![copy.to()]);
+        db.push_docs("FROM task_comment WHERE", vec![comment.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.transfer(&db, new_uid).await.unwrap());
+        assert_eq!(task.uid, new_uid);
+        // the counter of the old uid is decreased, the copy was counted by the first run.
+        assert_eq!(db.queries("UPDATE task_counter").len(), 1);
+        assert_eq!(db.queries("INSERT INTO task_comment").len(), 1);
+        assert_eq!(db.queries("DELETE FROM task_comment").len(), 1);
+        assert_eq!(db.queries("DELETE FROM task_log").len(), 1);
+        assert_eq!(db.queries("DELETE FROM task_by_participant").len(), 3);
+        assert_eq!(db.queries("DELETE FROM task_by_escalation").len(), 1);
+        assert_eq!(db.queries("DELETE FROM task WHERE").len(), 1);
+
+        // the old task is gone once it is moved.
+        let db = FakeDB::new();
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err = task.transfer(&db, new_uid).await.unwrap_err();
+        assert!(scylladb::is_not_found(&err));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn watch_with_fake_works() {
+        let doc = pending_task();
+        let watcher = xid::new();
+
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.watch(&db, watcher).await.unwrap());
+        assert!(task.watchers.contains(&watcher));
+        assert_eq!(
+            db.queries("UPDATE task SET watchers"),
+            vec![
+                "UPDATE task SET watchers=watchers+{?} WHERE uid=? AND id=? IF EXISTS".to_string()
+            ]
+        );
+
+        // watching again is a no-op.
+        let mut watched = doc.clone();
+        watched.watchers.insert(watcher);
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![watched.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(!task.watch(&db, watcher).await.unwrap());
+        assert!(db.queries("UPDATE task SET watchers").is_empty());
+
+        let mut full = doc.clone();
+        full.watchers = (0..Task::MAX_WATCHERS).map(|_| xid::new()).collect();
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![full.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err: HTTPError = task.watch(&db, watcher).await.unwrap_err().into();
+        assert_eq!(err.code, 400);
+
+        // the task is deleted meanwhile.
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        db.push_applied("UPDATE task SET watchers", false);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err: HTTPError = task.watch(&db, watcher).await.unwrap_err().into();
+        assert_eq!(err.code, 409);
+
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![watched.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(task.unwatch(&db, watcher).await.unwrap());
+        assert!(!task.watchers.contains(&watcher));
+        assert_eq!(
+            db.queries("UPDATE task SET watchers"),
+            vec![
+                "UPDATE task SET watchers=watchers-{?} WHERE uid=? AND id=? IF EXISTS".to_string()
+            ]
+        );
+
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        assert!(!task.unwatch(&db, watcher).await.unwrap());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn watcher_can_not_vote_with_fake_works() {
+        let mut doc = pending_task();
+        let watcher = xid::new();
+        doc.watchers.insert(watcher);
+
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err: HTTPError = task
+            .update_resolved(&db, watcher, "")
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 403);
+
+        // anyone votes on a task without participants, but the watchers.
+        doc.assignees.clear();
+        let db = FakeDB::new();
+        db.push_docs(GET_ONE, vec![doc.to()]);
+        let mut task = Task::with_pk(doc.uid, doc.id);
+        let err: HTTPError = task
+            .update_rejected(&db, watcher, "")
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 403);
+        assert!(task.can_vote(&xid::new()));
+
+        // a watcher that is also an assignee votes as the assignee.
+        doc.assignees.insert(watcher);
+        assert!(doc.can_vote(&watcher));
+        assert_eq!(doc.max_reachable_votes(), 1);
+    }
+
+    #[test]
+    fn watcher_notifications_works() {
+        let mut doc = pending_task();
+        let assignee = *doc.assignees.iter().next().unwrap();
+        let watcher = xid::new();
+        doc.watchers = [watcher, assignee, doc.uid].into_iter().collect();
+
+        // the creator and the participants have their own notifications.
+        doc.status = TaskStatus::Resolved;
+        let res = doc.watcher_notifications(3600);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].uid, watcher);
+        assert_eq!(res[0].tid, doc.id);
+        assert_eq!(res[0].sender, doc.uid);
+        assert_eq!(res[0].gid, doc.gid);
+        assert_eq!(res[0].status, NotificationStatus::Resolved);
+        assert_eq!(res[0].message, "task resolved");
+        assert_eq!(res[0]._ttl, 3600);
+
+        doc.status = TaskStatus::Rejected;
+        assert_eq!(
+            doc.watcher_notifications(0)[0].status,
+            NotificationStatus::Rejected
+        );
+        doc.status = TaskStatus::Expired;
+        let res = doc.watcher_notifications(0);
+        assert_eq!(res[0].status, NotificationStatus::Pending);
+        assert_eq!(res[0].message, "task expired");
+
+        doc.watchers.clear();
+        assert!(doc.watcher_notifications(0).is_empty());
     }
 
-    // #[tokio::test(flavor = "current_thread")]
-    // #[ignore]
-    // async fn task_model_works() {
-    // }
+    #[tokio::test(flavor = "current_thread")]
+    async fn list_with_fake_works() {
+        let db = FakeDB::new();
+        let uid = xid::new();
+        let token = Some(xid::new());
+        let status = Some(TaskStatus::Pending);
+        let kind = Some("test".to_string());
+
+        // page_token, status, kind, asc, the conditions of the statement and its values.
+        let cases = vec![
+            (None, None, None, false, "WHERE uid=? LIMIT ? USING TIMEOUT 3s", 2),
+            (
+                None,
+                status,
+                None,
+                false,
+                "WHERE uid=? AND status=? LIMIT ? USING TIMEOUT 3s",
+                3,
+            ),
+            (
+                token,
+                None,
+                None,
+                false,
+                "WHERE uid=? AND id<? LIMIT ? USING TIMEOUT 3s",
+                3,
+            ),
+            (
+                token,
+                status,
+                None,
+                false,
+                "WHERE uid=? AND status=? AND id<? LIMIT ? USING TIMEOUT 3s",
+                4,
+            ),
+            (
+                None,
+                None,
+                kind.clone(),
+                false,
+                "WHERE uid=? AND kind=? LIMIT ? USING TIMEOUT 3s",
+                3,
+            ),
+            (
+                token,
+                status,
+                kind.clone(),
+                false,
+                "WHERE uid=? AND kind=? AND status=? AND id<? LIMIT ? ALLOW FILTERING USING TIMEOUT 3s",
+                5,
+            ),
+            (
+                None,
+                None,
+                None,
+                true,
+                "WHERE uid=? ORDER BY id ASC LIMIT ? USING TIMEOUT 3s",
+                2,
+            ),
+            (
+                token,
+                status,
+                None,
+                true,
+                "WHERE uid=? AND status=? AND id>? ORDER BY id ASC LIMIT ? ALLOW FILTERING USING TIMEOUT 3s",
+                4,
+            ),
+        ];
+        for (i, (page_token, status, kind, asc, conditions, values)) in
+            cases.into_iter().enumerate()
+        {
+            let mut doc = Task::with_pk(uid, xid::new());
+            doc.kind = "test".to_string();
+            db.push_docs("FROM task WHERE", vec![doc.to()]);
+            let res = Task::list(&db, uid, vec![], 10, page_token, status, kind, asc)
+                .await
+                .unwrap();
+            assert_eq!(res.len(), 1);
+            assert_eq!(res[0].id, doc.id);
+            assert_eq!(res[0].kind, "test");
+            assert!(!res[0]._fields.contains(&"payload".to_string()));
+
+            let stmt = &db.statements()[i];
+            assert!(
+                stmt.query.ends_with(conditions),
+                "case {}: {}",
+                i,
+                stmt.query
+            );
+            assert_eq!(stmt.values, values, "case {}", i);
+        }
+    }
 }
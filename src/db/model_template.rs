@@ -0,0 +1,182 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+use std::collections::HashSet;
+
+use crate::db::scylladb::{extract_applied, CqlExecutor};
+
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct TaskTemplate {
+    pub gid: xid::Id,
+    pub id: xid::Id,
+    pub name: String,
+    pub kind: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub threshold: i16,
+    pub approvers: HashSet<xid::Id>,
+    pub assignees: HashSet<xid::Id>,
+    pub message: String,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl TaskTemplate {
+    pub fn with_pk(gid: xid::Id, id: xid::Id) -> Self {
+        Self {
+            gid,
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub async fn get_one(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM task_template WHERE gid=? AND id=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.gid.to_cql(), self.id.to_cql());
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    pub async fn save(&mut self, db: &impl CqlExecutor) -> anyhow::Result<bool> {
+        self.created_at = unix_ms() as i64;
+        self.updated_at = self.created_at;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO task_template ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                "TaskTemplate save failed, please try again".to_string(),
+            )
+            .into());
+        }
+
+        Ok(true)
+    }
+
+    pub async fn update(
+        &mut self,
+        db: &impl CqlExecutor,
+        cols: ColumnsMap,
+        updated_at: i64,
+    ) -> anyhow::Result<bool> {
+        let valid_fields = vec![
+            "name",
+            "kind",
+            "threshold",
+            "approvers",
+            "assignees",
+            "message",
+        ];
+        let update_fields = cols.keys();
+        for field in &update_fields {
+            if !valid_fields.contains(&field.as_str()) {
+                return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
+            }
+        }
+
+        let mut set_fields: Vec<String> = Vec::with_capacity(update_fields.len() + 1);
+        let mut params: Vec<CqlValue> = Vec::with_capacity(update_fields.len() + 1 + 3);
+
+        let new_updated_at = unix_ms() as i64;
+        set_fields.push("updated_at=?".to_string());
+        params.push(new_updated_at.to_cql());
+        for field in &update_fields {
+            set_fields.push(format!("{}=?", field));
+            params.push(cols.get(field).unwrap().to_owned());
+        }
+
+        let query = format!(
+            "UPDATE task_template SET {} WHERE gid=? AND id=? IF updated_at=?",
+            set_fields.join(",")
+        );
+        params.push(self.gid.to_cql());
+        params.push(self.id.to_cql());
+        params.push(updated_at.to_cql());
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                "TaskTemplate update failed, please try again".to_string(),
+            )
+            .into());
+        }
+
+        self.updated_at = new_updated_at;
+        Ok(true)
+    }
+
+    pub async fn delete(&mut self, db: &impl CqlExecutor) -> anyhow::Result<()> {
+        let query = "DELETE FROM task_template WHERE gid=? AND id=?";
+        let params = (self.gid.to_cql(), self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    pub async fn list(
+        db: &impl CqlExecutor,
+        gid: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<TaskTemplate>> {
+        let fields = Self::fields();
+
+        let rows = if let Some(id) = page_token {
+            let query = format!(
+                "SELECT {} FROM task_template WHERE gid=? AND id<? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (gid.to_cql(), id.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        } else {
+            let query = format!(
+                "SELECT {} FROM task_template WHERE gid=? LIMIT ? USING TIMEOUT 3s",
+                fields.join(",")
+            );
+            let params = (gid.to_cql(), page_size as i32);
+            db.execute_iter(query, params).await?
+        };
+
+        let mut res: Vec<TaskTemplate> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = TaskTemplate::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
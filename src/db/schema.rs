@@ -0,0 +1,202 @@
+use axum_web::context::unix_ms;
+
+use crate::db::scylladb::{exec_cqls, ScyllaDB};
+
+// the version of cql/schema_table.cql, it should be bumped when the schema changes
+// so that the migrations are ordered by it.
+pub const SCHEMA_VERSION: i32 = 16;
+
+pub const SCHEMA_TABLE: &str = include_str!("../../cql/schema_table.cql");
+
+// the column changes of each version, applied to keyspaces bootstrapped with an older version.
+// new tables need no migration since the tables are created if not exists.
+const MIGRATIONS: [(i32, &str); 11] = [
+    (
+        4,
+        "ALTER TABLE task ADD allow_late_ack BOOLEAN;
+        ALTER TABLE task_archive ADD allow_late_ack BOOLEAN;",
+    ),
+    (
+        6,
+        "ALTER TABLE task ADD approver_threshold SMALLINT;
+        ALTER TABLE task_archive ADD approver_threshold SMALLINT;",
+    ),
+    (
+        7,
+        "ALTER TABLE task ADD veto BOOLEAN;
+        ALTER TABLE task_archive ADD veto BOOLEAN;",
+    ),
+    (
+        8,
+        "ALTER TABLE task ADD sequence LIST<BLOB>;
+        ALTER TABLE task_archive ADD sequence LIST<BLOB>;",
+    ),
+    // tasks created before it read self_ack_allowed as true, see `Task::default`.
+    (
+        9,
+        "ALTER TABLE task ADD self_ack_allowed BOOLEAN;
+        ALTER TABLE task_archive ADD self_ack_allowed BOOLEAN;",
+    ),
+    // tasks created before it read payload_type as empty and payload_len as 0.
+    (
+        10,
+        "ALTER TABLE task ADD payload_type TEXT;
+        ALTER TABLE task ADD payload_len INT;
+        ALTER TABLE task_archive ADD payload_type TEXT;
+        ALTER TABLE task_archive ADD payload_len INT;
+        ALTER TABLE task_recurrence ADD payload_type TEXT;",
+    ),
+    (
+        11,
+        "ALTER TABLE task ADD payload_external BOOLEAN;
+        ALTER TABLE task_archive ADD payload_external BOOLEAN;",
+    ),
+    (
+        12,
+        "ALTER TABLE task ADD watchers SET<BLOB>;
+        ALTER TABLE task_archive ADD watchers SET<BLOB>;",
+    ),
+    (
+        13,
+        "ALTER TABLE task ADD final_reason TEXT;
+        ALTER TABLE task_archive ADD final_reason TEXT;
+        ALTER TABLE notification ADD reason TEXT;",
+    ),
+    // tasks created before it read visibility as empty, that is "group".
+    (
+        15,
+        "ALTER TABLE task ADD visibility TEXT;
+        ALTER TABLE task_archive ADD visibility TEXT;",
+    ),
+    // tasks finalized before it are counted again if they are reopened and finalized.
+    (
+        16,
+        "ALTER TABLE task ADD finalized_at BIGINT;
+        ALTER TABLE task_archive ADD finalized_at BIGINT;",
+    ),
+];
+
+const META_TABLE: &str = "CREATE TABLE IF NOT EXISTS meta (
+    name       TEXT,   -- the name of the meta row, e.g. \"schema\"
+    version    INT,    -- the schema version applied
+    updated_at BIGINT, -- when the version was applied
+    PRIMARY KEY (name)
+)";
+
+pub fn keyspace_cql(keyspace: &str, replication: &str) -> String {
+    format!(
+        "CREATE KEYSPACE IF NOT EXISTS {} WITH replication = {}",
+        keyspace, replication
+    )
+}
+
+impl ScyllaDB {
+    // creates the keyspace and the tables if not exists, then uses the keyspace,
+    // returns the schema version before the bootstrap, 0 for a fresh keyspace.
+    pub async fn bootstrap(&self, keyspace: &str, replication: &str) -> anyhow::Result<i32> {
+        if keyspace.is_empty()
+            || !keyspace
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(anyhow::anyhow!("invalid keyspace: {:?}", keyspace));
+        }
+
+        self.execute(keyspace_cql(keyspace, replication), &[])
+            .await?;
+        self.use_keyspace(keyspace).await?;
+        self.execute(META_TABLE, &[]).await?;
+
+        let version = self.schema_version().await?;
+        if version > SCHEMA_VERSION {
+            return Err(anyhow::anyhow!(
+                "schema version {} of keyspace {} is newer than {}",
+                version,
+                keyspace,
+                SCHEMA_VERSION
+            ));
+        }
+        if version < SCHEMA_VERSION {
+            exec_cqls(self, SCHEMA_TABLE).await?;
+            if version > 0 {
+                for (_, cqls) in MIGRATIONS.iter().filter(|(v, _)| *v > version) {
+                    exec_cqls(self, cqls).await?;
+                }
+            }
+            let query = "INSERT INTO meta (name,version,updated_at) VALUES (?,?,?)";
+            let params = ("schema", SCHEMA_VERSION, unix_ms() as i64);
+            self.execute(query, params).await?;
+        }
+
+        Ok(version)
+    }
+
+    // the schema version recorded in the meta table, 0 if none.
+    pub async fn schema_version(&self) -> anyhow::Result<i32> {
+        let query = "SELECT version FROM meta WHERE name=? LIMIT 1";
+        let res = self.execute(query, ("schema",)).await?;
+        let version = res
+            .maybe_first_row()?
+            .and_then(|row| row.columns[0].as_ref().and_then(|v| v.as_int()))
+            .unwrap_or(0);
+        Ok(version)
+    }
+}
+
+// connects to the test keyspace, it is bootstrapped on the first connection.
+#[cfg(test)]
+pub async fn test_db(keyspace: &str) -> ScyllaDB {
+    let cfg = crate::conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+    let db = ScyllaDB::new(cfg.scylla, "").await.unwrap();
+    db.bootstrap(
+        keyspace,
+        "{ 'class': 'SimpleStrategy', 'replication_factor': '1' }",
+    )
+    .await
+    .unwrap();
+    db
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyspace_cql_works() {
+        assert_eq!(
+            keyspace_cql(
+                "taskbase_test",
+                "{ 'class': 'SimpleStrategy', 'replication_factor': '1' }"
+            ),
+            "CREATE KEYSPACE IF NOT EXISTS taskbase_test WITH replication = { 'class': 'SimpleStrategy', 'replication_factor': '1' }"
+        );
+    }
+
+    #[test]
+    fn migrations_works() {
+        let mut prev = 0;
+        for (version, _) in MIGRATIONS {
+            assert!(version > prev && version <= SCHEMA_VERSION);
+            prev = version;
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn bootstrap_works() {
+        let db = test_db("taskbase_test").await;
+        assert_eq!(db.schema_version().await.unwrap(), SCHEMA_VERSION);
+
+        // bootstrapping again is a no-op.
+        let version = db
+            .bootstrap(
+                "taskbase_test",
+                "{ 'class': 'SimpleStrategy', 'replication_factor': '1' }",
+            )
+            .await
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+
+        assert!(db.bootstrap("taskbase;DROP", "{}").await.is_err());
+    }
+}
@@ -1,32 +1,94 @@
+use async_trait::async_trait;
 use futures::{stream::StreamExt, Stream};
 use scylla::{
-    frame::value::{BatchValues, ValueList},
-    statement::{Consistency, SerialConsistency},
-    transport::{query_result::QueryResult, Compression, ExecutionProfile},
+    batch::BatchType,
+    frame::{
+        response::result::CqlValue,
+        value::{BatchValues, ValueList},
+    },
+    transport::{
+        errors::{DbError, QueryError},
+        iterator::RowIterator,
+        query_result::{QueryResult, SingleRowError},
+        Compression, ExecutionProfile,
+    },
     CachingSession, Metrics, Session, SessionBuilder,
 };
-use std::{sync::Arc, time::Duration};
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 pub use scylla::{
     batch::Batch,
     frame::response::result::{ColumnType, Row},
     query::Query,
+    statement::{Consistency, SerialConsistency},
     Bytes,
 };
 
+use tracing::{field, Instrument};
+
 use crate::conf;
+use crate::crypto::Keyring;
+use crate::db::TaskCache;
+use crate::metrics::{self, statement_tag};
 
 pub struct ScyllaDB {
     session: CachingSession,
+    lwt_retry: RetryPolicy,
+    metrics: Option<Arc<metrics::Metrics>>,
+    keyring: Option<Arc<Keyring>>,
+    task_cache: Option<TaskCache>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff_ms: u64, // doubled on every retry
+}
+
+impl RetryPolicy {
+    // the backoff before the retry, jittered in [backoff / 2, backoff].
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.backoff_ms.saturating_mul(1 << retry.min(10)).max(1);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u64)
+            .unwrap_or_default();
+        Duration::from_millis(backoff / 2 + nanos % (backoff / 2 + 1))
+    }
+}
+
+// retries the operation on timeout errors with jittered backoff,
+// other errors and the results of the operation are returned as is.
+pub async fn retry_on_timeout<T, F, Fut>(policy: RetryPolicy, mut op: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut retry: u32 = 0;
+    loop {
+        match op().await {
+            Err(err) if retry < policy.max_retries && is_timeout(&err) => {
+                tokio::time::sleep(policy.backoff(retry)).await;
+                retry += 1;
+            }
+            res => return res,
+        }
+    }
 }
 
 impl ScyllaDB {
     pub async fn new(cfg: conf::ScyllaDB, keyspace: &str) -> anyhow::Result<Self> {
         // use tls https://github.com/scylladb/scylla-rust-driver/blob/main/examples/tls.rs
 
+        let consistency = parse_consistency(&cfg.consistency)?;
+        let serial_consistency = parse_serial_consistency(&cfg.serial_consistency)?;
         let handle = ExecutionProfile::builder()
-            .consistency(Consistency::Quorum)
-            .serial_consistency(Some(SerialConsistency::Serial))
+            .consistency(consistency)
+            .serial_consistency(Some(serial_consistency))
             .request_timeout(Some(Duration::from_secs(5)))
             .build()
             .into_handle();
@@ -45,9 +107,68 @@ impl ScyllaDB {
 
         Ok(Self {
             session: CachingSession::from(session, 100000),
+            lwt_retry: RetryPolicy {
+                max_retries: cfg.lwt_retries,
+                backoff_ms: cfg.lwt_backoff_ms,
+            },
+            metrics: None,
+            keyring: None,
+            task_cache: None,
         })
     }
 
+    // records the durations and errors of the statements in the metrics.
+    pub fn with_metrics(mut self, metrics: Arc<metrics::Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // encrypts the task payloads at rest with the keyring.
+    pub fn with_keyring(mut self, keyring: Arc<Keyring>) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    // caches the tasks hydrated into the notification lists, the mutations of the tasks
+    // through the session invalidate them.
+    pub fn with_task_cache(mut self, cache: TaskCache) -> Self {
+        self.task_cache = Some(cache);
+        self
+    }
+
+    // runs the statement in a span tagged by the statement that records the row count,
+    // and records the duration and the error in the metrics.
+    async fn trace<T: RowCount>(
+        &self,
+        tag: String,
+        fut: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        let span = tracing::info_span!("cql", statement = %tag, rows = field::Empty);
+        let start = Instant::now();
+        let res = fut.instrument(span.clone()).await;
+        if let Ok(rows) = &res {
+            span.record("rows", rows.row_count());
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_cql(&tag, start, res.is_ok());
+        }
+        res
+    }
+
+    pub async fn use_keyspace(&self, keyspace: &str) -> anyhow::Result<()> {
+        self.session
+            .get_session()
+            .use_keyspace(keyspace, false)
+            .await?;
+        Ok(())
+    }
+
+    // a cheap query that checks the session can reach the cluster.
+    pub async fn ping(&self) -> anyhow::Result<()> {
+        let _ = self.execute("SELECT now() FROM system.local", &[]).await?;
+        Ok(())
+    }
+
     pub fn metrics(&self) -> Arc<Metrics> {
         self.session.get_session().get_metrics()
     }
@@ -57,23 +178,125 @@ impl ScyllaDB {
         query: impl Into<Query>,
         params: impl ValueList,
     ) -> anyhow::Result<QueryResult> {
-        let res = self.session.execute(query, params).await?;
+        let query: Query = query.into();
+        let tag = statement_tag(&query.contents);
+        self.trace(tag, async {
+            Ok::<_, anyhow::Error>(self.session.execute(query, params).await?)
+        })
+        .await
+    }
+
+    // executes an idempotent conditional statement, it is retried on timeouts only,
+    // the caller should check whether it is applied, a condition not met is never retried.
+    pub async fn execute_lwt_with_retry(
+        &self,
+        query: impl Into<Query> + Clone,
+        params: impl ValueList + Clone,
+    ) -> anyhow::Result<QueryResult> {
+        let (res, _) = self.execute_lwt_retried(query, params).await?;
         Ok(res)
     }
 
+    // executes the conditional statement as `execute_lwt_with_retry`, and returns whether it
+    // was retried. A timed out attempt may have been applied, then the retry finds its
+    // condition not met by its own write.
+    pub async fn execute_lwt_retried(
+        &self,
+        query: impl Into<Query> + Clone,
+        params: impl ValueList + Clone,
+    ) -> anyhow::Result<(QueryResult, bool)> {
+        let mut attempts: u32 = 0;
+        let res = retry_on_timeout(self.lwt_retry, || {
+            attempts += 1;
+            self.execute(query.clone(), params.clone())
+        })
+        .await?;
+        Ok((res, attempts > 1))
+    }
+
+    // executes the statement with the consistency instead of the configured one.
+    pub async fn execute_with(
+        &self,
+        query: impl Into<Query>,
+        params: impl ValueList,
+        consistency: Consistency,
+    ) -> anyhow::Result<QueryResult> {
+        let query: Query = query.into();
+        let tag = statement_tag(&query.contents);
+        self.trace(tag, async {
+            // the prepared statement is cached and shared, so the consistency is set on a copy.
+            let mut prepared = self.session.add_prepared_statement(&query).await?;
+            prepared.set_consistency(consistency);
+            let res = self
+                .session
+                .get_session()
+                .execute(&prepared, params)
+                .await?;
+            Ok::<_, anyhow::Error>(res)
+        })
+        .await
+    }
+
     pub async fn execute_iter(
         &self,
         query: impl Into<Query>,
         params: impl ValueList,
     ) -> anyhow::Result<Vec<Row>> {
-        let mut rows_stream = self.session.execute_iter(query, params).await?;
+        let query: Query = query.into();
+        let tag = statement_tag(&query.contents);
+        self.trace(tag, async {
+            let rows_stream = self.session.execute_iter(query, params).await?;
+            collect_rows(rows_stream).await
+        })
+        .await
+    }
+
+    // executes the paged statement with the consistency instead of the configured one,
+    // e.g. ONE for the background scans which re-check every row they find.
+    pub async fn execute_iter_with(
+        &self,
+        query: impl Into<Query>,
+        params: impl ValueList,
+        consistency: Consistency,
+    ) -> anyhow::Result<Vec<Row>> {
+        let query: Query = query.into();
+        let tag = statement_tag(&query.contents);
+        self.trace(tag, async {
+            let mut prepared = self.session.add_prepared_statement(&query).await?;
+            prepared.set_consistency(consistency);
+            let rows_stream = self
+                .session
+                .get_session()
+                .execute_iter(prepared, params)
+                .await?;
+            collect_rows(rows_stream).await
+        })
+        .await
+    }
 
-        let (capacity, _) = rows_stream.size_hint();
-        let mut rows: Vec<Row> = Vec::with_capacity(capacity);
-        while let Some(next_row) = rows_stream.next().await {
-            rows.push(next_row?);
-        }
-        Ok(rows)
+    // executes the statement for a single page of page_size rows, resuming from the paging state
+    // of the previous page. The returned paging state is None when there are no more pages.
+    pub async fn execute_paged(
+        &self,
+        query: impl Into<Query>,
+        params: impl ValueList,
+        page_size: i32,
+        paging_state: Option<Vec<u8>>,
+    ) -> anyhow::Result<(Vec<Row>, Option<Vec<u8>>)> {
+        let query: Query = query.into();
+        let tag = statement_tag(&query.contents);
+        self.trace(tag, async {
+            let mut prepared = self.session.add_prepared_statement(&query).await?;
+            prepared.set_page_size(page_size);
+            let res = self
+                .session
+                .get_session()
+                .execute_paged(&prepared, params, paging_state.map(Bytes::from))
+                .await?;
+            let paging_state = res.paging_state.as_ref().map(|state| state.to_vec());
+            Ok::<_, anyhow::Error>((res.rows.unwrap_or_default(), paging_state))
+        })
+        .await
     }
 
     // https://opensource.docs.scylladb.com/master/cql/dml.html#batch-statement
@@ -88,8 +311,324 @@ impl ScyllaDB {
         for statement in statements {
             batch.append_statement(statement);
         }
-        let res = self.session.batch(&batch, values).await?;
-        Ok(res)
+        self.trace(statement_tag("BATCH"), async {
+            Ok::<_, anyhow::Error>(self.session.batch(&batch, values).await?)
+        })
+        .await
+    }
+
+    // executes the statements with their own params in an UNLOGGED batch, it saves round trips
+    // but it is not atomic across partitions, and conditional statements are not allowed.
+    pub async fn execute_batch(
+        &self,
+        statements: Vec<(String, Vec<CqlValue>)>,
+    ) -> anyhow::Result<QueryResult> {
+        let mut batch = Batch::new(BatchType::Unlogged);
+        let mut values: Vec<Vec<CqlValue>> = Vec::with_capacity(statements.len());
+        for (statement, params) in statements {
+            batch.append_statement(statement.as_str());
+            values.push(params);
+        }
+        self.trace(statement_tag("BATCH"), async {
+            Ok::<_, anyhow::Error>(self.session.batch(&batch, values).await?)
+        })
+        .await
+    }
+}
+
+// the statements the models execute, so that the models can run against a fake in the
+// unit tests, see `db::fake`. ScyllaDB implements it with its inherent methods.
+#[async_trait]
+pub trait CqlExecutor: Send + Sync {
+    async fn execute<Q, V>(&self, query: Q, params: V) -> anyhow::Result<QueryResult>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send;
+
+    async fn execute_lwt_with_retry<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+    ) -> anyhow::Result<QueryResult>
+    where
+        Q: Into<Query> + Clone + Send + Sync,
+        V: ValueList + Clone + Send + Sync;
+
+    async fn execute_lwt_retried<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+    ) -> anyhow::Result<(QueryResult, bool)>
+    where
+        Q: Into<Query> + Clone + Send + Sync,
+        V: ValueList + Clone + Send + Sync;
+
+    async fn execute_iter<Q, V>(&self, query: Q, params: V) -> anyhow::Result<Vec<Row>>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send;
+
+    async fn execute_iter_with<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+        consistency: Consistency,
+    ) -> anyhow::Result<Vec<Row>>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send;
+
+    async fn execute_paged<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+        page_size: i32,
+        paging_state: Option<Vec<u8>>,
+    ) -> anyhow::Result<(Vec<Row>, Option<Vec<u8>>)>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send;
+
+    async fn execute_batch(
+        &self,
+        statements: Vec<(String, Vec<CqlValue>)>,
+    ) -> anyhow::Result<QueryResult>;
+
+    // the keyring of the task payloads, the payloads are stored as is without one.
+    fn keyring(&self) -> Option<&Keyring> {
+        None
+    }
+
+    // the cache of the tasks, see `Task::get_cached` and `Invalidation`.
+    fn task_cache(&self) -> Option<&TaskCache> {
+        None
+    }
+}
+
+#[async_trait]
+impl CqlExecutor for ScyllaDB {
+    async fn execute<Q, V>(&self, query: Q, params: V) -> anyhow::Result<QueryResult>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        ScyllaDB::execute(self, query, params).await
+    }
+
+    async fn execute_lwt_with_retry<Q, V>(&self, query: Q, params: V) -> anyhow::Result<QueryResult>
+    where
+        Q: Into<Query> + Clone + Send + Sync,
+        V: ValueList + Clone + Send + Sync,
+    {
+        ScyllaDB::execute_lwt_with_retry(self, query, params).await
+    }
+
+    async fn execute_lwt_retried<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+    ) -> anyhow::Result<(QueryResult, bool)>
+    where
+        Q: Into<Query> + Clone + Send + Sync,
+        V: ValueList + Clone + Send + Sync,
+    {
+        ScyllaDB::execute_lwt_retried(self, query, params).await
+    }
+
+    async fn execute_iter<Q, V>(&self, query: Q, params: V) -> anyhow::Result<Vec<Row>>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        ScyllaDB::execute_iter(self, query, params).await
+    }
+
+    async fn execute_iter_with<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+        consistency: Consistency,
+    ) -> anyhow::Result<Vec<Row>>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        ScyllaDB::execute_iter_with(self, query, params, consistency).await
+    }
+
+    async fn execute_paged<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+        page_size: i32,
+        paging_state: Option<Vec<u8>>,
+    ) -> anyhow::Result<(Vec<Row>, Option<Vec<u8>>)>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        ScyllaDB::execute_paged(self, query, params, page_size, paging_state).await
+    }
+
+    async fn execute_batch(
+        &self,
+        statements: Vec<(String, Vec<CqlValue>)>,
+    ) -> anyhow::Result<QueryResult> {
+        ScyllaDB::execute_batch(self, statements).await
+    }
+
+    fn keyring(&self) -> Option<&Keyring> {
+        self.keyring.as_deref()
+    }
+
+    fn task_cache(&self) -> Option<&TaskCache> {
+        self.task_cache.as_ref()
+    }
+}
+
+// the handlers pass the shared session of AppState.
+#[async_trait]
+impl<T: CqlExecutor> CqlExecutor for Arc<T> {
+    async fn execute<Q, V>(&self, query: Q, params: V) -> anyhow::Result<QueryResult>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        self.as_ref().execute(query, params).await
+    }
+
+    async fn execute_lwt_with_retry<Q, V>(&self, query: Q, params: V) -> anyhow::Result<QueryResult>
+    where
+        Q: Into<Query> + Clone + Send + Sync,
+        V: ValueList + Clone + Send + Sync,
+    {
+        self.as_ref().execute_lwt_with_retry(query, params).await
+    }
+
+    async fn execute_lwt_retried<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+    ) -> anyhow::Result<(QueryResult, bool)>
+    where
+        Q: Into<Query> + Clone + Send + Sync,
+        V: ValueList + Clone + Send + Sync,
+    {
+        self.as_ref().execute_lwt_retried(query, params).await
+    }
+
+    async fn execute_iter<Q, V>(&self, query: Q, params: V) -> anyhow::Result<Vec<Row>>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        self.as_ref().execute_iter(query, params).await
+    }
+
+    async fn execute_iter_with<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+        consistency: Consistency,
+    ) -> anyhow::Result<Vec<Row>>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        self.as_ref()
+            .execute_iter_with(query, params, consistency)
+            .await
+    }
+
+    async fn execute_paged<Q, V>(
+        &self,
+        query: Q,
+        params: V,
+        page_size: i32,
+        paging_state: Option<Vec<u8>>,
+    ) -> anyhow::Result<(Vec<Row>, Option<Vec<u8>>)>
+    where
+        Q: Into<Query> + Send,
+        V: ValueList + Send,
+    {
+        self.as_ref()
+            .execute_paged(query, params, page_size, paging_state)
+            .await
+    }
+
+    async fn execute_batch(
+        &self,
+        statements: Vec<(String, Vec<CqlValue>)>,
+    ) -> anyhow::Result<QueryResult> {
+        self.as_ref().execute_batch(statements).await
+    }
+
+    fn keyring(&self) -> Option<&Keyring> {
+        self.as_ref().keyring()
+    }
+
+    fn task_cache(&self) -> Option<&TaskCache> {
+        self.as_ref().task_cache()
+    }
+}
+
+// the number of rows of a statement result, recorded in the span of the statement.
+trait RowCount {
+    fn row_count(&self) -> usize;
+}
+
+impl RowCount for QueryResult {
+    fn row_count(&self) -> usize {
+        self.rows.as_ref().map_or(0, |rows| rows.len())
+    }
+}
+
+impl RowCount for Vec<Row> {
+    fn row_count(&self) -> usize {
+        self.len()
+    }
+}
+
+impl RowCount for (Vec<Row>, Option<Vec<u8>>) {
+    fn row_count(&self) -> usize {
+        self.0.len()
+    }
+}
+
+async fn collect_rows(mut rows_stream: RowIterator) -> anyhow::Result<Vec<Row>> {
+    let (capacity, _) = rows_stream.size_hint();
+    let mut rows: Vec<Row> = Vec::with_capacity(capacity);
+    while let Some(next_row) = rows_stream.next().await {
+        rows.push(next_row?);
+    }
+    Ok(rows)
+}
+
+// parses the consistency name in conf, e.g. "QUORUM" or "LOCAL_QUORUM".
+pub fn parse_consistency(name: &str) -> anyhow::Result<Consistency> {
+    match name.trim().to_ascii_uppercase().as_str() {
+        "ANY" => Ok(Consistency::Any),
+        "ONE" => Ok(Consistency::One),
+        "TWO" => Ok(Consistency::Two),
+        "THREE" => Ok(Consistency::Three),
+        "QUORUM" => Ok(Consistency::Quorum),
+        "ALL" => Ok(Consistency::All),
+        "LOCAL_QUORUM" => Ok(Consistency::LocalQuorum),
+        "EACH_QUORUM" => Ok(Consistency::EachQuorum),
+        "LOCAL_ONE" => Ok(Consistency::LocalOne),
+        _ => Err(anyhow::anyhow!("invalid scylla consistency: {:?}", name)),
+    }
+}
+
+// parses the serial consistency name in conf, "SERIAL" or "LOCAL_SERIAL".
+pub fn parse_serial_consistency(name: &str) -> anyhow::Result<SerialConsistency> {
+    match name.trim().to_ascii_uppercase().as_str() {
+        "SERIAL" => Ok(SerialConsistency::Serial),
+        "LOCAL_SERIAL" => Ok(SerialConsistency::LocalSerial),
+        _ => Err(anyhow::anyhow!(
+            "invalid scylla serial consistency: {:?}",
+            name
+        )),
     }
 }
 
@@ -101,6 +640,35 @@ pub fn extract_applied(res: QueryResult) -> bool {
     res == Some(true)
 }
 
+// whether the error is a client side or server side timeout.
+pub fn is_timeout(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<QueryError>(),
+        Some(QueryError::TimeoutError)
+            | Some(QueryError::RequestTimeout(_))
+            | Some(QueryError::DbError(DbError::ReadTimeout { .. }, _))
+            | Some(QueryError::DbError(DbError::WriteTimeout { .. }, _))
+    )
+}
+
+// whether the error is a single row lookup that found no rows.
+pub fn is_not_found(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<SingleRowError>(),
+        Some(SingleRowError::BadNumberOfRows(0))
+    )
+}
+
+// maps the result of a single row lookup to whether the row exists,
+// other errors (timeouts, unavailable nodes, ...) are returned as is.
+pub fn exists(res: anyhow::Result<()>) -> anyhow::Result<bool> {
+    match res {
+        Ok(_) => Ok(true),
+        Err(err) if is_not_found(&err) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
 pub async fn exec_cqls(db: &ScyllaDB, cqls: &str) -> anyhow::Result<()> {
     let lines = cqls.lines();
     let mut cql = String::new();
@@ -154,6 +722,132 @@ mod tests {
         .await
     }
 
+    #[test]
+    fn is_timeout_works() {
+        assert!(is_timeout(&anyhow::Error::new(QueryError::TimeoutError)));
+        assert!(is_timeout(&anyhow::Error::new(QueryError::RequestTimeout(
+            "5s".to_string()
+        ))));
+        assert!(!is_timeout(&anyhow::Error::new(QueryError::DbError(
+            DbError::Unauthorized,
+            "".to_string()
+        ))));
+        assert!(!is_timeout(&anyhow::Error::msg("timeout")));
+    }
+
+    #[test]
+    fn exists_works() {
+        assert!(exists(Ok(())).unwrap());
+
+        let not_found = anyhow::Error::new(SingleRowError::BadNumberOfRows(0));
+        assert!(is_not_found(&not_found));
+        assert!(!exists(Err(not_found)).unwrap());
+
+        // database errors are not taken as a missing row.
+        for err in [
+            anyhow::Error::new(QueryError::TimeoutError),
+            anyhow::Error::new(QueryError::RequestTimeout("5s".to_string())),
+            anyhow::Error::new(QueryError::DbError(DbError::Overloaded, "".to_string())),
+            anyhow::Error::new(SingleRowError::BadNumberOfRows(2)),
+            anyhow::Error::msg("not found"),
+        ] {
+            assert!(!is_not_found(&err));
+            assert!(exists(Err(err)).is_err());
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn retry_on_timeout_works() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        let policy = RetryPolicy {
+            max_retries: 3,
+            backoff_ms: 1,
+        };
+
+        // transient timeouts are retried.
+        let attempts = AtomicU32::new(0);
+        let res = retry_on_timeout(policy, || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                return Err(anyhow::Error::new(QueryError::TimeoutError));
+            }
+            Ok(true)
+        })
+        .await;
+        assert!(res.unwrap());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // gives up after max_retries.
+        let attempts = AtomicU32::new(0);
+        let res: anyhow::Result<bool> = retry_on_timeout(policy, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(anyhow::Error::new(QueryError::RequestTimeout(
+                "5s".to_string(),
+            )))
+        })
+        .await;
+        assert!(is_timeout(&res.unwrap_err()));
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+
+        // other errors are not retried.
+        let attempts = AtomicU32::new(0);
+        let res: anyhow::Result<bool> = retry_on_timeout(policy, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(anyhow::Error::new(QueryError::DbError(
+                DbError::Unauthorized,
+                "".to_string(),
+            )))
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // a condition not met is a result, it is not retried.
+        let attempts = AtomicU32::new(0);
+        let res = retry_on_timeout(policy, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Ok(false)
+        })
+        .await;
+        assert!(!res.unwrap());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn parse_consistency_works() {
+        assert_eq!(parse_consistency("QUORUM").unwrap(), Consistency::Quorum);
+        assert_eq!(
+            parse_consistency("local_quorum").unwrap(),
+            Consistency::LocalQuorum
+        );
+        assert_eq!(parse_consistency(" ONE ").unwrap(), Consistency::One);
+        assert!(parse_consistency("LOCAL_SERIAL").is_err());
+        assert!(parse_consistency("").is_err());
+
+        assert_eq!(
+            parse_serial_consistency("SERIAL").unwrap(),
+            SerialConsistency::Serial
+        );
+        assert_eq!(
+            parse_serial_consistency("local_serial").unwrap(),
+            SerialConsistency::LocalSerial
+        );
+        assert!(parse_serial_consistency("QUORUM").is_err());
+    }
+
+    #[test]
+    fn retry_policy_backoff_works() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            backoff_ms: 100,
+        };
+        for retry in 0..3 {
+            let backoff = policy.backoff(retry).as_millis() as u64;
+            let max = 100 << retry;
+            assert!(backoff >= max / 2 && backoff <= max);
+        }
+    }
+
     #[tokio::test(flavor = "current_thread")]
     async fn exec_cqls_works() {
         let db = get_db().await;
@@ -164,4 +858,11 @@ mod tests {
         let schema = std::include_str!("../../cql/schema_table.cql");
         exec_cqls(db, schema).await.unwrap();
     }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn ping_works() {
+        let db = get_db().await;
+        db.ping().await.unwrap();
+    }
 }
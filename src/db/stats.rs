@@ -0,0 +1,181 @@
+use scylla_orm::{ColumnsMap, ToCqlVal};
+
+use crate::db::{scylladb::CqlExecutor, TaskStatus};
+
+// task outcome stats by group and day, kept in the task_stats table. A task is counted
+// once when it is first finalized from processing, by the day it is finalized on, see
+// `Task::record_stats`. counter updates are not idempotent and a retried write may count
+// twice, the late acks flipping a finalized task and the reverted or reopened tasks are
+// not recounted, so the stats are approximate.
+
+pub const DAY_MS: i64 = 24 * 3600 * 1000;
+
+// the day bucket of the unix timestamp in milliseconds, days since the epoch.
+pub fn day(ms: i64) -> i32 {
+    ms.div_euclid(DAY_MS) as i32
+}
+
+// the statuses counted in the stats.
+pub fn is_outcome(status: TaskStatus) -> bool {
+    matches!(
+        status,
+        TaskStatus::Resolved | TaskStatus::Rejected | TaskStatus::Expired
+    )
+}
+
+// counts the task finalized with the status at finalized_at. The resolution time from
+// created_at is summed for the resolved and rejected tasks, expired tasks have none.
+pub async fn record(
+    db: &impl CqlExecutor,
+    gid: xid::Id,
+    status: TaskStatus,
+    created_at: i64,
+    finalized_at: i64,
+) -> anyhow::Result<()> {
+    let column = match status {
+        TaskStatus::Resolved => "resolved",
+        TaskStatus::Rejected => "rejected",
+        TaskStatus::Expired => "expired",
+        _ => return Ok(()),
+    };
+
+    let day = day(finalized_at);
+    if status == TaskStatus::Expired {
+        let query = "UPDATE task_stats SET expired=expired+1 WHERE gid=? AND day=?";
+        let params = (gid.to_cql(), day);
+        let _ = db.execute(query, params).await?;
+        return Ok(());
+    }
+
+    let query = format!(
+        "UPDATE task_stats SET {}={}+1,resolution_ms=resolution_ms+? WHERE gid=? AND day=?",
+        column, column
+    );
+    let params = ((finalized_at - created_at).max(0), gid.to_cql(), day);
+    let _ = db.execute(query, params).await?;
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DayStats {
+    pub day: i32,
+    pub resolved: i64,
+    pub rejected: i64,
+    pub expired: i64,
+    pub resolution_ms: i64, // the sum of the resolution times of the resolved and rejected tasks
+}
+
+impl DayStats {
+    pub fn add(&mut self, other: &DayStats) {
+        self.resolved += other.resolved;
+        self.rejected += other.rejected;
+        self.expired += other.expired;
+        self.resolution_ms += other.resolution_ms;
+    }
+}
+
+// the day buckets of the group in [from_day, to_day], days without a finalized task are absent.
+pub async fn list(
+    db: &impl CqlExecutor,
+    gid: xid::Id,
+    from_day: i32,
+    to_day: i32,
+) -> anyhow::Result<Vec<DayStats>> {
+    let fields = vec![
+        "day".to_string(),
+        "resolved".to_string(),
+        "rejected".to_string(),
+        "expired".to_string(),
+        "resolution_ms".to_string(),
+    ];
+    let query = format!(
+        "SELECT {} FROM task_stats WHERE gid=? AND day>=? AND day<=? USING TIMEOUT 3s",
+        fields.join(",")
+    );
+    let params = (gid.to_cql(), from_day, to_day);
+    let rows = db.execute_iter(query, params).await?;
+
+    let mut res: Vec<DayStats> = Vec::with_capacity(rows.len());
+    for row in rows {
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(row, &fields)?;
+        let counter = |name: &str| {
+            cols.get(name)
+                .and_then(|v| v.as_counter())
+                .map(|c| c.0)
+                .unwrap_or(0)
+        };
+        res.push(DayStats {
+            day: cols.get_as("day")?,
+            resolved: counter("resolved"),
+            rejected: counter("rejected"),
+            expired: counter("expired"),
+            resolution_ms: counter("resolution_ms"),
+        });
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fake::FakeDB;
+
+    #[test]
+    fn day_works() {
+        assert_eq!(day(0), 0);
+        assert_eq!(day(DAY_MS - 1), 0);
+        assert_eq!(day(DAY_MS), 1);
+        assert_eq!(day(-1), -1);
+        assert!(is_outcome(TaskStatus::Expired));
+        assert!(!is_outcome(TaskStatus::Cancelled));
+        assert!(!is_outcome(TaskStatus::Pending));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn record_with_fake_works() {
+        let db = FakeDB::new();
+        let gid = xid::new();
+        record(&db, gid, TaskStatus::Resolved, 1000, DAY_MS + 3000)
+            .await
+            .unwrap();
+        record(&db, gid, TaskStatus::Expired, 1000, DAY_MS + 3000)
+            .await
+            .unwrap();
+        record(&db, gid, TaskStatus::Cancelled, 1000, DAY_MS + 3000)
+            .await
+            .unwrap();
+        assert_eq!(
+            db.queries("UPDATE task_stats"),
+            vec![
+                "UPDATE task_stats SET resolved=resolved+1,resolution_ms=resolution_ms+? WHERE gid=? AND day=?",
+                "UPDATE task_stats SET expired=expired+1 WHERE gid=? AND day=?",
+            ]
+        );
+
+        let mut total = DayStats {
+            day: 1,
+            resolved: 2,
+            rejected: 1,
+            expired: 0,
+            resolution_ms: 3000,
+        };
+        total.add(&DayStats {
+            day: 2,
+            resolved: 1,
+            rejected: 0,
+            expired: 4,
+            resolution_ms: 1000,
+        });
+        assert_eq!(
+            total,
+            DayStats {
+                day: 1,
+                resolved: 3,
+                rejected: 1,
+                expired: 4,
+                resolution_ms: 4000,
+            }
+        );
+    }
+}
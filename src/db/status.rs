@@ -0,0 +1,180 @@
+use axum_web::erring::HTTPError;
+use scylla_orm::{CqlValue, FromCqlVal, FromCqlValError, ToCqlVal};
+use serde::{Deserialize, Serialize};
+use std::{convert::TryFrom, fmt};
+
+// the status of a task, stored and serialized as the i8 code.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "i8", into = "i8")]
+pub enum TaskStatus {
+    #[default]
+    Pending, // 0, processing
+    Resolved,  // 1
+    Rejected,  // -1
+    Cancelled, // -2
+    Expired,   // -3
+    Trashed,   // -4
+}
+
+impl TaskStatus {
+    // the finalized statuses, votes no longer change them.
+    pub fn is_final(&self) -> bool {
+        matches!(self, Self::Resolved | Self::Rejected)
+    }
+}
+
+impl TryFrom<i8> for TaskStatus {
+    type Error = HTTPError;
+
+    fn try_from(val: i8) -> Result<Self, Self::Error> {
+        match val {
+            0 => Ok(Self::Pending),
+            1 => Ok(Self::Resolved),
+            -1 => Ok(Self::Rejected),
+            -2 => Ok(Self::Cancelled),
+            -3 => Ok(Self::Expired),
+            -4 => Ok(Self::Trashed),
+            _ => Err(HTTPError::new(
+                400,
+                format!("invalid task status, expected -4 to 1, got {}", val),
+            )),
+        }
+    }
+}
+
+impl From<TaskStatus> for i8 {
+    fn from(val: TaskStatus) -> Self {
+        match val {
+            TaskStatus::Pending => 0,
+            TaskStatus::Resolved => 1,
+            TaskStatus::Rejected => -1,
+            TaskStatus::Cancelled => -2,
+            TaskStatus::Expired => -3,
+            TaskStatus::Trashed => -4,
+        }
+    }
+}
+
+impl fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", i8::from(*self))
+    }
+}
+
+impl FromCqlVal for TaskStatus {
+    fn from_cql(cql_val: &CqlValue) -> Result<Self, FromCqlValError> {
+        let val: i8 = FromCqlVal::from_cql(cql_val)?;
+        Self::try_from(val).map_err(|_| FromCqlValError::BadVal)
+    }
+}
+
+impl ToCqlVal for TaskStatus {
+    fn to_cql(&self) -> CqlValue {
+        CqlValue::TinyInt(i8::from(*self))
+    }
+}
+
+// the status of a notification, the ack of the receiver.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "i8", into = "i8")]
+pub enum NotificationStatus {
+    #[default]
+    Pending, // 0, not acked
+    Resolved, // 1
+    Rejected, // -1
+}
+
+impl TryFrom<i8> for NotificationStatus {
+    type Error = HTTPError;
+
+    fn try_from(val: i8) -> Result<Self, Self::Error> {
+        match val {
+            0 => Ok(Self::Pending),
+            1 => Ok(Self::Resolved),
+            -1 => Ok(Self::Rejected),
+            _ => Err(HTTPError::new(
+                400,
+                format!("invalid notification status, expected -1 to 1, got {}", val),
+            )),
+        }
+    }
+}
+
+impl From<NotificationStatus> for i8 {
+    fn from(val: NotificationStatus) -> Self {
+        match val {
+            NotificationStatus::Pending => 0,
+            NotificationStatus::Resolved => 1,
+            NotificationStatus::Rejected => -1,
+        }
+    }
+}
+
+impl fmt::Display for NotificationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", i8::from(*self))
+    }
+}
+
+impl FromCqlVal for NotificationStatus {
+    fn from_cql(cql_val: &CqlValue) -> Result<Self, FromCqlValError> {
+        let val: i8 = FromCqlVal::from_cql(cql_val)?;
+        Self::try_from(val).map_err(|_| FromCqlValError::BadVal)
+    }
+}
+
+impl ToCqlVal for NotificationStatus {
+    fn to_cql(&self) -> CqlValue {
+        CqlValue::TinyInt(i8::from(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn task_status_works() {
+        for code in -4i8..=1 {
+            let status = TaskStatus::try_from(code).unwrap();
+            assert_eq!(i8::from(status), code);
+            assert_eq!(TaskStatus::from_cql(&status.to_cql()).unwrap(), status);
+            assert_eq!(status.to_string(), code.to_string());
+        }
+        assert_eq!(TaskStatus::default(), TaskStatus::Pending);
+        assert_eq!(TaskStatus::try_from(2).unwrap_err().code, 400);
+        assert_eq!(TaskStatus::try_from(-5).unwrap_err().code, 400);
+        assert!(TaskStatus::from_cql(&CqlValue::TinyInt(2)).is_err());
+
+        assert!(TaskStatus::Resolved.is_final());
+        assert!(TaskStatus::Rejected.is_final());
+        assert!(!TaskStatus::Expired.is_final());
+    }
+
+    #[test]
+    fn notification_status_works() {
+        for code in -1i8..=1 {
+            let status = NotificationStatus::try_from(code).unwrap();
+            assert_eq!(i8::from(status), code);
+            assert_eq!(
+                NotificationStatus::from_cql(&status.to_cql()).unwrap(),
+                status
+            );
+        }
+        assert_eq!(NotificationStatus::try_from(-2).unwrap_err().code, 400);
+        assert!(NotificationStatus::from_cql(&CqlValue::TinyInt(-4)).is_err());
+    }
+
+    #[test]
+    fn status_serde_works() {
+        let data = serde_json::to_string(&TaskStatus::Expired).unwrap();
+        assert_eq!(data, "-3");
+        let status: TaskStatus = serde_json::from_str("-1").unwrap();
+        assert_eq!(status, TaskStatus::Rejected);
+        assert!(serde_json::from_str::<TaskStatus>("2").is_err());
+
+        let data = serde_json::to_string(&NotificationStatus::Resolved).unwrap();
+        assert_eq!(data, "1");
+        assert!(serde_json::from_str::<NotificationStatus>("-3").is_err());
+    }
+}
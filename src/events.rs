@@ -0,0 +1,243 @@
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{mpsc, Semaphore};
+
+use axum_web::context::unix_ms;
+
+use crate::conf;
+use crate::db;
+
+// the header carries the hex encoded HMAC-SHA256 of the body, signed with the webhook secret.
+pub const SIGNATURE_HEADER: &str = "x-taskbase-signature";
+
+// events are dropped when the queue is full, so that deliveries never block the API.
+const QUEUE_SIZE: usize = 1024;
+const DELIVERY_CONCURRENCY: usize = 16;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const BACKOFF_BASE_MS: u64 = 500;
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct TaskEvent {
+    pub id: String,
+    pub uid: String,
+    pub gid: String,
+    pub kind: String,
+    pub status: db::TaskStatus,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub duedate: i64,
+    pub occurred_at: i64,
+}
+
+impl TaskEvent {
+    pub fn from(task: &db::Task) -> Self {
+        Self {
+            id: task.id.to_string(),
+            uid: task.uid.to_string(),
+            gid: task.gid.to_string(),
+            kind: task.kind.clone(),
+            status: task.status,
+            created_at: task.created_at,
+            updated_at: task.updated_at,
+            duedate: task.duedate,
+            occurred_at: unix_ms() as i64,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Webhooks {
+    tx: Option<mpsc::Sender<TaskEvent>>,
+}
+
+impl Webhooks {
+    // spawns the delivery loop, it should be called within a tokio runtime.
+    pub fn new(hooks: Vec<conf::Webhook>) -> Self {
+        if hooks.is_empty() {
+            return Self::default();
+        }
+
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(dispatch(hooks, rx));
+        Self { tx: Some(tx) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    // enqueues the event of a task that reached a terminal status (resolved, rejected or expired).
+    pub fn task_done(&self, task: &db::Task) {
+        if let Some(tx) = &self.tx {
+            if let Err(err) = tx.try_send(TaskEvent::from(task)) {
+                log::warn!(target: "webhook", "drop event of task {}: {}", task.id, err);
+            }
+        }
+    }
+}
+
+async fn dispatch(hooks: Vec<conf::Webhook>, mut rx: mpsc::Receiver<TaskEvent>) {
+    let client = reqwest::Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .build()
+        .expect("failed to build webhook client");
+    let hooks: Vec<Arc<conf::Webhook>> = hooks.into_iter().map(Arc::new).collect();
+    let limit = Arc::new(Semaphore::new(DELIVERY_CONCURRENCY));
+
+    while let Some(event) = rx.recv().await {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => Arc::new(body),
+            Err(err) => {
+                log::error!(target: "webhook", "encode event of task {} failed: {}", event.id, err);
+                continue;
+            }
+        };
+
+        for hook in hooks.iter().filter(|hook| hook.matches(&event.kind)) {
+            let permit = match limit.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => return,
+            };
+            let client = client.clone();
+            let hook = hook.clone();
+            let body = body.clone();
+            let id = event.id.clone();
+            tokio::spawn(async move {
+                if let Err(err) = deliver(&client, &hook, &body).await {
+                    log::error!(target: "webhook", "deliver event of task {} to {} failed: {}", id, hook.url, err);
+                }
+                drop(permit);
+            });
+        }
+    }
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// POSTs the signed body to the webhook, failed deliveries are retried with exponential backoff.
+pub async fn deliver(
+    client: &reqwest::Client,
+    hook: &conf::Webhook,
+    body: &[u8],
+) -> anyhow::Result<()> {
+    let signature = sign(&hook.secret, body);
+    let mut attempt: u32 = 0;
+    loop {
+        let res = client
+            .post(&hook.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .body(body.to_vec())
+            .send()
+            .await;
+        let err = match res {
+            Ok(res) if res.status().is_success() => return Ok(()),
+            Ok(res) => anyhow::anyhow!("unexpected status {}", res.status()),
+            Err(err) => err.into(),
+        };
+        if attempt >= hook.max_retries {
+            return Err(err);
+        }
+
+        let backoff = BACKOFF_BASE_MS << attempt.min(10);
+        log::warn!(target: "webhook", "deliver to {} failed, retry in {}ms: {}", hook.url, backoff, err);
+        tokio::time::sleep(Duration::from_millis(backoff)).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server,
+    };
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    #[test]
+    fn webhook_matches_works() {
+        let mut hook = conf::Webhook {
+            url: "http://127.0.0.1/hook".to_string(),
+            secret: "secret".to_string(),
+            kinds: vec![],
+            max_retries: 0,
+        };
+        assert!(hook.matches("approval"));
+
+        hook.kinds = vec!["approval".to_string()];
+        assert!(hook.matches("approval"));
+        assert!(!hook.matches("review"));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn deliver_works() {
+        let (tx, mut rx) = mpsc::channel::<(String, Vec<u8>)>(4);
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let make_svc = make_service_fn(move |_| {
+            let tx = tx.clone();
+            let attempts = attempts.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let tx = tx.clone();
+                    let attempts = attempts.clone();
+                    async move {
+                        let signature = req
+                            .headers()
+                            .get(SIGNATURE_HEADER)
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string();
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        // the first attempt fails to exercise the retry.
+                        if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                            let res = Response::builder().status(500).body(Body::empty());
+                            return Ok::<_, Infallible>(res.unwrap());
+                        }
+                        tx.send((signature, body.to_vec())).await.unwrap();
+                        Ok::<_, Infallible>(Response::new(Body::empty()))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let hook = conf::Webhook {
+            url: format!("http://{}/hook", addr),
+            secret: "secret".to_string(),
+            kinds: vec![],
+            max_retries: 2,
+        };
+        let mut task = db::Task::with_pk(xid::new(), xid::new());
+        task.kind = "approval".to_string();
+        task.status = db::TaskStatus::Resolved;
+        let body = serde_json::to_vec(&TaskEvent::from(&task)).unwrap();
+
+        let client = reqwest::Client::new();
+        deliver(&client, &hook, &body).await.unwrap();
+
+        let (signature, got) = rx.recv().await.unwrap();
+        assert_eq!(got, body);
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(&got);
+        mac.verify_slice(&hex::decode(signature).unwrap()).unwrap();
+
+        let event: TaskEvent = serde_json::from_slice(&got).unwrap();
+        assert_eq!(event.id, task.id.to_string());
+        assert_eq!(event.status, db::TaskStatus::Resolved);
+    }
+}
@@ -0,0 +1,12 @@
+pub mod api;
+pub mod auth;
+pub mod blobs;
+pub mod cli;
+pub mod conf;
+pub mod crypto;
+pub mod db;
+pub mod events;
+pub mod metrics;
+pub mod observability;
+pub mod router;
+pub mod worker;
@@ -1,17 +1,15 @@
-use std::{net::SocketAddr, sync::Arc};
+use clap::Parser;
+use std::{net::SocketAddr, time::Duration};
 
 use structured_logger::{async_json::new_writer, Builder};
-use tokio::{
-    io, signal,
-};
+use tokio::{io, signal, time};
+use tokio_util::sync::CancellationToken;
 
-mod api;
-mod conf;
-mod db;
-mod router;
+use taskbase::{api, cli, conf, observability, router, worker};
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> anyhow::Result<()> {
+    let args = cli::Cli::parse();
     let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
 
     Builder::with_level(cfg.log.level.as_str())
@@ -20,9 +18,37 @@ async fn main() -> anyhow::Result<()> {
 
     log::debug!("{:?}", cfg);
 
+    match args.command {
+        None | Some(cli::Command::Serve) => serve(cfg).await,
+        Some(cmd) => cli::run(cmd, cfg).await,
+    }
+}
+
+async fn serve(cfg: conf::Conf) -> anyhow::Result<()> {
+    if observability::init(&cfg.observability)? {
+        log::info!("export traces to {}", cfg.observability.endpoint);
+    }
+
     let server_cfg = cfg.server.clone();
     let server_env = cfg.env.clone();
+    let metrics_port = cfg.metrics.port;
+    let worker_cfg = cfg.worker.clone();
     let (app_state, app) = router::new(cfg).await?;
+    let worker = worker::spawn(app_state.clone(), worker_cfg);
+
+    if metrics_port != 0 {
+        let addr = SocketAddr::from(([0, 0, 0, 0], metrics_port));
+        let metrics = router::metrics(app_state.clone());
+        log::info!("serve metrics at {}", &addr);
+        tokio::spawn(async move {
+            if let Err(err) = axum::Server::bind(&addr)
+                .serve(metrics.into_make_service())
+                .await
+            {
+                log::error!("metrics server error: {}", err);
+            }
+        });
+    }
 
     let addr = SocketAddr::from(([0, 0, 0, 0], server_cfg.port));
     log::info!(
@@ -32,15 +58,48 @@ async fn main() -> anyhow::Result<()> {
         server_env,
         &addr
     );
-    axum::Server::bind(&addr)
+    let shutdown = app_state.shutdown.clone();
+    tokio::spawn(shutdown_signal(shutdown.clone()));
+
+    let server = axum::Server::bind(&addr)
         .serve(app.into_make_service())
-        .with_graceful_shutdown(shutdown_signal(app_state, server_cfg.graceful_shutdown))
-        .await?;
+        .with_graceful_shutdown({
+            let shutdown = shutdown.clone();
+            async move { shutdown.cancelled().await }
+        });
+    tokio::pin!(server);
+    tokio::select! {
+        res = &mut server => res?,
+        _ = shutdown.cancelled() => {}
+    }
+
+    // the server stops accepting connections, the requests in flight are drained
+    // within the drain window, and the rest are aborted.
+    let drain = Duration::from_secs(server_cfg.graceful_shutdown as u64);
+    let in_flight = app_state.metrics.in_flight();
+    let aborted = match time::timeout(drain, &mut server).await {
+        Ok(res) => {
+            res?;
+            0
+        }
+        Err(_) => app_state.metrics.in_flight(),
+    };
+    log::info!(
+        "{} in-flight requests drained, {} aborted",
+        (in_flight - aborted).max(0),
+        aborted
+    );
+
+    if time::timeout(drain, worker).await.is_err() {
+        log::warn!("worker did not stop in {}s", drain.as_secs());
+    }
+    observability::shutdown();
 
     Ok(())
 }
 
-async fn shutdown_signal(_app: Arc<api::AppState>, _wait_secs: usize) {
+// cancels the shutdown token on SIGTERM or SIGINT.
+async fn shutdown_signal(shutdown: CancellationToken) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -64,4 +123,5 @@ async fn shutdown_signal(_app: Arc<api::AppState>, _wait_secs: usize) {
     }
 
     log::info!("signal received, Goodbye!");
+    shutdown.cancel();
 }
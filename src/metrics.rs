@@ -0,0 +1,244 @@
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::{sync::Arc, time::Instant};
+
+use crate::conf;
+
+// the recorder of the service metrics, rendered in the Prometheus text format.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    http_in_flight: IntGauge,
+    cql_duration: HistogramVec,
+    cql_errors: IntCounterVec,
+    task_cache_hits: IntCounter,
+    task_cache_misses: IntCounter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new(&conf::Metrics::default()).expect("default metrics should be valid")
+    }
+}
+
+impl Metrics {
+    pub fn new(cfg: &conf::Metrics) -> anyhow::Result<Self> {
+        let registry = Registry::new_custom(Some(cfg.namespace.clone()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "HTTP requests by route, method and status",
+            ),
+            &["route", "method", "status"],
+        )?;
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request durations by route and method",
+            ),
+            &["route", "method"],
+        )?;
+        let http_in_flight = IntGauge::new("http_requests_in_flight", "HTTP requests in flight")?;
+        let cql_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "cql_statement_duration_seconds",
+                "CQL statement durations by statement",
+            ),
+            &["statement"],
+        )?;
+        let cql_errors = IntCounterVec::new(
+            Opts::new("cql_errors_total", "CQL statement errors by statement"),
+            &["statement"],
+        )?;
+
+        let task_cache_hits =
+            IntCounter::new("task_cache_hits_total", "Task reads served by the cache")?;
+        let task_cache_misses =
+            IntCounter::new("task_cache_misses_total", "Task reads missing the cache")?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_duration.clone()))?;
+        registry.register(Box::new(http_in_flight.clone()))?;
+        registry.register(Box::new(cql_duration.clone()))?;
+        registry.register(Box::new(cql_errors.clone()))?;
+        registry.register(Box::new(task_cache_hits.clone()))?;
+        registry.register(Box::new(task_cache_misses.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_duration,
+            http_in_flight,
+            cql_duration,
+            cql_errors,
+            task_cache_hits,
+            task_cache_misses,
+        })
+    }
+
+    pub fn observe_cql(&self, statement: &str, start: Instant, ok: bool) {
+        self.cql_duration
+            .with_label_values(&[statement])
+            .observe(start.elapsed().as_secs_f64());
+        if !ok {
+            self.cql_errors.with_label_values(&[statement]).inc();
+        }
+    }
+
+    pub fn observe_task_cache(&self, hit: bool) {
+        if hit {
+            self.task_cache_hits.inc();
+        } else {
+            self.task_cache_misses.inc();
+        }
+    }
+
+    // the number of requests in flight.
+    pub fn in_flight(&self) -> i64 {
+        self.http_in_flight.get()
+    }
+
+    pub fn render(&self) -> String {
+        let mut buf: Vec<u8> = Vec::new();
+        let encoder = TextEncoder::new();
+        if let Err(err) = encoder.encode(&self.registry.gather(), &mut buf) {
+            log::error!(target: "metrics", "encode metrics failed: {}", err);
+        }
+        String::from_utf8(buf).unwrap_or_default()
+    }
+}
+
+// a short tag of the CQL statement for the labels, the verb and the table, e.g. "select_task".
+pub fn statement_tag(cql: &str) -> String {
+    let mut words = cql.split_whitespace();
+    let verb = words.next().unwrap_or_default().to_ascii_lowercase();
+    let table = match verb.as_str() {
+        "select" | "delete" => words.skip_while(|w| !w.eq_ignore_ascii_case("from")).nth(1),
+        "insert" => words.skip_while(|w| !w.eq_ignore_ascii_case("into")).nth(1),
+        "update" => words.next(),
+        _ => None,
+    };
+    match table.and_then(|t| t.split(['(', ';']).next()) {
+        Some(table) if !table.is_empty() => format!("{}_{}", verb, table.to_ascii_lowercase()),
+        _ => verb,
+    }
+}
+
+// records the request counts, durations and the requests in flight by the matched route.
+pub async fn middleware<B>(
+    State(metrics): State<Arc<Metrics>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let method = req.method().to_string();
+
+    let start = Instant::now();
+    metrics.http_in_flight.inc();
+    let res = next.run(req).await;
+    metrics.http_in_flight.dec();
+
+    let status = res.status().as_u16().to_string();
+    metrics
+        .http_requests
+        .with_label_values(&[&route, &method, &status])
+        .inc();
+    metrics
+        .http_duration
+        .with_label_values(&[&route, &method])
+        .observe(start.elapsed().as_secs_f64());
+    res
+}
+
+pub fn response(metrics: &Metrics) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statement_tag_works() {
+        assert_eq!(
+            statement_tag("SELECT uid,id FROM task WHERE uid=? AND id=? LIMIT 1"),
+            "select_task"
+        );
+        assert_eq!(
+            statement_tag("INSERT INTO task_by_gid (gid,id) VALUES (?,?)"),
+            "insert_task_by_gid"
+        );
+        assert_eq!(
+            statement_tag("INSERT INTO meta(name,version) VALUES (?,?)"),
+            "insert_meta"
+        );
+        assert_eq!(
+            statement_tag("UPDATE task SET status=? WHERE uid=? AND id=? IF status=?"),
+            "update_task"
+        );
+        assert_eq!(
+            statement_tag("DELETE FROM notification WHERE uid=? AND tid=?"),
+            "delete_notification"
+        );
+        assert_eq!(
+            statement_tag("select now() from system.local"),
+            "select_system.local"
+        );
+        assert_eq!(statement_tag("BATCH"), "batch");
+        assert_eq!(statement_tag(""), "");
+    }
+
+    #[test]
+    fn metrics_works() {
+        let metrics = Metrics::default();
+        metrics.observe_cql("select_task", Instant::now(), true);
+        metrics.observe_cql("select_task", Instant::now(), false);
+        metrics
+            .http_requests
+            .with_label_values(&["/v1/task", "GET", "200"])
+            .inc();
+        metrics.observe_task_cache(true);
+        metrics.observe_task_cache(false);
+        metrics.observe_task_cache(false);
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "taskbase_cql_statement_duration_seconds_count{statement=\"select_task\"} 2"
+        ));
+        assert!(text.contains("taskbase_cql_errors_total{statement=\"select_task\"} 1"));
+        assert!(text.contains(
+            "taskbase_http_requests_total{method=\"GET\",route=\"/v1/task\",status=\"200\"} 1"
+        ));
+        assert!(text.contains("taskbase_http_requests_in_flight 0"));
+        assert!(text.contains("taskbase_task_cache_hits_total 1"));
+        assert!(text.contains("taskbase_task_cache_misses_total 2"));
+
+        let cfg = conf::Metrics {
+            namespace: "custom".to_string(),
+            port: 0,
+        };
+        let metrics = Metrics::new(&cfg).unwrap();
+        metrics.observe_cql("select_task", Instant::now(), true);
+        assert!(metrics
+            .render()
+            .contains("custom_cql_statement_duration_seconds"));
+    }
+}
@@ -0,0 +1,103 @@
+use axum::{extract::MatchedPath, http::Request, middleware::Next, response::Response};
+use opentelemetry::{
+    sdk::{trace, Resource},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use std::sync::Arc;
+use tracing::{field, Instrument};
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+use axum_web::context::ReqContext;
+
+use crate::api;
+use crate::conf;
+
+// installs the OTLP exporter of the tracing spans, returns false if it is not configured.
+// the spans are no-op without the exporter.
+pub fn init(cfg: &conf::Observability) -> anyhow::Result<bool> {
+    if !(0.0..=1.0).contains(&cfg.sample_ratio) {
+        return Err(anyhow::anyhow!(
+            "invalid observability sample_ratio, expected 0 to 1, got {}",
+            cfg.sample_ratio
+        ));
+    }
+    if cfg.endpoint.is_empty() {
+        return Ok(false);
+    }
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&cfg.endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_sampler(trace::Sampler::ParentBased(Box::new(
+                    trace::Sampler::TraceIdRatioBased(cfg.sample_ratio),
+                )))
+                .with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", api::APP_NAME),
+                    KeyValue::new("service.version", api::APP_VERSION),
+                ])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)?;
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+    Ok(true)
+}
+
+// flushes the spans not exported yet.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+// creates the root span of the request, it should be layered after context::middleware
+// so that the span carries the request id.
+pub async fn middleware<B>(req: Request<B>, next: Next<B>) -> Response {
+    let rid = req
+        .extensions()
+        .get::<Arc<ReqContext>>()
+        .map(|ctx| ctx.rid.clone())
+        .unwrap_or_default();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| req.uri().path().to_string());
+    let span = tracing::info_span!(
+        "request",
+        rid = %rid,
+        method = %req.method(),
+        route = %route,
+        status = field::Empty,
+    );
+
+    let res = next.run(req).instrument(span.clone()).await;
+    span.record("status", res.status().as_u16());
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_works() {
+        let cfg = conf::Observability::default();
+        assert!(!init(&cfg).unwrap());
+
+        for sample_ratio in [-0.1, 1.1, f64::NAN] {
+            let cfg = conf::Observability {
+                endpoint: "http://127.0.0.1:4317".to_string(),
+                sample_ratio,
+            };
+            assert!(init(&cfg).is_err());
+        }
+    }
+}
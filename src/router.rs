@@ -1,62 +1,550 @@
-use axum::{middleware, routing, Router};
-use std::sync::Arc;
+use axum::{
+    extract::{DefaultBodyLimit, State},
+    http::{HeaderName, HeaderValue, Method, Request},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing, Router,
+};
+use std::{
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
 use tower::ServiceBuilder;
 use tower_http::{
     catch_panic::CatchPanicLayer,
     compression::{predicate::SizeAbove, CompressionLayer},
+    cors::{Any, CorsLayer},
+    limit::RequestBodyLimitLayer,
 };
 
 use axum_web::context;
 use axum_web::encoding;
+use axum_web::erring::HTTPError;
 
 use crate::api;
+use crate::auth;
+use crate::blobs;
 use crate::conf;
+use crate::crypto;
 use crate::db;
+use crate::events;
+use crate::metrics;
+use crate::observability;
 
 pub async fn new(cfg: conf::Conf) -> anyhow::Result<(Arc<api::AppState>, Router)> {
+    let metrics_port = cfg.metrics.port;
+    let limits = cfg.limits.clone();
+    let cors = cfg.cors.as_ref().map(cors).transpose()?;
+    let auth = Arc::new(auth::Auth::new(&cfg.auth));
     let app_state = Arc::new(new_app_state(cfg).await?);
 
     let mds = ServiceBuilder::new()
+        .layer(middleware::from_fn_with_state(
+            app_state.metrics.clone(),
+            metrics::middleware,
+        ))
         .layer(CatchPanicLayer::new())
+        .layer(middleware::from_fn_with_state(auth, auth::middleware))
         .layer(middleware::from_fn(context::middleware))
-        .layer(CompressionLayer::new().compress_when(SizeAbove::new(encoding::MIN_ENCODING_SIZE)));
+        .layer(middleware::from_fn(observability::middleware))
+        .layer(CompressionLayer::new().compress_when(SizeAbove::new(encoding::MIN_ENCODING_SIZE)))
+        .layer(DefaultBodyLimit::max(limits.max_body_bytes));
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", routing::get(api::version))
         .route("/healthz", routing::get(api::healthz))
-        .nest(
-            "/v1/task",
-            Router::new()
-                .route("/", routing::post(api::task::create).get(api::task::get))
-                .route("/ack", routing::patch(api::task::ack))
-                .route("/list", routing::post(api::task::list))
-                .route("/delete", routing::post(api::task::delete)),
-            // .route("/batch_delete", routing::post(api::task::batch_delete)),
-        )
-        .nest(
-            "/v1/notification",
-            Router::new()
-                .route("/list", routing::post(api::notification::list))
-                .route("/delete", routing::post(api::notification::delete))
-                .route(
-                    "/batch_delete",
-                    routing::post(api::notification::batch_delete),
-                ),
-        )
-        .route_layer(mds)
-        .with_state(app_state.clone());
-
-    Ok((app_state, app))
+        .route("/readyz", routing::get(api::readyz))
+        .route("/internal/maintenance", routing::post(api::set_maintenance));
+    for ver in api::ApiVersion::ALL {
+        app = app
+            .nest(
+                &format!("/{}/task", ver.as_str()),
+                task_routes(ver, &limits, &app_state),
+            )
+            .nest(
+                &format!("/{}/notification", ver.as_str()),
+                notification_routes(ver, &limits, &app_state),
+            );
+    }
+    let mut app = app.route_layer(mds);
+    if metrics_port == 0 {
+        app = app.route("/metrics", routing::get(api::metrics));
+    }
+
+    // the preflight requests are answered before the routes, so they skip the auth.
+    let app = app.with_state(app_state.clone());
+    match cors {
+        Some(cors) => Ok((app_state, app.layer(cors))),
+        None => Ok((app_state, app)),
+    }
 }
 
-async fn new_app_state(cfg: conf::Conf) -> anyhow::Result<api::AppState> {
-    let keyspace = if cfg.env == "test" {
-        "taskbase_test"
+pub fn cors(cfg: &conf::Cors) -> anyhow::Result<CorsLayer> {
+    let wildcard = |vals: &[String]| vals.iter().any(|v| v == "*");
+    let mut layer = CorsLayer::new()
+        .allow_credentials(cfg.allow_credentials)
+        .max_age(Duration::from_secs(cfg.max_age));
+
+    layer = if wildcard(&cfg.allowed_origins) {
+        layer.allow_origin(Any)
+    } else {
+        layer.allow_origin(
+            cfg.allowed_origins
+                .iter()
+                .map(|v| v.parse::<HeaderValue>())
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+    };
+    layer = if wildcard(&cfg.allowed_methods) {
+        layer.allow_methods(Any)
+    } else {
+        layer.allow_methods(
+            cfg.allowed_methods
+                .iter()
+                .map(|v| v.parse::<Method>())
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+    };
+    layer = if wildcard(&cfg.allowed_headers) {
+        layer.allow_headers(Any)
     } else {
-        "taskbase"
+        layer.allow_headers(
+            cfg.allowed_headers
+                .iter()
+                .map(|v| v.parse::<HeaderName>())
+                .collect::<Result<Vec<_>, _>>()?,
+        )
     };
-    let scylla = db::scylladb::ScyllaDB::new(cfg.scylla, keyspace).await?;
+    layer = if wildcard(&cfg.expose_headers) {
+        layer.expose_headers(Any)
+    } else {
+        layer.expose_headers(
+            cfg.expose_headers
+                .iter()
+                .map(|v| v.parse::<HeaderName>())
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+    };
+    Ok(layer)
+}
+
+// the routes of /{version}/task, grouped by their timeouts, the writes are rejected in maintenance.
+fn task_routes(
+    ver: api::ApiVersion,
+    limits: &conf::Limits,
+    app_state: &Arc<api::AppState>,
+) -> Router<Arc<api::AppState>> {
+    let read_timeout = Duration::from_secs(limits.read_timeout);
+    let write_timeout = Duration::from_secs(limits.write_timeout);
+    let stream_timeout = Duration::from_secs(limits.stream_timeout);
+
+    let task_reads = Router::new()
+        .route("/", routing::get(api::task::get))
+        .route("/kinds", routing::get(api::task::kinds))
+        .route("/batch_get", routing::post(api::task::batch_get))
+        .route("/list", routing::post(api::task::list))
+        .route("/list_by_group", routing::post(api::task::list_by_group))
+        .route("/list_assigned", routing::post(api::task::list_assigned))
+        .route("/list_overdue", routing::post(api::task::list_overdue))
+        .route("/list_archived", routing::post(api::task::list_archived))
+        .route(
+            "/list_by_priority",
+            routing::post(api::task::list_by_priority),
+        )
+        .route("/count", routing::get(api::task::count))
+        .route("/stats", routing::get(api::task::stats))
+        .route("/calendar_token", routing::get(api::calendar::token))
+        .route("/acks", routing::get(api::task::acks))
+        .route("/log", routing::get(api::task::get_log))
+        .route("/comment/list", routing::post(api::comment::list))
+        .route("/recurrence/list", routing::post(api::recurrence::list))
+        .route("/template", routing::get(api::template::get))
+        .route("/template/list", routing::post(api::template::list))
+        .route_layer(middleware::from_fn_with_state(read_timeout, timeout));
+
+    let task_writes = Router::new()
+        .route(
+            "/",
+            routing::post(api::task::create).patch(api::task::update),
+        )
+        .route(
+            "/batch_create",
+            routing::post(api::task::batch_create).layer(DefaultBodyLimit::max(
+                limits
+                    .max_body_bytes
+                    .max(api::task::MAX_BATCH_CREATE_SIZE * 2),
+            )),
+        )
+        .route("/ack", routing::patch(api::task::ack))
+        .route("/batch_ack", routing::post(api::task::batch_ack))
+        .route("/unack", routing::post(api::task::unack))
+        .route("/watch", routing::post(api::task::watch))
+        .route("/unwatch", routing::post(api::task::unwatch))
+        .route("/assignees", routing::patch(api::task::update_assignees))
+        .route("/approvers", routing::patch(api::task::update_approvers))
+        .route("/delegate", routing::post(api::task::delegate))
+        .route("/recount", routing::post(api::task::recount))
+        .route("/cancel", routing::post(api::task::cancel))
+        .route("/reopen", routing::post(api::task::reopen))
+        .route("/transfer", routing::post(api::task::transfer))
+        .route("/delete", routing::post(api::task::delete))
+        .route("/restore", routing::post(api::task::restore))
+        .route("/batch_delete", routing::post(api::task::batch_delete))
+        .route("/comment", routing::post(api::comment::create))
+        .route("/comment/delete", routing::post(api::comment::delete))
+        .route("/recurrence", routing::post(api::recurrence::create))
+        .route("/recurrence/delete", routing::post(api::recurrence::delete))
+        .route(
+            "/template",
+            routing::post(api::template::create).patch(api::template::update),
+        )
+        .route("/template/delete", routing::post(api::template::delete))
+        .route_layer(middleware::from_fn_with_state(write_timeout, timeout))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            api::maintenance_middleware,
+        ));
+
+    // the import body is streamed, so it is not capped by the DefaultBodyLimit.
+    // the calendar is served without the auth, its token is checked by the handler.
+    let task_streams = Router::new()
+        .route("/export", routing::get(api::task::export))
+        .route("/calendar", routing::get(api::calendar::feed))
+        .route(
+            "/import",
+            routing::post(api::import::import)
+                .layer(RequestBodyLimitLayer::new(limits.max_import_bytes))
+                .layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    api::maintenance_middleware,
+                )),
+        )
+        .route_layer(middleware::from_fn_with_state(stream_timeout, timeout));
+
+    task_reads
+        .merge(task_writes)
+        .merge(task_streams)
+        .route_layer(middleware::from_fn_with_state(ver, api::version_middleware))
+}
+
+// the routes of /{version}/notification, grouped by their timeouts, the writes are rejected in maintenance.
+fn notification_routes(
+    ver: api::ApiVersion,
+    limits: &conf::Limits,
+    app_state: &Arc<api::AppState>,
+) -> Router<Arc<api::AppState>> {
+    let read_timeout = Duration::from_secs(limits.read_timeout);
+    let write_timeout = Duration::from_secs(limits.write_timeout);
+
+    let notification_reads = Router::new()
+        .route("/", routing::get(api::notification::get))
+        .route("/list", routing::post(api::notification::list))
+        .route("/group_list", routing::post(api::notification::group_list))
+        .route("/count", routing::get(api::notification::count))
+        .route("/digest", routing::post(api::notification::digest))
+        .route("/mutes", routing::get(api::notification::mutes))
+        .route_layer(middleware::from_fn_with_state(read_timeout, timeout));
+
+    let notification_writes = Router::new()
+        .route("/read", routing::post(api::notification::read))
+        .route(
+            "/group_delete",
+            routing::post(api::notification::group_delete),
+        )
+        .route(
+            "/group_role",
+            routing::patch(api::notification::update_group_role),
+        )
+        .route("/mute", routing::post(api::notification::mute))
+        .route("/unmute", routing::post(api::notification::unmute))
+        .route("/delete", routing::post(api::notification::delete))
+        .route(
+            "/batch_delete",
+            routing::post(api::notification::batch_delete),
+        )
+        .route_layer(middleware::from_fn_with_state(write_timeout, timeout))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            api::maintenance_middleware,
+        ));
+
+    notification_reads
+        .merge(notification_writes)
+        .route_layer(middleware::from_fn_with_state(ver, api::version_middleware))
+}
+
+// aborts the request when the handler does not respond in time, with a 504 error
+// in the error envelope instead of an empty response.
+pub async fn timeout<B>(
+    State(timeout): State<Duration>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    match time::timeout(timeout, next.run(req)).await {
+        Ok(res) => res,
+        Err(_) => HTTPError::new(
+            504,
+            format!("request timed out after {}ms", timeout.as_millis()),
+        )
+        .into_response(),
+    }
+}
+
+// the router of /metrics served on its own port.
+pub fn metrics(app_state: Arc<api::AppState>) -> Router {
+    Router::new()
+        .route("/metrics", routing::get(api::metrics))
+        .with_state(app_state)
+}
+
+pub fn keyspace(cfg: &conf::Conf) -> String {
+    if !cfg.scylla.keyspace.is_empty() {
+        return cfg.scylla.keyspace.clone();
+    }
+    if cfg.env == "test" {
+        "taskbase_test".to_string()
+    } else {
+        "taskbase".to_string()
+    }
+}
+
+// connects to the keyspace, its schema is migrated first when auto_migrate is enabled.
+pub async fn new_scylla(
+    cfg: conf::ScyllaDB,
+    keyspace: &str,
+) -> anyhow::Result<db::scylladb::ScyllaDB> {
+    if !cfg.auto_migrate {
+        return db::scylladb::ScyllaDB::new(cfg, keyspace).await;
+    }
+
+    let replication = cfg.replication.clone();
+    let scylla = db::scylladb::ScyllaDB::new(cfg, "").await?;
+    let version = scylla.bootstrap(keyspace, &replication).await?;
+    if version < db::schema::SCHEMA_VERSION {
+        log::info!(
+            "schema of {} migrated from version {} to {}",
+            keyspace,
+            version,
+            db::schema::SCHEMA_VERSION
+        );
+    }
+    Ok(scylla)
+}
+
+// the keyring of the task payloads at rest, none when the encryption is not configured.
+pub fn new_keyring(cfg: &conf::Conf) -> anyhow::Result<Option<Arc<crypto::Keyring>>> {
+    let keyring = cfg
+        .encryption
+        .as_ref()
+        .map(crypto::Keyring::new)
+        .transpose()?;
+    Ok(keyring.map(Arc::new))
+}
+
+async fn new_app_state(cfg: conf::Conf) -> anyhow::Result<api::AppState> {
+    let keyspace = keyspace(&cfg);
+    let metrics = Arc::new(metrics::Metrics::new(&cfg.metrics)?);
+    let keyring = new_keyring(&cfg)?;
+    let mut scylla = new_scylla(cfg.scylla, &keyspace)
+        .await?
+        .with_metrics(metrics.clone());
+    if let Some(keyring) = keyring {
+        scylla = scylla.with_keyring(keyring);
+    }
+    if cfg.task_cache.ttl_ms > 0 {
+        scylla = scylla.with_task_cache(db::TaskCache::new(
+            Duration::from_millis(cfg.task_cache.ttl_ms),
+            cfg.task_cache.capacity,
+        ));
+    }
     Ok(api::AppState {
         scylla: Arc::new(scylla),
+        keyspace,
+        trash_retention_ms: cfg.worker.trash_retention_ms(),
+        notification_ttl: cfg.notification_ttl_secs.min(i32::MAX as u32) as i32,
+        webhooks: events::Webhooks::new(cfg.webhooks),
+        max_payload_bytes: cfg.max_payload_bytes,
+        max_message_len: cfg.max_message_len,
+        max_page_size: cfg.max_page_size,
+        kinds: cfg.kinds,
+        metrics,
+        pin_uid: !cfg.auth.secrets.is_empty() && cfg.auth.pin_uid,
+        shutdown: CancellationToken::new(),
+        maintenance: AtomicBool::new(cfg.maintenance),
+        blobs: cfg.blobs.as_ref().map(blobs::Blobs::new).transpose()?,
+        calendar: cfg.calendar.clone(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::BodyStream, http::StatusCode};
+    use tower::ServiceExt;
+
+    use axum_web::erring::ErrorResponse;
+    use axum_web::object::PackObject;
+
+    use super::*;
+
+    async fn read_error(res: Response) -> HTTPError {
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let res: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        res.error
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn timeout_works() {
+        let app = Router::new()
+            .route(
+                "/sleep",
+                routing::get(|| async {
+                    time::sleep(Duration::from_secs(1)).await;
+                    "ok"
+                }),
+            )
+            .route("/fast", routing::get(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(
+                Duration::from_millis(20),
+                timeout,
+            ));
+
+        let req = Request::builder().uri("/fast").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = Request::builder()
+            .uri("/sleep")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        let err = read_error(res).await;
+        assert_eq!(err.code, 504);
+        assert_eq!(err.message, "request timed out after 20ms");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn body_limit_works() {
+        let app = Router::new()
+            .route(
+                "/echo",
+                routing::post(|_: PackObject<serde_json::Value>| async { "ok" }),
+            )
+            .route(
+                "/import",
+                routing::post(|_: BodyStream| async { "ok" }).layer(RequestBodyLimitLayer::new(64)),
+            )
+            .route_layer(DefaultBodyLimit::max(16));
+
+        let post = |uri: &str, body: String| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("content-type", "application/json")
+                .header("content-length", body.len())
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let res = app
+            .clone()
+            .oneshot(post("/echo", r#"{"a":1}"#.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app
+            .clone()
+            .oneshot(post("/echo", format!(r#"{{"a":"{}"}}"#, "x".repeat(32))))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let err = read_error(res).await;
+        assert_eq!(err.code, 413);
+
+        // the streamed body is capped by the RequestBodyLimitLayer of the route.
+        let res = app
+            .clone()
+            .oneshot(post("/import", "x".repeat(32)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app.oneshot(post("/import", "x".repeat(128))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn cors_works() {
+        let cfg: conf::Cors = serde_json::from_value(serde_json::json!({
+            "allowed_origins": ["https://www.yiwen.ai"],
+            "allow_credentials": true,
+        }))
+        .unwrap();
+        // the routes reject the requests without a token, as the auth does.
+        let app = Router::new()
+            .route("/v1/task/list", routing::post(|| async { "ok" }))
+            .route_layer(middleware::from_fn(
+                |_: Request<Body>, _: Next<Body>| async {
+                    HTTPError::new(401, "missing authorization token".to_string()).into_response()
+                },
+            ))
+            .layer(cors(&cfg).unwrap());
+
+        let preflight = |origin: &str| {
+            Request::builder()
+                .method("OPTIONS")
+                .uri("/v1/task/list")
+                .header("origin", origin)
+                .header("access-control-request-method", "POST")
+                .header(
+                    "access-control-request-headers",
+                    "authorization,content-type",
+                )
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = app
+            .clone()
+            .oneshot(preflight("https://www.yiwen.ai"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let headers = res.headers();
+        assert_eq!(
+            headers["access-control-allow-origin"],
+            "https://www.yiwen.ai"
+        );
+        assert_eq!(headers["access-control-allow-credentials"], "true");
+        assert_eq!(headers["access-control-max-age"], "600");
+        let methods = headers["access-control-allow-methods"].to_str().unwrap();
+        assert!(methods.contains("POST"));
+        let allowed = headers["access-control-allow-headers"].to_str().unwrap();
+        assert!(allowed.contains("content-type"));
+        assert!(allowed.contains("authorization"));
+
+        let res = app
+            .clone()
+            .oneshot(preflight("https://evil.example"))
+            .await
+            .unwrap();
+        assert!(res.headers().get("access-control-allow-origin").is_none());
+
+        // the actual request carries the CORS headers too.
+        let req = Request::builder()
+            .method("POST")
+            .uri("/v1/task/list")
+            .header("origin", "https://www.yiwen.ai")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            res.headers()["access-control-allow-origin"],
+            "https://www.yiwen.ai"
+        );
+    }
+}
@@ -0,0 +1,528 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use std::{sync::Arc, time::Duration};
+use tokio::{task::JoinHandle, time};
+
+use crate::api::{self, AppState};
+use crate::conf;
+use crate::db;
+use crate::events;
+
+// runs the scans until the shutdown, a scan in progress is not cut so that
+// the batches are not left half-applied.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Worker) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let scylla = app.scylla.clone();
+        let mut interval = time::interval(Duration::from_secs(cfg.scan_interval.max(1)));
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = app.shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            // the scans write, they are skipped until the maintenance ends.
+            if app.in_maintenance() {
+                log::info!(target: "worker", "scan skipped in maintenance");
+                continue;
+            }
+            match expire_tasks(
+                &scylla,
+                &app.webhooks,
+                app.notification_ttl,
+                unix_ms() as i64,
+                cfg.batch_size,
+            )
+            .await
+            {
+                Ok(0) => {}
+                Ok(expired) => log::info!(target: "worker", "{} tasks expired", expired),
+                Err(err) => log::error!(target: "worker", "expire tasks failed: {}", err),
+            }
+            match remind_tasks(&scylla, unix_ms() as i64, &cfg).await {
+                Ok(0) => {}
+                Ok(reminded) => log::info!(target: "worker", "{} tasks reminded", reminded),
+                Err(err) => log::error!(target: "worker", "remind tasks failed: {}", err),
+            }
+            match escalate_tasks(&scylla, unix_ms() as i64, cfg.batch_size).await {
+                Ok(0) => {}
+                Ok(escalated) => log::info!(target: "worker", "{} tasks escalated", escalated),
+                Err(err) => log::error!(target: "worker", "escalate tasks failed: {}", err),
+            }
+            match retry_notifications(&scylla, unix_ms() as i64, cfg.batch_size).await {
+                Ok(0) => {}
+                Ok(retried) => log::info!(target: "worker", "{} notifications retried", retried),
+                Err(err) => log::error!(target: "worker", "retry notifications failed: {}", err),
+            }
+            match run_recurrences(&app, unix_ms() as i64, cfg.batch_size).await {
+                Ok(0) => {}
+                Ok(created) => log::info!(target: "worker", "{} recurring tasks created", created),
+                Err(err) => log::error!(target: "worker", "run recurrences failed: {}", err),
+            }
+            let before = unix_ms() as i64 - cfg.trash_retention_ms();
+            match purge_trashed(&app, before, cfg.batch_size).await {
+                Ok(0) => {}
+                Ok(purged) => log::info!(target: "worker", "{} trashed tasks purged", purged),
+                Err(err) => log::error!(target: "worker", "purge trashed tasks failed: {}", err),
+            }
+            if cfg.archive_after_ms > 0 {
+                let before = unix_ms() as i64 - cfg.archive_after_ms as i64;
+                match archive_tasks(&scylla, before, cfg.batch_size).await {
+                    Ok(0) => {}
+                    Ok(archived) => log::info!(target: "worker", "{} tasks archived", archived),
+                    Err(err) => log::error!(target: "worker", "archive tasks failed: {}", err),
+                }
+            }
+        }
+        log::info!(target: "worker", "worker stopped");
+    })
+}
+
+// flips processing tasks whose duedate has passed to expired (-3), and notifies their
+// watchers, returns the number of expired tasks.
+pub async fn expire_tasks(
+    scylla: &db::scylladb::ScyllaDB,
+    webhooks: &events::Webhooks,
+    notification_ttl: i32,
+    now_ms: i64,
+    batch_size: u16,
+) -> anyhow::Result<usize> {
+    let docs = db::Task::list_expired(scylla, now_ms, batch_size).await?;
+    let mut expired: usize = 0;
+    for mut doc in docs {
+        match doc.expire(scylla).await {
+            Ok(true) => {
+                expired += 1;
+                // the duedate index only has the uid, id and duedate of the task.
+                match doc.get_one(scylla, vec![]).await {
+                    Ok(_) => {
+                        api::task::notify_watchers(scylla, &doc, notification_ttl).await;
+                        webhooks.task_done(&doc);
+                    }
+                    Err(err) => {
+                        log::warn!(target: "worker", "load expired task {} failed: {}", doc.id, err);
+                    }
+                }
+            }
+            Ok(false) => {}
+            Err(err) => {
+                log::warn!(target: "worker", "expire task {} failed: {}", doc.id, err);
+            }
+        }
+    }
+
+    Ok(expired)
+}
+
+// bumps the notifications of pending assignees for tasks entering the reminder window,
+// returns the number of reminded tasks.
+pub async fn remind_tasks(
+    scylla: &db::scylladb::ScyllaDB,
+    now_ms: i64,
+    cfg: &conf::Worker,
+) -> anyhow::Result<usize> {
+    let max_lead = cfg.max_reminder_lead_ms();
+    if max_lead <= 0 {
+        return Ok(0);
+    }
+
+    let docs = db::Task::list_reminding(scylla, now_ms, now_ms + max_lead, cfg.batch_size).await?;
+    let mut reminded: usize = 0;
+    for doc in docs {
+        if doc.duedate - cfg.reminder_lead_ms(&doc.kind) > now_ms {
+            continue;
+        }
+        if !doc.claim_reminder(scylla, now_ms).await? {
+            continue;
+        }
+
+        let mut task = db::Task::with_pk(doc.uid, doc.id);
+        let fields = vec![
+            "assignees".to_string(),
+            "resolved".to_string(),
+            "rejected".to_string(),
+        ];
+        if task.get_one(scylla, fields).await.is_err() || task.status != db::TaskStatus::Pending {
+            continue;
+        }
+
+        for uid in task.pending_assignees() {
+            let mut notif = db::Notification::with_pk(uid, task.id, task.uid);
+            if let Err(err) = notif.remind(scylla, now_ms).await {
+                log::warn!(target: "worker", "remind task {} to {} failed: {}", task.id, uid, err);
+            }
+        }
+        reminded += 1;
+    }
+
+    Ok(reminded)
+}
+
+// pings the approvers and the pending assignees of processing tasks not acked within
+// escalate_after_ms, a task is escalated at most once, returns the number of escalated tasks.
+pub async fn escalate_tasks(
+    scylla: &db::scylladb::ScyllaDB,
+    now_ms: i64,
+    batch_size: u16,
+) -> anyhow::Result<usize> {
+    let docs = db::Task::list_escalating(scylla, now_ms, batch_size).await?;
+    let mut escalated: usize = 0;
+    for (uid, escalate_at, id) in docs {
+        let mut task = db::Task::with_pk(uid, id);
+        let fields = vec![
+            "approvers".to_string(),
+            "assignees".to_string(),
+            "resolved".to_string(),
+            "rejected".to_string(),
+        ];
+        let applied = match task.get_one(scylla, fields).await {
+            Ok(_) if task.status == db::TaskStatus::Pending => {
+                task.escalate(scylla, now_ms).await?
+            }
+            _ => false,
+        };
+
+        if applied {
+            let receivers = task
+                .approvers
+                .iter()
+                .cloned()
+                .chain(task.pending_assignees());
+            for receiver in receivers {
+                let mut notif = db::Notification::with_pk(receiver, task.id, task.uid);
+                if let Err(err) = notif.escalate(scylla, now_ms).await {
+                    log::warn!(target: "worker", "escalate task {} to {} failed: {}", task.id, receiver, err);
+                }
+            }
+            escalated += 1;
+        }
+        // the index row is consumed whether the task is escalated or not.
+        db::Task::delete_escalation_index(scylla, uid, escalate_at, id).await?;
+    }
+
+    Ok(escalated)
+}
+
+// a notification failed to fan out is given up after the attempts.
+const NOTIFICATION_RETRY_MAX_ATTEMPTS: i32 = 10;
+
+// saves the notifications failed to fan out on task create, the retry records are removed
+// once saved, expired or the task is gone, returns the number of saved notifications.
+pub async fn retry_notifications(
+    scylla: &db::scylladb::ScyllaDB,
+    now_ms: i64,
+    batch_size: u16,
+) -> anyhow::Result<usize> {
+    let docs = db::NotificationRetry::list(scylla, batch_size).await?;
+    let mut retried: usize = 0;
+    for mut doc in docs {
+        let mut task = db::Task::with_pk(doc.sender, doc.tid);
+        let notif = match task.get_one(scylla, vec!["status".to_string()]).await {
+            Ok(_) => doc.notification(now_ms),
+            Err(err) => {
+                let err = HTTPError::from(err);
+                if err.code != 404 {
+                    log::warn!(target: "worker", "load task {} failed: {}", doc.tid, err);
+                    continue;
+                }
+                None
+            }
+        };
+
+        let res = match notif {
+            Some(mut notif) => match notif.save(scylla).await {
+                Ok(_) => {
+                    retried += 1;
+                    Ok(())
+                }
+                Err(err) => Err(HTTPError::from(err)),
+            },
+            None => Ok(()),
+        };
+        match res {
+            // 409: the notification exists already.
+            Err(err) if err.code != 409 => {
+                let attempts = doc.attempts + 1;
+                if attempts < NOTIFICATION_RETRY_MAX_ATTEMPTS {
+                    doc.update_attempts(scylla, attempts).await?;
+                    continue;
+                }
+                log::error!(target: "worker", "{}: notify {} failed after {} attempts: {}", doc.tid, doc.uid, attempts, err);
+            }
+            _ => {}
+        }
+        doc.delete(scylla).await?;
+    }
+
+    Ok(retried)
+}
+
+// creates a task for every recurrence whose next_run_at has passed, a recurrence creates
+// at most one task per scan however many runs were skipped, returns the number of created tasks.
+pub async fn run_recurrences(
+    app: &AppState,
+    now_ms: i64,
+    batch_size: u16,
+) -> anyhow::Result<usize> {
+    let docs = db::TaskRecurrence::list_due(&app.scylla, now_ms, batch_size).await?;
+    let mut created: usize = 0;
+    for mut doc in docs {
+        if !doc.advance(&app.scylla, now_ms).await? {
+            continue; // advanced by another worker
+        }
+
+        match api::task::create_task(app, api::recurrence::task_input(&doc), doc.uid).await {
+            Ok((task, fanout_failed)) => {
+                created += 1;
+                if !fanout_failed.is_empty() {
+                    log::warn!(target: "worker", "recurrence {} created task {} with {} fanout failures", doc.id, task.id, fanout_failed.len());
+                }
+            }
+            Err(err) => {
+                log::warn!(target: "worker", "run recurrence {} failed: {}", doc.id, err);
+            }
+        }
+    }
+
+    Ok(created)
+}
+
+// permanently removes tasks which were trashed before before_ms,
+// returns the number of purged tasks.
+pub async fn purge_trashed(
+    app: &AppState,
+    before_ms: i64,
+    batch_size: u16,
+) -> anyhow::Result<usize> {
+    let docs = db::Task::list_trashed(&app.scylla, before_ms, batch_size).await?;
+    let mut purged: usize = 0;
+    for doc in docs {
+        // re-check the status in case the task was restored after the scan.
+        let mut task = db::Task::with_pk(doc.uid, doc.id);
+        let fields = vec!["deleted_at".to_string()];
+        if task.get_one(&app.scylla, fields).await.is_err()
+            || task.status != db::TaskStatus::Trashed
+            || task.deleted_at >= before_ms
+        {
+            continue;
+        }
+
+        match api::task::purge_task(app, &mut task, doc.uid, None).await {
+            Ok(true) => purged += 1,
+            Ok(false) => {}
+            Err(err) => {
+                log::warn!(target: "worker", "purge task {} failed: {}", doc.id, err);
+            }
+        }
+    }
+
+    Ok(purged)
+}
+
+// moves resolved and rejected tasks last updated before before_ms to the archive table,
+// returns the number of archived tasks.
+pub async fn archive_tasks(
+    scylla: &db::scylladb::ScyllaDB,
+    before_ms: i64,
+    batch_size: u16,
+) -> anyhow::Result<usize> {
+    let docs = db::Task::list_archivable(scylla, before_ms, batch_size).await?;
+    let mut archived: usize = 0;
+    for mut doc in docs {
+        match doc.archive(scylla).await {
+            Ok(true) => archived += 1,
+            Ok(false) => {}
+            Err(err) => {
+                log::warn!(target: "worker", "archive task {} failed: {}", doc.id, err);
+            }
+        }
+    }
+
+    Ok(archived)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use tokio::sync::OnceCell;
+
+    use super::*;
+
+    static DB: OnceCell<db::scylladb::ScyllaDB> = OnceCell::const_new();
+
+    async fn get_db() -> &'static db::scylladb::ScyllaDB {
+        DB.get_or_init(|| async { db::schema::test_db("taskbase_test").await })
+            .await
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn expire_tasks_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let watcher = xid::new();
+        let mut doc = db::Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.duedate = unix_ms() as i64 + 1000;
+        doc.watchers.insert(watcher);
+        doc.save(db).await.unwrap();
+
+        expire_tasks(db, &events::Webhooks::default(), 0, unix_ms() as i64, 1000)
+            .await
+            .unwrap();
+        let mut task = db::Task::with_pk(uid, doc.id);
+        task.get_one(db, vec!["status".to_string()]).await.unwrap();
+        assert_eq!(task.status, db::TaskStatus::Pending);
+
+        time::sleep(Duration::from_millis(1100)).await;
+        let expired = expire_tasks(db, &events::Webhooks::default(), 0, unix_ms() as i64, 1000)
+            .await
+            .unwrap();
+        assert!(expired >= 1);
+        task.get_one(db, vec!["status".to_string()]).await.unwrap();
+        assert_eq!(task.status, db::TaskStatus::Expired);
+
+        let mut notif = db::Notification::with_pk(watcher, doc.id, uid);
+        notif.get_one(db).await.unwrap();
+        assert_eq!(notif.message, "task expired");
+
+        let res = db::Task::list_overdue(db, uid, unix_ms() as i64, 10, None)
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+    }
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn escalate_tasks_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let assignee = xid::new();
+
+        let mut doc = db::Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.created_at = unix_ms() as i64;
+        doc.assignees = HashSet::from([assignee]);
+        doc.escalate_after_ms = 1000;
+        doc.save(db).await.unwrap();
+        let mut notif = db::Notification::with_pk(assignee, doc.id, uid);
+        notif.read_at = unix_ms() as i64;
+        notif.save(db).await.unwrap();
+
+        let escalated = escalate_tasks(db, doc.created_at + 1001, 1000)
+            .await
+            .unwrap();
+        assert!(escalated >= 1);
+        let mut task = db::Task::with_pk(uid, doc.id);
+        task.get_one(db, vec!["escalated_at".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(task.escalated_at, doc.created_at + 1001);
+        notif.get_one(db).await.unwrap();
+        assert_eq!(notif.escalated_at, doc.created_at + 1001);
+        assert_eq!(notif.read_at, 0);
+
+        // escalated at most once.
+        assert!(!task.escalate(db, unix_ms() as i64).await.unwrap());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn retry_notifications_works() {
+        // a dedicated keyspace, its notification_by_tid table is dropped to fail the fanout.
+        let db = Arc::new(db::schema::test_db("taskbase_fanout_test").await);
+        let app = AppState {
+            scylla: db.clone(),
+            keyspace: "taskbase_fanout_test".to_string(),
+            trash_retention_ms: 0,
+            notification_ttl: 0,
+            webhooks: Default::default(),
+            max_payload_bytes: usize::MAX,
+            max_message_len: usize::MAX,
+            max_page_size: u16::MAX,
+            kinds: Default::default(),
+            metrics: Default::default(),
+            pin_uid: false,
+            shutdown: Default::default(),
+            maintenance: Default::default(),
+            blobs: None,
+            calendar: Default::default(),
+        };
+        let (a, b) = (xid::new(), xid::new());
+        let uid = xid::new();
+        let mut input = api::task::test_create_input(uid, xid::new(), &[a, b]);
+        input.kind = "test".to_string();
+
+        db.execute("DROP TABLE IF EXISTS notification_by_tid", &[])
+            .await
+            .unwrap();
+        let (task, mut failed) = api::task::create_task(&app, input, uid).await.unwrap();
+        failed.sort();
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(failed, expected);
+        assert!(db::Notification::with_pk(a, task.id, task.uid)
+            .get_one(&db)
+            .await
+            .is_err());
+
+        // the worker keeps the retry records until the notifications are saved.
+        assert_eq!(
+            retry_notifications(&db, unix_ms() as i64, 1000)
+                .await
+                .unwrap(),
+            0
+        );
+        db::scylladb::exec_cqls(&db, db::schema::SCHEMA_TABLE)
+            .await
+            .unwrap();
+        assert_eq!(
+            retry_notifications(&db, unix_ms() as i64, 1000)
+                .await
+                .unwrap(),
+            2
+        );
+        for uid in [a, b] {
+            let mut notif = db::Notification::with_pk(uid, task.id, task.uid);
+            notif.get_one(&db).await.unwrap();
+            assert_eq!(notif.gid, task.gid);
+        }
+        assert!(db::NotificationRetry::list(&db, 1000)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn remind_tasks_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let assignee = xid::new();
+        let now = unix_ms() as i64;
+        let cfg = conf::Worker {
+            scan_interval: 1,
+            batch_size: 1000,
+            reminder_lead: 3600,
+            reminder_kind_leads: Default::default(),
+            trash_retention: 3600,
+            archive_after_ms: 0,
+        };
+
+        let mut doc = db::Task::with_pk(uid, xid::new());
+        doc.kind = "test".to_string();
+        doc.threshold = 1;
+        doc.assignees = HashSet::from([assignee]);
+        doc.duedate = now + 60 * 1000;
+        doc.save(db).await.unwrap();
+        let mut notif = db::Notification::with_pk(assignee, doc.id, uid);
+        notif.save(db).await.unwrap();
+
+        assert!(remind_tasks(db, now, &cfg).await.unwrap() >= 1);
+        notif.get_one(db).await.unwrap();
+        assert_eq!(notif.reminded_at, now);
+
+        // reminded at most once.
+        remind_tasks(db, now + 1000, &cfg).await.unwrap();
+        notif.get_one(db).await.unwrap();
+        assert_eq!(notif.reminded_at, now);
+    }
+}
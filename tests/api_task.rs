@@ -0,0 +1,754 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+mod common;
+
+use common::TestApp;
+use taskbase::api::ApiVersion;
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn task_create_get_update_works() {
+    let app = TestApp::new().await;
+    for ver in ApiVersion::ALL {
+        check_create_get_update(&app, ver).await;
+    }
+    app.teardown().await;
+}
+
+async fn check_create_get_update(app: &TestApp, ver: ApiVersion) {
+    let v = ver.as_str();
+    let (uid, gid) = (xid::new(), xid::new());
+    let assignees = vec![xid::new().to_string(), xid::new().to_string()];
+
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(json!({
+                "uid": uid.to_string(),
+                "gid": gid.to_string(),
+                "kind": "test",
+                "assignees": assignees,
+                "message": "hello",
+                "payload": "",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    let id = res["result"]["id"].as_str().unwrap().to_string();
+    assert_eq!(res["result"]["status"], 0);
+
+    let get = format!("/{}/task?uid={}&id={}", v, uid, id);
+    let (status, res) = app.request("GET", &get, uid, None).await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["gid"], gid.to_string());
+    assert_eq!(res["result"]["kind"], "test");
+    assert_eq!(res["result"]["message"], "hello");
+    let updated_at = res["result"]["updated_at"].as_i64().unwrap();
+
+    // v2 renames duedate to due_at, and always includes the counts of the sets.
+    let (status, res) = app
+        .request("GET", &format!("{}&fields=message,duedate", get), uid, None)
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    match ver {
+        ApiVersion::V1 => {
+            assert_eq!(res["result"]["duedate"], 0);
+            assert!(res["result"].get("due_at").is_none());
+            assert!(res["result"].get("assignee_count").is_none());
+        }
+        ApiVersion::V2 => {
+            assert_eq!(res["result"]["due_at"], 0);
+            assert!(res["result"].get("duedate").is_none());
+            assert_eq!(res["result"]["assignee_count"], 2);
+            assert_eq!(res["result"]["resolved_count"], 0);
+            assert!(res["result"].get("assignees").is_none());
+        }
+    }
+
+    let (status, res) = app
+        .request(
+            "PATCH",
+            &format!("/{}/task", v),
+            uid,
+            Some(json!({
+                "uid": uid.to_string(),
+                "id": id,
+                "updated_at": updated_at,
+                "message": "updated",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert!(res["result"]["updated_at"].as_i64().unwrap() > updated_at);
+
+    // the update with the stale updated_at conflicts.
+    let (status, res) = app
+        .request(
+            "PATCH",
+            &format!("/{}/task", v),
+            uid,
+            Some(json!({
+                "uid": uid.to_string(),
+                "id": id,
+                "updated_at": updated_at,
+                "message": "stale",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", res);
+    assert_eq!(res["error"]["code"], 409);
+
+    let (status, res) = app.request("GET", &get, uid, None).await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["message"], "updated");
+
+    let (status, _) = app
+        .request(
+            "GET",
+            &format!("/{}/task?uid={}&id={}", v, uid, xid::new()),
+            uid,
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn task_ack_works() {
+    let app = TestApp::new().await;
+    for ver in ApiVersion::ALL {
+        check_ack(&app, ver).await;
+    }
+    app.teardown().await;
+}
+
+async fn check_ack(app: &TestApp, ver: ApiVersion) {
+    let v = ver.as_str();
+    let (uid, gid) = (xid::new(), xid::new());
+    let (a, b) = (xid::new(), xid::new());
+
+    // threshold 0, both assignees should resolve the task.
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(json!({
+                "uid": uid.to_string(),
+                "gid": gid.to_string(),
+                "kind": "test",
+                "assignees": [a.to_string(), b.to_string()],
+                "payload": "",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    let id = res["result"]["id"].as_str().unwrap().to_string();
+
+    let ack = |user: xid::Id, status: i8| {
+        json!({
+            "uid": user.to_string(),
+            "tid": id,
+            "sender": uid.to_string(),
+            "status": status,
+            "message": "ok",
+        })
+    };
+
+    let (status, res) = app
+        .request("PATCH", &format!("/{}/task/ack", v), a, Some(ack(a, 1)))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["result"], true);
+    assert_eq!(res["result"]["status"], 0);
+
+    // the ack reads the notification.
+    let (status, res) = app
+        .request(
+            "GET",
+            &format!(
+                "/{}/notification?uid={}&tid={}&sender={}&fields=duedate",
+                v, a, id, uid
+            ),
+            a,
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["ack_status"], 1);
+    assert!(res["result"]["read_at"].as_i64().unwrap() > 0);
+    match ver {
+        ApiVersion::V1 => {
+            assert_eq!(res["result"]["duedate"], 0);
+            assert!(res["result"].get("resolved_count").is_none());
+        }
+        ApiVersion::V2 => {
+            assert_eq!(res["result"]["due_at"], 0);
+            assert!(res["result"].get("duedate").is_none());
+            assert_eq!(res["result"]["assignee_count"], 2);
+            assert_eq!(res["result"]["resolved_count"], 1);
+        }
+    }
+
+    // a repeated ack is a no-op.
+    let (status, res) = app
+        .request("PATCH", &format!("/{}/task/ack", v), a, Some(ack(a, 1)))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["result"], false);
+
+    let (status, res) = app
+        .request("PATCH", &format!("/{}/task/ack", v), b, Some(ack(b, 1)))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["status"], 1);
+
+    let (status, res) = app
+        .request(
+            "GET",
+            &format!("/{}/task?uid={}&id={}", v, uid, id),
+            uid,
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["status"], 1);
+
+    let (status, res) = app
+        .request(
+            "GET",
+            &format!("/{}/task/acks?uid={}&id={}", v, uid, id),
+            uid,
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    let acks: Vec<&serde_json::Value> = res["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|ack| ack["uid"] == a.to_string() || ack["uid"] == b.to_string())
+        .collect();
+    assert_eq!(acks.len(), 2);
+    assert!(acks.iter().all(|ack| ack["status"] == 1));
+    assert!(acks.iter().all(|ack| ack["reason"] == ""));
+
+    // the default config has no reason codes.
+    let mut reasoned = ack(a, -1);
+    reasoned["reason"] = json!("duplicate");
+    let (status, res) = app
+        .request("PATCH", &format!("/{}/task/ack", v), a, Some(reasoned))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", res);
+
+    // a user who is not an assignee can not ack.
+    let c = xid::new();
+    let (status, _) = app
+        .request("PATCH", &format!("/{}/task/ack", v), c, Some(ack(c, 1)))
+        .await;
+    assert!(status.is_client_error());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn task_watch_works() {
+    let app = TestApp::new().await;
+    for ver in ApiVersion::ALL {
+        check_watch(&app, ver).await;
+    }
+    app.teardown().await;
+}
+
+async fn check_watch(app: &TestApp, ver: ApiVersion) {
+    let v = ver.as_str();
+    let (uid, gid) = (xid::new(), xid::new());
+    let (a, w) = (xid::new(), xid::new());
+
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(json!({
+                "uid": uid.to_string(),
+                "gid": gid.to_string(),
+                "kind": "test",
+                "assignees": [a.to_string()],
+                "payload": "",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    let id = res["result"]["id"].as_str().unwrap().to_string();
+    let watch = json!({
+        "uid": w.to_string(),
+        "tid": id,
+        "sender": uid.to_string(),
+    });
+
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task/watch", v),
+            w,
+            Some(watch.clone()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"], true);
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task/watch", v),
+            w,
+            Some(watch.clone()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"], false);
+
+    let (status, res) = app
+        .request(
+            "GET",
+            &format!("/{}/task?uid={}&id={}&fields=watchers", v, uid, id),
+            uid,
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["watchers"], json!([w.to_string()]));
+
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task/unwatch", v),
+            w,
+            Some(watch.clone()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"], true);
+    let (status, res) = app
+        .request("POST", &format!("/{}/task/unwatch", v), w, Some(watch))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"], false);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn task_stats_works() {
+    let app = TestApp::new().await;
+    for ver in ApiVersion::ALL {
+        check_stats(&app, ver).await;
+    }
+    app.teardown().await;
+}
+
+async fn check_stats(app: &TestApp, ver: ApiVersion) {
+    let v = ver.as_str();
+    let (uid, gid, a) = (xid::new(), xid::new(), xid::new());
+
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(json!({
+                "uid": uid.to_string(),
+                "gid": gid.to_string(),
+                "kind": "test",
+                "assignees": [a.to_string()],
+                "payload": "",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    let id = res["result"]["id"].as_str().unwrap().to_string();
+
+    let (status, res) = app
+        .request(
+            "PATCH",
+            &format!("/{}/task/ack", v),
+            a,
+            Some(json!({
+                "uid": a.to_string(),
+                "tid": id,
+                "sender": uid.to_string(),
+                "status": -1,
+                "message": "no",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["status"], -1);
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    let day = 24 * 3600 * 1000;
+    let path = format!(
+        "/{}/task/stats?gid={}&from={}&to={}",
+        v,
+        gid,
+        now - day,
+        now + day
+    );
+    let (status, res) = app.request("GET", &path, uid, None).await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["rejected"], 1);
+    assert_eq!(res["result"]["resolved"], 0);
+    assert_eq!(res["result"]["rejected_ratio"], 1.0);
+
+    let path = format!(
+        "/{}/task/stats?gid={}&from={}&to={}",
+        v,
+        gid,
+        now,
+        now - 2 * day
+    );
+    let (status, _) = app.request("GET", &path, uid, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn task_visibility_works() {
+    let app = TestApp::new().await;
+    for ver in ApiVersion::ALL {
+        check_visibility(&app, ver).await;
+    }
+    app.teardown().await;
+}
+
+async fn check_visibility(app: &TestApp, ver: ApiVersion) {
+    let v = ver.as_str();
+    let (uid, gid, a) = (xid::new(), xid::new(), xid::new());
+    let task = |visibility: &str, group_role: Option<i8>| {
+        json!({
+            "uid": uid.to_string(),
+            "gid": gid.to_string(),
+            "kind": "test",
+            "assignees": [a.to_string()],
+            "payload": "",
+            "group_role": group_role,
+            "visibility": visibility,
+        })
+    };
+
+    // a private task can not take a group role.
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(task("private", Some(1))),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", res);
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(task("secret", None)),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", res);
+
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(task("private", None)),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    let id = res["result"]["id"].as_str().unwrap().to_string();
+
+    let (status, res) = app
+        .request(
+            "GET",
+            &format!("/{}/task?uid={}&id={}&fields=visibility", v, uid, id),
+            uid,
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["visibility"], "private");
+
+    // the group sees the group tasks only.
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(task("group", Some(1))),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/notification/group_list", v),
+            uid,
+            Some(json!({ "gid": gid.to_string() })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    let items = res["result"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_ne!(items[0]["tid"], id);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn task_pinned_uid_works() {
+    let app = TestApp::with_pinned_uid().await;
+    for ver in ApiVersion::ALL {
+        check_pinned_uid(&app, ver).await;
+    }
+    app.teardown().await;
+}
+
+async fn check_pinned_uid(app: &TestApp, ver: ApiVersion) {
+    let v = ver.as_str();
+    let (uid, gid, a, other) = (xid::new(), xid::new(), xid::new(), xid::new());
+
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(json!({
+                "uid": uid.to_string(),
+                "gid": gid.to_string(),
+                "kind": "test",
+                "assignees": [a.to_string()],
+                "payload": "",
+                "group_role": 1,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    let id = res["result"]["id"].as_str().unwrap().to_string();
+
+    // the creator and the participants read the task, other users do not.
+    let get = format!("/{}/task?uid={}&id={}", v, uid, id);
+    for user in [uid, a] {
+        let (status, res) = app.request("GET", &get, user, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", res);
+    }
+    let (status, res) = app.request("GET", &get, other, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", res);
+
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task/comment", v),
+            a,
+            Some(json!({ "uid": uid.to_string(), "tid": id, "message": "hi" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+
+    let forbidden = [
+        (
+            "POST",
+            &format!("/{}/task/batch_get", v),
+            json!({ "uid": uid.to_string(), "ids": [id] }),
+        ),
+        (
+            "POST",
+            &format!("/{}/task/comment", v),
+            json!({ "uid": uid.to_string(), "tid": id, "message": "hi" }),
+        ),
+        (
+            "POST",
+            &format!("/{}/task/comment/list", v),
+            json!({ "uid": uid.to_string(), "tid": id }),
+        ),
+        (
+            "POST",
+            &format!("/{}/notification/group_delete", v),
+            json!({ "gid": gid.to_string(), "tid": id, "sender": uid.to_string() }),
+        ),
+        (
+            "PATCH",
+            &format!("/{}/notification/group_role", v),
+            json!({ "gid": gid.to_string(), "tid": id, "sender": uid.to_string(), "role": 0 }),
+        ),
+    ];
+    for (method, path, body) in forbidden {
+        let (status, res) = app.request(method, path, other, Some(body)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", path, res);
+    }
+
+    // the group routes need the admin scope, even for the members.
+    for path in [
+        &format!("/{}/task/list_by_group", v),
+        &format!("/{}/task/template/list", v),
+        &format!("/{}/notification/group_list", v),
+    ] {
+        let (status, res) = app
+            .request("POST", path, uid, Some(json!({ "gid": gid.to_string() })))
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", path, res);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn task_delegate_works() {
+    let app = TestApp::new().await;
+    for ver in ApiVersion::ALL {
+        check_delegate(&app, ver).await;
+    }
+    app.teardown().await;
+}
+
+async fn check_delegate(app: &TestApp, ver: ApiVersion) {
+    let v = ver.as_str();
+    let (uid, gid) = (xid::new(), xid::new());
+    let (a, b, c, d) = (xid::new(), xid::new(), xid::new(), xid::new());
+
+    // a is an assignee and an approver.
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(json!({
+                "uid": uid.to_string(),
+                "gid": gid.to_string(),
+                "kind": "test",
+                "assignees": [a.to_string(), b.to_string()],
+                "approvers": [a.to_string()],
+                "payload": "",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    let id = res["result"]["id"].as_str().unwrap().to_string();
+
+    let delegate = |from: xid::Id, to: xid::Id| {
+        json!({
+            "uid": uid.to_string(),
+            "id": id,
+            "from": from.to_string(),
+            "to": to.to_string(),
+        })
+    };
+    let ack = |user: xid::Id| {
+        json!({
+            "uid": user.to_string(),
+            "tid": id,
+            "sender": uid.to_string(),
+            "status": 1,
+            "message": "ok",
+        })
+    };
+
+    for (from, to) in [(a, c), (b, d)] {
+        let (status, res) = app
+            .request(
+                "POST",
+                &format!("/{}/task/delegate", v),
+                from,
+                Some(delegate(from, to)),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", res);
+    }
+
+    // the approver still acks as an approver, the plain assignee is out.
+    let (status, res) = app
+        .request("PATCH", &format!("/{}/task/ack", v), a, Some(ack(a)))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", res);
+    assert_eq!(res["result"]["result"], true);
+    let (status, res) = app
+        .request("PATCH", &format!("/{}/task/ack", v), b, Some(ack(b)))
+        .await;
+    assert_ne!(status, StatusCode::OK, "{}", res);
+    for user in [c, d] {
+        let (status, res) = app
+            .request("PATCH", &format!("/{}/task/ack", v), user, Some(ack(user)))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", res);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn task_create_duedate_works() {
+    let app = TestApp::new().await;
+    for ver in ApiVersion::ALL {
+        check_create_duedate(&app, ver).await;
+    }
+    app.teardown().await;
+}
+
+async fn check_create_duedate(app: &TestApp, ver: ApiVersion) {
+    let v = ver.as_str();
+    let (uid, gid, a) = (xid::new(), xid::new(), xid::new());
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    let day = 24 * 3600 * 1000;
+    let task = |duedate: Option<i64>| {
+        json!({
+            "uid": uid.to_string(),
+            "gid": gid.to_string(),
+            "kind": "test",
+            "assignees": [a.to_string()],
+            "payload": "",
+            "duedate": duedate,
+        })
+    };
+    let field = match ver {
+        ApiVersion::V1 => "duedate",
+        ApiVersion::V2 => "due_at",
+    };
+
+    // no duedate is stored as 0.
+    for (duedate, expected) in [(None, 0), (Some(now + day), now + day)] {
+        let (status, res) = app
+            .request("POST", &format!("/{}/task", v), uid, Some(task(duedate)))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", res);
+        let id = res["result"]["id"].as_str().unwrap().to_string();
+
+        let (status, res) = app
+            .request(
+                "GET",
+                &format!("/{}/task?uid={}&id={}&fields=duedate", v, uid, id),
+                uid,
+                None,
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", res);
+        assert_eq!(res["result"][field], expected);
+    }
+
+    let (status, res) = app
+        .request(
+            "POST",
+            &format!("/{}/task", v),
+            uid,
+            Some(task(Some(now - day))),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", res);
+    assert!(res["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("invalid duedate"));
+}
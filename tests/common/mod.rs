@@ -0,0 +1,108 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use taskbase::{
+    api::AppState,
+    auth::{Auth, Claims},
+    conf, router,
+};
+
+// the app over a keyspace of its own, created by the auto migration and dropped by `teardown`.
+// It connects to the comma-separated nodes in SCYLLA_URI, e.g. "127.0.0.1:9042",
+// or to the nodes in the config, see `make test-integration`.
+pub struct TestApp {
+    pub state: Arc<AppState>,
+    pub router: Router,
+    keyspace: String,
+    secret: Option<String>, // the requests carry tokens of their user when the auth is on
+}
+
+// the secret of the tokens of `TestApp::with_pinned_uid`.
+const TEST_SECRET: &str = "test secret";
+
+impl TestApp {
+    pub async fn new() -> Self {
+        Self::build(None).await
+    }
+
+    // the app with the auth on and pin_uid, the users can only act on their own data.
+    pub async fn with_pinned_uid() -> Self {
+        Self::build(Some(TEST_SECRET.to_string())).await
+    }
+
+    async fn build(secret: Option<String>) -> Self {
+        let mut cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        if let Ok(uri) = std::env::var("SCYLLA_URI") {
+            cfg.scylla.nodes = uri.split(',').map(|s| s.trim().to_string()).collect();
+        }
+
+        let keyspace = format!("taskbase_it_{}", xid::new());
+        cfg.scylla.keyspace = keyspace.clone();
+        cfg.scylla.auto_migrate = true;
+        cfg.scylla.replication =
+            "{ 'class': 'SimpleStrategy', 'replication_factor': '1' }".to_string();
+        if let Some(secret) = &secret {
+            cfg.auth.secrets = vec![secret.clone()];
+            cfg.auth.pin_uid = true;
+        }
+        let (state, router) = router::new(cfg).await.unwrap();
+        Self {
+            state,
+            router,
+            keyspace,
+            secret,
+        }
+    }
+
+    // sends the request as the user with a JSON body, and returns the JSON response.
+    pub async fn request(
+        &self,
+        method: &str,
+        uri: &str,
+        user: xid::Id,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("accept", "application/json")
+            .header("x-auth-user", user.to_string());
+        if let Some(secret) = &self.secret {
+            let claims = Claims {
+                exp: i64::MAX,
+                uid: user.to_string(),
+                ..Default::default()
+            };
+            req = req.header(
+                "authorization",
+                format!("Bearer {}", Auth::sign(secret, &claims)),
+            );
+        }
+        let req = match body {
+            Some(body) => req
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&body).unwrap())),
+            None => req.body(Body::empty()),
+        };
+
+        let res = self.router.clone().oneshot(req.unwrap()).await.unwrap();
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        if body.is_empty() {
+            return (status, Value::Null);
+        }
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    // a failed test leaves its keyspace behind, they are named taskbase_it_*.
+    pub async fn teardown(self) {
+        let query = format!("DROP KEYSPACE IF EXISTS {}", self.keyspace);
+        self.state.scylla.execute(query, &[]).await.unwrap();
+    }
+}